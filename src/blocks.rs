@@ -1,73 +1,194 @@
-pub use block_builder::BlockBuilder;
+use crate::util::coding::get_varint32;
+use crate::util::error::{Error, Result};
+pub use block_builder::{BlockBuilder, BLOCK_SIZE, DEFAULT_RESTART_INTERVAL};
 use bytes::{Buf, BufMut, Bytes};
 
+pub const SIZE_U16: usize = std::mem::size_of::<u16>();
+
 /// A block is the smallest unit of read and caching in LSM tree.
 /// It is a collection of sorted key-value pairs.
 /// The `actual` storage format is as below (After `Block::encode`):
 ///
 /// ``` text
-/// ----------------------------------------------------------------------------------------------------
-/// |             Data Section             |              Offset Section             |      Extra      |
-/// ----------------------------------------------------------------------------------------------------
-/// | Entry #1 | Entry #2 | ... | Entry #N | Offset #1 | Offset #2 | ... | Offset #N | num_of_elements |
-/// ----------------------------------------------------------------------------------------------------
+/// -------------------------------------------------------------------------------------------------------
+/// |             Data Section             |              Restart Section               |      Extra      |
+/// -------------------------------------------------------------------------------------------------------
+/// | Entry #1 | Entry #2 | ... | Entry #N | Restart #1 | Restart #2 | ... | Restart #M | num_of_restarts |
+/// -------------------------------------------------------------------------------------------------------
 /// ```
 /// The `Entry` storage format is as below (After `BlockBuilder::add()`):
 ///
 /// ``` text
-/// +----------------------------------------------------------------------------------------------------------------------+
-/// | Key Length (2 bytes) |  Key PayLoads(key-length bytes) | Value Length (2 bytes) | Value PayLoad (value-length bytes) |
-/// +----------------------------------------------------------------------------------------------------------------------+
+/// +------------------------------------------------------------------------------------------------------------+
+/// | Shared (varint) | Unshared (varint) | Value Length (varint) | Key Delta (unshared bytes) | Value PayLoad       |
+/// +------------------------------------------------------------------------------------------------------------+
 /// ```
-
-pub const SIZE_U16: usize = std::mem::size_of::<u16>();
+/// The three lengths are encoded as varint (see `util::coding`), so that they mostly take a
+/// single byte. `Shared` is the length of the prefix the key shares with the key of the
+/// previous entry, only the remaining `Key Delta` is stored. Every `restart interval` entries
+/// the sharing is reset to zero, such an entry is a `restart point` and holds the full key,
+/// its offset is recorded in the restart section. Searching a key starts with binary searching
+/// the restart points and then decodes the entries forward.
 pub struct Blocks {
     data: Vec<u8>,
-    offsets: Vec<u16>,
+    restarts: Vec<u16>,
     num_of_elements: usize,
 }
 
+/// The decoded header of the entry which starts at `offset` of the data section
+pub(crate) struct EntryHeader {
+    pub(crate) shared: usize,
+    pub(crate) unshared: usize,
+    pub(crate) value_length: usize,
+    /// position of the `Key Delta`
+    pub(crate) key_offset: usize,
+}
+
+impl EntryHeader {
+    pub(crate) fn value_offset(&self) -> usize {
+        self.key_offset + self.unshared
+    }
+    /// position of the next entry
+    pub(crate) fn end(&self) -> usize {
+        self.value_offset() + self.value_length
+    }
+}
+
 impl Blocks {
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
-        for offset in &self.offsets {
-            buf.put_u16(*offset);
+        for restart in &self.restarts {
+            buf.put_u16(*restart);
         }
-        let num_of_element = self.offsets.len() as u16;
-        buf.put_u16(num_of_element);
+        let num_of_restarts = self.restarts.len() as u16;
+        buf.put_u16(num_of_restarts);
         buf.into()
     }
-    pub fn decode(data: &[u8]) -> Self {
+    /// Decode the raw bytes produced by `Blocks::encode`.
+    /// Failed with `Corruption` if any entry or restart point exceeds the data section, the
+    /// offset of the `Corruption` is relative to the beginning of `data`
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < SIZE_U16 {
+            return Err(Error::corruption("", 0));
+        }
         let mut footer = &data[data.len() - SIZE_U16..];
-        let num_of_elements = footer.get_u16() as usize;
+        let num_of_restarts = footer.get_u16() as usize;
 
-        let offsets_portion =
-            &data[data.len() - SIZE_U16 - num_of_elements * SIZE_U16..data.len() - SIZE_U16];
+        let trailer_length = SIZE_U16 + num_of_restarts * SIZE_U16;
+        if trailer_length > data.len() {
+            return Err(Error::corruption("", (data.len() - SIZE_U16) as u64));
+        }
+        let restarts_portion = &data[data.len() - trailer_length..data.len() - SIZE_U16];
+        let data_portion = &data[..data.len() - trailer_length];
+        let restarts: Vec<u16> = restarts_portion
+            .chunks(SIZE_U16)
+            .map(|mut x| x.get_u16())
+            .collect();
 
-        let data_portion = &data[..data.len() - SIZE_U16 - num_of_elements * SIZE_U16];
-        Blocks {
+        // walk through all entries, every entry must be completely inside the data section
+        // and every restart point must be the beginning of an entry holding a full key
+        let mut num_of_elements = 0;
+        let mut offset = 0;
+        let mut previous_key_length = 0;
+        let mut next_restart = 0;
+        while offset < data_portion.len() {
+            let header = Self::decode_entry_header(data_portion, offset)
+                .ok_or_else(|| Error::corruption("", offset as u64))?;
+            if header.shared > previous_key_length {
+                return Err(Error::corruption("", offset as u64));
+            }
+            if next_restart < restarts.len() && restarts[next_restart] as usize == offset {
+                if header.shared != 0 {
+                    return Err(Error::corruption("", offset as u64));
+                }
+                next_restart += 1;
+            }
+            previous_key_length = header.shared + header.unshared;
+            num_of_elements += 1;
+            offset = header.end();
+        }
+        if next_restart != restarts.len() {
+            return Err(Error::corruption("", data_portion.len() as u64));
+        }
+        Ok(Blocks {
             data: data_portion.into(),
-            offsets: offsets_portion
-                .chunks(SIZE_U16)
-                .map(|mut x| x.get_u16())
-                .collect(),
+            restarts,
             num_of_elements,
+        })
+    }
+
+    /// Decode the header of the entry starting at `offset`, `None` if the entry exceeds the
+    /// boundary of `data`
+    pub(crate) fn decode_entry_header(data: &[u8], offset: usize) -> Option<EntryHeader> {
+        let mut position = offset;
+        let mut lengths = [0usize; 3];
+        for length in lengths.iter_mut() {
+            let (value, consumed) = get_varint32(data.get(position..)?)?;
+            *length = value as usize;
+            position += consumed;
+        }
+        let header = EntryHeader {
+            shared: lengths[0],
+            unshared: lengths[1],
+            value_length: lengths[2],
+            key_offset: position,
+        };
+        if header.end() > data.len() {
+            return None;
         }
+        Some(header)
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    pub(crate) fn restarts(&self) -> &[u16] {
+        self.restarts.as_slice()
+    }
+
+    pub fn num_of_elements(&self) -> usize {
+        self.num_of_elements
     }
-    pub fn largest_key(&self) -> &[u8] {
-        let offset = self.offsets[self.offsets.len() - 1] as usize;
-        let buf = self.data.as_slice();
-        let key_length = (&buf[offset..offset + SIZE_U16]).get_u16() as usize;
-        &buf[offset + SIZE_U16..offset + SIZE_U16 + key_length]
+
+    /// Reconstruct the key of the last entry, the scan starts from the last restart point
+    pub fn largest_key(&self) -> Vec<u8> {
+        let mut key = Vec::new();
+        let Some(last_restart) = self.restarts.last() else {
+            return key;
+        };
+        let mut offset = *last_restart as usize;
+        while offset < self.data.len() {
+            let header = Self::decode_entry_header(self.data.as_slice(), offset)
+                .expect("entries are verified while building or decoding the block");
+            key.truncate(header.shared);
+            key.extend_from_slice(&self.data[header.key_offset..header.value_offset()]);
+            offset = header.end();
+        }
+        key
     }
-    pub fn smallest_key(&self) -> &[u8] {
-        let offset = 0;
-        let buf = self.data.as_slice();
-        let key_length = (&buf[0..SIZE_U16]).get_u16() as usize;
-        &buf[SIZE_U16..SIZE_U16 + key_length]
+    pub fn smallest_key(&self) -> Vec<u8> {
+        match Self::decode_entry_header(self.data.as_slice(), 0) {
+            None => Vec::new(),
+            Some(header) => self.data[header.key_offset..header.value_offset()].to_vec(),
+        }
     }
     pub fn size(&self) -> u64 {
-        (self.data.len() + self.offsets.len() * 2 + 2) as u64
+        (self.data.len() + self.restarts.len() * SIZE_U16 + SIZE_U16) as u64
+    }
+
+    /// Apply `update` on the value of every entry in place, the length of the values stays
+    pub fn update_values<F>(&mut self, mut update: F)
+    where
+        F: FnMut(&mut [u8]),
+    {
+        let mut offset = 0;
+        while offset < self.data.len() {
+            let header = Self::decode_entry_header(self.data.as_slice(), offset)
+                .expect("entries are verified while building or decoding the block");
+            update(&mut self.data[header.value_offset()..header.end()]);
+            offset = header.end();
+        }
     }
 }
 
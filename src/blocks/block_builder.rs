@@ -1,103 +1,150 @@
 use crate::blocks::{Blocks, SIZE_U16};
-use anyhow::Result;
-use bytes::BufMut;
-use std::io;
+use crate::util::coding::{put_varint32, MAX_VARINT32_LENGTH};
+use crate::util::error::{Error, Result};
 
+/// size of a data block if not specified
 pub const BLOCK_SIZE: usize = 4 * 1024;
+/// number of entries between two restart points if not specified
+pub const DEFAULT_RESTART_INTERVAL: usize = 16;
 
 /// BlockBuilder accept the client provided records and generate the Block object
 /// after invoking build().
 /// Blocks layouts like this:
 /// ``` text
-/// ----------------------------------------------------------------------------------------------------
-/// |             Data Section             |              Offset Section             |      Extra      |
-/// ----------------------------------------------------------------------------------------------------
-/// | Entry #1 | Entry #2 | ... | Entry #N | Offset #1 | Offset #2 | ... | Offset #N | num_of_elements |
-/// ----------------------------------------------------------------------------------------------------
+/// -------------------------------------------------------------------------------------------------------
+/// |             Data Section             |              Restart Section               |      Extra      |
+/// -------------------------------------------------------------------------------------------------------
+/// | Entry #1 | Entry #2 | ... | Entry #N | Restart #1 | Restart #2 | ... | Restart #M | num_of_restarts |
+/// -------------------------------------------------------------------------------------------------------
 /// ```
 ///
 /// Entry encoding format is described below:
 /// ``` text
-/// +----------------------------------------------------------------------------------------------------------------------+
-/// | Key Length (2 bytes) |  Key PayLoads(key-length bytes) | Value Length (2 bytes) | Value PayLoad (value-length bytes) |
-/// +----------------------------------------------------------------------------------------------------------------------+
+/// +------------------------------------------------------------------------------------------------------------+
+/// | Shared (varint) | Unshared (varint) | Value Length (varint) | Key Delta (unshared bytes) | Value PayLoad       |
+/// +------------------------------------------------------------------------------------------------------------+
 /// ```
 /// Every record will be encoded in this format and save the raw bytes into the data field.
+/// The key of a record only keeps the part which differs from the key of the previous record,
+/// every `restart_interval` records a full key is stored to start a new `restart point`.
+/// Records must be added in ascending order of key to make the sharing effective.
 /// If a records with a `zero length` value pay-load, it means a deleting operation by client
-
 pub struct BlockBuilder {
     data: Vec<u8>,
-    offsets: Vec<u16>,
-    amount: usize,
+    restarts: Vec<u16>,
+    /// the key of the previous record
+    last_key: Vec<u8>,
+    /// number of records added since the last restart point
+    counter: usize,
+    restart_interval: usize,
+    /// the block is full once adding a record makes it larger than `block_size`
+    block_size: usize,
+    num_of_elements: usize,
+}
+impl Default for BlockBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl BlockBuilder {
     pub fn new() -> Self {
+        Self::with_restart_interval(DEFAULT_RESTART_INTERVAL)
+    }
+
+    /// Create a builder which starts a restart point every `restart_interval` records, an
+    /// interval of 1 disables the prefix compression
+    pub fn with_restart_interval(restart_interval: usize) -> Self {
+        Self::with_block_size(BLOCK_SIZE, restart_interval)
+    }
+
+    /// Create a builder whose blocks are about `block_size` bytes, the block size is limited
+    /// to 64KB because the restart points are 2 bytes offsets
+    pub fn with_block_size(block_size: usize, restart_interval: usize) -> Self {
         BlockBuilder {
             data: Vec::new(),
-            offsets: Vec::new(),
-            // the Extra field occupy
-            amount: 2,
+            restarts: Vec::new(),
+            last_key: Vec::new(),
+            counter: 0,
+            restart_interval: restart_interval.max(1),
+            block_size: block_size.min(u16::MAX as usize),
+            num_of_elements: 0,
         }
     }
-    /// return the length of bytes sequence after encoding the origin one
+    /// return the upper bound of the length of bytes sequence after encoding the origin one,
+    /// it is reached if the key shares nothing with the previous one
     pub fn evaluate_record_encoded_length(key: &[u8], value: &[u8]) -> usize {
-        SIZE_U16 + key.len() + SIZE_U16 + value.len()
+        3 * MAX_VARINT32_LENGTH + key.len() + value.len()
+    }
+
+    /// return the current encoded size of the block, including the restart section
+    pub fn estimated_size(&self) -> usize {
+        self.data.len() + (self.restarts.len() + 1) * SIZE_U16
     }
 
     /// add function will encode the `key` and `value` into the format described previously
+    /// Failed with `InvalidArgument` if the `key` is too long for the index blocks, or the
+    /// current block has no room for the record. The value is not limited, a record larger
+    /// than `block_size` makes a block of its own.
     pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if key.len() > u16::MAX as usize {
+            return Err(Error::InvalidArgument(format!(
+                "record with key of {} bytes and value of {} bytes is too long",
+                key.len(),
+                value.len()
+            )));
+        }
         // if the amount of bytes of current blocks exceeds the capacity of block limits
         // return error with info, If the first record inserting to current block exceeds
         // the block limits, current block will be extended
-        if Self::evaluate_record_encoded_length(key, value) + self.amount > BLOCK_SIZE {
-            use anyhow::Error;
-            return Err(Error::from(Box::new(io::Error::new(
-                io::ErrorKind::Other,
-                "your message here",
-            ))));
+        if Self::evaluate_record_encoded_length(key, value) + SIZE_U16 + self.estimated_size()
+            > self.block_size
+            && !self.is_empty()
+        {
+            return Err(Error::InvalidArgument("block overflow".to_string()));
         }
 
-        // write the offset of current record
-        self.offsets.push((self.amount - 2) as u16);
+        let shared = if self.counter < self.restart_interval && !self.is_empty() {
+            self.last_key
+                .iter()
+                .zip(key)
+                .take_while(|(a, b)| a == b)
+                .count()
+        } else {
+            // start a new restart point
+            self.restarts.push(self.data.len() as u16);
+            self.counter = 0;
+            0
+        };
+        let unshared = key.len() - shared;
 
-        // encoding key part
-        let key_length = key.len() as u16;
-        self.data.put_u16(key_length);
-        for iter in key {
-            self.data.push(iter.clone());
-        }
+        put_varint32(&mut self.data, shared as u32);
+        put_varint32(&mut self.data, unshared as u32);
+        put_varint32(&mut self.data, value.len() as u32);
+        self.data.extend_from_slice(&key[shared..]);
+        self.data.extend_from_slice(value);
 
-        // encoding value part
-        let value_length = value.len() as u16;
-        self.data.put_u16(value_length);
-        for iter in value {
-            self.data.push(iter.clone());
-        }
-
-        // increasing the amount base on actual encoded length
-        self.amount = self.amount + Self::evaluate_record_encoded_length(key, value);
-        return Ok(());
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key[shared..]);
+        self.counter += 1;
+        self.num_of_elements += 1;
+        Ok(())
     }
     pub fn build(&self) -> Blocks {
-        let mut buf = self.data.clone();
-        for offset in &self.offsets {
-            buf.put_u16(*offset);
-        }
-        buf.put_u16(self.offsets.len() as u16);
-
         Blocks {
             data: self.data.clone(),
-            offsets: self.offsets.clone(),
-            num_of_elements: self.offsets.len(),
+            restarts: self.restarts.clone(),
+            num_of_elements: self.num_of_elements,
         }
     }
 
     pub fn clean_up(&mut self) {
         self.data.clear();
-        self.offsets.clear();
-        self.amount = 2;
+        self.restarts.clear();
+        self.last_key.clear();
+        self.counter = 0;
+        self.num_of_elements = 0;
     }
     pub fn is_empty(&self) -> bool {
-        self.amount == 2
+        self.num_of_elements == 0
     }
 }
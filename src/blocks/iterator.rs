@@ -1,28 +1,85 @@
-use crate::blocks::{Blocks, SIZE_U16};
+use crate::blocks::Blocks;
+use crate::comparator::{bytewise_comparator, Comparator};
 use crate::iterator::Iterator;
-use bytes::Buf;
 use std::cmp::Ordering;
 use std::sync::Arc;
 
 /// RecordIterator yields the records in related blocks if the
 /// iterator it self is valid after invoking next()
-
+///
+/// Because of the prefix compression, the key of the current record is reconstructed into
+/// `key` while moving forward. Moving backward re-scans from the nearest restart point.
+/// `seek_to_key` expects the records sorted by the comparator of the iterator, bytewise
+/// unless it is created by `with_comparator`.
 pub struct BlockRecordIterator {
     block: Arc<Blocks>,
+    comparator: Arc<dyn Comparator>,
     is_valid: bool,
-    current_index: usize,
+    /// offset of the current record in the data section
+    current_offset: usize,
+    /// offset of the record after the current one
+    next_offset: usize,
+    /// index of the restart point where the current record belongs to
+    restart_index: usize,
+    key: Vec<u8>,
+    value_offset: usize,
+    value_length: usize,
 }
 impl BlockRecordIterator {
-    fn key_at_index(&self, index: usize) -> Result<&[u8], String> {
-        if index >= self.block.num_of_elements {
-            return Err(format!("given index out of range of block`:"));
+    pub fn with_comparator(block: Arc<Blocks>, comparator: Arc<dyn Comparator>) -> Self {
+        BlockRecordIterator {
+            block,
+            comparator,
+            is_valid: false,
+            current_offset: 0,
+            next_offset: 0,
+            restart_index: 0,
+            key: Vec::new(),
+            value_offset: 0,
+            value_length: 0,
         }
+    }
 
-        let data_offset = self.block.offsets[index] as usize;
-        let key_length = self.block.data[data_offset..data_offset + SIZE_U16]
-            .as_ref()
-            .get_u16() as usize;
-        Ok(self.block.data[data_offset + SIZE_U16..data_offset + SIZE_U16 + key_length].as_ref())
+    /// Return the full key stored at the `index`th restart point
+    fn key_at_restart(&self, index: usize) -> &[u8] {
+        let offset = self.block.restarts()[index] as usize;
+        let header = Blocks::decode_entry_header(self.block.data(), offset)
+            .expect("entries are verified while building or decoding the block");
+        &self.block.data()[header.key_offset..header.value_offset()]
+    }
+
+    /// Position the iterator to the record at `restart_index`th restart point
+    fn seek_to_restart_point(&mut self, restart_index: usize) {
+        self.key.clear();
+        self.restart_index = restart_index;
+        self.next_offset = self.block.restarts()[restart_index] as usize;
+        self.parse_next_entry();
+    }
+
+    /// Decode the record at `next_offset` and make it current, the iterator turns invalid if
+    /// there is no more record
+    fn parse_next_entry(&mut self) -> bool {
+        if self.next_offset >= self.block.data().len() {
+            self.is_valid = false;
+            return false;
+        }
+        let header = Blocks::decode_entry_header(self.block.data(), self.next_offset)
+            .expect("entries are verified while building or decoding the block");
+        self.current_offset = self.next_offset;
+        self.key.truncate(header.shared);
+        self.key
+            .extend_from_slice(&self.block.data()[header.key_offset..header.value_offset()]);
+        self.value_offset = header.value_offset();
+        self.value_length = header.value_length;
+        self.next_offset = header.end();
+        let restarts = self.block.restarts();
+        while self.restart_index + 1 < restarts.len()
+            && (restarts[self.restart_index + 1] as usize) <= self.current_offset
+        {
+            self.restart_index += 1;
+        }
+        self.is_valid = true;
+        true
     }
 }
 
@@ -30,94 +87,86 @@ impl Iterator for BlockRecordIterator {
     type Item = Arc<Blocks>;
 
     fn new(arg: Self::Item) -> Self {
-        BlockRecordIterator {
-            block: arg,
-            is_valid: false,
-            current_index: 0,
-        }
+        Self::with_comparator(arg, bytewise_comparator())
     }
 
     fn seek_to_first(&mut self) {
-        if self.block.offsets.len() == 0 {
+        if self.block.restarts().is_empty() {
             self.is_valid = false;
-            return ;
+            return;
         }
-        self.current_index = 0;
-        self.is_valid = true;
+        self.seek_to_restart_point(0);
     }
     fn seek_to_last(&mut self) {
-        if self.block.offsets.len() == 0 {
+        if self.block.restarts().is_empty() {
             self.is_valid = false;
-            return ;
+            return;
+        }
+        self.seek_to_restart_point(self.block.restarts().len() - 1);
+        while self.next_offset < self.block.data().len() {
+            self.parse_next_entry();
         }
-        self.current_index = self.block.offsets.len() - 1;
-        self.is_valid = true;
     }
-    fn seek_to_key(&mut self, key: &[u8])  {
-        // TODO(summerxwu): Maybe use binary search is better
-        if self.block.offsets.len() == 0 {
+    /// Position at the first record whose key is not less than `key`, the iterator turns
+    /// invalid if all records are less than `key`
+    fn seek_to_key(&mut self, key: &[u8]) {
+        if self.block.restarts().is_empty() {
             self.is_valid = false;
             return;
         }
-        let mut iter_index = self.current_index;
-        if self.key().cmp(key) == Ordering::Equal {
-            self.is_valid = true;
-            return ;
-        }
-        iter_index = iter_index + 1;
-        while (iter_index % self.block.offsets.len()) != self.current_index {
-            if let Ok(ikey) = self.key_at_index(iter_index) {
-                if ikey.cmp(key) == Ordering::Equal {
-                    self.current_index = iter_index;
-                    self.is_valid = true;
-                    return;
-                }
+        // binary search the last restart point whose key is less than `key`
+        let mut left = 0;
+        let mut right = self.block.restarts().len() - 1;
+        while left < right {
+            let mid = (left + right).div_ceil(2);
+            if self.comparator.compare(self.key_at_restart(mid), key) == Ordering::Less {
+                left = mid;
+            } else {
+                right = mid - 1;
             }
-            iter_index = iter_index + 1;
         }
-        self.is_valid = false;
-        return ;
+        // scan forward to the first record not less than `key`
+        self.seek_to_restart_point(left);
+        while self.is_valid && self.comparator.compare(&self.key, key) == Ordering::Less {
+            self.parse_next_entry();
+        }
     }
     fn is_valid(&self) -> bool {
         self.is_valid
     }
     fn next(&mut self) {
-        if self.current_index + 1 >= self.block.num_of_elements {
-            self.is_valid = false;
-            return ;
+        if !self.is_valid {
+            return;
         }
-        self.current_index = self.current_index + 1;
-        self.is_valid = true;
+        self.parse_next_entry();
     }
 
     fn prev(&mut self) {
-        if self.current_index == 0 {
+        if !self.is_valid {
+            return;
+        }
+        let original = self.current_offset;
+        if original == 0 {
             self.is_valid = false;
             return;
         }
-        self.current_index = self.current_index - 1;
-        self.is_valid = true;
+        // find the restart point before the current record and scan until the record just
+        // before the current one
+        let mut restart_index = self.restart_index;
+        while self.block.restarts()[restart_index] as usize >= original {
+            restart_index -= 1;
+        }
+        self.seek_to_restart_point(restart_index);
+        while self.next_offset < original {
+            self.parse_next_entry();
+        }
     }
 
     fn key(&self) -> &[u8] {
-        let data_offset = self.block.offsets[self.current_index] as usize;
-        let key_length = self.block.data[data_offset..data_offset + SIZE_U16]
-            .as_ref()
-            .get_u16() as usize;
-        self.block.data[data_offset + SIZE_U16..data_offset + SIZE_U16 + key_length].as_ref()
+        self.key.as_slice()
     }
 
     fn value(&self) -> &[u8] {
-        let data_offset = self.block.offsets[self.current_index] as usize;
-        let key_length = self.block.data[data_offset..data_offset + SIZE_U16]
-            .as_ref()
-            .get_u16() as usize;
-        let data_length = self.block.data
-            [data_offset + SIZE_U16 + key_length..data_offset + SIZE_U16 + key_length + SIZE_U16]
-            .as_ref()
-            .get_u16() as usize;
-        self.block.data[data_offset + SIZE_U16 + key_length + SIZE_U16
-            ..data_offset + SIZE_U16 + key_length + SIZE_U16 + data_length]
-            .as_ref()
+        &self.block.data()[self.value_offset..self.value_offset + self.value_length]
     }
 }
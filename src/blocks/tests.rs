@@ -2,6 +2,7 @@ use crate::blocks::block_builder::BlockBuilder;
 use crate::blocks::iterator::BlockRecordIterator;
 use crate::blocks::Blocks;
 use crate::iterator::Iterator;
+use crate::util::error::Error;
 use std::sync::Arc;
 
 fn create_block_with_rec_num(size: u8) -> Arc<Blocks> {
@@ -69,6 +70,111 @@ fn test_iterator_next() {
 #[test]
 fn test_block_boundary() {
     let block = create_block_with_rec_num(10);
-    assert_eq!("key_1".as_bytes(),block.smallest_key());
-    assert_eq!("key_10".as_bytes(),block.largest_key());
+    assert_eq!("key_1".as_bytes(), block.smallest_key());
+    assert_eq!("key_10".as_bytes(), block.largest_key());
+}
+
+#[test]
+fn test_decode_corrupted_block() {
+    let block = create_block_with_rec_num(10);
+    let buf = block.encode();
+    // drop the first entry, the offsets point beyond the remaining data section
+    let ret = Blocks::decode(&buf[8..]);
+    assert!(matches!(ret, Err(Error::Corruption { .. })));
+    let ret = Blocks::decode(&buf[..1]);
+    assert!(matches!(ret, Err(Error::Corruption { .. })));
+}
+
+#[test]
+fn test_oversized_record() {
+    let mut builder = BlockBuilder::new();
+    let value = vec![1u8; 8 * 1024];
+    // the first record of a block extends the block
+    builder
+        .add(b"key_1", value.as_slice())
+        .expect("Testing expect");
+    assert!(builder.add(b"key_2", b"value_2").is_err());
+    // the value is not limited, only the key is
+    let value = vec![1u8; 100 * 1024];
+    let mut builder = BlockBuilder::new();
+    builder
+        .add(b"key_1", value.as_slice())
+        .expect("Testing expect");
+    let block = Blocks::decode(builder.build().encode().as_ref()).expect("Testing expect");
+    let mut iter = BlockRecordIterator::new(Arc::new(block));
+    iter.seek_to_first();
+    assert_eq!(iter.value(), value.as_slice());
+    let key = vec![1u8; u16::MAX as usize + 1];
+    let mut builder = BlockBuilder::new();
+    assert!(matches!(
+        builder.add(key.as_slice(), b"value"),
+        Err(Error::InvalidArgument(_))
+    ));
+}
+
+fn create_prefix_heavy_block(restart_interval: usize) -> (BlockBuilder, Vec<(String, String)>) {
+    let mut builder = BlockBuilder::with_restart_interval(restart_interval);
+    let mut records = Vec::new();
+    for i in 0..50 {
+        let key = format!("tenant/42/user/{:06}/field_{}", i / 5, i % 5);
+        let value = format!("{}", i % 10);
+        if builder.add(key.as_bytes(), value.as_bytes()).is_err() {
+            break;
+        }
+        records.push((key, value));
+    }
+    (builder, records)
+}
+
+#[test]
+fn test_prefix_compression() {
+    let (builder, records) = create_prefix_heavy_block(16);
+    // size of the records if every key is stored in full with 2 bytes length fields
+    let raw_size: usize = records.iter().map(|(k, v)| 4 + k.len() + v.len()).sum();
+    let block = builder.build();
+    assert!(block.encode().len() * 3 < raw_size);
+
+    // decoded block yields all the records in order
+    let block = Arc::new(Blocks::decode(block.encode().as_ref()).expect("Testing expect"));
+    assert_eq!(block.num_of_elements(), records.len());
+    let mut iter = BlockRecordIterator::new(block.clone());
+    iter.seek_to_first();
+    for (key, value) in &records {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key.as_bytes());
+        assert_eq!(iter.value(), value.as_bytes());
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(block.largest_key(), records.last().unwrap().0.as_bytes());
+}
+
+#[test]
+fn test_iterator_seek_to_absent_key() {
+    let (builder, records) = create_prefix_heavy_block(4);
+    let mut iter = BlockRecordIterator::new(Arc::new(builder.build()));
+    // position at the first key greater than the given one
+    iter.seek_to_key(b"tenant/42/user/000003");
+    assert!(iter.is_valid());
+    assert_eq!(iter.key(), records[15].0.as_bytes());
+    iter.seek_to_key(b"a");
+    assert_eq!(iter.key(), records[0].0.as_bytes());
+    iter.seek_to_key(b"z");
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_iterator_prev() {
+    for restart_interval in [1, 3, 16] {
+        let (builder, records) = create_prefix_heavy_block(restart_interval);
+        let mut iter = BlockRecordIterator::new(Arc::new(builder.build()));
+        iter.seek_to_last();
+        for (key, value) in records.iter().rev() {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key.as_bytes());
+            assert_eq!(iter.value(), value.as_bytes());
+            iter.prev();
+        }
+        assert!(!iter.is_valid());
+    }
 }
@@ -0,0 +1,43 @@
+/// What a `CompactionFilter` decides to do with a record
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Keep,
+    /// drop the record, the key reads as deleted afterwards
+    Remove,
+    /// replace the value of the record
+    ChangeValue(Vec<u8>),
+}
+
+/// Describes the compaction a `CompactionFilter` is created for
+#[derive(Clone, Debug)]
+pub struct CompactionFilterContext {
+    /// the level being compacted, the output goes to `level + 1`
+    pub level: usize,
+    /// whether no level deeper than the output level holds any SSTable
+    pub is_bottommost_level: bool,
+}
+
+/// A `CompactionFilter` is called for every user value compaction writes, so that the
+/// application is able to drop or rewrite records, e.g. the expired ones, without issuing
+/// deletes. Deletions and merge operands not yet applied on a value are not passed to the
+/// filter.
+///
+/// A filter is used by a single compaction, it may keep state across the records of the
+/// compaction.
+pub trait CompactionFilter {
+    fn name(&self) -> &str;
+
+    /// Decide what to do with the record of `key` whose value is `value`, `level` is the
+    /// level being compacted
+    fn filter(&mut self, level: usize, key: &[u8], value: &[u8]) -> Decision;
+}
+
+/// `CompactionFilterFactory` creates a `CompactionFilter` for every compaction
+pub trait CompactionFilterFactory: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn create_compaction_filter(
+        &self,
+        context: &CompactionFilterContext,
+    ) -> Box<dyn CompactionFilter>;
+}
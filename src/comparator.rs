@@ -0,0 +1,60 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+/// the name of `BytewiseComparator`, the column families without a comparator recorded in
+/// the MANIFEST are taken as created with it
+pub const BYTEWISE_COMPARATOR_NAME: &str = "summer_kv.BytewiseComparator";
+
+/// A `Comparator` decides the order of the keys, every sorted structure of a column family
+/// follows it: the memtable, the data blocks and the key ranges of the SSTables, the levels
+/// and the iterators. It is set by `Options::comparator`.
+///
+/// The name of the comparator is recorded in the MANIFEST when the column family is created,
+/// opening the column family with a comparator of another name fails, so the name must
+/// change whenever the order changes.
+///
+/// Two keys compare `Equal` only if they are the same bytes.
+pub trait Comparator: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+impl fmt::Debug for dyn Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// `BytewiseComparator` orders the keys lexicographically by the unsigned bytes, the default
+/// order of the database
+pub struct BytewiseComparator {}
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        BYTEWISE_COMPARATOR_NAME
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// `ReverseBytewiseComparator` orders the keys the other way around of `BytewiseComparator`
+pub struct ReverseBytewiseComparator {}
+
+impl Comparator for ReverseBytewiseComparator {
+    fn name(&self) -> &str {
+        "summer_kv.ReverseBytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+/// Return the default comparator, see `Options::comparator`
+pub fn bytewise_comparator() -> Arc<dyn Comparator> {
+    Arc::new(BytewiseComparator {})
+}
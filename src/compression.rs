@@ -0,0 +1,208 @@
+use crate::util::coding::{get_varint32, put_varint32};
+use crate::util::error::{Error, Result};
+use std::sync::Arc;
+
+/// type byte of blocks stored as is
+pub const NO_COMPRESSION: u8 = 0;
+/// type byte of blocks compressed by `LzCompressor`
+pub const LZ_COMPRESSION: u8 = 1;
+
+/// A `Compressor` turns the encoded content of a block into a smaller one.
+/// Every compressor is identified by the type byte returned by `id()`, the byte is stored in
+/// the trailer of every block written to a SSTable, so that the reader is able to pick the
+/// compressor to decompress the block. Built-in compressors occupy the ids below 128, user
+/// defined compressors should use the others.
+pub trait Compressor: Send + Sync {
+    fn id(&self) -> u8;
+    fn compress(&self, input: &[u8]) -> Vec<u8>;
+    /// Failed with `Corruption` if `input` is not produced by `compress()`, the offset of the
+    /// `Corruption` is relative to the beginning of `input`
+    fn decompress(&self, input: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Return the built-in compressor identified by `id`
+pub fn builtin_compressor(id: u8) -> Option<Arc<dyn Compressor>> {
+    match id {
+        NO_COMPRESSION => Some(Arc::new(NoCompressor {})),
+        LZ_COMPRESSION => Some(Arc::new(LzCompressor {})),
+        _ => None,
+    }
+}
+
+/// Compressed output is only stored if it saves at least 1/8 of the input, otherwise the
+/// input is stored as is, the cost of decompression is not worth it
+pub fn is_worth_compressing(input_length: usize, compressed_length: usize) -> bool {
+    compressed_length < input_length - input_length / 8
+}
+
+/// `NoCompressor` keeps the blocks untouched
+pub struct NoCompressor {}
+
+impl Compressor for NoCompressor {
+    fn id(&self) -> u8 {
+        NO_COMPRESSION
+    }
+    fn compress(&self, input: &[u8]) -> Vec<u8> {
+        input.to_vec()
+    }
+    fn decompress(&self, input: &[u8]) -> Result<Vec<u8>> {
+        Ok(input.to_vec())
+    }
+}
+
+/// `LzCompressor` is a fast LZ77 style compressor which replaces the repeated byte sequences
+/// with references to their previous occurrence.
+/// The compressed format is as below:
+/// ``` text
+/// +---------------------------------------------------------------------+
+/// | Uncompressed Length (varint) | Sequence #1 | Sequence #2 | ... | #N |
+/// +---------------------------------------------------------------------+
+/// ```
+/// and every `Sequence` is:
+/// ``` text
+/// +------------------------------------------------------------------------------------+
+/// | Literal Length (varint) | Literals | Match Offset (2 bytes) | Match Length (varint)|
+/// +------------------------------------------------------------------------------------+
+/// ```
+/// The decoder copies the literals and then copies `Match Length + MIN_MATCH` bytes starting
+/// `Match Offset` bytes before the current output position. The last sequence only carries
+/// literals.
+pub struct LzCompressor {}
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+impl LzCompressor {
+    fn hash(data: &[u8]) -> usize {
+        let value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+}
+
+impl Compressor for LzCompressor {
+    fn id(&self) -> u8 {
+        LZ_COMPRESSION
+    }
+
+    fn compress(&self, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(input.len() / 2 + 8);
+        put_varint32(&mut output, input.len() as u32);
+        // position + 1 of the latest occurrence of every hashed 4 bytes sequence
+        let mut table = vec![0usize; 1 << HASH_BITS];
+        let mut literal_start = 0;
+        let mut position = 0;
+        while position + MIN_MATCH <= input.len() {
+            let hash = Self::hash(&input[position..]);
+            let candidate = table[hash];
+            table[hash] = position + 1;
+            if candidate == 0
+                || position - (candidate - 1) > MAX_OFFSET
+                || input[candidate - 1..candidate - 1 + MIN_MATCH]
+                    != input[position..position + MIN_MATCH]
+            {
+                position += 1;
+                continue;
+            }
+            let matched = candidate - 1;
+            let mut length = MIN_MATCH;
+            while position + length < input.len()
+                && input[matched + length] == input[position + length]
+            {
+                length += 1;
+            }
+            put_varint32(&mut output, (position - literal_start) as u32);
+            output.extend_from_slice(&input[literal_start..position]);
+            output.extend_from_slice(&((position - matched) as u16).to_le_bytes());
+            put_varint32(&mut output, (length - MIN_MATCH) as u32);
+            position += length;
+            literal_start = position;
+        }
+        put_varint32(&mut output, (input.len() - literal_start) as u32);
+        output.extend_from_slice(&input[literal_start..]);
+        output
+    }
+
+    fn decompress(&self, input: &[u8]) -> Result<Vec<u8>> {
+        let (length, mut position) = get_varint32(input).ok_or_else(|| Error::corruption("", 0))?;
+        let length = length as usize;
+        let mut output: Vec<u8> = Vec::with_capacity(length);
+        loop {
+            let (literal_length, consumed) = get_varint32(&input[position..])
+                .ok_or_else(|| Error::corruption("", position as u64))?;
+            position += consumed;
+            let literals = input
+                .get(position..position + literal_length as usize)
+                .ok_or_else(|| Error::corruption("", position as u64))?;
+            output.extend_from_slice(literals);
+            position += literal_length as usize;
+            if position == input.len() {
+                break;
+            }
+
+            let offset = input
+                .get(position..position + 2)
+                .map(|x| u16::from_le_bytes([x[0], x[1]]) as usize)
+                .ok_or_else(|| Error::corruption("", position as u64))?;
+            if offset == 0 || offset > output.len() {
+                return Err(Error::corruption("", position as u64));
+            }
+            position += 2;
+            let (match_length, consumed) = get_varint32(&input[position..])
+                .ok_or_else(|| Error::corruption("", position as u64))?;
+            position += consumed;
+            let match_length = match_length as usize + MIN_MATCH;
+            if output.len() + match_length > length {
+                return Err(Error::corruption("", position as u64));
+            }
+            // the match may overlap with the bytes it produces, copy byte by byte
+            let start = output.len() - offset;
+            for i in 0..match_length {
+                output.push(output[start + i]);
+            }
+        }
+        if output.len() != length {
+            return Err(Error::corruption("", position as u64));
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compression::{builtin_compressor, Compressor, LzCompressor, LZ_COMPRESSION};
+    use crate::util::error::Error;
+
+    #[test]
+    fn test_lz_round_trip() {
+        let compressor = LzCompressor {};
+        let mut repetitive = Vec::new();
+        for i in 0..200 {
+            repetitive.extend_from_slice(format!("tenant/42/user/{:06}/field", i).as_bytes());
+        }
+        let inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"abc".to_vec(),
+            vec![7u8; 10000],
+            (0..=255u8).collect(),
+            repetitive.clone(),
+        ];
+        for input in inputs {
+            let compressed = compressor.compress(input.as_slice());
+            let decompressed = compressor.decompress(compressed.as_slice()).unwrap();
+            assert_eq!(decompressed, input);
+        }
+        let compressed = compressor.compress(repetitive.as_slice());
+        assert!(compressed.len() * 4 < repetitive.len());
+    }
+
+    #[test]
+    fn test_lz_corrupted_input() {
+        let compressor = builtin_compressor(LZ_COMPRESSION).unwrap();
+        let compressed = compressor.compress(vec![7u8; 1000].as_slice());
+        let ret = compressor.decompress(&compressed[..compressed.len() - 1]);
+        assert!(matches!(ret, Err(Error::Corruption { .. })));
+        let ret = compressor.decompress(&[]);
+        assert!(matches!(ret, Err(Error::Corruption { .. })));
+    }
+}
@@ -1,40 +1,1265 @@
-use anyhow::Result;
+use crate::db::blob::{BlobFileSet, BlobWriter};
+use crate::db::column_family::{
+    ColumnFamilyData, ColumnFamilyDescriptor, ColumnFamilyHandle, DEFAULT_COLUMN_FAMILY_ID,
+    DEFAULT_COLUMN_FAMILY_NAME,
+};
+use crate::db::compaction::Compaction;
+use crate::db::format::{
+    decode_value, encode_expiring_payload, RangeTombstoneList, SequenceNumber, ValueType,
+};
+use crate::db::iterator::{DBIterator, DBIteratorParts, MergingIterator, SourceIterator};
+use crate::db::merge::MergeContext;
+use crate::db::optimistic_transaction::OptimisticTransaction;
+use crate::db::version::{FileMetaData, Version, VersionEdit, VersionSet, NUM_LEVELS};
+use crate::db::write_batch::WriteBatch;
+use crate::iterator::Iterator;
+use crate::memtable::logger::{LogRecordsBuilder, LoggerRecord, MemTableLogger, OperationType};
+use crate::memtable::MemTable;
+use crate::merge_operator::MergeOperator;
+use crate::options::{Options, ReadOptions};
+use crate::sstable::iterator::SSTableRecordIterator;
+use crate::sstable::sstable_builder::SSTableBuilder;
+use crate::sstable::SSTable;
+use crate::util::env::{
+    blobfile_path, link_or_copy, lockfile_path, logfile_path, parse_file_name, sstfile_path,
+    FileLock,
+};
+use crate::util::error::{Error, Result};
 use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockWriteGuard};
+use std::time::Duration;
 
-pub trait DB {
-    fn open(&self,path: &str) ->Result<()>;
-    fn close(&self);
-    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()>;
-    fn get(&self, key: &[u8]) -> Option<Bytes>;
-    fn delete(&self, key: &[u8]) -> Result<()>;
+pub mod backup_engine;
+pub mod blob;
+pub mod column_family;
+pub mod compaction;
+pub mod format;
+pub mod iterator;
+pub mod merge;
+pub mod optimistic_transaction;
+pub mod transaction_db;
+pub mod version;
+pub mod write_batch;
+pub mod write_batch_with_index;
+
+pub trait DB: Send + Sync {
+    fn close(&self) -> Result<()>;
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_cf(&ColumnFamilyHandle::default(), key, value)
+    }
+    fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_cf(&ColumnFamilyHandle::default(), key)
+    }
+    /// Return the values of `keys` in the same order, all of them are read from the database
+    /// at one moment. The keys are looked up together, every SSTable is searched once for
+    /// all the keys it may hold, and every data block once for all the keys falling in it.
+    fn multi_get(&self, keys: &[&[u8]]) -> Vec<Result<Option<Bytes>>> {
+        self.multi_get_cf(&ColumnFamilyHandle::default(), keys)
+    }
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.delete_cf(&ColumnFamilyHandle::default(), key)
+    }
+    /// Put a record of `key` which expires after `ttl` as told by the `clock` of `Options`,
+    /// the expired record reads as deleted and is removed by compaction
+    fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.put_with_ttl_cf(&ColumnFamilyHandle::default(), key, value, ttl)
+    }
+    /// Merge `operand` into the value of `key` by the `merge_operator` of `Options`, the
+    /// operands are combined when the key is read. Failed with `InvalidArgument` if there is
+    /// no merge operator configured.
+    fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.merge_cf(&ColumnFamilyHandle::default(), key, operand)
+    }
+    /// Delete every key in `[start, end)`. Nothing is deleted if `start` equals `end`,
+    /// failed with `InvalidArgument` if `start` is greater than `end`
+    fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.delete_range_cf(&ColumnFamilyHandle::default(), start, end)
+    }
+    /// Create an iterator over the records of the database at this moment, the iterator is
+    /// not positioned yet
+    fn iter(&self) -> Result<DBIterator> {
+        self.iter_cf(&ColumnFamilyHandle::default())
+    }
+    /// See `iter`, the iterator follows `read_options`
+    fn iter_with_options(&self, read_options: ReadOptions) -> Result<DBIterator> {
+        self.iter_cf_with_options(&ColumnFamilyHandle::default(), read_options)
+    }
+    /// Load the SSTables at `paths` written by `SstFileWriter`, see `ingest_external_file_cf`
+    fn ingest_external_file(&self, paths: &[&str]) -> Result<()> {
+        self.ingest_external_file_cf(&ColumnFamilyHandle::default(), paths)
+    }
+
+    // The operations on a column family fail with `InvalidArgument` if the column family
+    // does not exist, e.g. it is dropped.
+    fn put_cf(&self, cf: &ColumnFamilyHandle, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put_cf(cf, key, value);
+        self.write(batch)
+    }
+    fn get_cf(&self, cf: &ColumnFamilyHandle, key: &[u8]) -> Result<Option<Bytes>>;
+    fn multi_get_cf(&self, cf: &ColumnFamilyHandle, keys: &[&[u8]]) -> Vec<Result<Option<Bytes>>>;
+    fn delete_cf(&self, cf: &ColumnFamilyHandle, key: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete_cf(cf, key);
+        self.write(batch)
+    }
+    fn put_with_ttl_cf(
+        &self,
+        cf: &ColumnFamilyHandle,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put_with_ttl_cf(cf, key, value, ttl);
+        self.write(batch)
+    }
+    fn merge_cf(&self, cf: &ColumnFamilyHandle, key: &[u8], operand: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.merge_cf(cf, key, operand);
+        self.write(batch)
+    }
+    fn delete_range_cf(&self, cf: &ColumnFamilyHandle, start: &[u8], end: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range_cf(cf, start, end);
+        self.write(batch)
+    }
+    fn iter_cf(&self, cf: &ColumnFamilyHandle) -> Result<DBIterator> {
+        self.iter_cf_with_options(cf, ReadOptions::default())
+    }
+    fn iter_cf_with_options(
+        &self,
+        cf: &ColumnFamilyHandle,
+        read_options: ReadOptions,
+    ) -> Result<DBIterator>;
+    /// Load the SSTables at `paths` written by `SstFileWriter` into `cf` at once, without
+    /// rewriting them. The records of the SSTables are given one new sequence number, so
+    /// they are newer than every record in the database. Every SSTable is linked into the
+    /// database, or copied if it can not be linked, and placed at the deepest level where no
+    /// older record overlaps it.
+    ///
+    /// Failed with `InvalidArgument` if any SSTable is empty or its keys are out of order,
+    /// or if the SSTables overlap with each other
+    fn ingest_external_file_cf(&self, cf: &ColumnFamilyHandle, paths: &[&str]) -> Result<()>;
+    /// Create a checkpoint of the database in `dir`, which opens as an independent database
+    /// holding the records written so far. The memtables are flushed, then the SSTables and
+    /// the blob files are hard linked into `dir` along with a new MANIFEST.
+    /// Failed with `InvalidArgument` if `dir` exists
+    fn create_checkpoint(&self, dir: &str) -> Result<()>;
+    /// Follow the writes of the primary since the database is opened or caught up last
+    /// time, by replaying the new edits of the MANIFEST and the records appended to the logs.
+    /// Failed with `NotSupported` unless the database is opened as a secondary, see
+    /// `DBImpl::open_as_secondary`
+    fn try_catch_up_with_primary(&self) -> Result<()>;
+
+    /// Apply the updates of `batch` atomically, see `WriteBatch`
+    fn write(&self, batch: WriteBatch) -> Result<()>;
+
+    /// Create the column family `name` with `options` and record it in the MANIFEST.
+    /// Failed with `InvalidArgument` if the name is taken
+    fn create_column_family(&self, name: &str, options: Options) -> Result<ColumnFamilyHandle>;
+    /// Drop the column family `cf`, its records are not readable any more. Failed with
+    /// `InvalidArgument` for the default column family.
+    fn drop_column_family(&self, cf: &ColumnFamilyHandle) -> Result<()>;
+    /// Return the handle of the column family `name`, `None` if it does not exist
+    fn column_family(&self, name: &str) -> Option<ColumnFamilyHandle>;
+
+    /// Begin a transaction which checks for conflicts at commit, see `OptimisticTransaction`
+    fn begin_optimistic_transaction(&self) -> OptimisticTransaction<'_>;
+}
+
+/// `DBImpl` is a LSM tree made of a memtable and the SSTables of several levels for every
+/// column family.
+///
+/// Writes are logged to the log file shared by all the column families, then go to the
+/// memtable of their column family, which is flushed to a level 0 SSTable once it reaches
+/// `write_buffer_size`. Only the logging holds the write lock, the writes are inserted into
+/// the memtables by their writers at the same time, and become visible to the reads in the
+/// order they are logged, see `VisibleSequence`. The log file is switched on every flush,
+/// then the levels of the column family are compacted if necessary. The SSTables of every
+/// level are recorded in the MANIFEST (see `VersionSet`), on open the records of the logs
+/// not flushed yet are replayed and flushed.
+///
+/// The database may be opened by other processes along with the one writing it, see
+/// `open_read_only` and `open_as_secondary`.
+pub struct DBImpl {
+    dir: String,
+    mode: OpenMode,
+    state: RwLock<DBState>,
+    visible: VisibleSequence,
+}
+
+/// How a database is opened
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OpenMode {
+    /// the only process writing the database
+    Primary,
+    /// reads the database as it is on open, nothing is written
+    ReadOnly,
+    /// reads the database along with the primary, and follows it on
+    /// `try_catch_up_with_primary`. Nothing is written
+    Secondary,
+}
+
+struct DBState {
+    /// the log being written, `None` unless the database is opened as the primary
+    log: Option<MemTableLogger>,
+    versions: VersionSet,
+    closed: bool,
+    /// the number of records replayed from every log not flushed yet, see
+    /// `try_catch_up_with_primary`
+    replayed_logs: BTreeMap<u64, usize>,
+    /// the lock of the LOCK file held by the primary until closed, so that no other process
+    /// opens the database as the primary meanwhile
+    lock: Option<Box<dyn FileLock>>,
+}
+
+/// `VisibleSequence` is the sequence number of the latest write visible to the reads. A
+/// write is inserted into the memtables after it is logged and the write lock is released,
+/// then it becomes visible once all the writes logged before it are, so that a read never
+/// sees a part of a batch, or a write without the ones logged before it.
+///
+/// `versions.last_sequence` of the state is the sequence number of the latest write logged,
+/// the two are equal once no write is in progress.
+struct VisibleSequence {
+    sequence: Mutex<SequenceNumber>,
+    changed: Condvar,
+}
+
+impl VisibleSequence {
+    fn new(sequence: SequenceNumber) -> Self {
+        VisibleSequence {
+            sequence: Mutex::new(sequence),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn get(&self) -> SequenceNumber {
+        *self.sequence.lock().unwrap()
+    }
+
+    /// Make the writes numbered from `first` to `last` visible once the writes before them
+    /// are
+    fn publish(&self, first: SequenceNumber, last: SequenceNumber) {
+        let mut sequence = self.sequence.lock().unwrap();
+        while *sequence + 1 < first {
+            sequence = self.changed.wait(sequence).unwrap();
+        }
+        *sequence = last;
+        self.changed.notify_all();
+    }
+
+    /// Make the writes up to `sequence` visible, for the ones applied with no write in
+    /// progress
+    fn set(&self, sequence: SequenceNumber) {
+        *self.sequence.lock().unwrap() = sequence;
+        self.changed.notify_all();
+    }
+
+    /// Wait until the writes up to `sequence` are visible
+    fn wait_for(&self, sequence: SequenceNumber) {
+        let mut visible = self.sequence.lock().unwrap();
+        while *visible < sequence {
+            visible = self.changed.wait(visible).unwrap();
+        }
+    }
+}
+
+/// The lookup of one key of `DB::multi_get`
+struct KeyLookup {
+    context: MergeContext,
+    /// the greatest sequence number of the range tombstones covering the key in the sources
+    /// searched so far
+    covering_seq: SequenceNumber,
+    /// the first error of the lookup, the key is not searched any further then
+    status: Result<()>,
 }
 
-pub struct DBImpl {}
+impl KeyLookup {
+    fn new(now: u64, blob_files: Arc<BlobFileSet>) -> Self {
+        KeyLookup {
+            context: MergeContext::new(now, blob_files),
+            covering_seq: 0,
+            status: Ok(()),
+        }
+    }
+
+    /// Whether the older sources are still needed to resolve the value
+    fn is_pending(&self) -> bool {
+        self.status.is_ok() && !self.context.is_resolved()
+    }
+
+    fn add(&mut self, record: &[u8]) {
+        if self.status.is_ok() {
+            self.status = self.context.add(record, self.covering_seq);
+        }
+    }
+
+    fn value(
+        self,
+        key: &[u8],
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<Option<Bytes>> {
+        self.status?;
+        self.context.value(key, merge_operator)
+    }
+}
 
 impl DBImpl {
-    pub fn new() ->Self{
-        todo!()
+    /// Open the database in directory `dir`, the database is created if it does not exist.
+    /// Failed with `InvalidArgument` if the database has any column family other than the
+    /// default one, see `open_with_column_families`.
+    pub fn open(dir: &str, options: Options) -> Result<Self> {
+        Self::open_with_column_families(dir, options, Vec::new())
+    }
+
+    /// Open the database in directory `dir` with the default column family and
+    /// `column_families`, the database is created if it does not exist. Every column family
+    /// of the database must be opened.
+    ///
+    /// The LOCK file of the directory is locked until the database is closed or dropped.
+    /// Failed with `Busy` if the database is opened by another process, or by this process
+    /// without being closed
+    pub fn open_with_column_families(
+        dir: &str,
+        options: Options,
+        column_families: Vec<ColumnFamilyDescriptor>,
+    ) -> Result<Self> {
+        Self::open_with_mode(dir, options, column_families, OpenMode::Primary)
+    }
+
+    /// Open the database in directory `dir` read only, which holds the records written
+    /// so far. Nothing is written to the directory, the writes fail with `NotSupported`.
+    /// Failed with `NotFound` if there is no database in `dir`
+    pub fn open_read_only(dir: &str, options: Options) -> Result<Self> {
+        Self::open_read_only_with_column_families(dir, options, Vec::new())
+    }
+
+    /// See `open_read_only` and `open_with_column_families`
+    pub fn open_read_only_with_column_families(
+        dir: &str,
+        options: Options,
+        column_families: Vec<ColumnFamilyDescriptor>,
+    ) -> Result<Self> {
+        Self::open_with_mode(dir, options, column_families, OpenMode::ReadOnly)
+    }
+
+    /// Open the database in directory `primary_dir` which is being written by another
+    /// process, the primary. The secondary reads the records written so far like
+    /// `open_read_only`, then follows the writes of the primary on
+    /// `try_catch_up_with_primary`. The secondary keeps nothing but the memtables rebuilt
+    /// from the logs, so it writes no file, the directory of the primary is never written.
+    /// Failed with `NotFound` if there is no database in `primary_dir`
+    pub fn open_as_secondary(primary_dir: &str, options: Options) -> Result<Self> {
+        Self::open_as_secondary_with_column_families(primary_dir, options, Vec::new())
+    }
+
+    /// See `open_as_secondary` and `open_with_column_families`
+    pub fn open_as_secondary_with_column_families(
+        primary_dir: &str,
+        options: Options,
+        column_families: Vec<ColumnFamilyDescriptor>,
+    ) -> Result<Self> {
+        Self::open_with_mode(primary_dir, options, column_families, OpenMode::Secondary)
+    }
+
+    fn open_with_mode(
+        dir: &str,
+        options: Options,
+        column_families: Vec<ColumnFamilyDescriptor>,
+        mode: OpenMode,
+    ) -> Result<Self> {
+        if dir.is_empty() {
+            return Err(Error::InvalidArgument(
+                "the directory of the database is empty".to_string(),
+            ));
+        }
+        let env = options.env.clone();
+        let mut family_options = HashMap::new();
+        family_options.insert(DEFAULT_COLUMN_FAMILY_NAME.to_string(), Arc::new(options));
+        for descriptor in column_families {
+            if family_options.contains_key(descriptor.name.as_str()) {
+                return Err(Error::InvalidArgument(format!(
+                    "column family {} is given more than once",
+                    descriptor.name
+                )));
+            }
+            family_options.insert(descriptor.name, Arc::new(descriptor.options));
+        }
+        let mut lock = None;
+        let mut versions = if mode == OpenMode::Primary {
+            env.create_dir_all(dir)?;
+            lock = Some(env.lock_file(lockfile_path(dir).as_str())?);
+            VersionSet::recover(dir, &family_options)?
+        } else {
+            VersionSet::recover_read_only(dir, &family_options)?
+        };
+        let mut replayed_logs = BTreeMap::new();
+        Self::replay_logs(dir, &mut versions, &mut replayed_logs, &HashSet::new())?;
+        let mut state = DBState {
+            log: None,
+            versions,
+            closed: false,
+            replayed_logs,
+            lock,
+        };
+        if mode == OpenMode::Primary {
+            // every column family moves on to the new log, even if nothing is flushed
+            Self::switch_log(dir, &mut state)?;
+            let ids: Vec<u32> = state.versions.column_families().map(|x| x.id()).collect();
+            for id in ids {
+                Self::flush_column_family(&mut state, id)?;
+            }
+            state.replayed_logs.clear();
+        }
+        let visible = VisibleSequence::new(state.versions.last_sequence);
+        Ok(DBImpl {
+            dir: dir.to_string(),
+            mode,
+            state: RwLock::new(state),
+            visible,
+        })
     }
 
+    /// Replay the records of the logs in `dir` which are not flushed yet into the
+    /// memtables, a record is skipped if its column family is dropped or already flushed it.
+    /// `replayed` holds the number of records replayed from every log before, which are
+    /// only replayed again into the column families of `reset`.
+    fn replay_logs(
+        dir: &str,
+        versions: &mut VersionSet,
+        replayed: &mut BTreeMap<u64, usize>,
+        reset: &HashSet<u32>,
+    ) -> Result<()> {
+        let min_log_number = versions
+            .column_families()
+            .map(|x| x.log_number)
+            .min()
+            .unwrap_or(0);
+        let mut logs: Vec<u64> = versions
+            .env()
+            .get_children(dir)?
+            .into_iter()
+            .filter_map(|name| match parse_file_name(name.as_str())? {
+                (number, "log") if number >= min_log_number => Some(number),
+                _ => None,
+            })
+            .collect();
+        logs.sort();
+        for log in logs {
+            let path = logfile_path(dir, log as usize);
+            let records = match MemTableLogger::read_records(versions.env().as_ref(), path.as_str())
+            {
+                Ok(records) => records,
+                // deleted by the primary once flushed, see `try_catch_up_with_primary`
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let start = replayed.get(&log).copied().unwrap_or(0);
+            for (i, record) in records.iter().enumerate() {
+                if i < start && !reset.contains(&record.column_family()) {
+                    continue;
+                }
+                versions.last_sequence = versions.last_sequence.max(record.seq());
+                if let Some(cfd) = versions.column_family_mut(record.column_family()) {
+                    if log >= cfd.log_number {
+                        cfd.mem.add(record);
+                    }
+                }
+            }
+            replayed.insert(log, records.len());
+        }
+        replayed.retain(|log, _| *log >= min_log_number);
+        Ok(())
+    }
+
+    /// Start a new log, the records written from now on are logged to it
+    fn switch_log(dir: &str, state: &mut DBState) -> Result<()> {
+        let number = state.versions.new_file_number();
+        let log = MemTableLogger::new(state.versions.env().as_ref(), dir, number)?;
+        state.log = Some(log);
+        Ok(())
+    }
+
+    /// Lock the state to update the database once the writes in progress are visible, so
+    /// that the memtables are not inserted into meanwhile.
+    /// Failed with `ShutdownInProgress` if the database is closed, or `NotSupported` unless
+    /// the database is opened as the primary
+    fn lock_for_write(&self) -> Result<RwLockWriteGuard<'_, DBState>> {
+        let state = self.lock_for_logging()?;
+        self.visible.wait_for(state.versions.last_sequence);
+        Ok(state)
+    }
+
+    /// Lock the state to log a write, the writes logged before may still be in progress.
+    /// Failed like `lock_for_write`
+    fn lock_for_logging(&self) -> Result<RwLockWriteGuard<'_, DBState>> {
+        let state = self.state.write().unwrap();
+        if state.closed {
+            return Err(Error::ShutdownInProgress);
+        }
+        if self.mode != OpenMode::Primary {
+            return Err(Error::NotSupported(format!(
+                "the database is opened as {:?}",
+                self.mode
+            )));
+        }
+        Ok(state)
+    }
+
+    /// Return the names of the column families of the database in `dir` of `options.env`.
+    /// Failed with `NotFound` if there is no database in `dir`
+    pub fn list_column_families(dir: &str, options: &Options) -> Result<Vec<String>> {
+        VersionSet::list_column_families(dir, options.env.clone())
+    }
+
+    /// Return the value of `key` along with the sequence number of its latest update, see
+    /// `OptimisticTransaction`
+    pub(crate) fn get_with_sequence(
+        &self,
+        cf: &ColumnFamilyHandle,
+        key: &[u8],
+    ) -> Result<(Option<Bytes>, SequenceNumber)> {
+        let state = self.state.read().unwrap();
+        if state.closed {
+            return Err(Error::ShutdownInProgress);
+        }
+        let cfd = Self::column_family_data(&state, cf)?;
+        let visible = self.visible.get();
+        Ok((
+            Self::get_from(cfd, key, visible)?,
+            Self::latest_sequence(cfd, key, visible)?,
+        ))
+    }
+
+    /// Return the merge operator of the column family `cf`
+    pub(crate) fn merge_operator(
+        &self,
+        cf: &ColumnFamilyHandle,
+    ) -> Result<Option<Arc<dyn MergeOperator>>> {
+        let state = self.state.read().unwrap();
+        let cfd = Self::column_family_data(&state, cf)?;
+        Ok(cfd.options().merge_operator.clone())
+    }
+
+    /// Return the sequence number of the latest write visible, the snapshot of the database
+    /// at this moment, see `OptimisticTransaction`
+    pub(crate) fn last_sequence(&self) -> SequenceNumber {
+        self.visible.get()
+    }
+
+    /// Apply the updates of `batch` atomically if no key in `read_set`, given along with the
+    /// id of its column family, is updated after the sequence number `snapshot`.
+    /// Failed with `Busy` if any of them is updated, nothing is written then
+    pub(crate) fn write_unless_changed(
+        &self,
+        batch: WriteBatch,
+        read_set: &[(u32, Bytes)],
+        snapshot: SequenceNumber,
+    ) -> Result<()> {
+        let state = self.lock_for_write()?;
+        for (cf, key) in read_set {
+            let cfd = state.versions.column_family(*cf).ok_or_else(|| {
+                Error::Busy(format!("column family {} is dropped since it is read", cf))
+            })?;
+            if Self::latest_sequence(cfd, key, SequenceNumber::MAX)? > snapshot {
+                return Err(Error::Busy(format!(
+                    "key {:?} is updated since the transaction began",
+                    key
+                )));
+            }
+        }
+        self.write_to(state, batch)
+    }
+
+    /// Write the memtable of the column family `id` to a level 0 SSTable, then record that
+    /// the column family has no record in the logs before the current one. The log must be
+    /// switched before, so that the current log holds no record of the memtable.
+    fn flush_column_family(state: &mut DBState, id: u32) -> Result<()> {
+        let mut edit = VersionEdit::new();
+        edit.column_family = id;
+        edit.log_number = Some(state.log.as_ref().expect("only the primary flushes").seq());
+        // a new memtable takes the writes before the flushed one is frozen, the flushed one
+        // is put back if the flush fails so that its records are still read
+        let cfd = state.versions.column_family_mut(id).unwrap();
+        let new_mem = Arc::new(MemTable::with_options(cfd.options()));
+        let mem = std::mem::replace(&mut cfd.mem, new_mem);
+        let result =
+            Self::write_level0_table(&mut state.versions, id, &mem, &mut edit).and_then(|table| {
+                let tables = table.into_iter().collect();
+                state.versions.log_and_apply(edit, tables)
+            });
+        if let Err(e) = result {
+            state.versions.column_family_mut(id).unwrap().mem = mem;
+            return Err(e);
+        }
+        Self::maybe_compact(state, id)
+    }
+
+    /// Build a SSTable of `mem`, the memtable of the column family `id` taken out of it, and
+    /// add it to `edit` along with the blob files of its large values, `None` if the
+    /// memtable is empty
+    fn write_level0_table(
+        versions: &mut VersionSet,
+        id: u32,
+        mem: &MemTable,
+        edit: &mut VersionEdit,
+    ) -> Result<Option<Arc<SSTable>>> {
+        if mem.is_empty() {
+            return Ok(None);
+        }
+        let table_number = versions.new_file_number();
+        mem.mark_read_only();
+        let cfd = versions.column_family(id).unwrap();
+        let options = cfd.options().clone();
+        let blob_files = cfd.blob_files().clone();
+        let range_tombstones = mem.range_tombstones().clone();
+        let mut iter = mem.iter();
+        let mut builder = SSTableBuilder::new(options.clone(), 0);
+        let mut blob_writer = BlobWriter::new(options.clone(), blob_files.clone(), HashSet::new());
+        let now = options.clock.now_millis();
+        iter.seek_to_first();
+        while iter.is_valid() {
+            // fold the merge operands and drop the expired records like compaction does
+            let covering_seq = range_tombstones.max_covering_seq(iter.key());
+            let mut context = MergeContext::new(now, blob_files.clone());
+            context.add(iter.value(), covering_seq)?;
+            let record =
+                context.compacted_record(iter.key(), options.merge_operator.as_deref(), false)?;
+            if let Some(record) = record {
+                let record = blob_writer.process(versions, iter.key(), record)?;
+                builder.add(iter.key(), record.as_ref())?;
+            }
+            iter.next();
+        }
+        for tombstone in range_tombstones.iter() {
+            builder.add_range_tombstone(tombstone.clone());
+        }
+        let table = builder.build(versions.dir(), table_number as usize)?;
+        edit.add_file(
+            0,
+            FileMetaData::from_sstable(&table, blob_writer.take_references())?,
+        );
+        for file in blob_writer.finish()? {
+            edit.add_blob_file(file);
+        }
+        Ok(Some(Arc::new(table)))
+    }
+
+    /// Check that the keys of the external SSTable `table` at `path` are in order and none of
+    /// its records refers to a blob file.
+    /// Failed with `InvalidArgument` if it is not, or if the SSTable is empty
+    fn verify_external_file(table: &Arc<SSTable>, path: &str) -> Result<()> {
+        let comparator = table.comparator().clone();
+        let read_options = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        let mut iter =
+            SSTableRecordIterator::with_read_options(table.clone(), Arc::new(read_options));
+        let mut last_key: Option<Vec<u8>> = None;
+        iter.seek_to_first();
+        while iter.is_valid() {
+            if let Some(last_key) = last_key.as_deref() {
+                if comparator.compare(last_key, iter.key()).is_ge() {
+                    return Err(Error::InvalidArgument(format!(
+                        "the keys of {} are out of order",
+                        path
+                    )));
+                }
+            }
+            if decode_value(iter.value())?.value_type == ValueType::BlobIndex {
+                return Err(Error::InvalidArgument(format!(
+                    "{} refers to blob files",
+                    path
+                )));
+            }
+            last_key = Some(iter.key().to_vec());
+            iter.next();
+        }
+        iter.status()?;
+        if last_key.is_none() && table.range_tombstones().is_empty() {
+            return Err(Error::InvalidArgument(format!("{} is empty", path)));
+        }
+        Ok(())
+    }
+
+    /// Whether the memtable of `cfd` holds any record or range tombstone in
+    /// `[smallest, largest]`
+    fn memtable_overlaps(cfd: &ColumnFamilyData, smallest: &[u8], largest: &[u8]) -> bool {
+        let comparator = cfd.options().comparator.as_ref();
+        let mut iter = cfd.mem.iter();
+        iter.seek_to_key(smallest);
+        if iter.is_valid() && comparator.compare(iter.key(), largest).is_le() {
+            return true;
+        }
+        cfd.mem.range_tombstones().iter().any(|x| {
+            comparator.compare(&x.start, largest).is_le()
+                && comparator.compare(smallest, &x.end).is_lt()
+        })
+    }
+
+    /// Return the deepest level `[smallest, largest]` can be placed at, no level above it
+    /// holds any key in the range so that the ingested records stay above the older ones
+    fn ingestion_level(version: &Version, smallest: &[u8], largest: &[u8]) -> usize {
+        let mut level = 0;
+        for next in 0..NUM_LEVELS {
+            if !version
+                .overlapping_files(next, smallest, largest)
+                .is_empty()
+            {
+                break;
+            }
+            level = next;
+        }
+        level
+    }
+
+    /// Link the files of the current versions of `versions` from `dir` into `target`, then
+    /// write a MANIFEST recording nothing but the versions
+    fn write_checkpoint(versions: &VersionSet, dir: &str, target: &str) -> Result<()> {
+        for cfd in versions.column_families() {
+            let version = cfd.current();
+            for file in version.levels.iter().flatten() {
+                let number = file.number as usize;
+                link_or_copy(
+                    versions.env().as_ref(),
+                    sstfile_path(dir, number).as_str(),
+                    sstfile_path(target, number).as_str(),
+                )?;
+            }
+            for number in version.blob_files.keys() {
+                link_or_copy(
+                    versions.env().as_ref(),
+                    blobfile_path(dir, *number).as_str(),
+                    blobfile_path(target, *number).as_str(),
+                )?;
+            }
+        }
+        versions.write_snapshot(target, 1)
+    }
+
+    /// Compact the levels of the column family `id` while any of them needs to, then delete
+    /// the files no longer needed
+    fn maybe_compact(state: &mut DBState, id: u32) -> Result<()> {
+        while let Some(compaction) = Compaction::pick(state.versions.column_family(id).unwrap()) {
+            compaction.run(&mut state.versions)?;
+        }
+        Self::delete_obsolete_files(state)
+    }
+
+    /// Delete the files of the database no longer needed, see `VersionSet::obsolete_files`.
+    /// The SSTables held by the iterators are deleted by the next call once the iterators are
+    /// dropped.
+    fn delete_obsolete_files(state: &mut DBState) -> Result<()> {
+        let current_log = state
+            .log
+            .as_ref()
+            .expect("only the primary deletes files")
+            .seq();
+        for path in state.versions.obsolete_files(current_log)? {
+            // a file failed to remove is retried next time
+            let _ = state.versions.env().remove_file(path.as_str());
+        }
+        Ok(())
+    }
+
+    /// Return the value of `key` in the column family `cfd`, the writes to the memtable
+    /// newer than `visible_seq` are ignored
+    fn get_from(
+        cfd: &ColumnFamilyData,
+        key: &[u8],
+        visible_seq: SequenceNumber,
+    ) -> Result<Option<Bytes>> {
+        // the sources are searched from the newest to the oldest until the value is
+        // resolved, a record is deleted if any newer range tombstone covers it
+        let now = cfd.options().clock.now_millis();
+        let mut context = MergeContext::new(now, cfd.blob_files().clone());
+        let mut covering_seq = cfd.mem.max_covering_seq(key, visible_seq);
+        if let Some(entry) = cfd.mem.get_entry_at(key, visible_seq) {
+            context.add(entry.as_ref(), covering_seq)?;
+        }
+        if !context.is_resolved() {
+            for file in cfd.current().files_for_key(key) {
+                let table = cfd.table(file.number);
+                covering_seq = covering_seq.max(table.range_tombstones().max_covering_seq(key));
+                if !table.may_contain_key(key) {
+                    continue;
+                }
+                if let Some(entry) = table.get(key)? {
+                    context.add(entry.as_ref(), covering_seq)?;
+                    if context.is_resolved() {
+                        break;
+                    }
+                }
+            }
+        }
+        context.value(key, cfd.options().merge_operator.as_deref())
+    }
+
+    /// Return the values of `keys` in the column family `cfd`, see `DB::multi_get`. Like
+    /// `get_from`, the sources are searched from the newest to the oldest, but every SSTable
+    /// is searched once for the keys in its key range which are not resolved yet.
+    fn multi_get_from(
+        cfd: &ColumnFamilyData,
+        keys: &[&[u8]],
+        visible_seq: SequenceNumber,
+    ) -> Vec<Result<Option<Bytes>>> {
+        let comparator = cfd.options().comparator.as_ref();
+        let now = cfd.options().clock.now_millis();
+        let mut lookups: Vec<KeyLookup> = keys
+            .iter()
+            .map(|_| KeyLookup::new(now, cfd.blob_files().clone()))
+            .collect();
+        for (lookup, key) in lookups.iter_mut().zip(keys) {
+            lookup.covering_seq = cfd.mem.max_covering_seq(key, visible_seq);
+            if let Some(entry) = cfd.mem.get_entry_at(key, visible_seq) {
+                lookup.add(entry.as_ref());
+            }
+        }
+        // the SSTables expect the keys in order
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|a, b| comparator.compare(keys[*a], keys[*b]));
+        let version = cfd.current();
+        let files = version.levels[0]
+            .iter()
+            .rev()
+            .chain(version.levels[1..].iter().flatten());
+        for file in files {
+            let candidates: Vec<usize> = order
+                .iter()
+                .copied()
+                .filter(|x| lookups[*x].is_pending())
+                .filter(|x| file.overlaps(keys[*x], keys[*x], comparator))
+                .collect();
+            if candidates.is_empty() {
+                continue;
+            }
+            let table = cfd.table(file.number);
+            let mut probed = Vec::new();
+            for index in candidates {
+                let covering_seq = table.range_tombstones().max_covering_seq(keys[index]);
+                let lookup = &mut lookups[index];
+                lookup.covering_seq = lookup.covering_seq.max(covering_seq);
+                if table.may_contain_key(keys[index]) {
+                    probed.push(index);
+                }
+            }
+            let probed_keys: Vec<&[u8]> = probed.iter().map(|x| keys[*x]).collect();
+            match table.multi_get(probed_keys.as_slice()) {
+                Ok(entries) => {
+                    for (index, entry) in probed.iter().zip(entries) {
+                        if let Some(entry) = entry {
+                            lookups[*index].add(entry.as_ref());
+                        }
+                    }
+                }
+                Err(e) => {
+                    for index in probed {
+                        lookups[index].status = Err(e.clone());
+                    }
+                }
+            }
+        }
+        let merge_operator = cfd.options().merge_operator.as_deref();
+        lookups
+            .into_iter()
+            .zip(keys)
+            .map(|(lookup, key)| lookup.value(key, merge_operator))
+            .collect()
+    }
+
+    /// Return the sequence number of the latest update of `key` in the column family `cfd`,
+    /// including the range tombstones covering it. 0 if the key is never written, or the
+    /// deletion of the key has been dropped by compaction. The writes to the memtable newer
+    /// than `visible_seq` are ignored.
+    fn latest_sequence(
+        cfd: &ColumnFamilyData,
+        key: &[u8],
+        visible_seq: SequenceNumber,
+    ) -> Result<SequenceNumber> {
+        let mut sequence = cfd.mem.max_covering_seq(key, visible_seq);
+        if let Some(entry) = cfd.mem.get_entry_at(key, visible_seq) {
+            return Ok(sequence.max(decode_value(entry.as_ref())?.seq));
+        }
+        for file in cfd.current().files_for_key(key) {
+            let table = cfd.table(file.number);
+            sequence = sequence.max(table.range_tombstones().max_covering_seq(key));
+            if !table.may_contain_key(key) {
+                continue;
+            }
+            if let Some(entry) = table.get(key)? {
+                return Ok(sequence.max(decode_value(entry.as_ref())?.seq));
+            }
+        }
+        Ok(sequence)
+    }
+
+    /// Apply the updates of `batch`. The updates are logged while holding the write lock
+    /// `state`, then inserted into the memtables after the lock is released, see
+    /// `VisibleSequence`
+    fn write_to(&self, mut state: RwLockWriteGuard<'_, DBState>, batch: WriteBatch) -> Result<()> {
+        // check every update and assign the sequence numbers before logging anything
+        let mut records = Vec::with_capacity(batch.len());
+        let mut sequence = state.versions.last_sequence;
+        for record in batch.records() {
+            let cfd = state
+                .versions
+                .column_family(record.column_family)
+                .ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "column family {} does not exist",
+                        record.column_family
+                    ))
+                })?;
+            let mut value = record.value.clone();
+            let comparator = cfd.options().comparator.as_ref();
+            match record.opt {
+                OperationType::MERGE if cfd.options().merge_operator.is_none() => {
+                    return Err(Error::InvalidArgument(
+                        "merge operator is not configured".to_string(),
+                    ));
+                }
+                OperationType::RANGE_DELETE
+                    if comparator.compare(&record.key, &record.value).is_gt() =>
+                {
+                    return Err(Error::InvalidArgument(
+                        "the start key of the range is greater than the end key".to_string(),
+                    ));
+                }
+                OperationType::RANGE_DELETE if record.key == record.value => continue,
+                OperationType::PUT_WITH_TTL => {
+                    let ttl = record.ttl.unwrap_or_default().as_millis() as u64;
+                    let expire_at = cfd.options().clock.now_millis().saturating_add(ttl);
+                    value = encode_expiring_payload(expire_at, value.as_ref()).into();
+                }
+                _ => {}
+            }
+            // the index blocks of the SSTables hold the keys with their lengths in two bytes,
+            // a key they cannot hold must be rejected before it is logged. The values of any
+            // length fit in the blocks.
+            if record.key.len() > u16::MAX as usize {
+                return Err(Error::InvalidArgument(format!(
+                    "record with key of {} bytes is too long",
+                    record.key.len()
+                )));
+            }
+            sequence += 1;
+            records.push(LoggerRecord::new(
+                record.opt,
+                sequence,
+                record.column_family,
+                record.key.as_ref(),
+                value.as_ref(),
+            ));
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut builder = LogRecordsBuilder::new();
+        for record in &records {
+            builder.add(
+                *record.opt_type(),
+                record.seq(),
+                record.column_family(),
+                record.key(),
+                record.value(),
+            )?;
+        }
+        state
+            .log
+            .as_mut()
+            .expect("the primary has a log")
+            .log_and_sync(builder.build())?;
+        let first = state.versions.last_sequence + 1;
+        state.versions.last_sequence = sequence;
+        // the memtables are taken along with the records, a memtable flushed meanwhile
+        // waits for the write to become visible before it is frozen
+        let mut memtables = Vec::with_capacity(records.len());
+        for record in &records {
+            let cfd = state
+                .versions
+                .column_family(record.column_family())
+                .unwrap();
+            memtables.push((cfd.mem.clone(), cfd.options().write_buffer_size));
+        }
+        drop(state);
+
+        for (record, (mem, _)) in records.iter().zip(&memtables) {
+            mem.add(record);
+        }
+        self.visible.publish(first, sequence);
+        if memtables
+            .iter()
+            .any(|(mem, write_buffer_size)| mem.approximate_size() >= *write_buffer_size)
+        {
+            self.flush_full_memtables()?;
+        }
+        Ok(())
+    }
+
+    /// Flush the memtables which reach `write_buffer_size`, unless they are flushed by
+    /// another writer already
+    fn flush_full_memtables(&self) -> Result<()> {
+        let mut state = self.lock_for_write()?;
+        let full: Vec<u32> = state
+            .versions
+            .column_families()
+            .filter(|x| x.mem.approximate_size() >= x.options().write_buffer_size)
+            .map(|x| x.id())
+            .collect();
+        if !full.is_empty() {
+            Self::switch_log(self.dir.as_str(), &mut state)?;
+            for id in full {
+                Self::flush_column_family(&mut state, id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn column_family_data<'a>(
+        state: &'a DBState,
+        cf: &ColumnFamilyHandle,
+    ) -> Result<&'a ColumnFamilyData> {
+        state.versions.column_family(cf.id()).ok_or_else(|| {
+            Error::InvalidArgument(format!("column family {} does not exist", cf.name()))
+        })
+    }
 }
+
 impl DB for DBImpl {
-    fn open(&self,path: &str) ->Result<()> {
-        todo!()
+    fn close(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        state.closed = true;
+        state.lock = None;
+        Ok(())
     }
 
-    fn close(&self) {
-        todo!()
+    fn get_cf(&self, cf: &ColumnFamilyHandle, key: &[u8]) -> Result<Option<Bytes>> {
+        let state = self.state.read().unwrap();
+        if state.closed {
+            return Err(Error::ShutdownInProgress);
+        }
+        let cfd = Self::column_family_data(&state, cf)?;
+        Self::get_from(cfd, key, self.visible.get())
     }
 
-    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        todo!()
+    fn multi_get_cf(&self, cf: &ColumnFamilyHandle, keys: &[&[u8]]) -> Vec<Result<Option<Bytes>>> {
+        let state = self.state.read().unwrap();
+        let cfd = if state.closed {
+            Err(Error::ShutdownInProgress)
+        } else {
+            Self::column_family_data(&state, cf)
+        };
+        match cfd {
+            Ok(cfd) => Self::multi_get_from(cfd, keys, self.visible.get()),
+            Err(e) => keys.iter().map(|_| Err(e.clone())).collect(),
+        }
     }
 
-    fn get(&self, key: &[u8]) -> Option<Bytes> {
-        todo!()
+    fn iter_cf_with_options(
+        &self,
+        cf: &ColumnFamilyHandle,
+        read_options: ReadOptions,
+    ) -> Result<DBIterator> {
+        let state = self.state.read().unwrap();
+        if state.closed {
+            return Err(Error::ShutdownInProgress);
+        }
+        let cfd = Self::column_family_data(&state, cf)?;
+        let read_options = Arc::new(read_options);
+        let comparator = cfd.options().comparator.clone();
+        let visible = self.visible.get();
+        let mut children = vec![SourceIterator::MemTable(cfd.mem.iter_at(visible))];
+        let mut range_tombstones = RangeTombstoneList::with_comparator(comparator.clone());
+        for tombstone in cfd.mem.range_tombstones().iter() {
+            if tombstone.seq <= visible {
+                range_tombstones.add(tombstone.clone());
+            }
+        }
+        for file in cfd.current().levels.iter().flatten() {
+            let table = cfd.table(file.number);
+            // neither the records nor the range tombstones of the SSTable are within bounds
+            if read_options.reaches_upper_bound(table.smallest_key(), comparator.as_ref())
+                || read_options.before_lower_bound(table.largest_key(), comparator.as_ref())
+            {
+                continue;
+            }
+            range_tombstones.extend(table.range_tombstones());
+            children.push(SourceIterator::SSTable(
+                SSTableRecordIterator::with_read_options(table, read_options.clone()),
+            ));
+        }
+        Ok(DBIterator::new(DBIteratorParts {
+            inner: MergingIterator::with_comparator(children, comparator.clone()),
+            range_tombstones,
+            merge_operator: cfd.options().merge_operator.clone(),
+            now: cfd.options().clock.now_millis(),
+            blob_files: cfd.blob_files().clone(),
+            read_options,
+            prefix_extractor: cfd.options().prefix_extractor.clone(),
+            comparator,
+        }))
     }
 
-    fn delete(&self, key: &[u8]) -> Result<()> {
-        todo!()
+    fn ingest_external_file_cf(&self, cf: &ColumnFamilyHandle, paths: &[&str]) -> Result<()> {
+        let mut state = self.lock_for_write()?;
+        let cfd = Self::column_family_data(&state, cf)?;
+        let options = cfd.options().clone();
+        let comparator = options.comparator.clone();
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let table = Arc::new(SSTable::open_file(path, 0, None, options.clone())?);
+            Self::verify_external_file(&table, path)?;
+            files.push((*path, table));
+        }
+        files.sort_by(|a, b| comparator.compare(a.1.smallest_key(), b.1.smallest_key()));
+        for pair in files.windows(2) {
+            if comparator
+                .compare(pair[0].1.largest_key(), pair[1].1.smallest_key())
+                .is_ge()
+            {
+                return Err(Error::InvalidArgument(format!(
+                    "{} overlaps with {}",
+                    pair[0].0, pair[1].0
+                )));
+            }
+        }
+        if files.is_empty() {
+            return Ok(());
+        }
+        // the records in the memtable are older than the ingested ones, but they would be
+        // read first
+        if files
+            .iter()
+            .any(|(_, x)| Self::memtable_overlaps(cfd, x.smallest_key(), x.largest_key()))
+        {
+            Self::switch_log(self.dir.as_str(), &mut state)?;
+            Self::flush_column_family(&mut state, cf.id())?;
+        }
+
+        state.versions.last_sequence += 1;
+        let global_seq = state.versions.last_sequence;
+        self.visible.set(global_seq);
+        let version = Self::column_family_data(&state, cf)?.current().clone();
+        let mut edit = VersionEdit::new();
+        edit.column_family = cf.id();
+        let mut tables = Vec::with_capacity(files.len());
+        for (path, external) in files {
+            let level =
+                Self::ingestion_level(&version, external.smallest_key(), external.largest_key());
+            let number = state.versions.new_file_number() as usize;
+            let target = sstfile_path(self.dir.as_str(), number);
+            link_or_copy(state.versions.env().as_ref(), path, target.as_str())?;
+            let table =
+                SSTable::open_file(target.as_str(), number, Some(global_seq), options.clone())?;
+            edit.add_file(level, FileMetaData::from_sstable(&table, Vec::new())?);
+            tables.push(Arc::new(table));
+        }
+        state.versions.log_and_apply(edit, tables)?;
+        Self::maybe_compact(&mut state, cf.id())
+    }
+
+    fn create_checkpoint(&self, dir: &str) -> Result<()> {
+        let mut state = self.lock_for_write()?;
+        if state.versions.env().file_exists(dir) {
+            return Err(Error::InvalidArgument(format!("{} already exists", dir)));
+        }
+        // the checkpoint holds no log, every record is in the SSTables then
+        if state.versions.column_families().any(|x| !x.mem.is_empty()) {
+            Self::switch_log(self.dir.as_str(), &mut state)?;
+            let ids: Vec<u32> = state.versions.column_families().map(|x| x.id()).collect();
+            for id in ids {
+                Self::flush_column_family(&mut state, id)?;
+            }
+        }
+        let env = state.versions.env().clone();
+        env.create_dir_all(dir)?;
+        let result = Self::write_checkpoint(&state.versions, self.dir.as_str(), dir);
+        if result.is_err() {
+            let _ = env.remove_dir_all(dir);
+        }
+        result
+    }
+
+    fn try_catch_up_with_primary(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if state.closed {
+            return Err(Error::ShutdownInProgress);
+        }
+        if self.mode != OpenMode::Secondary {
+            return Err(Error::NotSupported(format!(
+                "the database is opened as {:?}",
+                self.mode
+            )));
+        }
+        let log_numbers: HashMap<u32, u64> = state
+            .versions
+            .column_families()
+            .map(|x| (x.id(), x.log_number))
+            .collect();
+        state.versions.catch_up()?;
+        // the memtables of the column families flushed by the primary since, and the new
+        // column families, are rebuilt from the logs not flushed yet
+        let mut reset = HashSet::new();
+        let ids: Vec<u32> = state.versions.column_families().map(|x| x.id()).collect();
+        for id in ids {
+            let cfd = state.versions.column_family_mut(id).unwrap();
+            if log_numbers.get(&id) != Some(&cfd.log_number) {
+                cfd.mem = Arc::new(MemTable::with_options(cfd.options()));
+                reset.insert(id);
+            }
+        }
+        let state = &mut *state;
+        Self::replay_logs(
+            self.dir.as_str(),
+            &mut state.versions,
+            &mut state.replayed_logs,
+            &reset,
+        )?;
+        self.visible.set(state.versions.last_sequence);
+        Ok(())
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let state = self.lock_for_logging()?;
+        self.write_to(state, batch)
+    }
+
+    fn create_column_family(&self, name: &str, options: Options) -> Result<ColumnFamilyHandle> {
+        let mut state = self.lock_for_write()?;
+        let log_number = state.log.as_ref().expect("the primary has a log").seq();
+        let cfd = state
+            .versions
+            .create_column_family(name, Arc::new(options), log_number)?;
+        Ok(cfd.handle())
+    }
+
+    fn drop_column_family(&self, cf: &ColumnFamilyHandle) -> Result<()> {
+        let mut state = self.lock_for_write()?;
+        if cf.id() == DEFAULT_COLUMN_FAMILY_ID {
+            return Err(Error::InvalidArgument(
+                "the default column family can not be dropped".to_string(),
+            ));
+        }
+        Self::column_family_data(&state, cf)?;
+        state.versions.drop_column_family(cf.id())?;
+        Self::delete_obsolete_files(&mut state)
+    }
+
+    fn column_family(&self, name: &str) -> Option<ColumnFamilyHandle> {
+        let state = self.state.read().unwrap();
+        state
+            .versions
+            .column_family_by_name(name)
+            .map(|x| x.handle())
+    }
+
+    fn begin_optimistic_transaction(&self) -> OptimisticTransaction<'_> {
+        OptimisticTransaction::new(self, self.last_sequence())
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,344 @@
+use crate::db::{DBImpl, DB};
+use crate::util::clock::{Clock, SystemClock};
+use crate::util::crc32;
+use crate::util::env::{
+    copy_file, parse_file_name, read_file, rename_and_sync, temp_file_path, write_file_atomically,
+    Env, PosixEnv,
+};
+use crate::util::error::{Error, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+/// `BackupEngine` keeps backups of databases in a directory. A backup copies the live
+/// SSTables, blob files and MANIFEST of a database at one point in time, which restores as
+/// an independent database.
+///
+/// The SSTables and blob files never change once written, they are shared by the backups
+/// holding the same file, told by the file number along with the checksum and the size, so
+/// that every backup only copies the files written since the previous one. The backup
+/// directory is laid out as
+/// ``` text
+/// meta/<backup id>                          the files of the backup, see `BackupMeta`
+/// private/<backup id>/MANIFEST-1, CURRENT   the files owned by the backup
+/// shared/<number>_<crc32>_<size>.sst        the files shared by the backups
+/// ```
+/// The directory is supposed to be used by one engine at a time.
+pub struct BackupEngine {
+    dir: String,
+    env: Arc<dyn Env>,
+    backups: BTreeMap<u32, BackupMeta>,
+}
+
+/// `BackupInfo` describes a backup kept by `BackupEngine`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub backup_id: u32,
+    /// when the backup is created, milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// the total size of the files of the backup, including the shared ones
+    pub size: u64,
+    pub num_files: usize,
+}
+
+/// `BackupMeta` is the metadata of a backup, written as a text file of lines
+/// ``` text
+/// <timestamp>
+/// <number of files>
+/// <file name> <crc32> <size>    for every file
+/// ```
+/// where the file names are the ones in the database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BackupMeta {
+    timestamp: u64,
+    files: Vec<BackupFile>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BackupFile {
+    name: String,
+    crc: u32,
+    size: u64,
+}
+
+impl BackupMeta {
+    fn encode(&self) -> String {
+        let mut buf = format!("{}\n{}\n", self.timestamp, self.files.len());
+        for file in &self.files {
+            buf.push_str(format!("{} {} {}\n", file.name, file.crc, file.size).as_str());
+        }
+        buf
+    }
+
+    /// Failed with `Corruption` if `raw` is not an encoded `BackupMeta`
+    fn decode(raw: &str, path: &str) -> Result<Self> {
+        let corruption = || Error::corruption(path, 0);
+        let mut lines = raw.lines();
+        let mut next_line = || lines.next().ok_or_else(corruption);
+        let timestamp = next_line()?.parse().map_err(|_| corruption())?;
+        let num_files: usize = next_line()?.parse().map_err(|_| corruption())?;
+        let mut files = Vec::with_capacity(num_files);
+        for _ in 0..num_files {
+            let fields: Vec<&str> = next_line()?.split(' ').collect();
+            let [name, crc, size] = fields.as_slice() else {
+                return Err(corruption());
+            };
+            files.push(BackupFile {
+                name: name.to_string(),
+                crc: crc.parse().map_err(|_| corruption())?,
+                size: size.parse().map_err(|_| corruption())?,
+            });
+        }
+        Ok(BackupMeta { timestamp, files })
+    }
+}
+
+impl BackupEngine {
+    /// Open the backups in directory `dir` of the file system of the operating system, see
+    /// `open_with_env`
+    pub fn open(dir: &str) -> Result<Self> {
+        Self::open_with_env(dir, Arc::new(PosixEnv {}))
+    }
+
+    /// Open the backups in directory `dir` of `env`, the directory is created if it does not
+    /// exist. The files left by the backups failed halfway are removed. The databases backed
+    /// up and restored must be on `env` as well, see `Options::env`.
+    pub fn open_with_env(dir: &str, env: Arc<dyn Env>) -> Result<Self> {
+        let mut engine = BackupEngine {
+            dir: dir.to_string(),
+            env,
+            backups: BTreeMap::new(),
+        };
+        for sub_dir in ["meta", "private", "shared"] {
+            engine
+                .env
+                .create_dir_all(format!("{}/{}", dir, sub_dir).as_str())?;
+        }
+        for name in engine.env.get_children(format!("{}/meta", dir).as_str())? {
+            let Ok(backup_id) = name.parse::<u32>() else {
+                // the meta file being written when the process crashed
+                let _ = engine
+                    .env
+                    .remove_file(format!("{}/meta/{}", dir, name).as_str());
+                continue;
+            };
+            let path = engine.meta_path(backup_id);
+            let raw = read_file(engine.env.as_ref(), path.as_str())?;
+            let raw = std::str::from_utf8(raw.as_slice())
+                .map_err(|_| Error::corruption(path.as_str(), 0))?;
+            engine
+                .backups
+                .insert(backup_id, BackupMeta::decode(raw, path.as_str())?);
+        }
+        engine.garbage_collect()?;
+        Ok(engine)
+    }
+
+    /// Back up the database `db`, return the id of the new backup. The memtables of the
+    /// database are flushed, see `DB::create_checkpoint`.
+    pub fn create_new_backup(&mut self, db: &DBImpl) -> Result<u32> {
+        let backup_id = self.backups.keys().next_back().map_or(1, |x| x + 1);
+        let private_dir = self.private_dir(backup_id);
+        let _ = self.env.remove_dir_all(private_dir.as_str());
+        let result = self.copy_checkpoint(db, backup_id);
+        if result.is_err() {
+            let _ = self.env.remove_dir_all(private_dir.as_str());
+        }
+        let meta = result?;
+        // the backup exists once its meta file is in place
+        write_file_atomically(
+            self.env.as_ref(),
+            self.meta_path(backup_id).as_str(),
+            meta.encode().as_bytes(),
+        )?;
+        self.backups.insert(backup_id, meta);
+        Ok(backup_id)
+    }
+
+    /// Create a checkpoint of `db` in the private directory of the backup `backup_id`, then
+    /// move its SSTables and blob files to the shared directory unless they are there
+    fn copy_checkpoint(&self, db: &DBImpl, backup_id: u32) -> Result<BackupMeta> {
+        let private_dir = self.private_dir(backup_id);
+        db.create_checkpoint(private_dir.as_str())?;
+        let timestamp = SystemClock {}.now_millis();
+        let mut files = Vec::new();
+        for name in self.env.get_children(private_dir.as_str())? {
+            let path = format!("{}/{}", private_dir, name);
+            let (crc, size) = file_checksum(self.env.as_ref(), path.as_str())?;
+            let file = BackupFile { name, crc, size };
+            if let Some(shared_path) = self.shared_path(&file) {
+                if !self.env.file_exists(shared_path.as_str()) {
+                    // the checkpoint links the files of the database, they are copied so
+                    // that the backup does not share the disk blocks with the database
+                    let temp_path = temp_file_path(shared_path.as_str());
+                    let _ = self.env.remove_file(temp_path.as_str());
+                    copy_file(self.env.as_ref(), path.as_str(), temp_path.as_str())?;
+                    rename_and_sync(self.env.as_ref(), temp_path.as_str(), shared_path.as_str())?;
+                }
+                self.env.remove_file(path.as_str())?;
+            }
+            files.push(file);
+        }
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(BackupMeta { timestamp, files })
+    }
+
+    /// Return the backups ordered by id
+    pub fn get_backup_info(&self) -> Vec<BackupInfo> {
+        self.backups
+            .iter()
+            .map(|(backup_id, meta)| BackupInfo {
+                backup_id: *backup_id,
+                timestamp: meta.timestamp,
+                size: meta.files.iter().map(|x| x.size).sum(),
+                num_files: meta.files.len(),
+            })
+            .collect()
+    }
+
+    /// Check that every file of the backup `backup_id` exists with the recorded checksum.
+    /// Failed with `NotFound` if there is no such backup, or `Corruption` naming the damaged
+    /// file
+    pub fn verify_backup(&self, backup_id: u32) -> Result<()> {
+        let meta = self.backups.get(&backup_id).ok_or(Error::NotFound)?;
+        for file in &meta.files {
+            let path = self.stored_path(backup_id, file);
+            match file_checksum(self.env.as_ref(), path.as_str()) {
+                Ok(checksum) if checksum == (file.crc, file.size) => {}
+                Ok(_) | Err(Error::NotFound) => return Err(Error::corruption(path.as_str(), 0)),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete the backup `backup_id` along with the shared files no other backup holds.
+    /// Failed with `NotFound` if there is no such backup
+    pub fn delete_backup(&mut self, backup_id: u32) -> Result<()> {
+        if self.backups.remove(&backup_id).is_none() {
+            return Err(Error::NotFound);
+        }
+        self.env.remove_file(self.meta_path(backup_id).as_str())?;
+        self.garbage_collect()
+    }
+
+    /// Delete the backups except the latest `num_backups_to_keep` ones
+    pub fn purge_old_backups(&mut self, num_backups_to_keep: usize) -> Result<()> {
+        let num_deleted = self.backups.len().saturating_sub(num_backups_to_keep);
+        let deleted: Vec<u32> = self.backups.keys().take(num_deleted).copied().collect();
+        for backup_id in deleted {
+            self.delete_backup(backup_id)?;
+        }
+        Ok(())
+    }
+
+    /// Restore the backup `backup_id` as a database in `db_dir`, every file is verified by
+    /// its checksum once copied.
+    /// Failed with `NotFound` if there is no such backup, or `InvalidArgument` if `db_dir`
+    /// exists and is not empty
+    pub fn restore_db_from_backup(&self, backup_id: u32, db_dir: &str) -> Result<()> {
+        let meta = self.backups.get(&backup_id).ok_or(Error::NotFound)?;
+        if self.env.get_children(db_dir).is_ok_and(|x| !x.is_empty()) {
+            return Err(Error::InvalidArgument(format!(
+                "{} is not an empty directory",
+                db_dir
+            )));
+        }
+        self.env.create_dir_all(db_dir)?;
+        let result = meta.files.iter().try_for_each(|file| {
+            let path = format!("{}/{}", db_dir, file.name);
+            let stored_path = self.stored_path(backup_id, file);
+            copy_file(self.env.as_ref(), stored_path.as_str(), path.as_str())?;
+            if file_checksum(self.env.as_ref(), path.as_str())? != (file.crc, file.size) {
+                return Err(Error::corruption(stored_path.as_str(), 0));
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            let _ = self.env.remove_dir_all(db_dir);
+        }
+        result
+    }
+
+    /// Remove the private directories and the shared files no backup holds
+    fn garbage_collect(&self) -> Result<()> {
+        for name in self
+            .env
+            .get_children(format!("{}/private", self.dir).as_str())?
+        {
+            if !name
+                .parse::<u32>()
+                .is_ok_and(|x| self.backups.contains_key(&x))
+            {
+                self.env
+                    .remove_dir_all(format!("{}/private/{}", self.dir, name).as_str())?;
+            }
+        }
+        let live: HashSet<String> = self
+            .backups
+            .values()
+            .flat_map(|x| x.files.iter())
+            .filter_map(|x| self.shared_path(x))
+            .collect();
+        for name in self
+            .env
+            .get_children(format!("{}/shared", self.dir).as_str())?
+        {
+            let path = format!("{}/shared/{}", self.dir, name);
+            if !live.contains(&path) {
+                self.env.remove_file(path.as_str())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn meta_path(&self, backup_id: u32) -> String {
+        format!("{}/meta/{}", self.dir, backup_id)
+    }
+
+    fn private_dir(&self, backup_id: u32) -> String {
+        format!("{}/private/{}", self.dir, backup_id)
+    }
+
+    /// Return where `file` is kept in the shared directory, `None` if the file is private
+    fn shared_path(&self, file: &BackupFile) -> Option<String> {
+        match parse_file_name(file.name.as_str())? {
+            (number, kind @ ("sst" | "blob")) => Some(format!(
+                "{}/shared/{}_{}_{}.{}",
+                self.dir, number, file.crc, file.size, kind
+            )),
+            _ => None,
+        }
+    }
+
+    /// Return where `file` of the backup `backup_id` is kept
+    fn stored_path(&self, backup_id: u32, file: &BackupFile) -> String {
+        self.shared_path(file)
+            .unwrap_or_else(|| format!("{}/{}", self.private_dir(backup_id), file.name))
+    }
+}
+
+/// Return the crc32 and the size of the file at `path`.
+/// Failed with `NotFound` if the file does not exist
+fn file_checksum(env: &dyn Env, path: &str) -> Result<(u32, u64)> {
+    let mut file = match env.new_sequential_file(path) {
+        Ok(file) => file,
+        Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::NotFound)
+        }
+        Err(e) => return Err(e),
+    };
+    let mut buf = vec![0; 64 * 1024];
+    let mut crc = 0;
+    let mut size = 0;
+    loop {
+        let read_size = file.read(buf.as_mut_slice())?;
+        if read_size == 0 {
+            return Ok((crc, size));
+        }
+        crc = crc32::extend(crc, &buf[..read_size]);
+        size += read_size as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,187 @@
+use crate::db::backup_engine::BackupEngine;
+use crate::db::{DBImpl, DB};
+use crate::options::{test_options, Options};
+use crate::util::env::{read_file, test_env};
+use crate::util::error::Error;
+
+/// `TestBackup` opens a database along with a backup directory, both are removed on drop
+struct TestBackup {
+    path: String,
+    db: DBImpl,
+}
+
+impl TestBackup {
+    fn new(name: &str) -> Self {
+        let path = format!("/summer_kv_test/backup_{}", name);
+        let _ = test_env().remove_dir_all(path.as_str());
+        let options = Options {
+            write_buffer_size: 1024,
+            ..test_options()
+        };
+        let db = DBImpl::open(format!("{}/db", path).as_str(), options).unwrap();
+        TestBackup { path, db }
+    }
+
+    fn backup_dir(&self) -> String {
+        format!("{}/backup", self.path)
+    }
+
+    fn open_engine(&self) -> BackupEngine {
+        BackupEngine::open_with_env(self.backup_dir().as_str(), test_env()).unwrap()
+    }
+
+    fn restore_dir(&self, backup_id: u32) -> String {
+        format!("{}/restore_{}", self.path, backup_id)
+    }
+
+    /// Return the number of files shared by the backups
+    fn num_shared_files(&self) -> usize {
+        test_env()
+            .get_children(format!("{}/shared", self.backup_dir()).as_str())
+            .unwrap()
+            .len()
+    }
+}
+
+impl Drop for TestBackup {
+    fn drop(&mut self) {
+        let _ = test_env().remove_dir_all(self.path.as_str());
+    }
+}
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn value_of(i: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{}", i, round).into_bytes()
+}
+
+/// Write the keys `[0, 100)` of `round`
+fn write_round(db: &DBImpl, round: usize) {
+    for i in 0..100 {
+        db.put(key_of(i).as_slice(), value_of(i, round).as_slice())
+            .unwrap();
+    }
+}
+
+fn check_round(db: &DBImpl, round: usize) {
+    for i in 0..100 {
+        assert_eq!(
+            db.get(key_of(i).as_slice()).unwrap(),
+            Some(value_of(i, round).into())
+        );
+    }
+}
+
+#[test]
+fn test_backup_and_restore() {
+    let test = TestBackup::new("restore");
+    let mut engine = test.open_engine();
+    let mut backup_ids = Vec::new();
+    for round in 0..3 {
+        write_round(&test.db, round);
+        backup_ids.push(engine.create_new_backup(&test.db).unwrap());
+    }
+    assert_eq!(backup_ids, vec![1, 2, 3]);
+    let infos = engine.get_backup_info();
+    assert_eq!(
+        infos.iter().map(|x| x.backup_id).collect::<Vec<_>>(),
+        backup_ids
+    );
+    assert!(infos.iter().all(|x| x.size > 0 && x.num_files > 2));
+    assert!(infos.windows(2).all(|x| x[0].timestamp <= x[1].timestamp));
+
+    // the backups survive reopening the engine
+    drop(engine);
+    let engine = test.open_engine();
+    assert_eq!(engine.get_backup_info(), infos);
+    for (round, backup_id) in backup_ids.iter().enumerate() {
+        engine.verify_backup(*backup_id).unwrap();
+        let restore_dir = test.restore_dir(*backup_id);
+        engine
+            .restore_db_from_backup(*backup_id, restore_dir.as_str())
+            .unwrap();
+        let db = DBImpl::open(restore_dir.as_str(), test_options()).unwrap();
+        check_round(&db, round);
+    }
+    assert!(matches!(
+        engine.restore_db_from_backup(1, test.restore_dir(1).as_str()),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        engine.restore_db_from_backup(9, test.restore_dir(9).as_str()),
+        Err(Error::NotFound)
+    ));
+    check_round(&test.db, 2);
+}
+
+#[test]
+fn test_purge_old_backups() {
+    let test = TestBackup::new("purge");
+    let mut engine = test.open_engine();
+    for round in 0..3 {
+        write_round(&test.db, round);
+        engine.create_new_backup(&test.db).unwrap();
+    }
+    // the files unchanged since the previous backup are shared
+    let num_shared_files = test.num_shared_files();
+    engine.create_new_backup(&test.db).unwrap();
+    assert_eq!(test.num_shared_files(), num_shared_files);
+    engine.purge_old_backups(2).unwrap();
+    let backup_ids: Vec<u32> = engine
+        .get_backup_info()
+        .iter()
+        .map(|x| x.backup_id)
+        .collect();
+    assert_eq!(backup_ids, vec![3, 4]);
+    assert!(test.num_shared_files() < num_shared_files);
+    assert!(matches!(engine.delete_backup(1), Err(Error::NotFound)));
+    for backup_id in [3, 4] {
+        engine.verify_backup(backup_id).unwrap();
+    }
+    // the new backups are numbered after the kept ones
+    assert_eq!(engine.create_new_backup(&test.db).unwrap(), 5);
+    engine.delete_backup(4).unwrap();
+    engine.verify_backup(3).unwrap();
+    engine.verify_backup(5).unwrap();
+    engine.purge_old_backups(0).unwrap();
+    assert!(engine.get_backup_info().is_empty());
+    assert_eq!(test.num_shared_files(), 0);
+}
+
+#[test]
+fn test_verify_backup_corruption() {
+    let test = TestBackup::new("verify");
+    let mut engine = test.open_engine();
+    write_round(&test.db, 0);
+    let backup_id = engine.create_new_backup(&test.db).unwrap();
+    engine.verify_backup(backup_id).unwrap();
+    let shared_dir = format!("{}/shared", test.backup_dir());
+    let env = test_env();
+    let victim = format!(
+        "{}/{}",
+        shared_dir,
+        env.get_children(shared_dir.as_str()).unwrap()[0]
+    );
+    let mut content = read_file(env.as_ref(), victim.as_str()).unwrap();
+    content[0] ^= 0xff;
+    env.remove_file(victim.as_str()).unwrap();
+    env.new_writable_file(victim.as_str())
+        .and_then(|mut file| file.append(content.as_slice()))
+        .unwrap();
+    assert!(matches!(
+        engine.verify_backup(backup_id),
+        Err(Error::Corruption { .. })
+    ));
+    assert!(matches!(
+        engine.restore_db_from_backup(backup_id, test.restore_dir(backup_id).as_str()),
+        Err(Error::Corruption { .. })
+    ));
+    assert!(!env.file_exists(test.restore_dir(backup_id).as_str()));
+    env.remove_file(victim.as_str()).unwrap();
+    assert!(matches!(
+        engine.verify_backup(backup_id),
+        Err(Error::Corruption { .. })
+    ));
+}
@@ -0,0 +1,272 @@
+use crate::db::format::{
+    decode_merge_payload, decode_value, encode_merge_payload, encode_value, BlobIndex, MergeBase,
+    ValueType,
+};
+use crate::db::version::{BlobFileMetaData, VersionSet};
+use crate::options::Options;
+use crate::util::coding::put_varint64;
+use crate::util::crc32;
+use crate::util::env::{blobfile_path, Env, RandomAccessFile, WritableFile};
+use crate::util::error::{Error, Result};
+use bytes::{Buf, BufMut, Bytes};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+
+/// A blob file is a sequence of records appended one after another, every record holds a
+/// value separated from the SSTables along with its key
+/// ``` text
+/// +------------------------------------------------------------------------------+
+/// | Key Length (varint) | Value Length (varint) | Key | Value | Checksum#4        |
+/// +------------------------------------------------------------------------------+
+/// ```
+/// The checksum is the crc32 of the value. The `BlobIndex` of a record points at the value,
+/// the key only tells whom the value belongs to when the file is inspected.
+const CHECKSUM_SIZE: usize = 4;
+
+/// `BlobFileBuilder` appends the values to a new blob file, the file is readable once
+/// finished
+pub struct BlobFileBuilder {
+    file: Box<dyn WritableFile>,
+    number: u64,
+    file_size: u64,
+}
+
+impl BlobFileBuilder {
+    pub fn new(env: &dyn Env, dir: &str, number: u64) -> Result<Self> {
+        Ok(BlobFileBuilder {
+            file: env.new_writable_file(blobfile_path(dir, number).as_str())?,
+            number,
+            file_size: 0,
+        })
+    }
+
+    /// Append the value of `key`, return where the value is
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<BlobIndex> {
+        let mut buf = Vec::with_capacity(key.len() + value.len() + 20);
+        put_varint64(&mut buf, key.len() as u64);
+        put_varint64(&mut buf, value.len() as u64);
+        buf.extend_from_slice(key);
+        let offset = self.file_size + buf.len() as u64;
+        buf.extend_from_slice(value);
+        buf.put_u32(crc32::value(value));
+        self.file.append(buf.as_slice())?;
+        self.file_size += buf.len() as u64;
+        Ok(BlobIndex {
+            file_number: self.number,
+            offset,
+            size: value.len() as u64,
+        })
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Sync the file, return its description for the MANIFEST
+    pub fn finish(mut self) -> Result<BlobFileMetaData> {
+        self.file.sync()?;
+        Ok(BlobFileMetaData {
+            number: self.number,
+            file_size: self.file_size,
+        })
+    }
+}
+
+/// `BlobFile` reads the values of a finished blob file
+pub struct BlobFile {
+    file: Box<dyn RandomAccessFile>,
+    number: u64,
+}
+
+impl BlobFile {
+    pub fn open(env: &dyn Env, dir: &str, number: u64) -> Result<Self> {
+        Ok(BlobFile {
+            file: env.new_random_access_file(blobfile_path(dir, number).as_str())?,
+            number,
+        })
+    }
+
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// Read the value at `index`.
+    /// Failed with `Corruption` if the checksum of the value mismatches
+    pub fn get(&self, index: &BlobIndex) -> Result<Bytes> {
+        let size = index.size as usize;
+        let raw = self.file.read_at(index.offset, size + CHECKSUM_SIZE)?;
+        let mut checksum = &raw[size..];
+        if crc32::value(&raw[..size]) != checksum.get_u32() {
+            return Err(Error::corruption(self.file.path(), index.offset));
+        }
+        Ok(raw.slice(..size))
+    }
+}
+
+/// `BlobFileSet` is the opened blob files of a column family, keyed by the file number
+#[derive(Clone, Default)]
+pub struct BlobFileSet {
+    files: HashMap<u64, Arc<BlobFile>>,
+}
+
+impl BlobFileSet {
+    pub fn insert(&mut self, file: Arc<BlobFile>) {
+        self.files.insert(file.number(), file);
+    }
+
+    pub fn remove(&mut self, number: u64) {
+        self.files.remove(&number);
+    }
+
+    pub fn contains(&self, number: u64) -> bool {
+        self.files.contains_key(&number)
+    }
+
+    /// Keep the files whose numbers satisfy `f`
+    pub fn retain(&mut self, f: impl Fn(u64) -> bool) {
+        self.files.retain(|number, _| f(*number));
+    }
+
+    /// Read the value at `index`.
+    /// Failed with `Corruption` if the blob file does not exist
+    pub fn get(&self, index: &BlobIndex) -> Result<Bytes> {
+        match self.files.get(&index.file_number) {
+            Some(file) => file.get(index),
+            None => Err(Error::corruption(
+                format!("{}.blob", index.file_number).as_str(),
+                index.offset,
+            )),
+        }
+    }
+}
+
+/// `BlobWriter` moves the large values of the records written to SSTables by flush and
+/// compaction into blob files, see `Options::enable_blob_files`. The values in the blob
+/// files of `relocated` are moved into the new blob files as well, the old files are
+/// deleted once no SSTable refers to them, see `Options::enable_blob_garbage_collection`.
+///
+/// The records refer to the blob files by `BlobIndex`, either as the record itself or as
+/// the base of a merge record. The blob files referred by the records of every SSTable are
+/// recorded in the MANIFEST along with the SSTable.
+pub struct BlobWriter {
+    options: Arc<Options>,
+    /// the blob files the records being written refer to
+    source: Arc<BlobFileSet>,
+    relocated: HashSet<u64>,
+    builder: Option<BlobFileBuilder>,
+    finished: Vec<BlobFileMetaData>,
+    /// the blob files referred by the records written since the last `take_references`
+    references: BTreeSet<u64>,
+}
+
+impl BlobWriter {
+    pub fn new(options: Arc<Options>, source: Arc<BlobFileSet>, relocated: HashSet<u64>) -> Self {
+        BlobWriter {
+            options,
+            source,
+            relocated,
+            builder: None,
+            finished: Vec::new(),
+            references: BTreeSet::new(),
+        }
+    }
+
+    /// Return the record to write to the SSTable instead of `record`, the new blob files
+    /// are numbered by `versions`
+    pub fn process(
+        &mut self,
+        versions: &mut VersionSet,
+        key: &[u8],
+        record: Bytes,
+    ) -> Result<Bytes> {
+        let decoded = decode_value(record.as_ref())?;
+        match decoded.value_type {
+            ValueType::Value
+                if self.options.enable_blob_files
+                    && decoded.payload.len() >= self.options.min_blob_size =>
+            {
+                let index = self.add(versions, key, decoded.payload)?;
+                let payload = index.encode();
+                Ok(encode_value(
+                    ValueType::BlobIndex,
+                    decoded.seq,
+                    payload.as_slice(),
+                ))
+            }
+            ValueType::BlobIndex => {
+                let index = self.relocate(versions, key, BlobIndex::decode(decoded.payload)?)?;
+                let payload = index.encode();
+                Ok(encode_value(
+                    ValueType::BlobIndex,
+                    decoded.seq,
+                    payload.as_slice(),
+                ))
+            }
+            ValueType::Merge => {
+                let (base, operands) = decode_merge_payload(decoded.payload)?;
+                let MergeBase::BlobIndex(index) = base else {
+                    return Ok(record);
+                };
+                let base = MergeBase::BlobIndex(self.relocate(versions, key, index)?);
+                let payload = encode_merge_payload(&base, operands.as_slice());
+                Ok(encode_value(
+                    ValueType::Merge,
+                    decoded.seq,
+                    payload.as_slice(),
+                ))
+            }
+            _ => Ok(record),
+        }
+    }
+
+    /// Move the value at `index` into the new blob files if its blob file is relocated,
+    /// return where the value is then
+    fn relocate(
+        &mut self,
+        versions: &mut VersionSet,
+        key: &[u8],
+        index: BlobIndex,
+    ) -> Result<BlobIndex> {
+        if !self.relocated.contains(&index.file_number) {
+            self.references.insert(index.file_number);
+            return Ok(index);
+        }
+        let value = self.source.get(&index)?;
+        self.add(versions, key, value.as_ref())
+    }
+
+    fn add(&mut self, versions: &mut VersionSet, key: &[u8], value: &[u8]) -> Result<BlobIndex> {
+        let builder = match self.builder.as_mut() {
+            Some(builder) => builder,
+            None => {
+                let number = versions.new_file_number();
+                self.builder.insert(BlobFileBuilder::new(
+                    self.options.env.as_ref(),
+                    versions.dir(),
+                    number,
+                )?)
+            }
+        };
+        let index = builder.add(key, value)?;
+        self.references.insert(index.file_number);
+        if builder.file_size() >= self.options.blob_file_size {
+            let builder = self.builder.take().unwrap();
+            self.finished.push(builder.finish()?);
+        }
+        Ok(index)
+    }
+
+    /// Return the blob files referred by the records written since the last call, the
+    /// records are the ones of a SSTable
+    pub fn take_references(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.references).into_iter().collect()
+    }
+
+    /// Finish the blob file being written, return all the new blob files
+    pub fn finish(mut self) -> Result<Vec<BlobFileMetaData>> {
+        if let Some(builder) = self.builder.take() {
+            self.finished.push(builder.finish()?);
+        }
+        Ok(self.finished)
+    }
+}
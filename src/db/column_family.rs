@@ -0,0 +1,226 @@
+use crate::db::blob::{BlobFile, BlobFileSet};
+use crate::db::version::{Version, VersionEdit};
+use crate::memtable::MemTable;
+use crate::options::Options;
+use crate::sstable::SSTable;
+use crate::util::env::sstfile_path;
+use crate::util::error::Result;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+pub const DEFAULT_COLUMN_FAMILY_ID: u32 = 0;
+
+/// `ColumnFamilyHandle` identifies a column family of an opened database. A column family
+/// dropped and created again with the same name gets a new id, the handles of the dropped
+/// one are not valid any more.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnFamilyHandle {
+    id: u32,
+    name: String,
+}
+
+impl Default for ColumnFamilyHandle {
+    /// Return the handle of the default column family, which exists in every database
+    fn default() -> Self {
+        ColumnFamilyHandle {
+            id: DEFAULT_COLUMN_FAMILY_ID,
+            name: DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+        }
+    }
+}
+
+impl ColumnFamilyHandle {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+}
+
+/// `ColumnFamilyDescriptor` names a column family to open along with its options
+#[derive(Clone)]
+pub struct ColumnFamilyDescriptor {
+    pub name: String,
+    pub options: Options,
+}
+
+impl ColumnFamilyDescriptor {
+    pub fn new(name: &str, options: Options) -> Self {
+        ColumnFamilyDescriptor {
+            name: name.to_string(),
+            options,
+        }
+    }
+}
+
+/// `ColumnFamilyData` is a column family of the database, a key space with its own memtable,
+/// levels of SSTables and options. The records of every column family are logged to the same
+/// log file.
+pub struct ColumnFamilyData {
+    id: u32,
+    name: String,
+    options: Arc<Options>,
+    /// logs numbered less than `log_number` hold no record of the column family which is not
+    /// flushed yet
+    pub log_number: u64,
+    /// shared with the writers inserting into it out of the lock of the database
+    pub mem: Arc<MemTable>,
+    current: Version,
+    /// the name of the comparator recorded in the MANIFEST, `None` for the column families
+    /// created before the comparators are recorded, which are in the bytewise order
+    pub(crate) comparator_name: Option<String>,
+    /// the opened SSTables of the current version, keyed by the file number
+    tables: HashMap<u64, Arc<SSTable>>,
+    /// the opened blob files of the current version, shared with the readers
+    blob_files: Arc<BlobFileSet>,
+}
+
+impl ColumnFamilyData {
+    pub fn new(id: u32, name: &str, options: Arc<Options>, log_number: u64) -> Self {
+        ColumnFamilyData {
+            id,
+            name: name.to_string(),
+            options: options.clone(),
+            log_number,
+            mem: Arc::new(MemTable::with_options(&options)),
+            current: Version::new(options.comparator.clone()),
+            comparator_name: None,
+            tables: HashMap::new(),
+            blob_files: Arc::new(BlobFileSet::default()),
+        }
+    }
+
+    pub fn handle(&self) -> ColumnFamilyHandle {
+        ColumnFamilyHandle {
+            id: self.id,
+            name: self.name.clone(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn options(&self) -> &Arc<Options> {
+        &self.options
+    }
+
+    pub fn current(&self) -> &Version {
+        &self.current
+    }
+
+    pub fn comparator_name(&self) -> Option<&str> {
+        self.comparator_name.as_deref()
+    }
+
+    pub fn table(&self, number: u64) -> Arc<SSTable> {
+        self.tables
+            .get(&number)
+            .expect("every file of the current version is opened")
+            .clone()
+    }
+
+    pub fn blob_files(&self) -> &Arc<BlobFileSet> {
+        &self.blob_files
+    }
+
+    /// Apply `edit` on the current version, the SSTables and the blob files added by `edit`
+    /// are passed by `tables` and `blob_files`. Return the opened SSTables deleted by `edit`.
+    pub fn apply(
+        &mut self,
+        edit: &VersionEdit,
+        tables: Vec<Arc<SSTable>>,
+        blob_files: Vec<Arc<BlobFile>>,
+    ) -> Vec<Arc<SSTable>> {
+        let mut deleted = Vec::new();
+        if let Some(log_number) = edit.log_number {
+            self.log_number = log_number;
+        }
+        if let Some(comparator) = &edit.comparator {
+            self.comparator_name = Some(comparator.clone());
+        }
+        for (_, number) in &edit.deleted_files {
+            deleted.extend(self.tables.remove(number));
+        }
+        self.current.apply(edit);
+        for table in tables {
+            self.tables.insert(table.seq() as u64, table);
+        }
+        if !edit.deleted_blob_files.is_empty() || !blob_files.is_empty() {
+            let set = Arc::make_mut(&mut self.blob_files);
+            for number in &edit.deleted_blob_files {
+                set.remove(*number);
+            }
+            for file in blob_files {
+                set.insert(file);
+            }
+        }
+        deleted
+    }
+
+    /// Return the opened SSTables of the current version
+    pub fn tables(&self) -> impl std::iter::Iterator<Item = &Arc<SSTable>> {
+        self.tables.values()
+    }
+
+    /// Replace the options the column family is created with, then open the SSTables and the
+    /// blob files of the current version in `dir`. The memtable must be empty.
+    pub fn open_tables(&mut self, dir: &str, options: Arc<Options>) -> Result<()> {
+        self.options = options;
+        self.mem = Arc::new(MemTable::with_options(&self.options));
+        self.current.set_comparator(self.options.comparator.clone());
+        self.tables.clear();
+        self.blob_files = Arc::new(BlobFileSet::default());
+        self.open_new_tables(dir)
+    }
+
+    /// Take the version of `replayed` in place of the current one, see
+    /// `VersionSet::switch_manifest`. The opened files no longer in the version are closed.
+    pub fn reset_version(&mut self, replayed: ColumnFamilyData) {
+        self.log_number = replayed.log_number;
+        self.comparator_name = replayed.comparator_name;
+        self.current = replayed.current;
+        self.current.set_comparator(self.options.comparator.clone());
+        let live: HashSet<u64> = self
+            .current
+            .levels
+            .iter()
+            .flatten()
+            .map(|x| x.number)
+            .collect();
+        self.tables.retain(|number, _| live.contains(number));
+        let blob_files = &self.current.blob_files;
+        Arc::make_mut(&mut self.blob_files).retain(|number| blob_files.contains_key(&number));
+    }
+
+    /// Open the SSTables and the blob files of the current version in `dir` which are not
+    /// opened yet
+    pub fn open_new_tables(&mut self, dir: &str) -> Result<()> {
+        for file in self.current.levels.iter().flatten() {
+            if self.tables.contains_key(&file.number) {
+                continue;
+            }
+            let sstable = SSTable::open_file(
+                sstfile_path(dir, file.number as usize).as_str(),
+                file.number as usize,
+                file.global_seq,
+                self.options.clone(),
+            )?;
+            self.tables.insert(file.number, Arc::new(sstable));
+        }
+        for number in self.current.blob_files.keys() {
+            if !self.blob_files.contains(*number) {
+                let file = Arc::new(BlobFile::open(self.options.env.as_ref(), dir, *number)?);
+                Arc::make_mut(&mut self.blob_files).insert(file);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+use crate::compaction_filter::{CompactionFilter, CompactionFilterContext, Decision};
+use crate::db::blob::{BlobFileSet, BlobWriter};
+use crate::db::column_family::ColumnFamilyData;
+use crate::db::format::{
+    decode_expiring_payload, decode_value, encode_expiring_payload, encode_value, BlobIndex,
+    RangeTombstoneList, ValueType,
+};
+use crate::db::iterator::{MergingIterator, SourceIterator};
+use crate::db::merge::MergeContext;
+use crate::db::version::{FileMetaData, Version, VersionEdit, VersionSet, NUM_LEVELS};
+use crate::iterator::Iterator;
+use crate::options::{CompactionStyle, Options, ReadOptions};
+use crate::sstable::iterator::SSTableRecordIterator;
+use crate::sstable::sstable_builder::SSTableBuilder;
+use crate::sstable::SSTable;
+use crate::util::error::Result;
+use bytes::Bytes;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// `Compaction` merges the files of `level` with the overlapping files of `level + 1` into
+/// new files of `level + 1`
+pub struct Compaction {
+    /// id of the column family the files belong to
+    pub column_family: u32,
+    pub level: usize,
+    /// `inputs[0]` are the files of `level`, `inputs[1]` are the files of `level + 1`
+    pub inputs: [Vec<Arc<FileMetaData>>; 2],
+    /// the inputs are dropped without producing any output, see `CompactionStyle::Fifo`
+    pub deletion_only: bool,
+}
+
+impl Compaction {
+    /// Pick the compaction the column family needs the most by its `compaction_style`,
+    /// `None` if every level is within its limit.
+    pub fn pick(cfd: &ColumnFamilyData) -> Option<Compaction> {
+        let (version, options) = (cfd.current(), cfd.options());
+        let (level, inputs) = match options.compaction_style {
+            CompactionStyle::Level => Self::pick_level(version, options)?,
+            CompactionStyle::Fifo {
+                max_table_files_size,
+            } => {
+                let inputs = Self::pick_fifo(version, max_table_files_size)?;
+                return Some(Compaction {
+                    column_family: cfd.id(),
+                    level: 0,
+                    inputs: [inputs, Vec::new()],
+                    deletion_only: true,
+                });
+            }
+        };
+        Some(Compaction {
+            column_family: cfd.id(),
+            level,
+            inputs,
+            deletion_only: false,
+        })
+    }
+
+    /// Level 0 is compacted once it holds `level0_file_num_compaction_trigger` files, all of
+    /// them are compacted together because they may overlap with each other. The other
+    /// levels are compacted once they exceed `max_bytes_for_level`, one file a time.
+    fn pick_level(
+        version: &Version,
+        options: &Options,
+    ) -> Option<(usize, [Vec<Arc<FileMetaData>>; 2])> {
+        let level = if version.levels[0].len() >= options.level0_file_num_compaction_trigger {
+            0
+        } else {
+            (1..NUM_LEVELS - 1)
+                .find(|x| version.level_size(*x) > options.max_bytes_for_level(*x))?
+        };
+        let inputs0 = if level == 0 {
+            version.levels[0].clone()
+        } else {
+            vec![version.levels[level][0].clone()]
+        };
+        let comparator = options.comparator.as_ref();
+        let smallest = inputs0
+            .iter()
+            .map(|x| x.smallest.clone())
+            .min_by(|a, b| comparator.compare(a, b))?;
+        let largest = inputs0
+            .iter()
+            .map(|x| x.largest.clone())
+            .max_by(|a, b| comparator.compare(a, b))?;
+        let inputs1 = version.overlapping_files(level + 1, smallest.as_ref(), largest.as_ref());
+        Some((level, [inputs0, inputs1]))
+    }
+
+    /// Return the oldest files of level 0 to drop so that the rest take no more than
+    /// `max_table_files_size` bytes
+    fn pick_fifo(version: &Version, max_table_files_size: u64) -> Option<Vec<Arc<FileMetaData>>> {
+        let mut size = version.level_size(0);
+        let mut inputs = Vec::new();
+        for file in &version.levels[0] {
+            if size <= max_table_files_size {
+                break;
+            }
+            size -= file.file_size;
+            inputs.push(file.clone());
+        }
+        (!inputs.is_empty()).then_some(inputs)
+    }
+
+    /// Return the blob files whose values are relocated by the compaction, the oldest
+    /// `blob_garbage_collection_age_cutoff` of the blob files of the column family
+    fn blob_files_to_relocate(version: &Version, options: &Options) -> HashSet<u64> {
+        if !options.enable_blob_garbage_collection {
+            return HashSet::new();
+        }
+        let count = version.blob_files.len() as f64 * options.blob_garbage_collection_age_cutoff;
+        version
+            .blob_files
+            .keys()
+            .take(count as usize)
+            .copied()
+            .collect()
+    }
+
+    fn output_level(&self) -> usize {
+        self.level + 1
+    }
+
+    /// Whether no level deeper than the output level holds any key in `[smallest, largest]`,
+    /// then deletion records and range tombstones in the range are useless once compacted
+    fn is_bottommost(&self, version: &Version, smallest: &[u8], largest: &[u8]) -> bool {
+        (self.output_level() + 1..NUM_LEVELS).all(|level| {
+            version
+                .overlapping_files(level, smallest, largest)
+                .is_empty()
+        })
+    }
+
+    /// Run the compaction and install the result as the current version of the column
+    /// family.
+    ///
+    /// Only the newest record of every key is kept, records covered by a newer range
+    /// tombstone and expired records are dropped. Deletion records and range tombstones are
+    /// dropped as well if there is nothing beneath them. Merge operands are folded, see
+    /// `MergeContext::compacted_record`. The user values are passed to the
+    /// `CompactionFilter` created by `compaction_filter_factory` if there is one. The output
+    /// is split once a file reaches `target_file_size`, the range tombstones are clipped so
+    /// that every output file only holds the part between its first key and the first key
+    /// of the next output. The large values are written to blob files, and the values in the
+    /// oldest blob files are relocated, see `BlobWriter`.
+    pub fn run(&self, versions: &mut VersionSet) -> Result<()> {
+        let mut edit = VersionEdit::new();
+        edit.column_family = self.column_family;
+        for (i, inputs) in self.inputs.iter().enumerate() {
+            for file in inputs {
+                edit.delete_file(self.level + i, file.number);
+            }
+        }
+        if self.deletion_only {
+            return versions.log_and_apply(edit, Vec::new());
+        }
+
+        let cfd = versions
+            .column_family(self.column_family)
+            .expect("the compacted column family exists");
+        let options = cfd.options().clone();
+        let version = cfd.current().clone();
+        let blob_files = cfd.blob_files().clone();
+        // the blocks read by compaction are not going to be read again
+        let read_options = Arc::new(ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        });
+        let mut children = Vec::new();
+        let mut range_tombstones = RangeTombstoneList::with_comparator(options.comparator.clone());
+        for file in self.inputs.iter().flatten() {
+            let table = cfd.table(file.number);
+            range_tombstones.extend(table.range_tombstones());
+            children.push(SourceIterator::SSTable(
+                SSTableRecordIterator::with_read_options(table, read_options.clone()),
+            ));
+        }
+        let mut merging_iter =
+            MergingIterator::with_comparator(children, options.comparator.clone());
+
+        let mut live_tombstones = RangeTombstoneList::with_comparator(options.comparator.clone());
+        for tombstone in range_tombstones.iter() {
+            if !self.is_bottommost(&version, &tombstone.start, &tombstone.end) {
+                live_tombstones.add(tombstone.clone());
+            }
+        }
+
+        let now = options.clock.now_millis();
+        let mut filter = options.compaction_filter_factory.as_ref().map(|factory| {
+            factory.create_compaction_filter(&CompactionFilterContext {
+                level: self.level,
+                is_bottommost_level: (self.output_level() + 1..NUM_LEVELS)
+                    .all(|level| version.levels[level].is_empty()),
+            })
+        });
+
+        let relocated = Self::blob_files_to_relocate(&version, &options);
+        let mut blob_writer = BlobWriter::new(options.clone(), blob_files.clone(), relocated);
+        let mut outputs = Vec::new();
+        let mut builder = SSTableBuilder::new(options.clone(), self.output_level());
+        // the first key of the current output, `None` for the first output
+        let mut lower_bound: Option<Vec<u8>> = None;
+        merging_iter.seek_to_first();
+        while merging_iter.is_valid() {
+            let key = merging_iter.key();
+            let covering_seq = range_tombstones.max_covering_seq(key);
+            let mut context = MergeContext::new(now, blob_files.clone());
+            for record in merging_iter.current_records() {
+                context.add(record, covering_seq)?;
+            }
+            let bottommost = self.is_bottommost(&version, key, key);
+            let mut record =
+                context.compacted_record(key, options.merge_operator.as_deref(), bottommost)?;
+            if let (Some(filter), Some(raw)) = (filter.as_mut(), record.as_ref()) {
+                record = self.apply_filter(filter.as_mut(), &blob_files, key, raw, bottommost)?;
+            }
+            if let Some(record) = record {
+                if builder.approximate_size() >= options.target_file_size {
+                    let upper_bound = key.to_vec();
+                    Self::add_tombstones(
+                        &mut builder,
+                        &live_tombstones,
+                        lower_bound.as_deref(),
+                        Some(upper_bound.as_slice()),
+                    );
+                    let output = Self::finish_output(versions, &mut builder, &mut blob_writer)?;
+                    outputs.push(output);
+                    builder = SSTableBuilder::new(options.clone(), self.output_level());
+                    lower_bound = Some(upper_bound);
+                }
+                let record = blob_writer.process(versions, key, record)?;
+                builder.add(key, record.as_ref())?;
+            }
+            merging_iter.next();
+        }
+        merging_iter.status()?;
+        Self::add_tombstones(&mut builder, &live_tombstones, lower_bound.as_deref(), None);
+        if !builder.is_empty() {
+            outputs.push(Self::finish_output(
+                versions,
+                &mut builder,
+                &mut blob_writer,
+            )?);
+        }
+
+        for (output, blob_files) in &outputs {
+            let file = FileMetaData::from_sstable(output, blob_files.clone())?;
+            edit.add_file(self.output_level(), file);
+        }
+        for file in blob_writer.finish()? {
+            edit.add_blob_file(file);
+        }
+        let tables = outputs.into_iter().map(|(table, _)| table).collect();
+        versions.log_and_apply(edit, tables)
+    }
+
+    /// Pass the user value of `record` to `filter`, return the record to write instead.
+    /// A removed record turns into a deletion so that the older records of the key stay
+    /// hidden, unless there is nothing beneath it. A value stored in a blob file is read
+    /// from `blob_files` for the filter.
+    fn apply_filter(
+        &self,
+        filter: &mut dyn CompactionFilter,
+        blob_files: &BlobFileSet,
+        key: &[u8],
+        record: &Bytes,
+        bottommost: bool,
+    ) -> Result<Option<Bytes>> {
+        let decoded = decode_value(record.as_ref())?;
+        let blob;
+        let (expire_at, value) = match decoded.value_type {
+            ValueType::Value => (None, decoded.payload),
+            ValueType::BlobIndex => {
+                blob = blob_files.get(&BlobIndex::decode(decoded.payload)?)?;
+                (None, blob.as_ref())
+            }
+            ValueType::ExpiringValue => {
+                let (expire_at, value) = decode_expiring_payload(decoded.payload)?;
+                (Some(expire_at), value)
+            }
+            _ => return Ok(Some(record.clone())),
+        };
+        let record = match filter.filter(self.level, key, value) {
+            Decision::Keep => Some(record.clone()),
+            Decision::Remove if bottommost => None,
+            Decision::Remove => Some(encode_value(ValueType::Deletion, decoded.seq, b"")),
+            // the changed value expires as the original one does
+            Decision::ChangeValue(value) => Some(match expire_at {
+                None => encode_value(ValueType::Value, decoded.seq, value.as_slice()),
+                Some(expire_at) => encode_value(
+                    ValueType::ExpiringValue,
+                    decoded.seq,
+                    encode_expiring_payload(expire_at, value.as_slice()).as_slice(),
+                ),
+            }),
+        };
+        Ok(record)
+    }
+
+    fn add_tombstones(
+        builder: &mut SSTableBuilder,
+        tombstones: &RangeTombstoneList,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) {
+        let comparator = tombstones.comparator().as_ref();
+        for tombstone in tombstones.iter() {
+            if let Some(clipped) = tombstone.clip(lower_bound, upper_bound, comparator) {
+                builder.add_range_tombstone(clipped);
+            }
+        }
+    }
+
+    /// Build the output SSTable, return it along with the blob files its records refer to
+    fn finish_output(
+        versions: &mut VersionSet,
+        builder: &mut SSTableBuilder,
+        blob_writer: &mut BlobWriter,
+    ) -> Result<(Arc<SSTable>, Vec<u64>)> {
+        let number = versions.new_file_number();
+        let table = builder.build(versions.dir(), number as usize)?;
+        Ok((Arc::new(table), blob_writer.take_references()))
+    }
+}
@@ -0,0 +1,414 @@
+use crate::comparator::{bytewise_comparator, Comparator};
+use crate::util::coding::{
+    get_length_prefixed_slice, get_varint32, get_varint64, put_length_prefixed_slice, put_varint32,
+    put_varint64,
+};
+use crate::util::error::{Error, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// Every write to the database is assigned a unique, increasing sequence number. A record
+/// with a greater sequence number is newer. Sequence number 0 is never assigned.
+pub type SequenceNumber = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    Deletion,
+    Value,
+    Merge,
+    /// a value which expires at some moment, see `encode_expiring_payload`
+    ExpiringValue,
+    /// a value stored in a blob file, the payload is a `BlobIndex`
+    BlobIndex,
+}
+
+impl ValueType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ValueType::Deletion => 0,
+            ValueType::Value => 1,
+            ValueType::Merge => 2,
+            ValueType::ExpiringValue => 3,
+            ValueType::BlobIndex => 4,
+        }
+    }
+    pub fn from_u8(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(ValueType::Deletion),
+            1 => Some(ValueType::Value),
+            2 => Some(ValueType::Merge),
+            3 => Some(ValueType::ExpiringValue),
+            4 => Some(ValueType::BlobIndex),
+            _ => None,
+        }
+    }
+}
+
+/// The value of the records stored in memtable and SSTable is prefixed with a header, the
+/// header tells what kind of operation the record is and when it happened.
+/// ``` text
+/// +-----------------------------------------------------------+
+/// | Value Type#1 | Sequence Number#8 | Payload (user value)   |
+/// +-----------------------------------------------------------+
+/// ```
+/// A `Deletion` record carries no payload, the payload of a `Merge` record is described in
+/// `encode_merge_payload`, the one of an `ExpiringValue` record in `encode_expiring_payload`
+/// and the one of a `BlobIndex` record in `BlobIndex::encode`.
+pub const VALUE_HEADER_SIZE: usize = 1 + 8;
+
+pub fn encode_value(value_type: ValueType, seq: SequenceNumber, payload: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(VALUE_HEADER_SIZE + payload.len());
+    buf.put_u8(value_type.to_u8());
+    buf.put_u64(seq);
+    buf.put_slice(payload);
+    buf.freeze()
+}
+
+pub struct DecodedValue<'a> {
+    pub value_type: ValueType,
+    pub seq: SequenceNumber,
+    pub payload: &'a [u8],
+}
+
+/// Decode the value produced by `encode_value`.
+/// Failed with `Corruption` if the header is broken, the offset of the `Corruption` is
+/// relative to the beginning of `raw`
+pub fn decode_value(raw: &[u8]) -> Result<DecodedValue<'_>> {
+    if raw.len() < VALUE_HEADER_SIZE {
+        return Err(Error::corruption("", 0));
+    }
+    let mut buf = raw;
+    let value_type = ValueType::from_u8(buf.get_u8()).ok_or_else(|| Error::corruption("", 0))?;
+    let seq = buf.get_u64();
+    Ok(DecodedValue {
+        value_type,
+        seq,
+        payload: &raw[VALUE_HEADER_SIZE..],
+    })
+}
+
+/// The payload of an `ExpiringValue` record is the user value prefixed with the moment it
+/// expires, in milliseconds since the unix epoch as told by `Options::clock`
+/// ``` text
+/// +-------------------------------------------+
+/// | Expire At#8 | Payload (user value)        |
+/// +-------------------------------------------+
+/// ```
+/// The write-ahead log stores the records written by `DB::put_with_ttl` in the same format.
+pub fn encode_expiring_payload(expire_at: u64, value: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(8 + value.len());
+    buf.put_u64(expire_at);
+    buf.put_slice(value);
+    buf
+}
+
+/// Decode the payload produced by `encode_expiring_payload`, return the expiry moment and
+/// the user value.
+/// Failed with `Corruption` if the payload is too short
+pub fn decode_expiring_payload(raw: &[u8]) -> Result<(u64, &[u8])> {
+    if raw.len() < 8 {
+        return Err(Error::corruption("", 0));
+    }
+    let mut buf = raw;
+    Ok((buf.get_u64(), &raw[8..]))
+}
+
+/// `BlobIndex` locates a value stored in a blob file, see `db::blob`. The records of the
+/// SSTables carry it instead of the large values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlobIndex {
+    pub file_number: u64,
+    /// offset of the value in the blob file
+    pub offset: u64,
+    /// length of the value
+    pub size: u64,
+}
+
+impl BlobIndex {
+    /// The blob index is encoded as
+    /// ``` text
+    /// +----------------------------------------------------------+
+    /// | File Number (varint) | Offset (varint) | Size (varint)   |
+    /// +----------------------------------------------------------+
+    /// ```
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_varint64(&mut buf, self.file_number);
+        put_varint64(&mut buf, self.offset);
+        put_varint64(&mut buf, self.size);
+        buf
+    }
+
+    /// Decode the blob index produced by `BlobIndex::encode`.
+    /// Failed with `Corruption` if the payload is broken
+    pub fn decode(raw: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let mut next = || {
+            let (value, consumed) =
+                get_varint64(&raw[offset..]).ok_or_else(|| Error::corruption("", offset as u64))?;
+            offset += consumed;
+            Ok::<u64, Error>(value)
+        };
+        let index = BlobIndex {
+            file_number: next()?,
+            offset: next()?,
+            size: next()?,
+        };
+        if offset != raw.len() {
+            return Err(Error::corruption("", offset as u64));
+        }
+        Ok(index)
+    }
+}
+
+/// The value a merge record applies its operands on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeBase {
+    /// the operands apply on the records older than the merge record
+    Unresolved,
+    /// the operands apply on a deleted key
+    Deletion,
+    Value(Bytes),
+    /// a value expiring at the moment of the first field, see `encode_expiring_payload`
+    ExpiringValue(u64, Bytes),
+    /// a value stored in a blob file
+    BlobIndex(BlobIndex),
+}
+
+/// The payload of a `Merge` record is the base and the operands ordered from the oldest to
+/// the newest
+/// ``` text
+/// +--------------------------------------------------------------------------------------+
+/// | Base Type#1 | Base Value (length prefixed) | Operand Count (varint) | Operand #1 | ... |
+/// +--------------------------------------------------------------------------------------+
+/// ```
+/// `Base Type` is 0 for `Unresolved`, 1 for `Deletion`, 2 for `Value`, 3 for
+/// `ExpiringValue` and 4 for `BlobIndex`, only `Value`, `ExpiringValue` and `BlobIndex`
+/// carry the `Base Value`, which is the payload of the record they come from. Every operand
+/// is length prefixed.
+pub fn encode_merge_payload(base: &MergeBase, operands: &[Bytes]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match base {
+        MergeBase::Unresolved => buf.push(0),
+        MergeBase::Deletion => buf.push(1),
+        MergeBase::Value(value) => {
+            buf.push(2);
+            put_length_prefixed_slice(&mut buf, value.as_ref());
+        }
+        MergeBase::ExpiringValue(expire_at, value) => {
+            buf.push(3);
+            let payload = encode_expiring_payload(*expire_at, value.as_ref());
+            put_length_prefixed_slice(&mut buf, payload.as_slice());
+        }
+        MergeBase::BlobIndex(index) => {
+            buf.push(4);
+            put_length_prefixed_slice(&mut buf, index.encode().as_slice());
+        }
+    }
+    put_varint32(&mut buf, operands.len() as u32);
+    for operand in operands {
+        put_length_prefixed_slice(&mut buf, operand.as_ref());
+    }
+    buf
+}
+
+/// Decode the payload produced by `encode_merge_payload`.
+/// Failed with `Corruption` if the payload is broken, the offset of the `Corruption` is
+/// relative to the beginning of `raw`
+pub fn decode_merge_payload(raw: &[u8]) -> Result<(MergeBase, Vec<Bytes>)> {
+    let corruption = |offset: usize| Error::corruption("", offset as u64);
+    let mut offset = 1;
+    let base = match raw.first() {
+        Some(0) => MergeBase::Unresolved,
+        Some(1) => MergeBase::Deletion,
+        Some(2) => {
+            let (value, consumed) =
+                get_length_prefixed_slice(&raw[offset..]).ok_or_else(|| corruption(offset))?;
+            offset += consumed;
+            MergeBase::Value(Bytes::copy_from_slice(value))
+        }
+        Some(3) => {
+            let (payload, consumed) =
+                get_length_prefixed_slice(&raw[offset..]).ok_or_else(|| corruption(offset))?;
+            let (expire_at, value) =
+                decode_expiring_payload(payload).map_err(|_| corruption(offset))?;
+            offset += consumed;
+            MergeBase::ExpiringValue(expire_at, Bytes::copy_from_slice(value))
+        }
+        Some(4) => {
+            let (payload, consumed) =
+                get_length_prefixed_slice(&raw[offset..]).ok_or_else(|| corruption(offset))?;
+            let index = BlobIndex::decode(payload).map_err(|_| corruption(offset))?;
+            offset += consumed;
+            MergeBase::BlobIndex(index)
+        }
+        _ => return Err(corruption(0)),
+    };
+    let (count, consumed) = get_varint32(&raw[offset..]).ok_or_else(|| corruption(offset))?;
+    offset += consumed;
+    let mut operands = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (operand, consumed) =
+            get_length_prefixed_slice(&raw[offset..]).ok_or_else(|| corruption(offset))?;
+        offset += consumed;
+        operands.push(Bytes::copy_from_slice(operand));
+    }
+    if offset != raw.len() {
+        return Err(corruption(offset));
+    }
+    Ok((base, operands))
+}
+
+/// `RangeTombstone` deletes every key in `[start, end)` written before `seq`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub seq: SequenceNumber,
+}
+
+impl RangeTombstone {
+    pub fn new(start: &[u8], end: &[u8], seq: SequenceNumber) -> Self {
+        RangeTombstone {
+            start: Bytes::copy_from_slice(start),
+            end: Bytes::copy_from_slice(end),
+            seq,
+        }
+    }
+
+    pub fn contains(&self, key: &[u8], comparator: &dyn Comparator) -> bool {
+        comparator.compare(&self.start, key).is_le() && comparator.compare(key, &self.end).is_lt()
+    }
+
+    /// Return the part of the tombstone inside `[lower, upper)`, `None` means unbounded.
+    /// `None` is returned if nothing is left
+    pub fn clip(
+        &self,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+        comparator: &dyn Comparator,
+    ) -> Option<RangeTombstone> {
+        let start = match lower {
+            Some(lower) if comparator.compare(lower, &self.start).is_gt() => {
+                Bytes::copy_from_slice(lower)
+            }
+            _ => self.start.clone(),
+        };
+        let end = match upper {
+            Some(upper) if comparator.compare(upper, &self.end).is_lt() => {
+                Bytes::copy_from_slice(upper)
+            }
+            _ => self.end.clone(),
+        };
+        if comparator.compare(&start, &end).is_ge() {
+            return None;
+        }
+        Some(RangeTombstone {
+            start,
+            end,
+            seq: self.seq,
+        })
+    }
+
+    /// Range tombstones are stored as block records whose key is `start`, the value is
+    /// ``` text
+    /// +---------------------------------------+
+    /// | Sequence Number (varint) | End Key    |
+    /// +---------------------------------------+
+    /// ```
+    pub fn encode_value(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_varint64(&mut buf, self.seq);
+        buf.extend_from_slice(self.end.as_ref());
+        buf
+    }
+
+    pub fn decode(start: &[u8], value: &[u8]) -> Result<Self> {
+        let (seq, consumed) = get_varint64(value).ok_or_else(|| Error::corruption("", 0))?;
+        Ok(RangeTombstone::new(start, &value[consumed..], seq))
+    }
+}
+
+/// A collection of range tombstones sorted by the start key in the order of the comparator
+#[derive(Clone, Debug)]
+pub struct RangeTombstoneList {
+    tombstones: Vec<RangeTombstone>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl Default for RangeTombstoneList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RangeTombstoneList {
+    /// Create a list in the bytewise order
+    pub fn new() -> Self {
+        Self::with_comparator(bytewise_comparator())
+    }
+
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        RangeTombstoneList {
+            tombstones: Vec::new(),
+            comparator,
+        }
+    }
+
+    pub fn add(&mut self, tombstone: RangeTombstone) {
+        let position = self.tombstones.partition_point(|x| {
+            self.comparator
+                .compare(&x.start, &tombstone.start)
+                .then(x.seq.cmp(&tombstone.seq))
+                != Ordering::Greater
+        });
+        self.tombstones.insert(position, tombstone);
+    }
+
+    pub fn extend(&mut self, other: &RangeTombstoneList) {
+        for tombstone in other.iter() {
+            self.add(tombstone.clone());
+        }
+    }
+
+    /// Return the greatest sequence number of the tombstones which contain `key`, 0 if none
+    /// of them contains `key`. A record of `key` is deleted if its sequence number is less
+    /// than the returned one.
+    pub fn max_covering_seq(&self, key: &[u8]) -> SequenceNumber {
+        self.tombstones
+            .iter()
+            .take_while(|x| self.comparator.compare(&x.start, key).is_le())
+            .filter(|x| x.contains(key, self.comparator.as_ref()))
+            .map(|x| x.seq)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, RangeTombstone> {
+        self.tombstones.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tombstones.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    /// Return the smallest start key and the greatest end key of the tombstones
+    pub fn key_range(&self) -> Option<(Bytes, Bytes)> {
+        let smallest = self.tombstones.first()?.start.clone();
+        let largest = self
+            .tombstones
+            .iter()
+            .map(|x| x.end.clone())
+            .max_by(|a, b| self.comparator.compare(a, b))?;
+        Some((smallest, largest))
+    }
+}
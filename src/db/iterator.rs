@@ -0,0 +1,506 @@
+use crate::comparator::{bytewise_comparator, Comparator};
+use crate::db::blob::BlobFileSet;
+use crate::db::format::{decode_value, RangeTombstoneList};
+use crate::db::merge::MergeContext;
+use crate::iterator::Iterator;
+use crate::memtable::iterator::MemTableIterator;
+use crate::merge_operator::MergeOperator;
+use crate::options::ReadOptions;
+use crate::slice_transform::SliceTransform;
+use crate::sstable::iterator::SSTableRecordIterator;
+use crate::util::error::Result;
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// `SourceIterator` is the iterator of one source of records of the database, a memtable or
+/// a SSTable. The records come with the header described in `db::format`.
+pub enum SourceIterator {
+    MemTable(MemTableIterator),
+    SSTable(SSTableRecordIterator),
+}
+
+macro_rules! dispatch {
+    ($self:ident, $iter:ident => $body:expr) => {
+        match $self {
+            SourceIterator::MemTable($iter) => $body,
+            SourceIterator::SSTable($iter) => $body,
+        }
+    };
+}
+
+impl SourceIterator {
+    fn seek_to_first(&mut self) {
+        dispatch!(self, iter => iter.seek_to_first())
+    }
+    fn seek_to_last(&mut self) {
+        dispatch!(self, iter => iter.seek_to_last())
+    }
+    fn seek_to_key(&mut self, key: &[u8]) {
+        dispatch!(self, iter => iter.seek_to_key(key))
+    }
+    fn is_valid(&self) -> bool {
+        dispatch!(self, iter => iter.is_valid())
+    }
+    fn next(&mut self) {
+        dispatch!(self, iter => iter.next())
+    }
+    fn prev(&mut self) {
+        dispatch!(self, iter => iter.prev())
+    }
+    fn key(&self) -> &[u8] {
+        dispatch!(self, iter => iter.key())
+    }
+    fn value(&self) -> &[u8] {
+        dispatch!(self, iter => iter.value())
+    }
+    fn status(&self) -> Result<()> {
+        dispatch!(self, iter => iter.status())
+    }
+    /// Whether the source may hold keys with `prefix`, see `SSTable::may_contain_prefix`
+    fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        match self {
+            SourceIterator::MemTable(_) => true,
+            SourceIterator::SSTable(iter) => iter.sstable().may_contain_prefix(prefix),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+/// `MergingIterator` merges the records of several sources into one sorted sequence.
+/// Every source holds at most one record of a key, but different sources may hold the same
+/// key, the merging iterator yields every key once with the record of the greatest sequence
+/// number. The sources are sorted by the comparator of the merging iterator.
+///
+/// Moving forward, every child is positioned at the first record greater than or equal to
+/// the current key, and the current key is the smallest key of the children. Moving backward
+/// it is the other way around. Changing the direction re-positions the children.
+///
+/// Positioned by `seek_to_key_in_prefix`, the children without the prefix are set aside
+/// until the next seek.
+pub struct MergingIterator {
+    children: Vec<SourceIterator>,
+    /// the children set aside by `seek_to_key_in_prefix`
+    skipped: Vec<SourceIterator>,
+    comparator: Arc<dyn Comparator>,
+    direction: Direction,
+    /// index of the child holding the newest record of the current key
+    current: Option<usize>,
+    status: Result<()>,
+}
+
+impl MergingIterator {
+    pub fn with_comparator(children: Vec<SourceIterator>, comparator: Arc<dyn Comparator>) -> Self {
+        MergingIterator {
+            children,
+            skipped: Vec::new(),
+            comparator,
+            direction: Direction::Forward,
+            current: None,
+            status: Ok(()),
+        }
+    }
+
+    /// Position at the first key greater than or equal to `key` like `seek_to_key`, the
+    /// children which can not hold keys with `prefix` are left out until the next seek. The
+    /// keys returned are only meaningful until the first key without `prefix`.
+    pub fn seek_to_key_in_prefix(&mut self, key: &[u8], prefix: &[u8]) {
+        self.restore_skipped();
+        let (children, skipped) = std::mem::take(&mut self.children)
+            .into_iter()
+            .partition(|x| x.may_contain_prefix(prefix));
+        self.children = children;
+        self.skipped = skipped;
+        self.direction = Direction::Forward;
+        self.children.iter_mut().for_each(|x| x.seek_to_key(key));
+        self.find_current();
+    }
+
+    fn restore_skipped(&mut self) {
+        self.children.append(&mut self.skipped);
+    }
+
+    /// Pick the current key among the children, the smallest one moving forward and the
+    /// largest one moving backward
+    fn find_current(&mut self) {
+        self.current = None;
+        let mut current_seq = 0;
+        for (index, child) in self.children.iter().enumerate() {
+            if !child.is_valid() {
+                if self.status.is_ok() {
+                    if let Err(e) = child.status() {
+                        self.status = Err(e);
+                    }
+                }
+                continue;
+            }
+            let seq = match decode_value(child.value()) {
+                Ok(decoded) => decoded.seq,
+                Err(e) => {
+                    self.status = Err(e);
+                    0
+                }
+            };
+            let replace = match self.current {
+                None => true,
+                Some(current) => {
+                    let ordering = self
+                        .comparator
+                        .compare(child.key(), self.children[current].key());
+                    let ordering = match self.direction {
+                        Direction::Forward => ordering,
+                        Direction::Backward => ordering.reverse(),
+                    };
+                    ordering.is_lt() || (ordering.is_eq() && seq > current_seq)
+                }
+            };
+            if replace {
+                self.current = Some(index);
+                current_seq = seq;
+            }
+        }
+        if self.status.is_err() {
+            self.current = None;
+        }
+    }
+
+    /// Return all the records of the current key, from the newest to the oldest
+    pub fn current_records(&self) -> Vec<&[u8]> {
+        let Some(current) = self.current else {
+            return Vec::new();
+        };
+        let key = self.children[current].key();
+        let mut records: Vec<&[u8]> = self
+            .children
+            .iter()
+            .filter(|x| x.is_valid() && self.comparator.compare(x.key(), key).is_eq())
+            .map(|x| x.value())
+            .collect();
+        records.sort_by_key(|x| std::cmp::Reverse(decode_value(x).map(|x| x.seq).unwrap_or(0)));
+        records
+    }
+
+    /// Move every child holding the current key one step in the current direction
+    fn step(&mut self) {
+        let Some(current) = self.current else {
+            return;
+        };
+        let key = self.children[current].key().to_vec();
+        let comparator = self.comparator.as_ref();
+        for child in self.children.iter_mut() {
+            if child.is_valid() && comparator.compare(child.key(), key.as_slice()).is_eq() {
+                match self.direction {
+                    Direction::Forward => child.next(),
+                    Direction::Backward => child.prev(),
+                }
+            }
+        }
+        self.find_current();
+    }
+}
+
+impl Iterator for MergingIterator {
+    type Item = Vec<SourceIterator>;
+
+    fn new(arg: Self::Item) -> Self {
+        Self::with_comparator(arg, bytewise_comparator())
+    }
+
+    fn seek_to_first(&mut self) {
+        self.restore_skipped();
+        self.direction = Direction::Forward;
+        self.children.iter_mut().for_each(|x| x.seek_to_first());
+        self.find_current();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.restore_skipped();
+        self.direction = Direction::Backward;
+        self.children.iter_mut().for_each(|x| x.seek_to_last());
+        self.find_current();
+    }
+
+    fn seek_to_key(&mut self, key: &[u8]) {
+        self.restore_skipped();
+        self.direction = Direction::Forward;
+        self.children.iter_mut().for_each(|x| x.seek_to_key(key));
+        self.find_current();
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn next(&mut self) {
+        let Some(current) = self.current else {
+            return;
+        };
+        if self.direction == Direction::Backward {
+            // position every child after the current key
+            let key = self.children[current].key().to_vec();
+            for child in self.children.iter_mut() {
+                child.seek_to_key(key.as_slice());
+            }
+            self.direction = Direction::Forward;
+            self.find_current();
+        }
+        self.step();
+    }
+
+    fn prev(&mut self) {
+        let Some(current) = self.current else {
+            return;
+        };
+        if self.direction == Direction::Forward {
+            // position every child before the current key, then the children holding the
+            // current key are stepped back along with the current one
+            let key = self.children[current].key().to_vec();
+            let comparator = self.comparator.as_ref();
+            for child in self.children.iter_mut() {
+                child.seek_to_key(key.as_slice());
+                if !child.is_valid() {
+                    child.seek_to_last();
+                } else if comparator.compare(child.key(), key.as_slice()).is_ne() {
+                    child.prev();
+                }
+            }
+            self.direction = Direction::Backward;
+            self.find_current();
+        }
+        self.step();
+    }
+
+    fn key(&self) -> &[u8] {
+        self.children[self.current.unwrap()].key()
+    }
+
+    /// Return the newest record of the current key, with the header
+    fn value(&self) -> &[u8] {
+        self.children[self.current.unwrap()].value()
+    }
+
+    fn status(&self) -> Result<()> {
+        self.status.clone()
+    }
+}
+
+/// Everything a `DBIterator` is made of
+pub struct DBIteratorParts {
+    pub inner: MergingIterator,
+    /// the range tombstones of all the sources of `inner`
+    pub range_tombstones: RangeTombstoneList,
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// the moment the iterator is created, see `Clock`
+    pub now: u64,
+    /// the blob files the records of the sources refer to
+    pub blob_files: Arc<BlobFileSet>,
+    pub read_options: Arc<ReadOptions>,
+    pub prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    /// the comparator the sources of `inner` are sorted by
+    pub comparator: Arc<dyn Comparator>,
+}
+
+/// `DBIterator` is the iterator of the user records of the database, the deleted, expired
+/// records and the records covered by range tombstones are skipped, the merge operands are
+/// applied and the values are returned without the header.
+///
+/// The iterator stays within the bounds of its `ReadOptions`, and within the prefix of the
+/// seek key in prefix mode.
+pub struct DBIterator {
+    inner: MergingIterator,
+    range_tombstones: RangeTombstoneList,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    now: u64,
+    blob_files: Arc<BlobFileSet>,
+    read_options: Arc<ReadOptions>,
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    comparator: Arc<dyn Comparator>,
+    /// the prefix the keys are confined to, set by `seek_to_key` in prefix mode
+    prefix: Option<Vec<u8>>,
+    /// the user value of the current record
+    value: Option<Bytes>,
+    status: Result<()>,
+}
+
+impl DBIterator {
+    pub(crate) fn merge_operator(&self) -> Option<Arc<dyn MergeOperator>> {
+        self.merge_operator.clone()
+    }
+
+    /// Return the comparator the keys are ordered by
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    /// Return the options the iterator reads with, it stays within their bounds
+    pub fn read_options(&self) -> &ReadOptions {
+        &self.read_options
+    }
+
+    /// Whether `key` has the prefix the keys are confined to since the last `seek_to_key`,
+    /// always if they are not, see `ReadOptions::prefix_same_as_start`. The keys out of the
+    /// domain of the extractor have no prefix.
+    pub(crate) fn is_in_prefix(&self, key: &[u8]) -> bool {
+        match (&self.prefix, self.prefix_extractor.as_deref()) {
+            (Some(prefix), Some(extractor)) => {
+                extractor.in_domain(key) && extractor.transform(key) == prefix.as_slice()
+            }
+            _ => true,
+        }
+    }
+
+    /// Resolve the user value of the current key of `inner`, `None` if it is invisible to
+    /// the user
+    fn resolve(&self) -> Result<Option<Bytes>> {
+        let key = self.inner.key();
+        let covering_seq = self.range_tombstones.max_covering_seq(key);
+        let mut context = MergeContext::new(self.now, self.blob_files.clone());
+        for record in self.inner.current_records() {
+            context.add(record, covering_seq)?;
+        }
+        context.value(key, self.merge_operator.as_deref())
+    }
+
+    /// Whether the current key of `inner` is beyond the bounds or the prefix in the
+    /// direction of the move
+    fn is_out_of_range(&self, forward: bool) -> bool {
+        let key = self.inner.key();
+        let comparator = self.comparator.as_ref();
+        let out_of_bound = if forward {
+            self.read_options.reaches_upper_bound(key, comparator)
+        } else {
+            self.read_options.before_lower_bound(key, comparator)
+        };
+        out_of_bound || !self.is_in_prefix(key)
+    }
+
+    fn skip_hidden(&mut self, forward: bool) {
+        self.value = None;
+        while self.inner.is_valid() && self.status.is_ok() {
+            if self.is_out_of_range(forward) {
+                return;
+            }
+            match self.resolve() {
+                Ok(Some(value)) => {
+                    self.value = Some(value);
+                    return;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    self.status = Err(e);
+                    return;
+                }
+            }
+            if forward {
+                self.inner.next();
+            } else {
+                self.inner.prev();
+            }
+        }
+    }
+}
+
+impl Iterator for DBIterator {
+    type Item = DBIteratorParts;
+
+    fn new(arg: Self::Item) -> Self {
+        DBIterator {
+            inner: arg.inner,
+            range_tombstones: arg.range_tombstones,
+            merge_operator: arg.merge_operator,
+            now: arg.now,
+            blob_files: arg.blob_files,
+            read_options: arg.read_options,
+            prefix_extractor: arg.prefix_extractor,
+            comparator: arg.comparator,
+            prefix: None,
+            value: None,
+            status: Ok(()),
+        }
+    }
+
+    fn seek_to_first(&mut self) {
+        self.prefix = None;
+        match self.read_options.iterate_lower_bound.clone() {
+            Some(lower_bound) => self.inner.seek_to_key(lower_bound.as_slice()),
+            None => self.inner.seek_to_first(),
+        }
+        self.skip_hidden(true);
+    }
+
+    fn seek_to_last(&mut self) {
+        self.prefix = None;
+        match self.read_options.iterate_upper_bound.clone() {
+            Some(upper_bound) => {
+                // the last key before the first one not less than the bound
+                self.inner.seek_to_key(upper_bound.as_slice());
+                if self.inner.is_valid() {
+                    self.inner.prev();
+                } else {
+                    self.inner.seek_to_last();
+                }
+            }
+            None => self.inner.seek_to_last(),
+        }
+        self.skip_hidden(false);
+    }
+
+    fn seek_to_key(&mut self, key: &[u8]) {
+        self.prefix = match &self.prefix_extractor {
+            Some(extractor)
+                if self.read_options.prefix_same_as_start && extractor.in_domain(key) =>
+            {
+                Some(extractor.transform(key).to_vec())
+            }
+            _ => None,
+        };
+        let target = match &self.read_options.iterate_lower_bound {
+            Some(lower_bound) if self.comparator.compare(key, lower_bound).is_lt() => {
+                lower_bound.as_slice()
+            }
+            _ => key,
+        };
+        match &self.prefix {
+            // the SSTables without the prefix are not read
+            Some(prefix) => self.inner.seek_to_key_in_prefix(target, prefix.as_slice()),
+            None => self.inner.seek_to_key(target),
+        }
+        self.skip_hidden(true);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.status.is_ok() && self.value.is_some()
+    }
+
+    fn next(&mut self) {
+        if !self.is_valid() {
+            return;
+        }
+        self.inner.next();
+        self.skip_hidden(true);
+    }
+
+    fn prev(&mut self) {
+        if !self.is_valid() {
+            return;
+        }
+        self.inner.prev();
+        self.skip_hidden(false);
+    }
+
+    fn key(&self) -> &[u8] {
+        self.inner.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.value.as_ref().unwrap().as_ref()
+    }
+
+    fn status(&self) -> Result<()> {
+        self.status.clone().and(self.inner.status())
+    }
+}
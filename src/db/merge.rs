@@ -0,0 +1,198 @@
+use crate::db::blob::BlobFileSet;
+use crate::db::format::{
+    decode_expiring_payload, decode_merge_payload, decode_value, encode_expiring_payload,
+    encode_merge_payload, encode_value, BlobIndex, MergeBase, SequenceNumber, ValueType,
+};
+use crate::merge_operator::MergeOperator;
+use crate::util::error::{Error, Result};
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// `MergeContext` collects the records of a key from the newest to the oldest, until the
+/// value the merge operands apply on is known. The values expired at `now` are taken as
+/// deleted. A value stored in a blob file is only read from `blob_files` once it is needed.
+pub struct MergeContext {
+    /// sequence number of the newest record
+    seq: SequenceNumber,
+    /// the merge operands, ordered from the oldest to the newest
+    operands: Vec<Bytes>,
+    base: MergeBase,
+    /// whether the newest record is covered by a range tombstone
+    covered: bool,
+    /// milliseconds since the unix epoch, see `Clock`
+    now: u64,
+    /// the blob files the `BlobIndex` records refer to
+    blob_files: Arc<BlobFileSet>,
+}
+
+impl MergeContext {
+    pub fn new(now: u64, blob_files: Arc<BlobFileSet>) -> Self {
+        MergeContext {
+            seq: 0,
+            operands: Vec::new(),
+            base: MergeBase::Unresolved,
+            covered: false,
+            now,
+            blob_files,
+        }
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.base != MergeBase::Unresolved
+    }
+
+    /// Add the next older `record` with the header, `covering_seq` is the greatest sequence
+    /// number of the range tombstones covering the key. Records after the base is resolved
+    /// are ignored.
+    pub fn add(&mut self, record: &[u8], covering_seq: SequenceNumber) -> Result<()> {
+        if self.is_resolved() {
+            return Ok(());
+        }
+        let decoded = decode_value(record)?;
+        if self.seq == 0 {
+            self.seq = decoded.seq;
+        }
+        if decoded.seq < covering_seq {
+            self.covered = self.operands.is_empty();
+            self.base = MergeBase::Deletion;
+            return Ok(());
+        }
+        match decoded.value_type {
+            ValueType::Deletion => self.base = MergeBase::Deletion,
+            ValueType::Value => {
+                self.base = MergeBase::Value(Bytes::copy_from_slice(decoded.payload));
+            }
+            ValueType::ExpiringValue => {
+                let (expire_at, value) = decode_expiring_payload(decoded.payload)?;
+                self.base = MergeBase::ExpiringValue(expire_at, Bytes::copy_from_slice(value));
+            }
+            ValueType::BlobIndex => {
+                self.base = MergeBase::BlobIndex(BlobIndex::decode(decoded.payload)?);
+            }
+            ValueType::Merge => {
+                let (base, mut operands) = decode_merge_payload(decoded.payload)?;
+                operands.append(&mut self.operands);
+                self.operands = operands;
+                self.base = base;
+            }
+        }
+        if let MergeBase::ExpiringValue(expire_at, _) = self.base {
+            if expire_at <= self.now {
+                self.base = MergeBase::Deletion;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the user visible value, the unresolved base is taken as absent
+    pub fn value(
+        &self,
+        key: &[u8],
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<Option<Bytes>> {
+        let base = self.read_blob(&self.base)?;
+        Self::full_merge(key, &base, self.operands.as_slice(), merge_operator)
+    }
+
+    /// Return `base`, a `BlobIndex` base is replaced by the value read from its blob file
+    fn read_blob(&self, base: &MergeBase) -> Result<MergeBase> {
+        match base {
+            MergeBase::BlobIndex(index) => Ok(MergeBase::Value(self.blob_files.get(index)?)),
+            base => Ok(base.clone()),
+        }
+    }
+
+    fn full_merge(
+        key: &[u8],
+        base: &MergeBase,
+        operands: &[Bytes],
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<Option<Bytes>> {
+        let existing = match base {
+            MergeBase::Value(value) | MergeBase::ExpiringValue(_, value) => Some(value.as_ref()),
+            _ => None,
+        };
+        if operands.is_empty() {
+            return Ok(existing.map(Bytes::copy_from_slice));
+        }
+        let merge_operator = merge_operator.ok_or_else(|| {
+            Error::InvalidArgument("merge operator is not configured".to_string())
+        })?;
+        let operands: Vec<&[u8]> = operands.iter().map(|x| x.as_ref()).collect();
+        let value = merge_operator.full_merge(key, existing, operands.as_slice())?;
+        Ok(Some(Bytes::from(value)))
+    }
+
+    /// Return the record replacing the collected ones in compaction, `None` if nothing needs
+    /// to be kept. `bottommost` tells that there is no older record of the key beyond the
+    /// collected ones.
+    ///
+    /// The operands are applied on the base if the base is resolved, otherwise they are
+    /// combined by `MergeOperator::partial_merge`. The operands are kept as they are if the
+    /// merge operator fails. The value produced by applying the operands on an expiring value
+    /// does not expire. A value stored in a blob file stays there unless the operands apply
+    /// on it.
+    ///
+    /// Failed if the value the operands apply on can not be read from its blob file
+    pub fn compacted_record(
+        &self,
+        key: &[u8],
+        merge_operator: Option<&dyn MergeOperator>,
+        bottommost: bool,
+    ) -> Result<Option<Bytes>> {
+        if self.covered {
+            return Ok(None);
+        }
+        if self.operands.is_empty() {
+            return Ok(match &self.base {
+                MergeBase::Value(value) => {
+                    Some(encode_value(ValueType::Value, self.seq, value.as_ref()))
+                }
+                MergeBase::ExpiringValue(expire_at, value) => {
+                    let payload = encode_expiring_payload(*expire_at, value.as_ref());
+                    Some(encode_value(
+                        ValueType::ExpiringValue,
+                        self.seq,
+                        payload.as_slice(),
+                    ))
+                }
+                MergeBase::BlobIndex(index) => Some(encode_value(
+                    ValueType::BlobIndex,
+                    self.seq,
+                    index.encode().as_slice(),
+                )),
+                _ if bottommost => None,
+                _ => Some(encode_value(ValueType::Deletion, self.seq, b"")),
+            });
+        }
+        let base = match &self.base {
+            MergeBase::Unresolved if bottommost => MergeBase::Deletion,
+            base => base.clone(),
+        };
+        if let Some(merge_operator) = merge_operator {
+            if base != MergeBase::Unresolved {
+                let operands = self.operands.as_slice();
+                let existing = self.read_blob(&base)?;
+                if let Ok(Some(value)) =
+                    Self::full_merge(key, &existing, operands, Some(merge_operator))
+                {
+                    let record = encode_value(ValueType::Value, self.seq, value.as_ref());
+                    return Ok(Some(record));
+                }
+            } else if self.operands.len() > 1 {
+                let operands: Vec<&[u8]> = self.operands.iter().map(|x| x.as_ref()).collect();
+                if let Some(operand) = merge_operator.partial_merge(key, operands.as_slice()) {
+                    let payload = encode_merge_payload(&base, &[Bytes::from(operand)]);
+                    let record = encode_value(ValueType::Merge, self.seq, payload.as_slice());
+                    return Ok(Some(record));
+                }
+            }
+        }
+        let payload = encode_merge_payload(&base, self.operands.as_slice());
+        Ok(Some(encode_value(
+            ValueType::Merge,
+            self.seq,
+            payload.as_slice(),
+        )))
+    }
+}
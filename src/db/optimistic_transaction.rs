@@ -0,0 +1,91 @@
+use crate::db::column_family::ColumnFamilyHandle;
+use crate::db::format::SequenceNumber;
+use crate::db::write_batch::WriteBatch;
+use crate::db::DBImpl;
+use crate::util::error::{Error, Result};
+use bytes::Bytes;
+use std::collections::HashSet;
+
+/// `OptimisticTransaction` buffers its writes and reads the database at its snapshot, the
+/// sequence number of the latest write when the transaction begins. No lock is taken before
+/// commit, at commit the keys read are checked, the transaction fails with `Busy` if any of
+/// them is updated after the snapshot, otherwise the writes are applied atomically as a
+/// `WriteBatch`. The caller retries the transaction on `Busy`.
+///
+/// A read fails with `Busy` as well once the key is found updated after the snapshot, the
+/// database does not keep the value at the snapshot then, and the commit would fail anyway.
+/// So all the reads of the transaction see the database at the same moment.
+///
+/// The reads do not see the writes buffered by the transaction itself. Dropping the
+/// transaction without committing discards the writes.
+pub struct OptimisticTransaction<'a> {
+    db: &'a DBImpl,
+    batch: WriteBatch,
+    snapshot: SequenceNumber,
+    /// the keys read, keyed by the column family id and the key
+    read_set: HashSet<(u32, Bytes)>,
+}
+
+impl<'a> OptimisticTransaction<'a> {
+    pub(crate) fn new(db: &'a DBImpl, snapshot: SequenceNumber) -> Self {
+        OptimisticTransaction {
+            db,
+            batch: WriteBatch::new(),
+            snapshot,
+            read_set: HashSet::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_cf(&ColumnFamilyHandle::default(), key)
+    }
+
+    /// Read `key` from the database at the snapshot and track it for the conflict check at
+    /// commit. Failed with `Busy` if the key is updated after the snapshot
+    pub fn get_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8]) -> Result<Option<Bytes>> {
+        let (value, sequence) = self.db.get_with_sequence(cf, key)?;
+        if sequence > self.snapshot {
+            return Err(Error::Busy(format!(
+                "key {:?} is updated since the transaction began",
+                key
+            )));
+        }
+        self.read_set.insert((cf.id(), Bytes::copy_from_slice(key)));
+        Ok(value)
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.batch.put(key, value);
+    }
+
+    pub fn put_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8], value: &[u8]) {
+        self.batch.put_cf(cf, key, value);
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.batch.delete(key);
+    }
+
+    pub fn delete_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8]) {
+        self.batch.delete_cf(cf, key);
+    }
+
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) {
+        self.batch.merge(key, operand);
+    }
+
+    pub fn merge_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8], operand: &[u8]) {
+        self.batch.merge_cf(cf, key, operand);
+    }
+
+    /// Apply the writes of the transaction if none of the keys read is updated after the
+    /// snapshot. Failed with `Busy` on conflict, nothing is written then.
+    pub fn commit(self) -> Result<()> {
+        let read_set: Vec<(u32, Bytes)> = self.read_set.into_iter().collect();
+        self.db
+            .write_unless_changed(self.batch, read_set.as_slice(), self.snapshot)
+    }
+
+    /// Discard the writes of the transaction
+    pub fn rollback(self) {}
+}
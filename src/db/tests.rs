@@ -0,0 +1,2349 @@
+use crate::compaction_filter::{
+    CompactionFilter, CompactionFilterContext, CompactionFilterFactory, Decision,
+};
+use crate::comparator::ReverseBytewiseComparator;
+use crate::db::column_family::{ColumnFamilyDescriptor, ColumnFamilyHandle};
+use crate::db::format::{decode_merge_payload, decode_value, ValueType};
+use crate::db::iterator::DBIterator;
+use crate::db::version::{BlobFileMetaData, VersionEdit, NUM_LEVELS};
+use crate::db::write_batch::WriteBatch;
+use crate::db::write_batch_with_index::WriteBatchWithIndex;
+use crate::db::{DBImpl, DB};
+use crate::iterator::Iterator;
+use crate::merge_operator::{BytesAppendOperator, UInt64AddOperator};
+use crate::options::{test_options, CompactionStyle, MemTableFactory, Options, ReadOptions};
+use crate::slice_transform::{FixedPrefixTransform, SliceTransform};
+use crate::sstable::sst_file_writer::SstFileWriter;
+use crate::util::clock::ManualClock;
+use crate::util::env::{
+    parse_file_name, test_env, Env, FileLock, RandomAccessFile, SequentialFile, WritableFile,
+};
+use crate::util::error::{Error, Result};
+use bytes::Bytes;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// `TestDB` opens a database in its own directory and removes the directory on drop
+struct TestDB {
+    path: String,
+    options: Options,
+    column_families: Vec<ColumnFamilyDescriptor>,
+    db: Option<DBImpl>,
+}
+
+impl TestDB {
+    fn new(name: &str, options: Options) -> Self {
+        let path = format!("/summer_kv_test/db_{}", name);
+        let _ = test_env().remove_dir_all(path.as_str());
+        let db = DBImpl::open(path.as_str(), options.clone()).unwrap();
+        TestDB {
+            path,
+            options,
+            column_families: Vec::new(),
+            db: Some(db),
+        }
+    }
+
+    fn db(&self) -> &DBImpl {
+        self.db.as_ref().unwrap()
+    }
+
+    fn reopen(&mut self) {
+        self.db.take().unwrap().close().unwrap();
+        self.db = Some(self.open().unwrap());
+    }
+
+    fn open(&self) -> Result<DBImpl> {
+        DBImpl::open_with_column_families(
+            self.path.as_str(),
+            self.options.clone(),
+            self.column_families.clone(),
+        )
+    }
+
+    /// Collect all the records by a forward scan
+    fn scan(&self) -> Vec<(Bytes, Bytes)> {
+        let mut iter = self.db().iter().unwrap();
+        let mut records = Vec::new();
+        iter.seek_to_first();
+        while iter.is_valid() {
+            records.push((
+                Bytes::copy_from_slice(iter.key()),
+                Bytes::copy_from_slice(iter.value()),
+            ));
+            iter.next();
+        }
+        iter.status().unwrap();
+        records
+    }
+}
+
+impl Drop for TestDB {
+    fn drop(&mut self) {
+        self.db.take();
+        let _ = test_env().remove_dir_all(self.path.as_str());
+    }
+}
+
+/// Options making the memtable flushed and the levels compacted frequently
+fn small_options() -> Options {
+    Options {
+        write_buffer_size: 1024,
+        level0_file_num_compaction_trigger: 2,
+        max_bytes_for_level_base: 8 * 1024,
+        target_file_size: 2 * 1024,
+        ..test_options()
+    }
+}
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn value_of(i: usize) -> Vec<u8> {
+    format!("value_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_put_get_delete() {
+    let test_db = TestDB::new("put_get_delete", test_options());
+    let db = test_db.db();
+    db.put(b"key1", b"value1").unwrap();
+    db.put(b"key2", b"value2").unwrap();
+    db.delete(b"key1").unwrap();
+    assert_eq!(db.get(b"key1").unwrap(), None);
+    assert_eq!(db.get(b"key2").unwrap(), Some("value2".into()));
+    assert_eq!(db.get(b"key3").unwrap(), None);
+}
+
+#[test]
+fn test_delete_range() {
+    let test_db = TestDB::new("delete_range", test_options());
+    let db = test_db.db();
+    for i in 0..10 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    db.delete_range(key_of(3).as_slice(), key_of(7).as_slice())
+        .unwrap();
+    // written after the range tombstone
+    db.put(key_of(5).as_slice(), b"new").unwrap();
+    for i in 0..10 {
+        let expected = match i {
+            5 => Some(Bytes::from("new")),
+            3..=6 => None,
+            _ => Some(Bytes::from(value_of(i))),
+        };
+        assert_eq!(db.get(key_of(i).as_slice()).unwrap(), expected);
+    }
+    let keys: Vec<Bytes> = test_db.scan().into_iter().map(|x| x.0).collect();
+    let expected: Vec<Bytes> = [0, 1, 2, 5, 7, 8, 9]
+        .iter()
+        .map(|x| Bytes::from(key_of(*x)))
+        .collect();
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn test_delete_range_invalid_argument() {
+    let test_db = TestDB::new("delete_range_invalid_argument", test_options());
+    let db = test_db.db();
+    db.put(b"b", b"b").unwrap();
+    assert!(matches!(
+        db.delete_range(b"c", b"a"),
+        Err(Error::InvalidArgument(_))
+    ));
+    db.delete_range(b"b", b"b").unwrap();
+    assert_eq!(db.get(b"b").unwrap(), Some("b".into()));
+}
+
+#[test]
+fn test_delete_range_across_levels() {
+    let test_db = TestDB::new("delete_range_across_levels", small_options());
+    let db = test_db.db();
+    for i in 0..500 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    db.delete_range(key_of(100).as_slice(), key_of(400).as_slice())
+        .unwrap();
+    for i in 450..500 {
+        db.put(key_of(i).as_slice(), b"overwritten").unwrap();
+    }
+    for i in 0..500 {
+        let expected = match i {
+            100..=399 => None,
+            450..=499 => Some(Bytes::from("overwritten")),
+            _ => Some(Bytes::from(value_of(i))),
+        };
+        assert_eq!(db.get(key_of(i).as_slice()).unwrap(), expected);
+    }
+    assert_eq!(test_db.scan().len(), 200);
+}
+
+#[test]
+fn test_recover() {
+    let mut test_db = TestDB::new("recover", small_options());
+    for i in 0..300 {
+        test_db
+            .db()
+            .put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    test_db
+        .db()
+        .delete_range(key_of(10).as_slice(), key_of(20).as_slice())
+        .unwrap();
+    test_db.db().delete(key_of(299).as_slice()).unwrap();
+    test_db.reopen();
+    // the records are still there, part of them are replayed from the log
+    for i in 0..300 {
+        let expected = match i {
+            10..=19 | 299 => None,
+            _ => Some(Bytes::from(value_of(i))),
+        };
+        assert_eq!(test_db.db().get(key_of(i).as_slice()).unwrap(), expected);
+    }
+    // the sequence numbers keep increasing after recovery
+    test_db.db().put(key_of(15).as_slice(), b"again").unwrap();
+    test_db.reopen();
+    assert_eq!(
+        test_db.db().get(key_of(15).as_slice()).unwrap(),
+        Some("again".into())
+    );
+}
+
+#[test]
+fn test_compaction_drops_covered_records() {
+    let test_db = TestDB::new("compaction_drops_covered_records", small_options());
+    let db = test_db.db();
+    for i in 0..1000 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    db.delete_range(key_of(0).as_slice(), key_of(1000).as_slice())
+        .unwrap();
+    for i in 0..1000 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    db.delete_range(key_of(0).as_slice(), key_of(1000).as_slice())
+        .unwrap();
+    // push everything down by writing to other keys
+    for i in 1000..3000 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    assert_eq!(db.get(key_of(10).as_slice()).unwrap(), None);
+
+    let state = db.state.read().unwrap();
+    let version = state.versions.default_column_family().current();
+    let covered_tables = version
+        .levels
+        .iter()
+        .flatten()
+        .filter(|x| x.smallest.as_ref() < key_of(1000).as_slice())
+        .count();
+    // the covered records are dropped along with the tombstone at the bottom level
+    assert_eq!(covered_tables, 0);
+    assert!(version.levels[1..].iter().any(|x| !x.is_empty()));
+}
+
+#[test]
+fn test_iterator_direction() {
+    let test_db = TestDB::new("iterator_direction", small_options());
+    let db = test_db.db();
+    for i in (0..200).step_by(2) {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    db.delete_range(key_of(50).as_slice(), key_of(60).as_slice())
+        .unwrap();
+    for i in (1..200).step_by(2) {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    db.delete(key_of(101).as_slice()).unwrap();
+    let visible: Vec<usize> = (0..200)
+        .filter(|x| !(50..60).contains(x) || x % 2 == 1)
+        .filter(|x| *x != 101)
+        .collect();
+
+    let mut iter = db.iter().unwrap();
+    iter.seek_to_last();
+    for i in visible.iter().rev() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(*i).as_slice());
+        iter.prev();
+    }
+    assert!(!iter.is_valid());
+
+    iter.seek_to_key(key_of(50).as_slice());
+    assert_eq!(iter.key(), key_of(51).as_slice());
+    iter.prev();
+    assert_eq!(iter.key(), key_of(49).as_slice());
+    iter.next();
+    assert_eq!(iter.key(), key_of(51).as_slice());
+    iter.next();
+    assert_eq!(iter.key(), key_of(53).as_slice());
+    assert_eq!(iter.value(), value_of(53).as_slice());
+}
+
+#[test]
+fn test_iterator_snapshot() {
+    let test_db = TestDB::new("iterator_snapshot", test_options());
+    let db = test_db.db();
+    db.put(b"a", b"1").unwrap();
+    let mut iter = db.iter().unwrap();
+    db.put(b"b", b"2").unwrap();
+    iter.seek_to_first();
+    assert_eq!(iter.key(), b"a".as_slice());
+    iter.next();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_memtable_factories() {
+    let mut scans = Vec::new();
+    for (name, factory) in [
+        ("memtable_skiplist", MemTableFactory::SkipList),
+        ("memtable_btree", MemTableFactory::BTree),
+        ("memtable_vector", MemTableFactory::Vector),
+        (
+            "memtable_hash_linklist",
+            MemTableFactory::HashLinkList { bucket_count: 16 },
+        ),
+    ] {
+        let options = Options {
+            memtable_factory: factory,
+            prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(7))),
+            ..counter_options()
+        };
+        let mut test_db = TestDB::new(name, options);
+        let db = test_db.db();
+        let mut iter = db.iter().unwrap();
+        for i in 0..300 {
+            db.put(key_of(i).as_slice(), value_of(i).as_slice())
+                .unwrap();
+            db.put(key_of(i / 2).as_slice(), &(i as u64).to_le_bytes())
+                .unwrap();
+            db.merge(key_of(i / 3).as_slice(), &1u64.to_le_bytes())
+                .unwrap();
+        }
+        db.delete_range(key_of(10).as_slice(), key_of(20).as_slice())
+            .unwrap();
+        db.delete(key_of(250).as_slice()).unwrap();
+        iter.seek_to_first();
+        assert!(!iter.is_valid());
+        let records = test_db.scan();
+        test_db.reopen();
+        assert_eq!(test_db.scan(), records);
+        scans.push(records);
+    }
+    assert!(scans.iter().all(|x| *x == scans[0]));
+}
+
+/// `FailingEnv` is the test file system, except that creating a SSTable fails while
+/// `fail_sstables` is set
+struct FailingEnv {
+    fail_sstables: AtomicBool,
+}
+
+impl Env for FailingEnv {
+    fn new_sequential_file(&self, path: &str) -> Result<Box<dyn SequentialFile>> {
+        test_env().new_sequential_file(path)
+    }
+
+    fn new_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>> {
+        test_env().new_random_access_file(path)
+    }
+
+    fn new_writable_file(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        if self.fail_sstables.load(Ordering::SeqCst) && path.ends_with(".sst.tmp") {
+            return Err(std::io::Error::other(path.to_string()).into());
+        }
+        test_env().new_writable_file(path)
+    }
+
+    fn new_appendable_file(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        test_env().new_appendable_file(path)
+    }
+
+    fn file_exists(&self, path: &str) -> bool {
+        test_env().file_exists(path)
+    }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> {
+        test_env().get_children(dir)
+    }
+
+    fn create_dir_all(&self, dir: &str) -> Result<()> {
+        test_env().create_dir_all(dir)
+    }
+
+    fn remove_file(&self, path: &str) -> Result<()> {
+        test_env().remove_file(path)
+    }
+
+    fn remove_dir_all(&self, dir: &str) -> Result<()> {
+        test_env().remove_dir_all(dir)
+    }
+
+    fn rename_file(&self, from: &str, to: &str) -> Result<()> {
+        test_env().rename_file(from, to)
+    }
+
+    fn link_file(&self, from: &str, to: &str) -> Result<()> {
+        test_env().link_file(from, to)
+    }
+
+    fn sync_dir(&self, dir: &str) -> Result<()> {
+        test_env().sync_dir(dir)
+    }
+
+    fn lock_file(&self, path: &str) -> Result<Box<dyn FileLock>> {
+        test_env().lock_file(path)
+    }
+}
+
+#[test]
+fn test_failed_flush() {
+    let env = Arc::new(FailingEnv {
+        fail_sstables: AtomicBool::new(false),
+    });
+    let options = Options {
+        memtable_factory: MemTableFactory::Vector,
+        env: env.clone(),
+        ..small_options()
+    };
+    let mut test_db = TestDB::new("failed_flush", options);
+    let db = test_db.db();
+    env.fail_sstables.store(true, Ordering::SeqCst);
+    let mut failed = 0;
+    for i in 0..100 {
+        if db
+            .put(key_of(i).as_slice(), value_of(i).as_slice())
+            .is_err()
+        {
+            failed += 1;
+        }
+    }
+    assert!(failed > 0);
+    assert_eq!(
+        db.get(key_of(0).as_slice()).unwrap(),
+        Some(value_of(0).into())
+    );
+    // the memtable failed to flush still takes the writes, and is flushed at last
+    env.fail_sstables.store(false, Ordering::SeqCst);
+    for i in 100..200 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    let expected: Vec<(Bytes, Bytes)> = (0..200)
+        .map(|i| (key_of(i).into(), value_of(i).into()))
+        .collect();
+    assert_eq!(test_db.scan(), expected);
+    test_db.reopen();
+    assert_eq!(test_db.scan(), expected);
+}
+
+#[test]
+fn test_closed_db() {
+    let test_db = TestDB::new("closed_db", test_options());
+    let db = test_db.db();
+    db.close().unwrap();
+    assert!(matches!(db.put(b"a", b"1"), Err(Error::ShutdownInProgress)));
+    assert!(matches!(db.get(b"a"), Err(Error::ShutdownInProgress)));
+}
+
+#[test]
+fn test_version_edit_encode_and_decode() {
+    let test_db = TestDB::new("version_edit", small_options());
+    for i in 0..300 {
+        test_db
+            .db()
+            .put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    let mut edit = VersionEdit::new();
+    edit.log_number = Some(3);
+    edit.last_sequence = Some(300);
+    edit.delete_file(1, 4);
+    let state = test_db.db().state.read().unwrap();
+    for (level, files) in state
+        .versions
+        .default_column_family()
+        .current()
+        .levels
+        .iter()
+        .enumerate()
+    {
+        for file in files {
+            edit.add_file(level, file.as_ref().clone());
+        }
+    }
+    edit.new_files[0].1.blob_files = vec![7, 9];
+    edit.new_files[1].1.global_seq = Some(42);
+    edit.add_blob_file(BlobFileMetaData {
+        number: 9,
+        file_size: 4096,
+    });
+    edit.delete_blob_file(5);
+    let decoded = VersionEdit::decode(edit.encode().as_slice()).unwrap();
+    assert_eq!(decoded, edit);
+    let mut edit = VersionEdit::new();
+    edit.column_family = 2;
+    edit.column_family_add = Some("index".to_string());
+    edit.comparator = Some("summer_kv.ReverseBytewiseComparator".to_string());
+    edit.max_column_family = Some(2);
+    assert_eq!(VersionEdit::decode(edit.encode().as_slice()).unwrap(), edit);
+    assert!(VersionEdit::decode(&[99, 9]).is_err());
+}
+
+fn counter_options() -> Options {
+    Options {
+        merge_operator: Some(Arc::new(UInt64AddOperator {})),
+        ..small_options()
+    }
+}
+
+fn counter_of(db: &DBImpl, key: &[u8]) -> Option<u64> {
+    db.get(key)
+        .unwrap()
+        .map(|x| u64::from_le_bytes(x.as_ref().try_into().unwrap()))
+}
+
+#[test]
+fn test_concurrent_writes() {
+    let mut test_db = TestDB::new("concurrent_writes", counter_options());
+    let db = test_db.db();
+    std::thread::scope(|scope| {
+        for t in 0..4 {
+            scope.spawn(move || {
+                for i in 0..200 {
+                    let key = key_of(t * 1000 + i);
+                    let mut batch = WriteBatch::new();
+                    batch.put(key.as_slice(), value_of(i).as_slice());
+                    batch.put([b"mirror/", key.as_slice()].concat().as_slice(), b"");
+                    batch.merge(b"counter", &1u64.to_le_bytes());
+                    db.write(batch).unwrap();
+                }
+            });
+        }
+        // the readers see every batch as a whole, the memtables are flushed meanwhile
+        scope.spawn(|| {
+            for _ in 0..50 {
+                let mut iter = db.iter().unwrap();
+                let mut counter = 0;
+                let mut keys = 0;
+                let mut mirrors = 0;
+                iter.seek_to_first();
+                while iter.is_valid() {
+                    match iter.key() {
+                        b"counter" => {
+                            counter = u64::from_le_bytes(iter.value().try_into().unwrap())
+                        }
+                        key if key.starts_with(b"mirror/") => mirrors += 1,
+                        _ => keys += 1,
+                    }
+                    iter.next();
+                }
+                assert_eq!(keys, mirrors);
+                assert_eq!(keys, counter);
+            }
+        });
+    });
+    assert_eq!(counter_of(db, b"counter"), Some(800));
+    let check = |db: &DBImpl| {
+        for t in 0..4 {
+            for i in 0..200 {
+                assert_eq!(
+                    db.get(key_of(t * 1000 + i).as_slice()).unwrap(),
+                    Some(value_of(i).into())
+                );
+            }
+        }
+    };
+    check(db);
+    test_db.reopen();
+    check(test_db.db());
+    assert_eq!(counter_of(test_db.db(), b"counter"), Some(800));
+}
+
+#[test]
+fn test_merge_without_operator() {
+    let test_db = TestDB::new("merge_without_operator", test_options());
+    let ret = test_db.db().merge(b"a", b"1");
+    assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+}
+
+#[test]
+fn test_merge_counter() {
+    let mut test_db = TestDB::new("merge_counter", counter_options());
+    let one = 1u64.to_le_bytes();
+    // the counters are spread over the memtable and the levels
+    for round in 0..20 {
+        for i in 0..50 {
+            test_db.db().merge(key_of(i).as_slice(), &one).unwrap();
+        }
+    }
+    test_db
+        .db()
+        .put(key_of(0).as_slice(), &100u64.to_le_bytes())
+        .unwrap();
+    test_db.db().merge(key_of(0).as_slice(), &one).unwrap();
+    test_db.db().delete(key_of(1).as_slice()).unwrap();
+    test_db.db().merge(key_of(1).as_slice(), &one).unwrap();
+    test_db
+        .db()
+        .delete_range(key_of(2).as_slice(), key_of(3).as_slice())
+        .unwrap();
+    test_db.db().merge(key_of(2).as_slice(), &one).unwrap();
+
+    let check = |test_db: &TestDB| {
+        assert_eq!(counter_of(test_db.db(), key_of(0).as_slice()), Some(101));
+        assert_eq!(counter_of(test_db.db(), key_of(1).as_slice()), Some(1));
+        assert_eq!(counter_of(test_db.db(), key_of(2).as_slice()), Some(1));
+        for i in 3..50 {
+            assert_eq!(counter_of(test_db.db(), key_of(i).as_slice()), Some(20));
+        }
+        let records = test_db.scan();
+        assert_eq!(records.len(), 50);
+        assert_eq!(records[3].1.as_ref(), 20u64.to_le_bytes());
+    };
+    check(&test_db);
+    test_db.reopen();
+    check(&test_db);
+}
+
+#[test]
+fn test_merge_beyond_block_size() {
+    let options = Options {
+        merge_operator: Some(Arc::new(BytesAppendOperator::new(b""))),
+        write_buffer_size: 64 * 1024,
+        ..test_options()
+    };
+    let mut test_db = TestDB::new("merge_beyond_block_size", options);
+    let operand = vec![b'x'; 1024];
+    // the operands reach the size of the memtable and flush it
+    for _ in 0..100 {
+        test_db.db().merge(b"list", operand.as_slice()).unwrap();
+    }
+    test_db.db().put(b"other", b"value").unwrap();
+    assert_eq!(
+        test_db.db().get(b"list").unwrap().unwrap().len(),
+        100 * 1024
+    );
+    test_db.reopen();
+    assert_eq!(
+        test_db.db().get(b"list").unwrap().unwrap().len(),
+        100 * 1024
+    );
+    assert_eq!(test_db.db().get(b"other").unwrap(), Some("value".into()));
+}
+
+#[test]
+fn test_merge_folded_in_compaction() {
+    let test_db = TestDB::new("merge_folded_in_compaction", counter_options());
+    let db = test_db.db();
+    let one = 1u64.to_le_bytes();
+    for i in 0..2000 {
+        db.merge(b"counter", &one).unwrap();
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    assert_eq!(counter_of(db, b"counter"), Some(2000));
+
+    // every SSTable holds a single folded record of the counter
+    let state = db.state.read().unwrap();
+    for file in state
+        .versions
+        .default_column_family()
+        .current()
+        .levels
+        .iter()
+        .flatten()
+    {
+        let table = state.versions.default_column_family().table(file.number);
+        if let Some(record) = table.get(b"counter").unwrap() {
+            let decoded = decode_value(record.as_ref()).unwrap();
+            if decoded.value_type == ValueType::Merge {
+                let (_, operands) = decode_merge_payload(decoded.payload).unwrap();
+                assert_eq!(operands.len(), 1);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_merge_bytes_append() {
+    let options = Options {
+        merge_operator: Some(Arc::new(BytesAppendOperator::new(b","))),
+        ..test_options()
+    };
+    let test_db = TestDB::new("merge_bytes_append", options);
+    let db = test_db.db();
+    db.merge(b"list", b"a").unwrap();
+    db.merge(b"list", b"b").unwrap();
+    assert_eq!(db.get(b"list").unwrap(), Some("a,b".into()));
+    db.put(b"list", b"x").unwrap();
+    db.merge(b"list", b"c").unwrap();
+    assert_eq!(db.get(b"list").unwrap(), Some("x,c".into()));
+}
+
+/// Removes the sessions and strips the payload of the soft deleted rows
+struct TestFilter {
+    level: usize,
+}
+
+impl CompactionFilter for TestFilter {
+    fn name(&self) -> &str {
+        "TestFilter"
+    }
+
+    fn filter(&mut self, level: usize, key: &[u8], value: &[u8]) -> Decision {
+        assert_eq!(level, self.level);
+        if key.starts_with(b"session/") {
+            Decision::Remove
+        } else if value.starts_with(b"soft_deleted") {
+            Decision::ChangeValue(b"tombstone".to_vec())
+        } else {
+            Decision::Keep
+        }
+    }
+}
+
+#[derive(Default)]
+struct TestFilterFactory {
+    levels: Mutex<Vec<usize>>,
+}
+
+impl CompactionFilterFactory for TestFilterFactory {
+    fn name(&self) -> &str {
+        "TestFilterFactory"
+    }
+
+    fn create_compaction_filter(
+        &self,
+        context: &CompactionFilterContext,
+    ) -> Box<dyn CompactionFilter> {
+        self.levels.lock().unwrap().push(context.level);
+        Box::new(TestFilter {
+            level: context.level,
+        })
+    }
+}
+
+#[test]
+fn test_compaction_filter() {
+    let factory = Arc::new(TestFilterFactory::default());
+    let options = Options {
+        compaction_filter_factory: Some(factory.clone()),
+        ..small_options()
+    };
+    let test_db = TestDB::new("compaction_filter", options);
+    let db = test_db.db();
+    for i in 0..50 {
+        db.put(format!("session/{}", i).as_bytes(), b"alive")
+            .unwrap();
+        db.put(format!("row/{}", i).as_bytes(), b"soft_deleted")
+            .unwrap();
+        if i == 0 {
+            // the filter does not apply before compaction
+            assert_eq!(db.get(b"session/0").unwrap(), Some("alive".into()));
+        }
+    }
+    for i in 0..2000 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    assert!(!factory.levels.lock().unwrap().is_empty());
+    for i in 0..50 {
+        assert_eq!(db.get(format!("session/{}", i).as_bytes()).unwrap(), None);
+        assert_eq!(
+            db.get(format!("row/{}", i).as_bytes()).unwrap(),
+            Some("tombstone".into())
+        );
+    }
+    assert_eq!(
+        db.get(key_of(1999).as_slice()).unwrap(),
+        Some(value_of(1999).into())
+    );
+}
+
+fn clock_options(clock: Arc<ManualClock>) -> Options {
+    Options {
+        clock,
+        merge_operator: Some(Arc::new(BytesAppendOperator::new(b","))),
+        ..small_options()
+    }
+}
+
+#[test]
+fn test_put_with_ttl() {
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let mut test_db = TestDB::new("put_with_ttl", clock_options(clock.clone()));
+    let db = test_db.db();
+    db.put_with_ttl(b"session", b"alive", Duration::from_secs(10))
+        .unwrap();
+    db.put_with_ttl(b"long", b"alive", Duration::from_secs(100))
+        .unwrap();
+    db.put(b"plain", b"alive").unwrap();
+    db.put_with_ttl(b"list", b"a", Duration::from_secs(10))
+        .unwrap();
+    db.merge(b"list", b"b").unwrap();
+    assert_eq!(db.get(b"session").unwrap(), Some("alive".into()));
+    assert_eq!(db.get(b"list").unwrap(), Some("a,b".into()));
+
+    clock.advance(Duration::from_secs(10));
+    // the records expire after reopening as well, they are replayed from the log
+    test_db.reopen();
+    let db = test_db.db();
+    assert_eq!(db.get(b"session").unwrap(), None);
+    assert_eq!(db.get(b"long").unwrap(), Some("alive".into()));
+    assert_eq!(db.get(b"list").unwrap(), Some("b".into()));
+    let keys: Vec<Bytes> = test_db.scan().into_iter().map(|x| x.0).collect();
+    assert_eq!(
+        keys,
+        vec![Bytes::from("list"), "long".into(), "plain".into()]
+    );
+}
+
+#[test]
+fn test_expired_records_removed_by_compaction() {
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let test_db = TestDB::new("expired_records_removed", clock_options(clock.clone()));
+    let db = test_db.db();
+    for i in 0..100 {
+        db.put_with_ttl(
+            key_of(i).as_slice(),
+            value_of(i).as_slice(),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+    }
+    clock.advance(Duration::from_secs(1));
+    for i in 100..2000 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    assert_eq!(db.get(key_of(0).as_slice()).unwrap(), None);
+    let state = db.state.read().unwrap();
+    for file in state
+        .versions
+        .default_column_family()
+        .current()
+        .levels
+        .iter()
+        .flatten()
+    {
+        assert!(file.smallest.as_ref() >= key_of(100).as_slice());
+    }
+}
+
+fn scan_cf(db: &DBImpl, cf: &ColumnFamilyHandle) -> Vec<(Bytes, Bytes)> {
+    let mut iter = db.iter_cf(cf).unwrap();
+    let mut records = Vec::new();
+    iter.seek_to_first();
+    while iter.is_valid() {
+        records.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next();
+    }
+    records
+}
+
+#[test]
+fn test_column_families() {
+    let mut test_db = TestDB::new("column_families", small_options());
+    let db = test_db.db();
+    let meta = db.create_column_family("meta", test_options()).unwrap();
+    let content_options = Options {
+        block_size: 512,
+        ..small_options()
+    };
+    let content = db
+        .create_column_family("content", content_options.clone())
+        .unwrap();
+    assert!(matches!(
+        db.create_column_family("meta", test_options()),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert_eq!(db.column_family("meta"), Some(meta.clone()));
+
+    // the same key lives in every column family independently
+    db.put(b"key", b"default").unwrap();
+    db.put_cf(&meta, b"key", b"meta").unwrap();
+    for i in 0..500 {
+        db.put_cf(&content, key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    db.delete_cf(&content, key_of(0).as_slice()).unwrap();
+    assert_eq!(db.get(b"key").unwrap(), Some("default".into()));
+    assert_eq!(db.get_cf(&meta, b"key").unwrap(), Some("meta".into()));
+    assert_eq!(db.get_cf(&content, b"key").unwrap(), None);
+    assert_eq!(scan_cf(db, &meta).len(), 1);
+    assert_eq!(scan_cf(db, &content).len(), 499);
+    {
+        let state = db.state.read().unwrap();
+        let cfd = state.versions.column_family(content.id()).unwrap();
+        assert!(cfd.current().levels.iter().any(|x| !x.is_empty()));
+        assert!(state
+            .versions
+            .default_column_family()
+            .current()
+            .levels
+            .iter()
+            .all(|x| x.is_empty()));
+    }
+
+    // every column family must be opened
+    test_db.db.take();
+    assert!(matches!(test_db.open(), Err(Error::InvalidArgument(_))));
+    let mut names = DBImpl::list_column_families(test_db.path.as_str(), &test_db.options).unwrap();
+    names.sort();
+    assert_eq!(names, vec!["content", "default", "meta"]);
+    test_db.column_families = vec![
+        ColumnFamilyDescriptor::new("meta", test_options()),
+        ColumnFamilyDescriptor::new("content", content_options),
+    ];
+    test_db.db = Some(test_db.open().unwrap());
+    let db = test_db.db();
+    assert_eq!(db.column_family("content"), Some(content.clone()));
+    assert_eq!(db.get(b"key").unwrap(), Some("default".into()));
+    assert_eq!(db.get_cf(&meta, b"key").unwrap(), Some("meta".into()));
+    assert_eq!(scan_cf(db, &content).len(), 499);
+
+    // the dropped column family is gone after reopening as well
+    db.drop_column_family(&meta).unwrap();
+    assert!(matches!(
+        db.get_cf(&meta, b"key"),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        db.drop_column_family(&ColumnFamilyHandle::default()),
+        Err(Error::InvalidArgument(_))
+    ));
+    test_db.column_families.remove(0);
+    test_db.reopen();
+    let db = test_db.db();
+    assert_eq!(db.column_family("meta"), None);
+    let meta_again = db.create_column_family("meta", test_options()).unwrap();
+    assert_ne!(meta_again.id(), meta.id());
+    assert_eq!(db.get_cf(&meta_again, b"key").unwrap(), None);
+}
+
+#[test]
+fn test_write_batch_across_column_families() {
+    let mut test_db = TestDB::new("write_batch", test_options());
+    let db = test_db.db();
+    let index = db.create_column_family("index", test_options()).unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"doc/1", b"hello");
+    batch.put_cf(&index, b"hello", b"doc/1");
+    batch.delete_range_cf(&index, b"a", b"b");
+    db.write(batch).unwrap();
+
+    // an invalid update rejects the whole batch
+    let mut batch = WriteBatch::new();
+    batch.put(b"doc/2", b"world");
+    batch.merge_cf(&index, b"world", b"doc/2");
+    assert!(matches!(db.write(batch), Err(Error::InvalidArgument(_))));
+    assert_eq!(db.get(b"doc/2").unwrap(), None);
+
+    test_db.column_families = vec![ColumnFamilyDescriptor::new("index", test_options())];
+    test_db.reopen();
+    let db = test_db.db();
+    assert_eq!(db.get(b"doc/1").unwrap(), Some("hello".into()));
+    assert_eq!(db.get_cf(&index, b"hello").unwrap(), Some("doc/1".into()));
+    assert_eq!(db.get_cf(&index, b"doc/1").unwrap(), None);
+}
+
+#[test]
+fn test_fifo_compaction() {
+    let test_db = TestDB::new("fifo_compaction", test_options());
+    let db = test_db.db();
+    let options = Options {
+        compaction_style: CompactionStyle::Fifo {
+            max_table_files_size: 8 * 1024,
+        },
+        ..small_options()
+    };
+    let events = db.create_column_family("events", options).unwrap();
+    for i in 0..2000 {
+        db.put_cf(&events, key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    // the oldest records are dropped along with their SSTables
+    assert_eq!(db.get_cf(&events, key_of(0).as_slice()).unwrap(), None);
+    assert_eq!(
+        db.get_cf(&events, key_of(1999).as_slice()).unwrap(),
+        Some(value_of(1999).into())
+    );
+    let state = db.state.read().unwrap();
+    let version = state.versions.column_family(events.id()).unwrap().current();
+    assert!(version.level_size(0) <= 8 * 1024);
+    assert!(version.levels[1..].iter().all(|x| x.is_empty()));
+}
+
+#[test]
+fn test_optimistic_transaction() {
+    let test_db = TestDB::new("optimistic_transaction", test_options());
+    let db = test_db.db();
+    let audit = db.create_column_family("audit", test_options()).unwrap();
+    db.put(b"alice", b"100").unwrap();
+    db.put(b"bob", b"0").unwrap();
+
+    let mut txn = db.begin_optimistic_transaction();
+    assert_eq!(txn.get(b"alice").unwrap(), Some("100".into()));
+    assert_eq!(txn.get(b"bob").unwrap(), Some("0".into()));
+    txn.put(b"alice", b"70");
+    txn.put(b"bob", b"30");
+    txn.put_cf(&audit, b"1", b"alice->bob 30");
+    // the buffered writes are not visible before commit
+    assert_eq!(db.get(b"alice").unwrap(), Some("100".into()));
+
+    // a concurrent transaction updates a key read by the first one
+    let mut other = db.begin_optimistic_transaction();
+    assert_eq!(other.get(b"bob").unwrap(), Some("0".into()));
+    other.put(b"bob", b"5");
+    other.commit().unwrap();
+    assert!(matches!(txn.commit(), Err(Error::Busy(_))));
+    assert_eq!(db.get(b"alice").unwrap(), Some("100".into()));
+    assert_eq!(db.get_cf(&audit, b"1").unwrap(), None);
+
+    // the retry goes through
+    let mut txn = db.begin_optimistic_transaction();
+    assert_eq!(txn.get(b"bob").unwrap(), Some("5".into()));
+    txn.get(b"alice").unwrap();
+    txn.put(b"alice", b"70");
+    txn.put(b"bob", b"35");
+    txn.put_cf(&audit, b"1", b"alice->bob 30");
+    txn.commit().unwrap();
+    assert_eq!(db.get(b"alice").unwrap(), Some("70".into()));
+    assert_eq!(db.get(b"bob").unwrap(), Some("35".into()));
+    assert_eq!(
+        db.get_cf(&audit, b"1").unwrap(),
+        Some("alice->bob 30".into())
+    );
+}
+
+#[test]
+fn test_optimistic_transaction_conflicts() {
+    let test_db = TestDB::new("optimistic_transaction_conflicts", small_options());
+    let db = test_db.db();
+    // a key absent when read
+    let mut txn = db.begin_optimistic_transaction();
+    assert_eq!(txn.get(b"new").unwrap(), None);
+    txn.put(b"new", b"1");
+    db.put(b"new", b"2").unwrap();
+    assert!(matches!(txn.commit(), Err(Error::Busy(_))));
+
+    // a key updated after the transaction began, even before it is read, and a key read
+    // after the other one is updated
+    db.put(b"x", b"1").unwrap();
+    db.put(b"y", b"1").unwrap();
+    let mut txn = db.begin_optimistic_transaction();
+    db.put(b"x", b"2").unwrap();
+    assert!(matches!(txn.get(b"x"), Err(Error::Busy(_))));
+    let mut txn = db.begin_optimistic_transaction();
+    assert_eq!(txn.get(b"y").unwrap(), Some("1".into()));
+    db.put(b"x", b"3").unwrap();
+    assert!(matches!(txn.get(b"x"), Err(Error::Busy(_))));
+    txn.put(b"y", b"2");
+    db.put(b"y", b"3").unwrap();
+    assert!(matches!(txn.commit(), Err(Error::Busy(_))));
+    assert_eq!(db.get(b"y").unwrap(), Some("3".into()));
+
+    // a key covered by a later range tombstone
+    db.put(b"b", b"1").unwrap();
+    let mut txn = db.begin_optimistic_transaction();
+    txn.get(b"b").unwrap();
+    txn.put(b"c", b"1");
+    db.delete_range(b"a", b"c").unwrap();
+    assert!(matches!(txn.commit(), Err(Error::Busy(_))));
+
+    // the records of the keys read are flushed and compacted in the meantime, which is not
+    // a conflict by itself
+    let mut txn = db.begin_optimistic_transaction();
+    assert_eq!(txn.get(b"new").unwrap(), Some("2".into()));
+    for i in 0..2000 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    txn.put(b"new", b"3");
+    txn.commit().unwrap();
+    assert_eq!(db.get(b"new").unwrap(), Some("3".into()));
+
+    let mut txn = db.begin_optimistic_transaction();
+    txn.put(b"new", b"4");
+    txn.rollback();
+    assert_eq!(db.get(b"new").unwrap(), Some("3".into()));
+}
+
+#[test]
+fn test_write_batch_with_index_get() {
+    let options = Options {
+        merge_operator: Some(Arc::new(BytesAppendOperator::new(b","))),
+        ..test_options()
+    };
+    let test_db = TestDB::new("write_batch_with_index_get", options);
+    let db = test_db.db();
+    let other = db.create_column_family("other", test_options()).unwrap();
+    for key in ["a", "b", "c", "d", "e"] {
+        db.put(key.as_bytes(), b"db").unwrap();
+    }
+
+    let mut batch = WriteBatchWithIndex::new();
+    batch.put(b"a", b"batch");
+    batch.delete(b"b");
+    batch.merge(b"c", b"x");
+    batch.merge(b"c", b"y");
+    batch.put(b"new", b"batch");
+    batch.delete_range(b"d", b"f");
+    batch.merge(b"e", b"z");
+    batch.put_cf(&other, b"a", b"other");
+    assert_eq!(batch.len(), 8);
+    let get = |key: &[u8]| batch.get_from_batch_and_db(db, key).unwrap();
+    assert_eq!(get(b"a"), Some("batch".into()));
+    assert_eq!(get(b"b"), None);
+    assert_eq!(get(b"c"), Some("db,x,y".into()));
+    assert_eq!(get(b"d"), None);
+    assert_eq!(get(b"e"), Some("z".into()));
+    assert_eq!(get(b"new"), Some("batch".into()));
+    assert_eq!(get(b"missing"), None);
+    assert_eq!(
+        batch.get_from_batch_and_db_cf(db, &other, b"a").unwrap(),
+        Some("other".into())
+    );
+    // nothing is written before the batch is
+    assert_eq!(db.get(b"a").unwrap(), Some("db".into()));
+
+    db.write(batch.into_write_batch()).unwrap();
+    assert_eq!(db.get(b"a").unwrap(), Some("batch".into()));
+    assert_eq!(db.get(b"b").unwrap(), None);
+    assert_eq!(db.get(b"c").unwrap(), Some("db,x,y".into()));
+    assert_eq!(db.get(b"d").unwrap(), None);
+    assert_eq!(db.get(b"e").unwrap(), Some("z".into()));
+    assert_eq!(db.get_cf(&other, b"a").unwrap(), Some("other".into()));
+}
+
+#[test]
+fn test_write_batch_with_index_iterator() {
+    let options = Options {
+        merge_operator: Some(Arc::new(BytesAppendOperator::new(b","))),
+        ..test_options()
+    };
+    let test_db = TestDB::new("write_batch_with_index_iterator", options);
+    let db = test_db.db();
+    for key in ["b", "d", "f", "h", "j"] {
+        db.put(key.as_bytes(), b"db").unwrap();
+    }
+    let mut batch = WriteBatchWithIndex::new();
+    batch.put(b"a", b"batch");
+    batch.put(b"d", b"batch");
+    batch.delete(b"f");
+    batch.merge(b"h", b"x");
+    batch.delete_range(b"i", b"z");
+    batch.put(b"k", b"batch");
+
+    let expected: Vec<(Bytes, Bytes)> = [
+        ("a", "batch"),
+        ("b", "db"),
+        ("d", "batch"),
+        ("h", "db,x"),
+        ("k", "batch"),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.into(), value.into()))
+    .collect();
+    let mut iter = batch.iter_with_base(db.iter().unwrap());
+    let mut records = Vec::new();
+    iter.seek_to_first();
+    while iter.is_valid() {
+        records.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next();
+    }
+    assert!(iter.status().is_ok());
+    assert_eq!(records, expected);
+
+    let mut records = Vec::new();
+    iter.seek_to_last();
+    while iter.is_valid() {
+        records.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.prev();
+    }
+    records.reverse();
+    assert_eq!(records, expected);
+
+    // change the direction in the middle
+    iter.seek_to_key(b"c");
+    assert_eq!(iter.key(), b"d");
+    iter.prev();
+    assert_eq!(iter.key(), b"b");
+    iter.prev();
+    assert_eq!(iter.key(), b"a");
+    iter.next();
+    assert_eq!(iter.key(), b"b");
+    iter.next();
+    assert_eq!(iter.key(), b"d");
+    iter.next();
+    assert_eq!(iter.key(), b"h");
+    iter.prev();
+    assert_eq!(iter.key(), b"d");
+    iter.seek_to_key(b"i");
+    assert_eq!(iter.key(), b"k");
+    iter.next();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_write_batch_with_index_iterator_read_options() {
+    let options = Options {
+        prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(1))),
+        ..test_options()
+    };
+    let test_db = TestDB::new("write_batch_with_index_iterator_read_options", options);
+    let db = test_db.db();
+    for key in ["b1", "c1"] {
+        db.put(key.as_bytes(), b"db").unwrap();
+    }
+    let mut batch = WriteBatchWithIndex::new();
+    for key in ["a1", "b2", "c2", "d1"] {
+        batch.put(key.as_bytes(), b"batch");
+    }
+
+    // the keys of the batch beyond the bounds are skipped like the ones of the database
+    let read_options = ReadOptions {
+        iterate_lower_bound: Some(b"b".to_vec()),
+        iterate_upper_bound: Some(b"d".to_vec()),
+        ..ReadOptions::default()
+    };
+    let mut iter = batch.iter_with_base(db.iter_with_options(read_options).unwrap());
+    let mut keys = Vec::new();
+    iter.seek_to_first();
+    while iter.is_valid() {
+        keys.push(iter.key().to_vec());
+        iter.next();
+    }
+    assert_eq!(keys, [b"b1", b"b2", b"c1", b"c2"].map(|x| x.to_vec()));
+    iter.seek_to_last();
+    assert_eq!(iter.key(), b"c2");
+    iter.seek_to_key(b"a");
+    assert_eq!(iter.key(), b"b1");
+    iter.seek_to_key(b"c3");
+    assert!(!iter.is_valid());
+
+    let read_options = ReadOptions {
+        prefix_same_as_start: true,
+        ..ReadOptions::default()
+    };
+    let mut iter = batch.iter_with_base(db.iter_with_options(read_options).unwrap());
+    iter.seek_to_key(b"b");
+    assert_eq!(iter.key(), b"b1");
+    iter.next();
+    assert_eq!(iter.key(), b"b2");
+    iter.next();
+    assert!(!iter.is_valid());
+}
+
+fn collect(iter: &mut DBIterator) -> Vec<Bytes> {
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(Bytes::copy_from_slice(iter.key()));
+        iter.next();
+    }
+    keys
+}
+
+#[test]
+fn test_iterator_read_options() {
+    let options = Options {
+        prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(3))),
+        ..test_options()
+    };
+    let mut test_db = TestDB::new("iterator_read_options", options);
+    for tenant in 1..=3 {
+        for i in 0..5 {
+            let key = format!("t{}/{}", tenant, i);
+            test_db.db().put(key.as_bytes(), b"v").unwrap();
+        }
+    }
+    // flush the records to a SSTable, then update some of them in the memtable
+    test_db.reopen();
+    let db = test_db.db();
+    db.delete(b"t2/0").unwrap();
+    db.put(b"t2/5", b"v").unwrap();
+    db.put(b"t3/5", b"v").unwrap();
+    let keys = |tenant: u32, range: std::ops::Range<u32>| -> Vec<Bytes> {
+        range
+            .map(|i| Bytes::from(format!("t{}/{}", tenant, i)))
+            .collect()
+    };
+
+    let mut iter = db
+        .iter_with_options(ReadOptions {
+            iterate_lower_bound: Some(b"t2/".to_vec()),
+            iterate_upper_bound: Some(b"t3/".to_vec()),
+            ..ReadOptions::default()
+        })
+        .unwrap();
+    iter.seek_to_first();
+    assert_eq!(collect(&mut iter), keys(2, 1..6));
+    iter.seek_to_last();
+    assert_eq!(iter.key(), b"t2/5");
+    let mut backward = Vec::new();
+    while iter.is_valid() {
+        backward.push(Bytes::copy_from_slice(iter.key()));
+        iter.prev();
+    }
+    backward.reverse();
+    assert_eq!(backward, keys(2, 1..6));
+    iter.seek_to_key(b"t1/3");
+    assert_eq!(iter.key(), b"t2/1");
+    iter.seek_to_key(b"t3/0");
+    assert!(!iter.is_valid());
+
+    // a bound in the middle of the memtable and the SSTable
+    let mut iter = db
+        .iter_with_options(ReadOptions {
+            iterate_upper_bound: Some(b"t1/3".to_vec()),
+            fill_cache: false,
+            ..ReadOptions::default()
+        })
+        .unwrap();
+    iter.seek_to_first();
+    assert_eq!(collect(&mut iter), keys(1, 0..3));
+
+    let mut iter = db
+        .iter_with_options(ReadOptions {
+            prefix_same_as_start: true,
+            ..ReadOptions::default()
+        })
+        .unwrap();
+    iter.seek_to_key(b"t3/2");
+    assert_eq!(collect(&mut iter), keys(3, 2..6));
+    iter.seek_to_key(b"t2/");
+    assert_eq!(collect(&mut iter), keys(2, 1..6));
+    // the keys shorter than the prefix are out of the domain
+    iter.seek_to_key(b"t2");
+    assert_eq!(collect(&mut iter).len(), 11);
+    // no prefix once positioned at the first key
+    iter.seek_to_first();
+    assert_eq!(collect(&mut iter).len(), 16);
+}
+
+#[test]
+fn test_prefix_filter() {
+    let options = Options {
+        prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(3))),
+        ..test_options()
+    };
+    let mut test_db = TestDB::new("prefix_filter", options);
+    // one SSTable for every tenant
+    for tenant in 1..=3 {
+        for i in 0..5 {
+            let key = format!("t{}/{}", tenant, i);
+            test_db.db().put(key.as_bytes(), b"v").unwrap();
+        }
+        test_db.reopen();
+    }
+    let db = test_db.db();
+    db.put(b"t2/9", b"v").unwrap();
+    let keys = |tenant: u32, range: &[u32]| -> Vec<Bytes> {
+        range
+            .iter()
+            .map(|i| Bytes::from(format!("t{}/{}", tenant, i)))
+            .collect()
+    };
+
+    let mut iter = db
+        .iter_with_options(ReadOptions {
+            prefix_same_as_start: true,
+            ..ReadOptions::default()
+        })
+        .unwrap();
+    iter.seek_to_key(b"t2/2");
+    assert_eq!(collect(&mut iter), keys(2, &[2, 3, 4, 9]));
+    // move backward within the prefix
+    iter.seek_to_key(b"t2/3");
+    iter.prev();
+    assert_eq!(iter.key(), b"t2/2");
+    iter.prev();
+    iter.prev();
+    assert_eq!(iter.key(), b"t2/0");
+    iter.prev();
+    assert!(!iter.is_valid());
+    iter.seek_to_key(b"t4/");
+    assert!(!iter.is_valid());
+    // the SSTables skipped by the prefix seek are back for the full scan
+    iter.seek_to_first();
+    assert_eq!(collect(&mut iter).len(), 16);
+
+    assert_eq!(db.get(b"t1/3").unwrap(), Some("v".into()));
+    assert_eq!(db.get(b"t3/4").unwrap(), Some("v".into()));
+    assert_eq!(db.get(b"t4/0").unwrap(), None);
+}
+
+/// `TenantTransform` takes the bytes before the first `/` of a key as the prefix, so the
+/// prefixes of the keys vary in length
+struct TenantTransform;
+
+impl SliceTransform for TenantTransform {
+    fn name(&self) -> &str {
+        "test.TenantTransform"
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..key.iter().position(|b| *b == b'/').unwrap()]
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        key.contains(&b'/')
+    }
+}
+
+#[test]
+fn test_prefix_of_variable_length() {
+    let options = Options {
+        prefix_extractor: Some(Arc::new(TenantTransform)),
+        ..test_options()
+    };
+    let mut test_db = TestDB::new("prefix_of_variable_length", options);
+    for key in ["a/1", "ab/1", "ab/2", "abc/1", "abc/2"] {
+        test_db.db().put(key.as_bytes(), b"v").unwrap();
+    }
+    test_db.reopen();
+    let db = test_db.db();
+    db.put(b"ab/3", b"v").unwrap();
+    db.put(b"abd/1", b"v").unwrap();
+
+    let mut iter = db
+        .iter_with_options(ReadOptions {
+            prefix_same_as_start: true,
+            ..ReadOptions::default()
+        })
+        .unwrap();
+    // "abc/1" starts with "ab" but its prefix is "abc"
+    iter.seek_to_key(b"ab/1");
+    assert_eq!(
+        collect(&mut iter),
+        vec![
+            Bytes::from("ab/1"),
+            Bytes::from("ab/2"),
+            Bytes::from("ab/3")
+        ]
+    );
+    iter.seek_to_key(b"ab/2");
+    iter.prev();
+    iter.prev();
+    assert!(!iter.is_valid());
+
+    let mut batch = WriteBatchWithIndex::new();
+    batch.put(b"ab/4", b"v");
+    batch.put(b"abc/0", b"v");
+    let mut iter = batch.iter_with_base(
+        db.iter_with_options(ReadOptions {
+            prefix_same_as_start: true,
+            ..ReadOptions::default()
+        })
+        .unwrap(),
+    );
+    iter.seek_to_key(b"ab/3");
+    assert_eq!(iter.key(), b"ab/3");
+    iter.next();
+    assert_eq!(iter.key(), b"ab/4");
+    iter.next();
+    assert!(!iter.is_valid());
+}
+
+fn reverse_options() -> Options {
+    Options {
+        comparator: Arc::new(ReverseBytewiseComparator {}),
+        ..small_options()
+    }
+}
+
+#[test]
+fn test_reverse_comparator() {
+    let mut test_db = TestDB::new("reverse_comparator", reverse_options());
+    for i in 0..300 {
+        test_db
+            .db()
+            .put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    // the range starts from the greater key in the reverse order
+    assert!(matches!(
+        test_db
+            .db()
+            .delete_range(key_of(100).as_slice(), key_of(200).as_slice()),
+        Err(Error::InvalidArgument(_))
+    ));
+    test_db
+        .db()
+        .delete_range(key_of(200).as_slice(), key_of(100).as_slice())
+        .unwrap();
+    test_db.reopen();
+    for i in 0..300 {
+        let expected = match i {
+            101..=200 => None,
+            _ => Some(Bytes::from(value_of(i))),
+        };
+        assert_eq!(test_db.db().get(key_of(i).as_slice()).unwrap(), expected);
+    }
+    let keys: Vec<Bytes> = test_db.scan().into_iter().map(|x| x.0).collect();
+    let expected: Vec<Bytes> = (0..300)
+        .rev()
+        .filter(|x| !(101..=200).contains(x))
+        .map(|x| Bytes::from(key_of(x)))
+        .collect();
+    assert_eq!(keys, expected);
+
+    let mut iter = test_db
+        .db()
+        .iter_with_options(ReadOptions {
+            iterate_lower_bound: Some(key_of(250)),
+            iterate_upper_bound: Some(key_of(240)),
+            ..ReadOptions::default()
+        })
+        .unwrap();
+    iter.seek_to_first();
+    let expected: Vec<Bytes> = (241..=250).rev().map(|x| Bytes::from(key_of(x))).collect();
+    assert_eq!(collect(&mut iter), expected);
+    let mut iter = test_db.db().iter().unwrap();
+    iter.seek_to_key(key_of(150).as_slice());
+    assert_eq!(iter.key(), key_of(100).as_slice());
+    iter.prev();
+    assert_eq!(iter.key(), key_of(201).as_slice());
+}
+
+#[test]
+fn test_comparator_mismatch() {
+    let mut test_db = TestDB::new("comparator_mismatch", test_options());
+    let cf = test_db
+        .db()
+        .create_column_family("reverse", reverse_options())
+        .unwrap();
+    test_db.db().put_cf(&cf, b"a", b"1").unwrap();
+    test_db.db().put_cf(&cf, b"b", b"2").unwrap();
+    test_db.db().close().unwrap();
+    test_db.column_families = vec![ColumnFamilyDescriptor::new("reverse", test_options())];
+    assert!(matches!(test_db.open(), Err(Error::InvalidArgument(_))));
+    test_db.column_families = vec![ColumnFamilyDescriptor::new("reverse", reverse_options())];
+    test_db.options = reverse_options();
+    assert!(matches!(test_db.open(), Err(Error::InvalidArgument(_))));
+    test_db.options = test_options();
+    test_db.reopen();
+    let cf = test_db.db().column_family("reverse").unwrap();
+    assert_eq!(
+        scan_cf(test_db.db(), &cf),
+        vec![
+            (Bytes::from("b"), Bytes::from("2")),
+            (Bytes::from("a"), Bytes::from("1"))
+        ]
+    );
+}
+
+#[test]
+fn test_multi_get() {
+    let mut test_db = TestDB::new("multi_get", small_options());
+    for i in 0..500 {
+        test_db
+            .db()
+            .put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    test_db
+        .db()
+        .delete_range(key_of(100).as_slice(), key_of(200).as_slice())
+        .unwrap();
+    for i in (0..500).step_by(7) {
+        test_db.db().delete(key_of(i).as_slice()).unwrap();
+    }
+    for i in 150..160 {
+        test_db.db().put(key_of(i).as_slice(), b"again").unwrap();
+    }
+    // the keys are unordered, duplicated and partly absent
+    let keys: Vec<Vec<u8>> = (0..600)
+        .rev()
+        .step_by(3)
+        .chain([5, 155, 155, 1000])
+        .map(key_of)
+        .collect();
+    let keys: Vec<&[u8]> = keys.iter().map(|x| x.as_slice()).collect();
+    let check = |db: &DBImpl| {
+        let values = db.multi_get(keys.as_slice());
+        assert_eq!(values.len(), keys.len());
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(value.unwrap(), db.get(key).unwrap());
+        }
+    };
+    check(test_db.db());
+    assert_eq!(
+        test_db
+            .db()
+            .multi_get(&[
+                key_of(155).as_slice(),
+                key_of(150).as_slice(),
+                key_of(1).as_slice()
+            ])
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap(),
+        vec![
+            Some("again".into()),
+            Some("again".into()),
+            Some(value_of(1).into())
+        ]
+    );
+    test_db.reopen();
+    check(test_db.db());
+    assert!(test_db.db().multi_get(&[]).is_empty());
+    test_db.db().close().unwrap();
+    assert!(matches!(
+        test_db.db().multi_get(&[b"a", b"b"]).as_slice(),
+        [
+            Err(Error::ShutdownInProgress),
+            Err(Error::ShutdownInProgress)
+        ]
+    ));
+}
+
+#[test]
+fn test_multi_get_merge() {
+    let test_db = TestDB::new("multi_get_merge", counter_options());
+    let db = test_db.db();
+    let one = 1u64.to_le_bytes();
+    for _ in 0..20 {
+        for i in 0..50 {
+            db.merge(key_of(i).as_slice(), &one).unwrap();
+        }
+    }
+    db.delete(key_of(1).as_slice()).unwrap();
+    let keys: Vec<Vec<u8>> = (0..50).map(key_of).collect();
+    let keys: Vec<&[u8]> = keys.iter().map(|x| x.as_slice()).collect();
+    for (i, value) in db.multi_get(keys.as_slice()).into_iter().enumerate() {
+        let expected = (i != 1).then(|| Bytes::copy_from_slice(&20u64.to_le_bytes()));
+        assert_eq!(value.unwrap(), expected);
+    }
+}
+
+fn blob_options() -> Options {
+    Options {
+        enable_blob_files: true,
+        min_blob_size: 100,
+        merge_operator: Some(Arc::new(BytesAppendOperator::new(b","))),
+        ..small_options()
+    }
+}
+
+fn large_value_of(i: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{}|", i, round).repeat(10).into_bytes()
+}
+
+/// Return the numbers of the blob files in the directory of the database
+fn blob_files_of(test_db: &TestDB) -> Vec<u64> {
+    let mut numbers: Vec<u64> = test_env()
+        .get_children(test_db.path.as_str())
+        .unwrap()
+        .into_iter()
+        .filter_map(|name| match parse_file_name(name.as_str())? {
+            (number, "blob") => Some(number),
+            _ => None,
+        })
+        .collect();
+    numbers.sort();
+    numbers
+}
+
+#[test]
+fn test_blob_files() {
+    let mut test_db = TestDB::new("blob_files", blob_options());
+    let db = test_db.db();
+    for i in 0..100 {
+        db.put(key_of(i).as_slice(), large_value_of(i, 0).as_slice())
+            .unwrap();
+        db.put(key_of(i + 100).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    db.merge(key_of(0).as_slice(), b"tail").unwrap();
+    db.delete(key_of(1).as_slice()).unwrap();
+    let check = |db: &DBImpl| {
+        let mut expected_0 = large_value_of(0, 0);
+        expected_0.extend_from_slice(b",tail");
+        assert_eq!(
+            db.get(key_of(0).as_slice()).unwrap(),
+            Some(expected_0.into())
+        );
+        assert_eq!(db.get(key_of(1).as_slice()).unwrap(), None);
+        assert_eq!(
+            db.get(key_of(50).as_slice()).unwrap(),
+            Some(large_value_of(50, 0).into())
+        );
+        assert_eq!(
+            db.get(key_of(150).as_slice()).unwrap(),
+            Some(value_of(50).into())
+        );
+        let keys = [key_of(2), key_of(99), key_of(199)];
+        let keys: Vec<&[u8]> = keys.iter().map(|x| x.as_slice()).collect();
+        let values: Vec<Option<Bytes>> = db
+            .multi_get(keys.as_slice())
+            .into_iter()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            values,
+            vec![
+                Some(large_value_of(2, 0).into()),
+                Some(large_value_of(99, 0).into()),
+                Some(value_of(99).into())
+            ]
+        );
+    };
+    check(db);
+    let records = test_db.scan();
+    assert_eq!(records.len(), 199);
+    assert_eq!(records[1].1.as_ref(), large_value_of(2, 0).as_slice());
+
+    let state = db.state.read().unwrap();
+    let version = state.versions.default_column_family().current();
+    let blob_files: Vec<u64> = version.blob_files.keys().copied().collect();
+    assert!(!blob_files.is_empty());
+    assert_eq!(blob_files, blob_files_of(&test_db));
+    assert!(version
+        .levels
+        .iter()
+        .flatten()
+        .flat_map(|x| x.blob_files.iter())
+        .all(|x| blob_files.contains(x)));
+    drop(state);
+    test_db.reopen();
+    check(test_db.db());
+    assert_eq!(test_db.scan(), records);
+}
+
+#[test]
+fn test_blob_garbage_collection() {
+    for gc in [false, true] {
+        let options = Options {
+            enable_blob_garbage_collection: gc,
+            blob_garbage_collection_age_cutoff: 1.0,
+            ..blob_options()
+        };
+        let mut test_db = TestDB::new("blob_garbage_collection", options);
+        let db = test_db.db();
+        for i in 0..100 {
+            db.put(key_of(i).as_slice(), large_value_of(i, 0).as_slice())
+                .unwrap();
+        }
+        let original = blob_files_of(&test_db);
+        // every flushed file spans all the keys, so that compaction reads every SSTable
+        for round in 1..20 {
+            db.put(key_of(0).as_slice(), large_value_of(0, round).as_slice())
+                .unwrap();
+            db.put(key_of(99).as_slice(), large_value_of(99, round).as_slice())
+                .unwrap();
+        }
+        let current = blob_files_of(&test_db);
+        let survived = original.iter().filter(|x| current.contains(x)).count();
+        if gc {
+            // the live values are relocated, and the old files are deleted
+            assert_eq!(survived, 0);
+        } else {
+            assert!(survived > 0);
+        }
+        let state = db.state.read().unwrap();
+        let version = state.versions.default_column_family().current();
+        let blob_files: Vec<u64> = version.blob_files.keys().copied().collect();
+        assert_eq!(blob_files, current);
+        drop(state);
+        for _ in 0..2 {
+            let db = test_db.db();
+            assert_eq!(
+                db.get(key_of(0).as_slice()).unwrap(),
+                Some(large_value_of(0, 19).into())
+            );
+            for i in 1..99 {
+                assert_eq!(
+                    db.get(key_of(i).as_slice()).unwrap(),
+                    Some(large_value_of(i, 0).into())
+                );
+            }
+            test_db.reopen();
+        }
+    }
+}
+
+#[test]
+fn test_blob_files_large_values() {
+    let options = Options {
+        enable_blob_files: true,
+        min_blob_size: 1024,
+        level0_file_num_compaction_trigger: 2,
+        ..test_options()
+    };
+    let mut test_db = TestDB::new("blob_files_large_values", options);
+    let large_value = |round: u8| vec![round; 100 * 1024];
+    let num_files_of = |test_db: &TestDB, level: usize| {
+        let state = test_db.db().state.read().unwrap();
+        state.versions.default_column_family().current().levels[level].len()
+    };
+    test_db.db().put(b"key", large_value(1).as_slice()).unwrap();
+    assert_eq!(
+        test_db.db().get(b"key").unwrap(),
+        Some(large_value(1).into())
+    );
+    // the memtable is flushed on open
+    test_db.reopen();
+    assert_eq!(num_files_of(&test_db, 0), 1);
+    assert_eq!(
+        test_db.db().get(b"key").unwrap(),
+        Some(large_value(1).into())
+    );
+    test_db.db().put(b"key", large_value(2).as_slice()).unwrap();
+    test_db.reopen();
+    assert_eq!(num_files_of(&test_db, 0), 0);
+    assert_eq!(
+        test_db.db().get(b"key").unwrap(),
+        Some(large_value(2).into())
+    );
+    assert_eq!(blob_files_of(&test_db).len(), 1);
+
+    // the values with a TTL stay in the SSTables whatever their size
+    test_db
+        .db()
+        .put_with_ttl(b"ttl", large_value(3).as_slice(), Duration::from_secs(3600))
+        .unwrap();
+    assert_eq!(
+        test_db.db().get(b"ttl").unwrap(),
+        Some(large_value(3).into())
+    );
+    test_db.reopen();
+    assert_eq!(
+        test_db.db().get(b"ttl").unwrap(),
+        Some(large_value(3).into())
+    );
+    assert_eq!(blob_files_of(&test_db).len(), 1);
+
+    // the large values and merge results are kept in the SSTables without blob files
+    let options = Options {
+        merge_operator: Some(Arc::new(BytesAppendOperator::new(b""))),
+        level0_file_num_compaction_trigger: 2,
+        ..test_options()
+    };
+    let mut test_db = TestDB::new("large_values_without_blob_files", options);
+    test_db.db().put(b"key", large_value(1).as_slice()).unwrap();
+    test_db
+        .db()
+        .merge(b"list", large_value(1).as_slice())
+        .unwrap();
+    test_db.reopen();
+    test_db
+        .db()
+        .merge(b"list", large_value(2).as_slice())
+        .unwrap();
+    test_db.reopen();
+    assert_eq!(num_files_of(&test_db, 0), 0);
+    assert_eq!(
+        test_db.db().get(b"key").unwrap(),
+        Some(large_value(1).into())
+    );
+    assert_eq!(
+        test_db.db().get(b"list").unwrap(),
+        Some([large_value(1), large_value(2)].concat().into())
+    );
+    assert!(matches!(
+        test_db.db().put(vec![0; 1 << 16].as_slice(), b"v"),
+        Err(Error::InvalidArgument(_))
+    ));
+}
+
+/// Path of the external SSTable `name` for the test `test`, removed if it exists
+fn external_file_path(test: &str, name: &str) -> String {
+    let dir = format!("/summer_kv_test/external_{}", test);
+    test_env().create_dir_all(dir.as_str()).unwrap();
+    let path = format!("{}/{}.sst", dir, name);
+    let _ = test_env().remove_file(path.as_str());
+    path
+}
+
+#[test]
+fn test_ingest_external_file() {
+    let mut test_db = TestDB::new("ingest_external_file", counter_options());
+    let db = test_db.db();
+    for i in 0..100 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    db.merge(key_of(100).as_slice(), 1u64.to_le_bytes().as_slice())
+        .unwrap();
+    // the iterator created before the ingestion does not see the ingested records
+    let mut iter = db.iter().unwrap();
+
+    let first = external_file_path("ingest", "first");
+    let mut writer = SstFileWriter::new(first.as_str(), counter_options());
+    writer
+        .delete_range(key_of(10).as_slice(), key_of(20).as_slice())
+        .unwrap();
+    for i in 20..30 {
+        writer
+            .put(key_of(i).as_slice(), value_of(i + 1000).as_slice())
+            .unwrap();
+    }
+    writer.delete(key_of(30).as_slice()).unwrap();
+    writer.finish().unwrap();
+    let second = external_file_path("ingest", "second");
+    let mut writer = SstFileWriter::new(second.as_str(), counter_options());
+    writer
+        .merge(key_of(100).as_slice(), 2u64.to_le_bytes().as_slice())
+        .unwrap();
+    writer
+        .put(key_of(200).as_slice(), value_of(200).as_slice())
+        .unwrap();
+    writer.finish().unwrap();
+    db.ingest_external_file(&[second.as_str(), first.as_str()])
+        .unwrap();
+
+    let check = |db: &DBImpl| {
+        assert_eq!(
+            db.get(key_of(9).as_slice()).unwrap(),
+            Some(value_of(9).into())
+        );
+        for i in 10..20 {
+            assert_eq!(db.get(key_of(i).as_slice()).unwrap(), None);
+        }
+        for i in 20..30 {
+            assert_eq!(
+                db.get(key_of(i).as_slice()).unwrap(),
+                Some(value_of(i + 1000).into())
+            );
+        }
+        assert_eq!(db.get(key_of(30).as_slice()).unwrap(), None);
+        assert_eq!(counter_of(db, key_of(100).as_slice()), Some(3));
+        assert_eq!(
+            db.get(key_of(200).as_slice()).unwrap(),
+            Some(value_of(200).into())
+        );
+    };
+    check(db);
+    iter.seek_to_key(key_of(20).as_slice());
+    assert_eq!(iter.value(), value_of(20).as_slice());
+    iter.seek_to_key(key_of(101).as_slice());
+    assert!(!iter.is_valid());
+    drop(iter);
+    test_db.reopen();
+    check(test_db.db());
+    // the ingested files are compacted along with the written ones
+    for i in 1000..1300 {
+        test_db
+            .db()
+            .put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    check(test_db.db());
+    test_db.reopen();
+    check(test_db.db());
+}
+
+#[test]
+fn test_ingest_external_file_level() {
+    let test_db = TestDB::new("ingest_external_file_level", test_options());
+    let db = test_db.db();
+    db.put(key_of(5).as_slice(), value_of(5).as_slice())
+        .unwrap();
+    let ingest = |name: &str, start: usize| {
+        let path = external_file_path("level", name);
+        let mut writer = SstFileWriter::new(path.as_str(), test_options());
+        for i in start..start + 10 {
+            writer
+                .put(key_of(i).as_slice(), value_of(i + 1000).as_slice())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        db.ingest_external_file(&[path.as_str()]).unwrap();
+        let state = db.state.read().unwrap();
+        let version = state.versions.default_column_family().current();
+        let level = version
+            .levels
+            .iter()
+            .position(|files| {
+                files.iter().any(|x| {
+                    x.global_seq.is_some() && x.smallest.as_ref() == key_of(start).as_slice()
+                })
+            })
+            .unwrap();
+        level
+    };
+    let bottom = ingest("disjoint", 100);
+    assert_eq!(bottom, NUM_LEVELS - 1);
+    // the file overlapping the bottommost one is placed right above it
+    assert_eq!(ingest("overlapping", 105), NUM_LEVELS - 2);
+    // the memtable holding an overlapping key is flushed, the file goes above the flushed one
+    db.put(key_of(300).as_slice(), value_of(300).as_slice())
+        .unwrap();
+    assert_eq!(ingest("memtable", 295), 0);
+    assert_eq!(
+        db.get(key_of(300).as_slice()).unwrap(),
+        Some(value_of(1300).into())
+    );
+    assert_eq!(
+        db.get(key_of(5).as_slice()).unwrap(),
+        Some(value_of(5).into())
+    );
+}
+
+#[test]
+fn test_ingest_external_file_invalid_argument() {
+    let test_db = TestDB::new("ingest_external_file_invalid", test_options());
+    let db = test_db.db();
+    let path = external_file_path("invalid", "unsorted");
+    let mut writer = SstFileWriter::new(path.as_str(), test_options());
+    writer.put(key_of(2).as_slice(), b"").unwrap();
+    assert!(matches!(
+        writer.put(key_of(1).as_slice(), b""),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        writer.put(key_of(2).as_slice(), b""),
+        Err(Error::InvalidArgument(_))
+    ));
+    writer.finish().unwrap();
+    let writer = SstFileWriter::new(path.as_str(), test_options());
+    assert!(matches!(writer.finish(), Err(Error::InvalidArgument(_))));
+
+    let paths: Vec<String> = (0..2)
+        .map(|x| {
+            let path = external_file_path("invalid", format!("overlapping_{}", x).as_str());
+            let mut writer = SstFileWriter::new(path.as_str(), test_options());
+            writer.put(key_of(x).as_slice(), b"").unwrap();
+            writer.put(key_of(x + 10).as_slice(), b"").unwrap();
+            writer.finish().unwrap();
+            path
+        })
+        .collect();
+    let paths: Vec<&str> = paths.iter().map(|x| x.as_str()).collect();
+    assert!(matches!(
+        db.ingest_external_file(paths.as_slice()),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert_eq!(db.get(key_of(0).as_slice()).unwrap(), None);
+    db.ingest_external_file(&paths[..1]).unwrap();
+    assert_eq!(db.get(key_of(10).as_slice()).unwrap(), Some(Bytes::new()));
+}
+
+#[test]
+fn test_create_checkpoint() {
+    let test_db = TestDB::new("checkpoint", blob_options());
+    let db = test_db.db();
+    let meta = db.create_column_family("meta", test_options()).unwrap();
+    for i in 0..200 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    for i in 0..10 {
+        db.put(key_of(i + 1000).as_slice(), large_value_of(i, 0).as_slice())
+            .unwrap();
+    }
+    db.delete(key_of(0).as_slice()).unwrap();
+    db.put_cf(&meta, b"key", b"meta").unwrap();
+
+    let checkpoint = "/summer_kv_test/checkpoint_copy";
+    let _ = test_env().remove_dir_all(checkpoint);
+    db.create_checkpoint(checkpoint).unwrap();
+    assert!(matches!(
+        db.create_checkpoint(checkpoint),
+        Err(Error::InvalidArgument(_))
+    ));
+    // the writes after the checkpoint stay in the origin
+    db.put(key_of(1).as_slice(), b"changed").unwrap();
+    db.put_cf(&meta, b"key", b"changed").unwrap();
+    for i in 200..400 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+
+    let open = || {
+        DBImpl::open_with_column_families(
+            checkpoint,
+            blob_options(),
+            vec![ColumnFamilyDescriptor::new("meta", test_options())],
+        )
+        .unwrap()
+    };
+    for _ in 0..2 {
+        let copy = open();
+        let meta = copy.column_family("meta").unwrap();
+        assert_eq!(copy.get(key_of(0).as_slice()).unwrap(), None);
+        for i in 1..200 {
+            assert_eq!(
+                copy.get(key_of(i).as_slice()).unwrap(),
+                Some(value_of(i).into())
+            );
+        }
+        assert_eq!(copy.get(key_of(200).as_slice()).unwrap(), None);
+        for i in 0..10 {
+            assert_eq!(
+                copy.get(key_of(i + 1000).as_slice()).unwrap(),
+                Some(large_value_of(i, 0).into())
+            );
+        }
+        assert_eq!(copy.get_cf(&meta, b"key").unwrap(), Some("meta".into()));
+        // the checkpoint is written independently of the origin
+        copy.put(key_of(5000).as_slice(), b"copy").unwrap();
+        copy.close().unwrap();
+    }
+    assert_eq!(
+        db.get(key_of(1).as_slice()).unwrap(),
+        Some("changed".into())
+    );
+    assert_eq!(db.get(key_of(5000).as_slice()).unwrap(), None);
+    assert_eq!(db.get_cf(&meta, b"key").unwrap(), Some("changed".into()));
+    test_env().remove_dir_all(checkpoint).unwrap();
+}
+
+/// Return the names of the files in the directory of the database
+fn files_of(test_db: &TestDB) -> Vec<String> {
+    let mut names = test_env().get_children(test_db.path.as_str()).unwrap();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_open_read_only() {
+    let mut test_db = TestDB::new("read_only", small_options());
+    let meta = test_db
+        .db()
+        .create_column_family("meta", test_options())
+        .unwrap();
+    test_db.column_families = vec![ColumnFamilyDescriptor::new("meta", test_options())];
+    for i in 0..300 {
+        test_db
+            .db()
+            .put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    test_db.db().delete(key_of(0).as_slice()).unwrap();
+    test_db.db().put_cf(&meta, b"key", b"meta").unwrap();
+
+    let files = files_of(&test_db);
+    let db = DBImpl::open_read_only_with_column_families(
+        test_db.path.as_str(),
+        small_options(),
+        test_db.column_families.clone(),
+    )
+    .unwrap();
+    assert_eq!(files_of(&test_db), files);
+    assert_eq!(db.get(key_of(0).as_slice()).unwrap(), None);
+    for i in 1..300 {
+        assert_eq!(
+            db.get(key_of(i).as_slice()).unwrap(),
+            Some(value_of(i).into())
+        );
+    }
+    let meta = db.column_family("meta").unwrap();
+    assert_eq!(db.get_cf(&meta, b"key").unwrap(), Some("meta".into()));
+    assert!(matches!(
+        db.put(b"key", b"value"),
+        Err(Error::NotSupported(_))
+    ));
+    assert!(matches!(
+        db.create_column_family("index", test_options()),
+        Err(Error::NotSupported(_))
+    ));
+    assert!(matches!(
+        db.try_catch_up_with_primary(),
+        Err(Error::NotSupported(_))
+    ));
+    // the writes of the primary since are not seen
+    test_db.db().put(key_of(0).as_slice(), b"new").unwrap();
+    assert_eq!(db.get(key_of(0).as_slice()).unwrap(), None);
+    drop(db);
+    test_db.reopen();
+    assert_eq!(
+        test_db.db().get(key_of(0).as_slice()).unwrap(),
+        Some("new".into())
+    );
+    assert!(matches!(
+        DBImpl::open_read_only("/summer_kv_test/db_read_only_missing", test_options()),
+        Err(Error::NotFound)
+    ));
+}
+
+#[test]
+fn test_open_as_secondary() {
+    let mut test_db = TestDB::new("secondary", counter_options());
+    let db = test_db.db();
+    for i in 0..100 {
+        db.put(key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    let secondary = DBImpl::open_as_secondary(test_db.path.as_str(), counter_options()).unwrap();
+    assert_eq!(
+        secondary.get(key_of(99).as_slice()).unwrap(),
+        Some(value_of(99).into())
+    );
+    assert!(matches!(
+        secondary.delete(key_of(0).as_slice()),
+        Err(Error::NotSupported(_))
+    ));
+
+    for round in 1..4u64 {
+        // the primary flushes and compacts in between, and leaves a tail in the log
+        let db = test_db.db();
+        for i in 0..100 {
+            db.put(
+                key_of(i + 100 * round as usize).as_slice(),
+                value_of(i).as_slice(),
+            )
+            .unwrap();
+        }
+        db.delete(key_of(round as usize).as_slice()).unwrap();
+        db.merge(b"counter", 1u64.to_le_bytes().as_slice()).unwrap();
+        assert_eq!(
+            secondary.get(key_of(round as usize).as_slice()).unwrap(),
+            Some(value_of(round as usize).into())
+        );
+        secondary.try_catch_up_with_primary().unwrap();
+        for i in 1..=round as usize {
+            assert_eq!(secondary.get(key_of(i).as_slice()).unwrap(), None);
+        }
+        assert_eq!(counter_of(&secondary, b"counter"), Some(round));
+        for i in 0..100 {
+            let key = key_of(i + 100 * round as usize);
+            assert_eq!(
+                secondary.get(key.as_slice()).unwrap(),
+                Some(value_of(i).into())
+            );
+        }
+        if round == 2 {
+            // the primary flushes everything on reopen
+            test_db.reopen();
+        }
+    }
+}
+
+#[test]
+fn test_lock_file() {
+    let mut test_db = TestDB::new("lock_file", test_options());
+    test_db.db().put(b"key", b"value").unwrap();
+    match test_db.open() {
+        Err(Error::Busy(msg)) => assert!(msg.contains(format!("{}/LOCK", test_db.path).as_str())),
+        _ => panic!("the second primary is opened"),
+    }
+    // the read only and secondary instances do not lock the database
+    let db = DBImpl::open_read_only(test_db.path.as_str(), test_options()).unwrap();
+    assert_eq!(db.get(b"key").unwrap(), Some("value".into()));
+    drop(db);
+    // the lock is released on close and on drop
+    test_db.reopen();
+    drop(test_db.db.take());
+    test_db.db = Some(test_db.open().unwrap());
+    assert_eq!(test_db.db().get(b"key").unwrap(), Some("value".into()));
+}
+
+/// Return the numbers of the files of `kind` in the directory of the database
+fn numbers_of(test_db: &TestDB, kind: &str) -> Vec<u64> {
+    let mut numbers: Vec<u64> = files_of(test_db)
+        .iter()
+        .filter_map(|x| match parse_file_name(x.as_str())? {
+            (number, x) if x == kind => Some(number),
+            _ => None,
+        })
+        .collect();
+    numbers.sort();
+    numbers
+}
+
+/// Return the numbers of the SSTables of the current version of the default column family
+fn live_tables_of(test_db: &TestDB) -> Vec<u64> {
+    let state = test_db.db().state.read().unwrap();
+    let version = state.versions.default_column_family().current();
+    let mut numbers: Vec<u64> = version.levels.iter().flatten().map(|x| x.number).collect();
+    numbers.sort();
+    numbers
+}
+
+#[test]
+fn test_obsolete_files_deleted() {
+    let mut test_db = TestDB::new("obsolete_files", small_options());
+    for i in 0..1000 {
+        test_db
+            .db()
+            .put(key_of(i % 200).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    assert_eq!(numbers_of(&test_db, "sst"), live_tables_of(&test_db));
+    // the log being written along with the one not flushed yet at most
+    assert!(numbers_of(&test_db, "log").len() <= 2);
+
+    // the SSTables read by an iterator are kept until it is dropped
+    let mut iter = test_db.db().iter().unwrap();
+    let pinned = live_tables_of(&test_db);
+    for i in 1000..2000 {
+        test_db
+            .db()
+            .put(key_of(i % 200).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    let files = numbers_of(&test_db, "sst");
+    assert!(pinned.iter().all(|x| files.contains(x)));
+    assert!(pinned.iter().any(|x| !live_tables_of(&test_db).contains(x)));
+    iter.seek_to_first();
+    assert_eq!(iter.value(), value_of(800).as_slice());
+    drop(iter);
+    test_db.db().put(b"key", b"value").unwrap();
+    let manifests = numbers_of(&test_db, "MANIFEST");
+    assert_eq!(manifests.len(), 1);
+    test_db.reopen();
+    assert_eq!(numbers_of(&test_db, "sst"), live_tables_of(&test_db));
+    // the MANIFEST is rolled on open and the previous one deleted
+    let rolled = numbers_of(&test_db, "MANIFEST");
+    assert_eq!(rolled.len(), 1);
+    assert!(rolled[0] > manifests[0]);
+
+    // the files left by a crash are deleted on open
+    for name in ["99999.sst", "99998.sst.tmp", "MANIFEST-99997", "1.log"] {
+        test_env()
+            .new_writable_file(format!("{}/{}", test_db.path, name).as_str())
+            .and_then(|mut file| file.append(b"garbage"))
+            .unwrap();
+    }
+    test_db.reopen();
+    let files = files_of(&test_db);
+    for name in ["99999.sst", "99998.sst.tmp", "MANIFEST-99997", "1.log"] {
+        assert!(!files.contains(&name.to_string()));
+    }
+    assert_eq!(numbers_of(&test_db, "MANIFEST").len(), 1);
+    assert_eq!(numbers_of(&test_db, "sst"), live_tables_of(&test_db));
+    for i in 0..200 {
+        assert_eq!(
+            test_db.db().get(key_of(i).as_slice()).unwrap(),
+            Some(value_of(i + 1800).into())
+        );
+    }
+}
+
+#[test]
+fn test_dropped_column_family_files_deleted() {
+    let test_db = TestDB::new("dropped_column_family_files", test_options());
+    let db = test_db.db();
+    let cf = db.create_column_family("dropped", small_options()).unwrap();
+    for i in 0..300 {
+        db.put_cf(&cf, key_of(i).as_slice(), value_of(i).as_slice())
+            .unwrap();
+    }
+    assert!(!numbers_of(&test_db, "sst").is_empty());
+    db.drop_column_family(&cf).unwrap();
+    assert!(numbers_of(&test_db, "sst").is_empty());
+}
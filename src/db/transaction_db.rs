@@ -0,0 +1,237 @@
+use crate::db::column_family::ColumnFamilyHandle;
+use crate::db::transaction_db::lock_manager::{LockKey, LockManager};
+use crate::db::write_batch::WriteBatch;
+use crate::db::{DBImpl, DB};
+use crate::options::Options;
+use crate::util::error::{Error, Result};
+use bytes::Bytes;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+pub mod lock_manager;
+
+/// `TransactionDBOptions` controls the locking of the transactions of a `TransactionDB`
+#[derive(Clone, Debug)]
+pub struct TransactionDBOptions {
+    /// how long a transaction waits for a key locked by another transaction by default,
+    /// see `Transaction::set_lock_timeout`
+    pub lock_timeout: Duration,
+    /// whether a transaction about to wait for a lock checks that the wait does not close a
+    /// cycle of transactions waiting for each other
+    pub deadlock_detect: bool,
+}
+
+impl Default for TransactionDBOptions {
+    fn default() -> Self {
+        TransactionDBOptions {
+            lock_timeout: Duration::from_secs(1),
+            deadlock_detect: true,
+        }
+    }
+}
+
+/// `TransactionDB` runs pessimistic transactions over a database. A transaction locks every
+/// key it writes or reads by `Transaction::get_for_update` until it commits or rolls back,
+/// other transactions touching the key wait for it. Suitable for contended keys, for which
+/// the retries of `OptimisticTransaction` would keep failing.
+///
+/// The writes made through `db` bypass the locks.
+pub struct TransactionDB {
+    db: DBImpl,
+    options: TransactionDBOptions,
+    lock_manager: LockManager,
+    next_transaction_id: AtomicU64,
+}
+
+impl TransactionDB {
+    /// Open the database in directory `dir`, see `DBImpl::open`
+    pub fn open(dir: &str, options: Options, txn_db_options: TransactionDBOptions) -> Result<Self> {
+        Ok(Self::new(DBImpl::open(dir, options)?, txn_db_options))
+    }
+
+    pub fn new(db: DBImpl, options: TransactionDBOptions) -> Self {
+        TransactionDB {
+            db,
+            options,
+            lock_manager: LockManager::new(),
+            next_transaction_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Return the underlying database
+    pub fn db(&self) -> &DBImpl {
+        &self.db
+    }
+
+    pub fn begin_transaction(&self) -> Transaction<'_> {
+        Transaction {
+            txn_db: self,
+            id: self.next_transaction_id.fetch_add(1, Ordering::SeqCst),
+            lock_timeout: self.options.lock_timeout,
+            batch: WriteBatch::new(),
+            locked_keys: HashSet::new(),
+            aborted: false,
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.db.get(key)
+    }
+
+    /// Put `key` by a transaction of its own, which waits for the lock of the key
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut txn = self.begin_transaction();
+        txn.put(key, value)?;
+        txn.commit()
+    }
+
+    /// Delete `key` by a transaction of its own, which waits for the lock of the key
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        let mut txn = self.begin_transaction();
+        txn.delete(key)?;
+        txn.commit()
+    }
+}
+
+/// `Transaction` is a pessimistic transaction of a `TransactionDB`. The writes are buffered
+/// until commit, the keys written or read by `get_for_update` stay locked until the
+/// transaction commits, rolls back or is dropped.
+///
+/// A transaction chosen as the victim of a deadlock is aborted, its locks are released and
+/// its writes are discarded, every later operation fails with `Busy`.
+///
+/// The reads do not see the writes buffered by the transaction itself.
+pub struct Transaction<'a> {
+    txn_db: &'a TransactionDB,
+    id: u64,
+    lock_timeout: Duration,
+    batch: WriteBatch,
+    locked_keys: HashSet<LockKey>,
+    aborted: bool,
+}
+
+impl Transaction<'_> {
+    /// Set how long the transaction waits for a key locked by another transaction
+    pub fn set_lock_timeout(&mut self, lock_timeout: Duration) {
+        self.lock_timeout = lock_timeout;
+    }
+
+    /// Read `key` without locking it
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_cf(&ColumnFamilyHandle::default(), key)
+    }
+
+    pub fn get_cf(&self, cf: &ColumnFamilyHandle, key: &[u8]) -> Result<Option<Bytes>> {
+        self.check_aborted()?;
+        self.txn_db.db.get_cf(cf, key)
+    }
+
+    /// Lock `key` exclusively and read it, the key stays locked until the transaction ends.
+    /// Failed with `TimedOut` if the lock is not acquired within the lock timeout, or
+    /// `Deadlock` if the transaction is aborted to break a deadlock.
+    pub fn get_for_update(&mut self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_for_update_cf(&ColumnFamilyHandle::default(), key)
+    }
+
+    pub fn get_for_update_cf(
+        &mut self,
+        cf: &ColumnFamilyHandle,
+        key: &[u8],
+    ) -> Result<Option<Bytes>> {
+        self.lock(cf, key)?;
+        self.txn_db.db.get_cf(cf, key)
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_cf(&ColumnFamilyHandle::default(), key, value)
+    }
+
+    pub fn put_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8], value: &[u8]) -> Result<()> {
+        self.lock(cf, key)?;
+        self.batch.put_cf(cf, key, value);
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.delete_cf(&ColumnFamilyHandle::default(), key)
+    }
+
+    pub fn delete_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8]) -> Result<()> {
+        self.lock(cf, key)?;
+        self.batch.delete_cf(cf, key);
+        Ok(())
+    }
+
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.merge_cf(&ColumnFamilyHandle::default(), key, operand)
+    }
+
+    pub fn merge_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.lock(cf, key)?;
+        self.batch.merge_cf(cf, key, operand);
+        Ok(())
+    }
+
+    /// Apply the writes of the transaction atomically and release the locks
+    pub fn commit(mut self) -> Result<()> {
+        self.check_aborted()?;
+        let batch = std::mem::take(&mut self.batch);
+        self.txn_db.db.write(batch)
+    }
+
+    /// Discard the writes of the transaction and release the locks
+    pub fn rollback(self) {}
+
+    fn lock(&mut self, cf: &ColumnFamilyHandle, key: &[u8]) -> Result<()> {
+        self.check_aborted()?;
+        let lock_key = (cf.id(), Bytes::copy_from_slice(key));
+        let ret = self.txn_db.lock_manager.lock(
+            self.id,
+            lock_key.clone(),
+            self.lock_timeout,
+            self.txn_db.options.deadlock_detect,
+        );
+        match ret {
+            Ok(()) => {
+                self.locked_keys.insert(lock_key);
+                Ok(())
+            }
+            Err(e @ Error::Deadlock { .. }) => {
+                self.abort();
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Release the locks and discard the writes
+    fn abort(&mut self) {
+        self.aborted = true;
+        self.batch.clear();
+        self.release_locks();
+    }
+
+    fn release_locks(&mut self) {
+        let keys: Vec<LockKey> = self.locked_keys.drain().collect();
+        self.txn_db.lock_manager.unlock(self.id, keys.as_slice());
+    }
+
+    fn check_aborted(&self) -> Result<()> {
+        if self.aborted {
+            return Err(Error::Busy(
+                "the transaction is aborted to break a deadlock".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.release_locks();
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,120 @@
+use crate::util::error::{Error, Result};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A locked key, made of the column family id and the user key
+pub type LockKey = (u32, Bytes);
+
+/// `LockManager` hands out exclusive per-key locks to transactions, identified by their ids.
+///
+/// A transaction waits for one key at a time, so the wait-for graph has at most one edge out
+/// of every transaction: from the waiting transaction to the owner of the key. A cycle is
+/// searched for every time a transaction is about to wait, the transaction closing the cycle
+/// is the victim.
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    /// notified whenever a key is unlocked
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct LockTable {
+    /// the transaction holding each locked key
+    owners: HashMap<LockKey, u64>,
+    /// the key each waiting transaction waits for
+    waiting: HashMap<u64, LockKey>,
+}
+
+impl LockTable {
+    /// Return the keys of the cycle `txn` would close by waiting for `key`, from `key` on
+    fn find_cycle(&self, txn: u64, key: &LockKey) -> Option<Vec<Vec<u8>>> {
+        let mut keys = vec![key.1.to_vec()];
+        let mut owner = *self.owners.get(key)?;
+        // every transaction shows up at most once on a path without cycle
+        for _ in 0..=self.waiting.len() {
+            if owner == txn {
+                return Some(keys);
+            }
+            let next = self.waiting.get(&owner)?;
+            keys.push(next.1.to_vec());
+            owner = *self.owners.get(next)?;
+        }
+        None
+    }
+}
+
+impl Default for LockManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        LockManager {
+            table: Mutex::new(LockTable::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Lock `key` for the transaction `txn`, waiting at most `timeout` for the owner to
+    /// release it. Locking a key already held by `txn` succeeds at once.
+    /// Failed with `TimedOut` if the key is not released in time, or `Deadlock` if
+    /// `deadlock_detect` is set and waiting for the key closes a cycle of transactions
+    /// waiting for each other.
+    pub fn lock(
+        &self,
+        txn: u64,
+        key: LockKey,
+        timeout: Duration,
+        deadlock_detect: bool,
+    ) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut table = self.table.lock().unwrap();
+        loop {
+            match table.owners.get(&key) {
+                None => {
+                    table.waiting.remove(&txn);
+                    table.owners.insert(key, txn);
+                    return Ok(());
+                }
+                Some(owner) if *owner == txn => {
+                    table.waiting.remove(&txn);
+                    return Ok(());
+                }
+                Some(_) => {}
+            }
+            if deadlock_detect {
+                if let Some(keys) = table.find_cycle(txn, &key) {
+                    table.waiting.remove(&txn);
+                    return Err(Error::Deadlock { keys });
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                table.waiting.remove(&txn);
+                return Err(Error::TimedOut(format!(
+                    "lock of key {:?} is not released within {:?}",
+                    key.1, timeout
+                )));
+            }
+            table.waiting.insert(txn, key.clone());
+            table = self.released.wait_timeout(table, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Release the `keys` held by the transaction `txn`, the keys held by others are left
+    /// untouched
+    pub fn unlock(&self, txn: u64, keys: &[LockKey]) {
+        let mut table = self.table.lock().unwrap();
+        for key in keys {
+            if table.owners.get(key) == Some(&txn) {
+                table.owners.remove(key);
+            }
+        }
+        table.waiting.remove(&txn);
+        self.released.notify_all();
+    }
+}
@@ -0,0 +1,133 @@
+use crate::db::transaction_db::{TransactionDB, TransactionDBOptions};
+use crate::options::test_options;
+use crate::util::env::test_env;
+use crate::util::error::Error;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// `TestTransactionDB` opens a database in its own directory and removes the directory on
+/// drop
+struct TestTransactionDB {
+    path: String,
+    txn_db: TransactionDB,
+}
+
+impl TestTransactionDB {
+    fn new(name: &str, options: TransactionDBOptions) -> Self {
+        let path = format!("/summer_kv_test/txn_db_{}", name);
+        let _ = test_env().remove_dir_all(path.as_str());
+        let txn_db = TransactionDB::open(path.as_str(), test_options(), options).unwrap();
+        TestTransactionDB { path, txn_db }
+    }
+}
+
+impl Drop for TestTransactionDB {
+    fn drop(&mut self) {
+        let _ = test_env().remove_dir_all(self.path.as_str());
+    }
+}
+
+#[test]
+fn test_get_for_update_blocks_writers() {
+    let test_db = TestTransactionDB::new("blocks_writers", TransactionDBOptions::default());
+    let txn_db = &test_db.txn_db;
+    txn_db.put(b"balance", b"100").unwrap();
+
+    let mut txn = txn_db.begin_transaction();
+    assert_eq!(txn.get_for_update(b"balance").unwrap(), Some("100".into()));
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            // waits until the first transaction commits
+            txn_db.put(b"balance", b"0").unwrap();
+            sender.send(()).unwrap();
+        });
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        txn.put(b"balance", b"50").unwrap();
+        txn.commit().unwrap();
+        receiver.recv().unwrap();
+    });
+    assert_eq!(txn_db.get(b"balance").unwrap(), Some("0".into()));
+}
+
+#[test]
+fn test_lock_timeout() {
+    let options = TransactionDBOptions {
+        lock_timeout: Duration::from_millis(50),
+        ..TransactionDBOptions::default()
+    };
+    let test_db = TestTransactionDB::new("lock_timeout", options);
+    let txn_db = &test_db.txn_db;
+    let mut owner = txn_db.begin_transaction();
+    owner.put(b"a", b"1").unwrap();
+    let mut waiter = txn_db.begin_transaction();
+    assert!(matches!(
+        waiter.get_for_update(b"a"),
+        Err(Error::TimedOut(_))
+    ));
+    // a timeout does not abort the transaction
+    waiter.put(b"b", b"2").unwrap();
+    owner.rollback();
+    waiter.set_lock_timeout(Duration::ZERO);
+    assert_eq!(waiter.get_for_update(b"a").unwrap(), None);
+    waiter.commit().unwrap();
+    assert_eq!(txn_db.get(b"a").unwrap(), None);
+    assert_eq!(txn_db.get(b"b").unwrap(), Some("2".into()));
+}
+
+#[test]
+fn test_deadlock_detection() {
+    let options = TransactionDBOptions {
+        lock_timeout: Duration::from_secs(10),
+        ..TransactionDBOptions::default()
+    };
+    let test_db = TestTransactionDB::new("deadlock_detection", options);
+    let txn_db = &test_db.txn_db;
+    let mut first = txn_db.begin_transaction();
+    let mut second = txn_db.begin_transaction();
+    first.put(b"a", b"first").unwrap();
+    second.put(b"b", b"second").unwrap();
+    std::thread::scope(|scope| {
+        let waiting = scope.spawn(move || {
+            // waits for `second`, which is aborted below
+            first.put(b"b", b"first").unwrap();
+            first.commit().unwrap();
+        });
+        // let `first` start waiting
+        std::thread::sleep(Duration::from_millis(100));
+        match second.get_for_update(b"a") {
+            Err(Error::Deadlock { keys }) => {
+                assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec()]);
+            }
+            _ => panic!("expect deadlock"),
+        }
+        assert!(matches!(second.put(b"c", b"c"), Err(Error::Busy(_))));
+        assert!(matches!(second.commit(), Err(Error::Busy(_))));
+        waiting.join().unwrap();
+    });
+    assert_eq!(txn_db.get(b"a").unwrap(), Some("first".into()));
+    assert_eq!(txn_db.get(b"b").unwrap(), Some("first".into()));
+    assert_eq!(txn_db.get(b"c").unwrap(), None);
+}
+
+#[test]
+fn test_concurrent_increments() {
+    let test_db = TestTransactionDB::new("concurrent_increments", TransactionDBOptions::default());
+    let txn_db = &test_db.txn_db;
+    txn_db.put(b"counter", &0u64.to_le_bytes()).unwrap();
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..25 {
+                    let mut txn = txn_db.begin_transaction();
+                    let value = txn.get_for_update(b"counter").unwrap().unwrap();
+                    let counter = u64::from_le_bytes(value.as_ref().try_into().unwrap());
+                    txn.put(b"counter", &(counter + 1).to_le_bytes()).unwrap();
+                    txn.commit().unwrap();
+                }
+            });
+        }
+    });
+    let value = txn_db.get(b"counter").unwrap().unwrap();
+    assert_eq!(value.as_ref(), 100u64.to_le_bytes());
+}
@@ -0,0 +1,996 @@
+use crate::comparator::{Comparator, BYTEWISE_COMPARATOR_NAME};
+use crate::db::blob::BlobFile;
+use crate::db::column_family::{
+    ColumnFamilyData, DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY_NAME,
+};
+use crate::db::format::SequenceNumber;
+use crate::options::Options;
+use crate::sstable::SSTable;
+use crate::util::coding::{
+    get_length_prefixed_slice, get_varint32, get_varint64, put_length_prefixed_slice, put_varint32,
+    put_varint64,
+};
+use crate::util::env::{
+    blobfile_path, current_path, manifest_path, parse_file_name, read_file, write_file_atomically,
+    Env, WritableFile,
+};
+use crate::util::error::{Error, Result};
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+/// number of levels of the LSM tree
+pub const NUM_LEVELS: usize = 7;
+
+/// `FileMetaData` describes a SSTable belonging to a level
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileMetaData {
+    pub number: u64,
+    pub file_size: u64,
+    /// smallest and largest key of the SSTable, see `SSTable::smallest_key`
+    pub smallest: Bytes,
+    pub largest: Bytes,
+    /// the blob files the records of the SSTable refer to, see `db::blob`
+    pub blob_files: Vec<u64>,
+    /// the sequence number of every record of an ingested SSTable, see `SSTable::open_file`
+    pub global_seq: Option<SequenceNumber>,
+}
+
+impl FileMetaData {
+    /// Describe `sstable` whose records refer to `blob_files`
+    pub fn from_sstable(sstable: &SSTable, blob_files: Vec<u64>) -> Result<Self> {
+        Ok(FileMetaData {
+            number: sstable.seq() as u64,
+            file_size: sstable.file_size()?,
+            smallest: Bytes::copy_from_slice(sstable.smallest_key()),
+            largest: Bytes::copy_from_slice(sstable.largest_key()),
+            blob_files,
+            global_seq: sstable.global_seq(),
+        })
+    }
+
+    /// Whether the key range of the file overlaps `[smallest, largest]` in the order of
+    /// `comparator`
+    pub fn overlaps(&self, smallest: &[u8], largest: &[u8], comparator: &dyn Comparator) -> bool {
+        comparator.compare(&self.smallest, largest).is_le()
+            && comparator.compare(smallest, &self.largest).is_le()
+    }
+}
+
+const TAG_LOG_NUMBER: u32 = 1;
+const TAG_NEXT_FILE_NUMBER: u32 = 2;
+const TAG_LAST_SEQUENCE: u32 = 3;
+const TAG_DELETED_FILE: u32 = 4;
+const TAG_NEW_FILE: u32 = 5;
+const TAG_COLUMN_FAMILY: u32 = 6;
+const TAG_COLUMN_FAMILY_ADD: u32 = 7;
+const TAG_COLUMN_FAMILY_DROP: u32 = 8;
+const TAG_MAX_COLUMN_FAMILY: u32 = 9;
+/// `BlobFileMetaData` describes a blob file of a column family
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlobFileMetaData {
+    pub number: u64,
+    pub file_size: u64,
+}
+
+const TAG_COMPARATOR: u32 = 10;
+const TAG_NEW_FILE_WITH_BLOBS: u32 = 11;
+const TAG_NEW_BLOB_FILE: u32 = 12;
+const TAG_DELETED_BLOB_FILE: u32 = 13;
+const TAG_INGESTED_FILE: u32 = 14;
+
+/// `VersionEdit` is the difference between two versions of the LSM tree, the MANIFEST file
+/// is a sequence of edits, replaying them from the beginning rebuilds the latest version.
+///
+/// An edit is encoded as a sequence of tagged fields, every field starts with a varint tag:
+/// ``` text
+/// +-------------------------------------------------------------------------------+
+/// | 1 | log number                                                                 |
+/// | 2 | next file number                                                           |
+/// | 3 | last sequence                                                              |
+/// | 4 | level | file number                                     (deleted file)     |
+/// | 5 | level | file number | file size | smallest | largest    (new file)         |
+/// | 6 | column family id                                                           |
+/// | 7 | column family name                              (column family created)    |
+/// | 8 |                                                 (column family dropped)    |
+/// | 9 | max column family id                                                       |
+/// | 10| comparator name                                                            |
+/// | 11| level | file number | file size | smallest | largest | blob file count     |
+/// |   | | blob file number | ...                  (new file referring to blobs)    |
+/// | 12| blob file number | file size                          (new blob file)      |
+/// | 13| blob file number                                    (deleted blob file)    |
+/// | 14| level | file number | file size | smallest | largest | global sequence     |
+/// |   |                                                       (ingested file)      |
+/// +-------------------------------------------------------------------------------+
+/// ```
+/// numbers are varint, keys and names are length prefixed. Every edit is stored in the
+/// MANIFEST prefixed with its length as a varint. The column family id is omitted for the
+/// default column family. The comparator name is recorded along with the creation of the
+/// column family, a column family without it is in the bytewise order.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct VersionEdit {
+    /// id of the column family the edit applies to
+    pub column_family: u32,
+    /// the edit creates the column family `column_family` by the name
+    pub column_family_add: Option<String>,
+    /// the edit drops the column family `column_family`
+    pub column_family_drop: bool,
+    /// the greatest id of the column families ever created
+    pub max_column_family: Option<u32>,
+    /// the name of the comparator the column family is created with
+    pub comparator: Option<String>,
+    /// logs numbered less than `log_number` hold no record of the column family which is not
+    /// flushed yet
+    pub log_number: Option<u64>,
+    pub next_file_number: Option<u64>,
+    pub last_sequence: Option<SequenceNumber>,
+    pub deleted_files: Vec<(usize, u64)>,
+    pub new_files: Vec<(usize, FileMetaData)>,
+    pub deleted_blob_files: Vec<u64>,
+    pub new_blob_files: Vec<BlobFileMetaData>,
+}
+
+impl VersionEdit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn delete_file(&mut self, level: usize, number: u64) {
+        self.deleted_files.push((level, number));
+    }
+
+    pub fn add_file(&mut self, level: usize, file: FileMetaData) {
+        self.new_files.push((level, file));
+    }
+
+    pub fn delete_blob_file(&mut self, number: u64) {
+        self.deleted_blob_files.push(number);
+    }
+
+    pub fn add_blob_file(&mut self, file: BlobFileMetaData) {
+        self.new_blob_files.push(file);
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if self.column_family != 0 {
+            put_varint32(&mut buf, TAG_COLUMN_FAMILY);
+            put_varint32(&mut buf, self.column_family);
+        }
+        if let Some(name) = &self.column_family_add {
+            put_varint32(&mut buf, TAG_COLUMN_FAMILY_ADD);
+            put_length_prefixed_slice(&mut buf, name.as_bytes());
+        }
+        if self.column_family_drop {
+            put_varint32(&mut buf, TAG_COLUMN_FAMILY_DROP);
+        }
+        if let Some(comparator) = &self.comparator {
+            put_varint32(&mut buf, TAG_COMPARATOR);
+            put_length_prefixed_slice(&mut buf, comparator.as_bytes());
+        }
+        if let Some(max_column_family) = self.max_column_family {
+            put_varint32(&mut buf, TAG_MAX_COLUMN_FAMILY);
+            put_varint32(&mut buf, max_column_family);
+        }
+        if let Some(log_number) = self.log_number {
+            put_varint32(&mut buf, TAG_LOG_NUMBER);
+            put_varint64(&mut buf, log_number);
+        }
+        if let Some(next_file_number) = self.next_file_number {
+            put_varint32(&mut buf, TAG_NEXT_FILE_NUMBER);
+            put_varint64(&mut buf, next_file_number);
+        }
+        if let Some(last_sequence) = self.last_sequence {
+            put_varint32(&mut buf, TAG_LAST_SEQUENCE);
+            put_varint64(&mut buf, last_sequence);
+        }
+        for (level, number) in &self.deleted_files {
+            put_varint32(&mut buf, TAG_DELETED_FILE);
+            put_varint32(&mut buf, *level as u32);
+            put_varint64(&mut buf, *number);
+        }
+        for (level, file) in &self.new_files {
+            // the ingested files never refer to blob files
+            if file.global_seq.is_some() {
+                put_varint32(&mut buf, TAG_INGESTED_FILE);
+            } else if file.blob_files.is_empty() {
+                put_varint32(&mut buf, TAG_NEW_FILE);
+            } else {
+                put_varint32(&mut buf, TAG_NEW_FILE_WITH_BLOBS);
+            }
+            put_varint32(&mut buf, *level as u32);
+            put_varint64(&mut buf, file.number);
+            put_varint64(&mut buf, file.file_size);
+            put_length_prefixed_slice(&mut buf, file.smallest.as_ref());
+            put_length_prefixed_slice(&mut buf, file.largest.as_ref());
+            if let Some(global_seq) = file.global_seq {
+                put_varint64(&mut buf, global_seq);
+            } else if !file.blob_files.is_empty() {
+                put_varint32(&mut buf, file.blob_files.len() as u32);
+                for number in &file.blob_files {
+                    put_varint64(&mut buf, *number);
+                }
+            }
+        }
+        for number in &self.deleted_blob_files {
+            put_varint32(&mut buf, TAG_DELETED_BLOB_FILE);
+            put_varint64(&mut buf, *number);
+        }
+        for file in &self.new_blob_files {
+            put_varint32(&mut buf, TAG_NEW_BLOB_FILE);
+            put_varint64(&mut buf, file.number);
+            put_varint64(&mut buf, file.file_size);
+        }
+        buf
+    }
+
+    /// Decode the edit produced by `VersionEdit::encode`.
+    /// Failed with `Corruption` if any field is broken, the offset of the `Corruption` is
+    /// relative to the beginning of `raw`
+    pub fn decode(raw: &[u8]) -> Result<Self> {
+        let mut edit = VersionEdit::new();
+        let mut offset = 0;
+        while offset < raw.len() {
+            let field_offset = offset;
+            let corruption = || Error::corruption("", field_offset as u64);
+            let next_u64 = |offset: &mut usize| -> Result<u64> {
+                let (value, consumed) = get_varint64(&raw[*offset..]).ok_or_else(corruption)?;
+                *offset += consumed;
+                Ok(value)
+            };
+            let (tag, consumed) = get_varint32(&raw[offset..]).ok_or_else(corruption)?;
+            offset += consumed;
+            match tag {
+                TAG_COLUMN_FAMILY => edit.column_family = next_u64(&mut offset)? as u32,
+                TAG_COLUMN_FAMILY_ADD => {
+                    let (name, consumed) =
+                        get_length_prefixed_slice(&raw[offset..]).ok_or_else(corruption)?;
+                    offset += consumed;
+                    let name = std::str::from_utf8(name).map_err(|_| corruption())?;
+                    edit.column_family_add = Some(name.to_string());
+                }
+                TAG_COLUMN_FAMILY_DROP => edit.column_family_drop = true,
+                TAG_COMPARATOR => {
+                    let (name, consumed) =
+                        get_length_prefixed_slice(&raw[offset..]).ok_or_else(corruption)?;
+                    offset += consumed;
+                    let name = std::str::from_utf8(name).map_err(|_| corruption())?;
+                    edit.comparator = Some(name.to_string());
+                }
+                TAG_MAX_COLUMN_FAMILY => {
+                    edit.max_column_family = Some(next_u64(&mut offset)? as u32)
+                }
+                TAG_LOG_NUMBER => edit.log_number = Some(next_u64(&mut offset)?),
+                TAG_NEXT_FILE_NUMBER => edit.next_file_number = Some(next_u64(&mut offset)?),
+                TAG_LAST_SEQUENCE => edit.last_sequence = Some(next_u64(&mut offset)?),
+                TAG_DELETED_FILE => {
+                    let level = next_u64(&mut offset)? as usize;
+                    let number = next_u64(&mut offset)?;
+                    if level >= NUM_LEVELS {
+                        return Err(corruption());
+                    }
+                    edit.delete_file(level, number);
+                }
+                TAG_NEW_FILE | TAG_NEW_FILE_WITH_BLOBS | TAG_INGESTED_FILE => {
+                    let level = next_u64(&mut offset)? as usize;
+                    let number = next_u64(&mut offset)?;
+                    let file_size = next_u64(&mut offset)?;
+                    let (smallest, consumed) =
+                        get_length_prefixed_slice(&raw[offset..]).ok_or_else(corruption)?;
+                    offset += consumed;
+                    let (largest, consumed) =
+                        get_length_prefixed_slice(&raw[offset..]).ok_or_else(corruption)?;
+                    offset += consumed;
+                    let mut blob_files = Vec::new();
+                    if tag == TAG_NEW_FILE_WITH_BLOBS {
+                        for _ in 0..next_u64(&mut offset)? {
+                            blob_files.push(next_u64(&mut offset)?);
+                        }
+                    }
+                    let mut global_seq = None;
+                    if tag == TAG_INGESTED_FILE {
+                        global_seq = Some(next_u64(&mut offset)?);
+                    }
+                    if level >= NUM_LEVELS {
+                        return Err(corruption());
+                    }
+                    edit.add_file(
+                        level,
+                        FileMetaData {
+                            number,
+                            file_size,
+                            smallest: Bytes::copy_from_slice(smallest),
+                            largest: Bytes::copy_from_slice(largest),
+                            blob_files,
+                            global_seq,
+                        },
+                    );
+                }
+                TAG_NEW_BLOB_FILE => {
+                    let number = next_u64(&mut offset)?;
+                    let file_size = next_u64(&mut offset)?;
+                    edit.add_blob_file(BlobFileMetaData { number, file_size });
+                }
+                TAG_DELETED_BLOB_FILE => edit.delete_blob_file(next_u64(&mut offset)?),
+                _ => return Err(corruption()),
+            }
+        }
+        Ok(edit)
+    }
+}
+
+/// `Version` is the set of SSTables of every level at some moment. Files of level 0 are
+/// sorted by file number, the newer the later, they may overlap with each other. Files of
+/// the other levels are sorted by the smallest key and never overlap. The keys are compared
+/// by the comparator of the column family.
+///
+/// The version also holds the blob files the SSTables refer to, ordered by file number
+/// which is the order they are written in.
+#[derive(Clone)]
+pub struct Version {
+    pub levels: Vec<Vec<Arc<FileMetaData>>>,
+    pub blob_files: BTreeMap<u64, BlobFileMetaData>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl Version {
+    pub fn new(comparator: Arc<dyn Comparator>) -> Self {
+        Version {
+            levels: vec![Vec::new(); NUM_LEVELS],
+            blob_files: BTreeMap::new(),
+            comparator,
+        }
+    }
+
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    /// Replace the comparator and sort the levels by it again
+    pub fn set_comparator(&mut self, comparator: Arc<dyn Comparator>) {
+        self.comparator = comparator;
+        for level in 1..NUM_LEVELS {
+            self.sort_level(level);
+        }
+    }
+
+    fn sort_level(&mut self, level: usize) {
+        let comparator = self.comparator.clone();
+        self.levels[level].sort_by(|a, b| comparator.compare(&a.smallest, &b.smallest));
+    }
+
+    pub fn apply(&mut self, edit: &VersionEdit) {
+        for (level, number) in &edit.deleted_files {
+            self.levels[*level].retain(|x| x.number != *number);
+        }
+        for (level, file) in &edit.new_files {
+            self.levels[*level].push(Arc::new(file.clone()));
+            if *level == 0 {
+                self.levels[0].sort_by_key(|x| x.number);
+            } else {
+                self.sort_level(*level);
+            }
+        }
+        for number in &edit.deleted_blob_files {
+            self.blob_files.remove(number);
+        }
+        for file in &edit.new_blob_files {
+            self.blob_files.insert(file.number, file.clone());
+        }
+    }
+
+    /// Return the blob files no SSTable refers to
+    pub fn unreferenced_blob_files(&self) -> Vec<u64> {
+        let referenced: HashSet<u64> = self
+            .levels
+            .iter()
+            .flatten()
+            .flat_map(|x| x.blob_files.iter().copied())
+            .collect();
+        self.blob_files
+            .keys()
+            .copied()
+            .filter(|x| !referenced.contains(x))
+            .collect()
+    }
+
+    /// Return the files of `level` which overlap `[smallest, largest]`
+    pub fn overlapping_files(
+        &self,
+        level: usize,
+        smallest: &[u8],
+        largest: &[u8],
+    ) -> Vec<Arc<FileMetaData>> {
+        self.levels[level]
+            .iter()
+            .filter(|x| x.overlaps(smallest, largest, self.comparator.as_ref()))
+            .cloned()
+            .collect()
+    }
+
+    pub fn level_size(&self, level: usize) -> u64 {
+        self.levels[level].iter().map(|x| x.file_size).sum()
+    }
+
+    /// Return the files whose key range contains `key`, from the newest to the oldest
+    pub fn files_for_key(&self, key: &[u8]) -> Vec<Arc<FileMetaData>> {
+        let mut files: Vec<Arc<FileMetaData>> = self.levels[0]
+            .iter()
+            .rev()
+            .filter(|x| x.overlaps(key, key, self.comparator.as_ref()))
+            .cloned()
+            .collect();
+        for level in &self.levels[1..] {
+            files.extend(
+                level
+                    .iter()
+                    .filter(|x| x.overlaps(key, key, self.comparator.as_ref()))
+                    .cloned(),
+            );
+        }
+        files
+    }
+}
+
+/// `VersionSet` keeps the column families of the database along with the MANIFEST recording
+/// how their versions come. The default column family always exists, the other ones are
+/// created and dropped by the edits.
+pub struct VersionSet {
+    dir: String,
+    env: Arc<dyn Env>,
+    manifest_path: String,
+    /// the MANIFEST opened to append the edits, which is only opened by the first write so
+    /// that nothing is written unless the database is opened as the primary
+    manifest: Option<Box<dyn WritableFile>>,
+    pub next_file_number: u64,
+    pub last_sequence: SequenceNumber,
+    max_column_family: u32,
+    column_families: BTreeMap<u32, ColumnFamilyData>,
+    /// the size of the MANIFEST replayed, see `catch_up`
+    replayed_manifest_size: u64,
+    /// the SSTables no longer in any current version, their files are deleted once no
+    /// reader holds them, see `obsolete_files`
+    obsolete_tables: Vec<Arc<SSTable>>,
+}
+
+/// The options of the column families replayed from the MANIFEST before they are opened,
+/// which are the default ones on `env`
+fn default_options(env: &Arc<dyn Env>) -> Arc<Options> {
+    Arc::new(Options {
+        env: env.clone(),
+        ..Options::default()
+    })
+}
+
+impl VersionSet {
+    /// Load the column families recorded by the MANIFEST named in the CURRENT file of `dir`,
+    /// a new MANIFEST is created if there is no CURRENT file. `column_families` gives the
+    /// options of every column family by name, including the default one.
+    ///
+    /// Failed with `InvalidArgument` if any column family of the database is missing from
+    /// `column_families`, any of `column_families` does not exist, or the comparator of any
+    /// column family differs from the one it is created with.
+    pub fn recover(dir: &str, column_families: &HashMap<String, Arc<Options>>) -> Result<Self> {
+        let options = column_families
+            .get(DEFAULT_COLUMN_FAMILY_NAME)
+            .cloned()
+            .unwrap_or_default();
+        let version_set = match Self::load(dir, options.env.clone())? {
+            Some(mut version_set) => {
+                version_set.roll_manifest()?;
+                version_set
+            }
+            None => Self::create(dir, options.env.clone(), options.comparator.name())?,
+        };
+        version_set.open_column_families(column_families)
+    }
+
+    /// Like `recover`, but nothing is written to `dir`.
+    /// Failed with `NotFound` if there is no database in `dir`
+    pub fn recover_read_only(
+        dir: &str,
+        column_families: &HashMap<String, Arc<Options>>,
+    ) -> Result<Self> {
+        let options = column_families
+            .get(DEFAULT_COLUMN_FAMILY_NAME)
+            .cloned()
+            .unwrap_or_default();
+        Self::load(dir, options.env.clone())?
+            .ok_or(Error::NotFound)?
+            .open_column_families(column_families)
+    }
+
+    /// Check `column_families` against the column families recorded in the MANIFEST, then
+    /// open them with the given options, see `recover`
+    fn open_column_families(
+        mut self,
+        column_families: &HashMap<String, Arc<Options>>,
+    ) -> Result<Self> {
+        for name in column_families.keys() {
+            if self.column_family_by_name(name).is_none() {
+                return Err(Error::InvalidArgument(format!(
+                    "column family {} does not exist",
+                    name
+                )));
+            }
+        }
+        for cfd in self.column_families.values_mut() {
+            let options = column_families.get(cfd.name()).ok_or_else(|| {
+                Error::InvalidArgument(format!("column family {} is not opened", cfd.name()))
+            })?;
+            let comparator_name = cfd.comparator_name().unwrap_or(BYTEWISE_COMPARATOR_NAME);
+            if comparator_name != options.comparator.name() {
+                return Err(Error::InvalidArgument(format!(
+                    "column family {} is created with comparator {}, not {}",
+                    cfd.name(),
+                    comparator_name,
+                    options.comparator.name()
+                )));
+            }
+            cfd.open_tables(self.dir.as_str(), options.clone())?;
+        }
+        Ok(self)
+    }
+
+    /// Return the names of the column families of the database in `dir` of `env`
+    pub fn list_column_families(dir: &str, env: Arc<dyn Env>) -> Result<Vec<String>> {
+        let version_set = Self::load(dir, env)?.ok_or(Error::NotFound)?;
+        Ok(version_set
+            .column_families()
+            .map(|x| x.name().to_string())
+            .collect())
+    }
+
+    fn new(
+        dir: &str,
+        env: Arc<dyn Env>,
+        manifest_path: String,
+        manifest: Option<Box<dyn WritableFile>>,
+    ) -> Self {
+        let default = ColumnFamilyData::new(
+            DEFAULT_COLUMN_FAMILY_ID,
+            DEFAULT_COLUMN_FAMILY_NAME,
+            default_options(&env),
+            0,
+        );
+        VersionSet {
+            dir: dir.to_string(),
+            env,
+            manifest_path,
+            manifest,
+            next_file_number: 2,
+            last_sequence: 0,
+            max_column_family: DEFAULT_COLUMN_FAMILY_ID,
+            column_families: BTreeMap::from([(DEFAULT_COLUMN_FAMILY_ID, default)]),
+            replayed_manifest_size: 0,
+            obsolete_tables: Vec::new(),
+        }
+    }
+
+    /// Replay the MANIFEST named in the CURRENT file of `dir`, `None` if there is no CURRENT
+    /// file. The column families are given the default options and the SSTables are not
+    /// opened yet.
+    fn load(dir: &str, env: Arc<dyn Env>) -> Result<Option<Self>> {
+        let current = current_path(dir);
+        if !env.file_exists(current.as_str()) {
+            return Ok(None);
+        }
+        let manifest_path = Self::current_manifest_path(dir, env.as_ref())?;
+        let mut version_set = VersionSet::new(dir, env, manifest_path, None);
+        version_set.replay_manifest()?;
+        Ok(Some(version_set))
+    }
+
+    /// Return the path of the MANIFEST named in the CURRENT file of `dir`
+    fn current_manifest_path(dir: &str, env: &dyn Env) -> Result<String> {
+        let current = current_path(dir);
+        let manifest_name = read_file(env, current.as_str())?;
+        let manifest_name = std::str::from_utf8(manifest_name.as_ref())
+            .map_err(|_| Error::corruption(current.as_str(), 0))?
+            .trim_end();
+        Ok(format!("{}/{}", dir, manifest_name))
+    }
+
+    /// Go on with a new MANIFEST starting with a snapshot of the current versions, so that
+    /// the edits do not pile up in one MANIFEST across the opens. The previous MANIFEST is
+    /// deleted by `obsolete_files`.
+    fn roll_manifest(&mut self) -> Result<()> {
+        let manifest_number = self.new_file_number();
+        self.write_snapshot(self.dir(), manifest_number)?;
+        self.manifest_path = manifest_path(self.dir(), manifest_number as usize);
+        self.manifest = None;
+        self.replayed_manifest_size = 0;
+        Ok(())
+    }
+
+    /// Replay the MANIFEST the primary rolled to since it is replayed last time, see
+    /// `roll_manifest`. The new MANIFEST starts with a snapshot of the versions which
+    /// replaces the ones replayed so far, the column families keep their options, memtables
+    /// and opened files.
+    fn switch_manifest(&mut self, manifest_path: String) -> Result<()> {
+        let default = ColumnFamilyData::new(
+            DEFAULT_COLUMN_FAMILY_ID,
+            DEFAULT_COLUMN_FAMILY_NAME,
+            default_options(&self.env),
+            0,
+        );
+        let mut previous = std::mem::replace(
+            &mut self.column_families,
+            BTreeMap::from([(DEFAULT_COLUMN_FAMILY_ID, default)]),
+        );
+        self.manifest_path = manifest_path;
+        self.replayed_manifest_size = 0;
+        if let Err(e) = self.replay_manifest() {
+            self.column_families = previous;
+            return Err(e);
+        }
+        let replayed = std::mem::take(&mut self.column_families);
+        for (id, replayed) in replayed {
+            let cfd = match previous.remove(&id) {
+                Some(mut cfd) => {
+                    cfd.reset_version(replayed);
+                    cfd
+                }
+                None => replayed,
+            };
+            self.column_families.insert(id, cfd);
+        }
+        Ok(())
+    }
+
+    /// Apply the edits appended to the MANIFEST since it is replayed last time
+    fn replay_manifest(&mut self) -> Result<()> {
+        let (edits, end) = self.read_manifest(self.replayed_manifest_size)?;
+        for (edit, offset) in edits {
+            self.apply(&edit)
+                .map_err(|e| e.locate(self.manifest_path.as_str(), offset))?;
+        }
+        self.replayed_manifest_size = end;
+        Ok(())
+    }
+
+    /// Apply the edits appended to the MANIFEST by another process since it is replayed
+    /// last time, then open the SSTables and the blob files they add. The column families
+    /// created since are opened with the default options.
+    pub fn catch_up(&mut self) -> Result<()> {
+        let mut retry = true;
+        loop {
+            let manifest_path = Self::current_manifest_path(self.dir(), self.env.as_ref())?;
+            if manifest_path != self.manifest_path {
+                self.switch_manifest(manifest_path)?;
+            }
+            let replayed = self.replayed_manifest_size;
+            self.replay_manifest()?;
+            let opened = self
+                .column_families
+                .values_mut()
+                .try_for_each(|x| x.open_new_tables(self.dir.as_str()));
+            match opened {
+                // the other process deletes a file only after the edit dropping it is
+                // written, which is replayed by the next round
+                Err(Error::IoError(e))
+                    if e.kind() == std::io::ErrorKind::NotFound
+                        && (retry || self.replayed_manifest_size > replayed) =>
+                {
+                    retry = false;
+                }
+                opened => return opened,
+            }
+        }
+    }
+
+    /// Create a new MANIFEST in `dir` recording that the default column family is created
+    /// with the comparator `comparator_name`
+    fn create(dir: &str, env: Arc<dyn Env>, comparator_name: &str) -> Result<Self> {
+        let manifest_number = 1;
+        let path = manifest_path(dir, manifest_number);
+        // the MANIFEST left by a crash before the CURRENT file is written
+        let _ = env.remove_file(path.as_str());
+        let manifest = env.new_writable_file(path.as_str())?;
+        let mut version_set = VersionSet::new(dir, env, path, Some(manifest));
+        version_set.next_file_number = manifest_number as u64 + 1;
+        let mut edit = VersionEdit::new();
+        edit.comparator = Some(comparator_name.to_string());
+        version_set.log_and_apply(edit, Vec::new())?;
+        write_file_atomically(
+            version_set.env.as_ref(),
+            current_path(dir).as_str(),
+            format!("MANIFEST-{}\n", manifest_number).as_bytes(),
+        )?;
+        Ok(version_set)
+    }
+
+    /// Read the edits of the MANIFEST from `start` along with their offsets, the decoding
+    /// stops at the first broken edit which may be partially written when the process
+    /// crashed or is still being written. Return the edits and where the decoding stops.
+    fn read_manifest(&self, start: u64) -> Result<(Vec<(VersionEdit, u64)>, u64)> {
+        let manifest = self
+            .env
+            .new_random_access_file(self.manifest_path.as_str())?;
+        let size = manifest.size()?.saturating_sub(start);
+        let buf = manifest.read_at(start, size as usize)?;
+        let mut edits = Vec::new();
+        let mut offset = 0;
+        while offset < buf.len() {
+            let Some((length, consumed)) = get_varint32(&buf[offset..]) else {
+                break;
+            };
+            let Some(raw) = buf.get(offset + consumed..offset + consumed + length as usize) else {
+                break;
+            };
+            let edit_offset = start + (offset + consumed) as u64;
+            edits.push((
+                VersionEdit::decode(raw)
+                    .map_err(|e| e.locate(self.manifest_path.as_str(), edit_offset))?,
+                edit_offset,
+            ));
+            offset += consumed + length as usize;
+        }
+        Ok((edits, start + offset as u64))
+    }
+
+    /// Apply an edit read from the MANIFEST.
+    /// Failed with `Corruption` if the edit refers to a column family which does not exist
+    fn apply(&mut self, edit: &VersionEdit) -> Result<()> {
+        if let Some(next_file_number) = edit.next_file_number {
+            self.next_file_number = next_file_number;
+        }
+        if let Some(last_sequence) = edit.last_sequence {
+            self.last_sequence = last_sequence;
+        }
+        if let Some(max_column_family) = edit.max_column_family {
+            self.max_column_family = max_column_family;
+        }
+        if let Some(name) = &edit.column_family_add {
+            let mut cfd = ColumnFamilyData::new(
+                edit.column_family,
+                name.as_str(),
+                default_options(&self.env),
+                edit.log_number.unwrap_or(0),
+            );
+            cfd.comparator_name = edit.comparator.clone();
+            self.column_families.insert(edit.column_family, cfd);
+            return Ok(());
+        }
+        if edit.column_family_drop {
+            self.column_families.remove(&edit.column_family);
+            return Ok(());
+        }
+        self.column_families
+            .get_mut(&edit.column_family)
+            .ok_or_else(|| Error::corruption("", 0))?
+            .apply(edit, Vec::new(), Vec::new());
+        Ok(())
+    }
+
+    /// Write `edit` to the MANIFEST along with the counters
+    fn write_edit(&mut self, edit: &mut VersionEdit) -> Result<()> {
+        let buf = self.manifest_record(edit);
+        let manifest = match self.manifest.as_mut() {
+            Some(manifest) => manifest,
+            None => self
+                .manifest
+                .insert(self.env.new_appendable_file(self.manifest_path.as_str())?),
+        };
+        manifest.append(buf.as_slice())?;
+        manifest.sync()
+    }
+
+    /// Encode `edit` along with the counters as a length prefixed record of the MANIFEST
+    fn manifest_record(&self, edit: &mut VersionEdit) -> Vec<u8> {
+        edit.next_file_number = Some(self.next_file_number);
+        edit.last_sequence = Some(self.last_sequence);
+        edit.max_column_family = Some(self.max_column_family);
+        let raw = edit.encode();
+        let mut buf = Vec::with_capacity(raw.len() + 5);
+        put_varint32(&mut buf, raw.len() as u32);
+        buf.extend_from_slice(raw.as_slice());
+        buf
+    }
+
+    /// Write a new MANIFEST numbered `manifest_number` to `dir` holding nothing but the
+    /// current versions of the column families, then the CURRENT file naming it. The files
+    /// of the versions are not copied.
+    pub fn write_snapshot(&self, dir: &str, manifest_number: u64) -> Result<()> {
+        let mut manifest = self
+            .env
+            .new_writable_file(manifest_path(dir, manifest_number as usize).as_str())?;
+        for cfd in self.column_families() {
+            let mut edit = VersionEdit::new();
+            edit.column_family = cfd.id();
+            edit.comparator = cfd.comparator_name().map(|x| x.to_string());
+            edit.log_number = Some(cfd.log_number);
+            if cfd.id() != DEFAULT_COLUMN_FAMILY_ID {
+                // the files are added by another edit, the creation of a column family
+                // carries nothing else
+                edit.column_family_add = Some(cfd.name().to_string());
+                manifest.append(self.manifest_record(&mut edit).as_slice())?;
+                edit = VersionEdit::new();
+                edit.column_family = cfd.id();
+            }
+            let version = cfd.current();
+            for (level, files) in version.levels.iter().enumerate() {
+                for file in files {
+                    edit.add_file(level, file.as_ref().clone());
+                }
+            }
+            for file in version.blob_files.values() {
+                edit.add_blob_file(file.clone());
+            }
+            manifest.append(self.manifest_record(&mut edit).as_slice())?;
+        }
+        manifest.sync()?;
+        write_file_atomically(
+            self.env.as_ref(),
+            current_path(dir).as_str(),
+            format!("MANIFEST-{}\n", manifest_number).as_bytes(),
+        )
+    }
+
+    /// Record `edit` in the MANIFEST along with the counters and make it the current version
+    /// of its column family. The SSTables added by `edit` are passed by `tables`, the blob
+    /// files added are opened here.
+    ///
+    /// The blob files no SSTable refers to once `edit` is applied are dropped by the edit as
+    /// well, and removed from `dir`.
+    pub fn log_and_apply(
+        &mut self,
+        mut edit: VersionEdit,
+        tables: Vec<Arc<SSTable>>,
+    ) -> Result<()> {
+        let cfd = self
+            .column_family(edit.column_family)
+            .expect("the column family of the edit exists");
+        edit.log_number.get_or_insert(cfd.log_number);
+        let mut version = cfd.current().clone();
+        version.apply(&edit);
+        let unreferenced = version.unreferenced_blob_files();
+        edit.deleted_blob_files.extend(unreferenced);
+        let mut blob_files = Vec::with_capacity(edit.new_blob_files.len());
+        for file in &edit.new_blob_files {
+            blob_files.push(Arc::new(BlobFile::open(
+                self.env.as_ref(),
+                self.dir(),
+                file.number,
+            )?));
+        }
+        self.write_edit(&mut edit)?;
+        let deleted = self
+            .column_families
+            .get_mut(&edit.column_family)
+            .expect("the column family of the edit exists")
+            .apply(&edit, tables, blob_files);
+        self.obsolete_tables.extend(deleted);
+        for number in &edit.deleted_blob_files {
+            // the readers holding the file keep reading it, a file failed to remove is not
+            // referred to any more
+            let _ = self
+                .env
+                .remove_file(blobfile_path(self.dir(), *number).as_str());
+        }
+        Ok(())
+    }
+
+    /// Create the column family `name` and record it in the MANIFEST, the records of the
+    /// column family are logged to the logs numbered from `log_number`.
+    /// Failed with `InvalidArgument` if the name is taken
+    pub fn create_column_family(
+        &mut self,
+        name: &str,
+        options: Arc<Options>,
+        log_number: u64,
+    ) -> Result<&ColumnFamilyData> {
+        if self.column_family_by_name(name).is_some() {
+            return Err(Error::InvalidArgument(format!(
+                "column family {} already exists",
+                name
+            )));
+        }
+        let id = self.max_column_family + 1;
+        let mut edit = VersionEdit::new();
+        edit.column_family = id;
+        edit.column_family_add = Some(name.to_string());
+        edit.comparator = Some(options.comparator.name().to_string());
+        edit.log_number = Some(log_number);
+        self.max_column_family = id;
+        self.write_edit(&mut edit)?;
+        let mut cfd = ColumnFamilyData::new(id, name, options, log_number);
+        cfd.comparator_name = edit.comparator;
+        Ok(self.column_families.entry(id).or_insert(cfd))
+    }
+
+    /// Drop the column family `id` and record it in the MANIFEST, the records of the column
+    /// family are not readable any more
+    pub fn drop_column_family(&mut self, id: u32) -> Result<()> {
+        let mut edit = VersionEdit::new();
+        edit.column_family = id;
+        edit.column_family_drop = true;
+        self.write_edit(&mut edit)?;
+        if let Some(cfd) = self.column_families.remove(&id) {
+            self.obsolete_tables.extend(cfd.tables().cloned());
+        }
+        Ok(())
+    }
+
+    pub fn new_file_number(&mut self) -> u64 {
+        let number = self.next_file_number;
+        self.next_file_number += 1;
+        number
+    }
+
+    pub fn column_family(&self, id: u32) -> Option<&ColumnFamilyData> {
+        self.column_families.get(&id)
+    }
+
+    pub fn column_family_mut(&mut self, id: u32) -> Option<&mut ColumnFamilyData> {
+        self.column_families.get_mut(&id)
+    }
+
+    pub fn column_family_by_name(&self, name: &str) -> Option<&ColumnFamilyData> {
+        self.column_families.values().find(|x| x.name() == name)
+    }
+
+    pub fn default_column_family(&self) -> &ColumnFamilyData {
+        self.column_family(DEFAULT_COLUMN_FAMILY_ID)
+            .expect("the default column family always exists")
+    }
+
+    /// Return the column families ordered by id
+    pub fn column_families(&self) -> impl std::iter::Iterator<Item = &ColumnFamilyData> {
+        self.column_families.values()
+    }
+
+    pub fn dir(&self) -> &str {
+        self.dir.as_str()
+    }
+
+    pub fn env(&self) -> &Arc<dyn Env> {
+        &self.env
+    }
+
+    /// Return the files in `dir` the database does not need any more: the SSTables and the
+    /// blob files neither in any current version nor held by any reader, the logs flushed
+    /// by every column family except `current_log`, the MANIFEST files not in use and the
+    /// temporary files.
+    ///
+    /// Every file of `dir` is either created by the database or completely written before
+    /// the edit adding it is applied, so the files are only collected when no flush or
+    /// compaction is in progress.
+    pub fn obsolete_files(&mut self, current_log: u64) -> Result<Vec<String>> {
+        // the readers holding a table keep reading it, no matter whether it is in a version
+        self.obsolete_tables.retain(|x| Arc::strong_count(x) > 1);
+        let mut live: HashSet<u64> = self
+            .obsolete_tables
+            .iter()
+            .map(|x| x.seq() as u64)
+            .collect();
+        for cfd in self.column_families.values() {
+            let version = cfd.current();
+            live.extend(version.levels.iter().flatten().map(|x| x.number));
+            live.extend(version.blob_files.keys());
+        }
+        let min_log_number = self
+            .column_families()
+            .map(|x| x.log_number)
+            .min()
+            .unwrap_or(0);
+        let manifest_name = std::path::Path::new(self.manifest_path.as_str())
+            .file_name()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default();
+        let mut files = Vec::new();
+        for name in self.env.get_children(self.dir())? {
+            let obsolete = match parse_file_name(name.as_str()) {
+                _ if name.ends_with(".tmp") => true,
+                Some((number, "sst" | "blob")) => !live.contains(&number),
+                Some((number, "log")) => number < min_log_number && number != current_log,
+                Some((_, "MANIFEST")) => name != manifest_name,
+                _ => false,
+            };
+            if obsolete {
+                files.push(format!("{}/{}", self.dir(), name));
+            }
+        }
+        Ok(files)
+    }
+}
@@ -0,0 +1,119 @@
+use crate::db::column_family::{ColumnFamilyHandle, DEFAULT_COLUMN_FAMILY_ID};
+use crate::memtable::logger::OperationType;
+use bytes::Bytes;
+use std::time::Duration;
+
+/// `WriteBatch` collects updates which `DB::write` applies atomically, the updates may span
+/// several column families. The updates are logged as a single write, after a crash either
+/// all of them are recovered or none of them.
+///
+/// The updates are checked when the batch is written, the whole batch is rejected if any
+/// of them is invalid.
+#[derive(Clone, Default)]
+pub struct WriteBatch {
+    records: Vec<BatchRecord>,
+}
+
+#[derive(Clone)]
+pub(crate) struct BatchRecord {
+    pub column_family: u32,
+    pub opt: OperationType,
+    pub key: Bytes,
+    pub value: Bytes,
+    /// time to live of the value of a `PUT_WITH_TTL` record, the expiry moment is decided
+    /// when the batch is written
+    pub ttl: Option<Duration>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.add(DEFAULT_COLUMN_FAMILY_ID, OperationType::PUT, key, value);
+    }
+
+    pub fn put_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8], value: &[u8]) {
+        self.add(cf.id(), OperationType::PUT, key, value);
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.add(DEFAULT_COLUMN_FAMILY_ID, OperationType::DELETE, key, b"");
+    }
+
+    pub fn delete_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8]) {
+        self.add(cf.id(), OperationType::DELETE, key, b"");
+    }
+
+    /// See `DB::put_with_ttl`
+    pub fn put_with_ttl(&mut self, key: &[u8], value: &[u8], ttl: Duration) {
+        self.put_with_ttl_cf(&ColumnFamilyHandle::default(), key, value, ttl);
+    }
+
+    pub fn put_with_ttl_cf(
+        &mut self,
+        cf: &ColumnFamilyHandle,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) {
+        self.records.push(BatchRecord {
+            column_family: cf.id(),
+            opt: OperationType::PUT_WITH_TTL,
+            key: Bytes::copy_from_slice(key),
+            value: Bytes::copy_from_slice(value),
+            ttl: Some(ttl),
+        });
+    }
+
+    /// See `DB::merge`
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) {
+        self.add(DEFAULT_COLUMN_FAMILY_ID, OperationType::MERGE, key, operand);
+    }
+
+    pub fn merge_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8], operand: &[u8]) {
+        self.add(cf.id(), OperationType::MERGE, key, operand);
+    }
+
+    /// See `DB::delete_range`
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.add(
+            DEFAULT_COLUMN_FAMILY_ID,
+            OperationType::RANGE_DELETE,
+            start,
+            end,
+        );
+    }
+
+    pub fn delete_range_cf(&mut self, cf: &ColumnFamilyHandle, start: &[u8], end: &[u8]) {
+        self.add(cf.id(), OperationType::RANGE_DELETE, start, end);
+    }
+
+    fn add(&mut self, column_family: u32, opt: OperationType, key: &[u8], value: &[u8]) {
+        self.records.push(BatchRecord {
+            column_family,
+            opt,
+            key: Bytes::copy_from_slice(key),
+            value: Bytes::copy_from_slice(value),
+            ttl: None,
+        });
+    }
+
+    /// Return the number of updates in the batch
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    pub(crate) fn records(&self) -> &[BatchRecord] {
+        self.records.as_slice()
+    }
+}
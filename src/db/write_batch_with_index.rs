@@ -0,0 +1,537 @@
+use crate::comparator::{bytewise_comparator, Comparator};
+use crate::db::column_family::ColumnFamilyHandle;
+use crate::db::format::MergeBase;
+use crate::db::iterator::DBIterator;
+use crate::db::write_batch::WriteBatch;
+use crate::db::{DBImpl, DB};
+use crate::iterator::Iterator;
+use crate::merge_operator::MergeOperator;
+use crate::util::error::{Error, Result};
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `WriteBatchWithIndex` is a `WriteBatch` which also indexes its updates by key, so the
+/// pending updates can be read merged with the records of the database, by
+/// `get_from_batch_and_db` and by the iterator of `iter_with_base`. The updates of the batch
+/// win over the database: a key put or deleted in the batch reads as the batch says, the
+/// merge operands of the batch apply on the value in the database unless the batch puts or
+/// deletes the key before them.
+///
+/// A value put with a time to live reads as a plain value, its expiry moment is only decided
+/// when the batch is written.
+///
+/// The ranges deleted and the iterators follow the comparator of the batch, it must be the
+/// comparator of the column families the batch updates, see `with_comparator`.
+#[derive(Clone)]
+pub struct WriteBatchWithIndex {
+    batch: WriteBatch,
+    /// the state of every key updated in the batch, keyed by the column family id and the key
+    index: BTreeMap<(u32, Bytes), IndexEntry>,
+    /// the non-empty ranges deleted in the batch, with the column family id
+    range_deletions: Vec<(u32, Bytes, Bytes)>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl Default for WriteBatchWithIndex {
+    fn default() -> Self {
+        Self::with_comparator(bytewise_comparator())
+    }
+}
+
+/// The state of a key after the updates of the batch
+#[derive(Clone)]
+struct IndexEntry {
+    /// `Unresolved` if the operands apply on the value in the database
+    base: MergeBase,
+    /// the merge operands, ordered from the oldest to the newest
+    operands: Vec<Bytes>,
+}
+
+impl IndexEntry {
+    /// Return the value of the key, `existing` is the value in the database
+    fn value(
+        &self,
+        key: &[u8],
+        existing: impl FnOnce() -> Result<Option<Bytes>>,
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<Option<Bytes>> {
+        let base = match &self.base {
+            MergeBase::Unresolved => existing()?,
+            MergeBase::Deletion => None,
+            MergeBase::Value(value) | MergeBase::ExpiringValue(_, value) => Some(value.clone()),
+            MergeBase::BlobIndex(_) => unreachable!("the batch holds the values themselves"),
+        };
+        if self.operands.is_empty() {
+            return Ok(base);
+        }
+        let merge_operator = merge_operator.ok_or_else(|| {
+            Error::InvalidArgument("merge operator is not configured".to_string())
+        })?;
+        let operands: Vec<&[u8]> = self.operands.iter().map(|x| x.as_ref()).collect();
+        let value = merge_operator.full_merge(key, base.as_deref(), operands.as_slice())?;
+        Ok(Some(Bytes::from(value)))
+    }
+}
+
+impl WriteBatchWithIndex {
+    /// Create a batch for the column families in the bytewise order
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a batch for the column families in the order of `comparator`
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        WriteBatchWithIndex {
+            batch: WriteBatch::new(),
+            index: BTreeMap::new(),
+            range_deletions: Vec::new(),
+            comparator,
+        }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.put_cf(&ColumnFamilyHandle::default(), key, value);
+    }
+
+    pub fn put_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8], value: &[u8]) {
+        self.batch.put_cf(cf, key, value);
+        self.set(
+            cf.id(),
+            key,
+            MergeBase::Value(Bytes::copy_from_slice(value)),
+        );
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.delete_cf(&ColumnFamilyHandle::default(), key);
+    }
+
+    pub fn delete_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8]) {
+        self.batch.delete_cf(cf, key);
+        self.set(cf.id(), key, MergeBase::Deletion);
+    }
+
+    /// See `DB::put_with_ttl`
+    pub fn put_with_ttl(&mut self, key: &[u8], value: &[u8], ttl: Duration) {
+        self.put_with_ttl_cf(&ColumnFamilyHandle::default(), key, value, ttl);
+    }
+
+    pub fn put_with_ttl_cf(
+        &mut self,
+        cf: &ColumnFamilyHandle,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) {
+        self.batch.put_with_ttl_cf(cf, key, value, ttl);
+        self.set(
+            cf.id(),
+            key,
+            MergeBase::Value(Bytes::copy_from_slice(value)),
+        );
+    }
+
+    /// See `DB::merge`
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) {
+        self.merge_cf(&ColumnFamilyHandle::default(), key, operand);
+    }
+
+    pub fn merge_cf(&mut self, cf: &ColumnFamilyHandle, key: &[u8], operand: &[u8]) {
+        self.batch.merge_cf(cf, key, operand);
+        let base = if self.is_range_deleted(cf.id(), key) {
+            MergeBase::Deletion
+        } else {
+            MergeBase::Unresolved
+        };
+        self.index
+            .entry((cf.id(), Bytes::copy_from_slice(key)))
+            .or_insert(IndexEntry {
+                base,
+                operands: Vec::new(),
+            })
+            .operands
+            .push(Bytes::copy_from_slice(operand));
+    }
+
+    /// See `DB::delete_range`
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.delete_range_cf(&ColumnFamilyHandle::default(), start, end);
+    }
+
+    pub fn delete_range_cf(&mut self, cf: &ColumnFamilyHandle, start: &[u8], end: &[u8]) {
+        self.batch.delete_range_cf(cf, start, end);
+        // an invalid range is rejected when the batch is written
+        if self.comparator.compare(start, end).is_ge() {
+            return;
+        }
+        let comparator = self.comparator.clone();
+        self.index.retain(|(id, key), _| {
+            *id != cf.id()
+                || comparator.compare(start, key).is_gt()
+                || comparator.compare(key, end).is_ge()
+        });
+        self.range_deletions.push((
+            cf.id(),
+            Bytes::copy_from_slice(start),
+            Bytes::copy_from_slice(end),
+        ));
+    }
+
+    /// Return the number of updates in the batch
+    pub fn len(&self) -> usize {
+        self.batch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.batch.clear();
+        self.index.clear();
+        self.range_deletions.clear();
+    }
+
+    /// Return the updates as a `WriteBatch`, in the order they are made
+    pub fn write_batch(&self) -> &WriteBatch {
+        &self.batch
+    }
+
+    /// Turn the batch into a `WriteBatch` for `DB::write`
+    pub fn into_write_batch(self) -> WriteBatch {
+        self.batch
+    }
+
+    /// Return the value of `key` in the database with the updates of the batch applied
+    pub fn get_from_batch_and_db(&self, db: &DBImpl, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_from_batch_and_db_cf(db, &ColumnFamilyHandle::default(), key)
+    }
+
+    pub fn get_from_batch_and_db_cf(
+        &self,
+        db: &DBImpl,
+        cf: &ColumnFamilyHandle,
+        key: &[u8],
+    ) -> Result<Option<Bytes>> {
+        match self.index.get(&(cf.id(), Bytes::copy_from_slice(key))) {
+            Some(entry) => {
+                let merge_operator = if entry.operands.is_empty() {
+                    None
+                } else {
+                    db.merge_operator(cf)?
+                };
+                entry.value(key, || db.get_cf(cf, key), merge_operator.as_deref())
+            }
+            None if self.is_range_deleted(cf.id(), key) => Ok(None),
+            None => db.get_cf(cf, key),
+        }
+    }
+
+    /// Create an iterator over the records of `base` with the updates of the batch to the
+    /// default column family applied, `base` is an iterator of the default column family,
+    /// see `DB::iter`. The iterator is not positioned yet.
+    pub fn iter_with_base(&self, base: DBIterator) -> BaseDeltaIterator {
+        self.iter_with_base_cf(&ColumnFamilyHandle::default(), base)
+    }
+
+    /// See `iter_with_base`, `base` is an iterator of the column family `cf`
+    pub fn iter_with_base_cf(
+        &self,
+        cf: &ColumnFamilyHandle,
+        base: DBIterator,
+    ) -> BaseDeltaIterator {
+        let merge_operator = base.merge_operator();
+        // the keys of the batch out of the bounds of the base are never visited
+        let read_options = base.read_options();
+        let comparator = self.comparator.as_ref();
+        let mut delta: Vec<(Bytes, IndexEntry)> = self
+            .index
+            .range((cf.id(), Bytes::new())..)
+            .take_while(|((id, _), _)| *id == cf.id())
+            .filter(|((_, key), _)| {
+                !read_options.before_lower_bound(key, comparator)
+                    && !read_options.reaches_upper_bound(key, comparator)
+            })
+            .map(|((_, key), entry)| (key.clone(), entry.clone()))
+            .collect();
+        delta.sort_by(|(a, _), (b, _)| self.comparator.compare(a, b));
+        let range_deletions = self
+            .range_deletions
+            .iter()
+            .filter(|(id, _, _)| *id == cf.id())
+            .map(|(_, start, end)| (start.clone(), end.clone()))
+            .collect();
+        BaseDeltaIterator::new(BaseDeltaIteratorParts {
+            base,
+            delta,
+            range_deletions,
+            merge_operator,
+            comparator: self.comparator.clone(),
+        })
+    }
+
+    fn set(&mut self, column_family: u32, key: &[u8], base: MergeBase) {
+        let entry = IndexEntry {
+            base,
+            operands: Vec::new(),
+        };
+        self.index
+            .insert((column_family, Bytes::copy_from_slice(key)), entry);
+    }
+
+    fn is_range_deleted(&self, column_family: u32, key: &[u8]) -> bool {
+        self.range_deletions.iter().any(|(id, start, end)| {
+            *id == column_family
+                && self.comparator.compare(start, key).is_le()
+                && self.comparator.compare(key, end).is_lt()
+        })
+    }
+}
+
+/// Everything a `BaseDeltaIterator` is made of
+pub struct BaseDeltaIteratorParts {
+    base: DBIterator,
+    /// the keys updated in the batch, sorted by `comparator`
+    delta: Vec<(Bytes, IndexEntry)>,
+    range_deletions: Vec<(Bytes, Bytes)>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    comparator: Arc<dyn Comparator>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+/// `BaseDeltaIterator` merges the user records of a `DBIterator`, the base, with the keys
+/// updated in a `WriteBatchWithIndex`, the delta. A key in both takes the value of the delta
+/// applied on the base, the keys deleted by the batch are skipped. The delta keeps within the
+/// bounds and the prefix of the base like the base does.
+///
+/// Like `MergingIterator`, moving forward the base and the delta are positioned at the
+/// first key greater than or equal to the current key, and the current key is the smaller
+/// of the two. Moving backward it is the other way around.
+pub struct BaseDeltaIterator {
+    base: DBIterator,
+    delta: Vec<(Bytes, IndexEntry)>,
+    /// the position in `delta`, `delta.len()` if it runs out of keys
+    delta_index: usize,
+    range_deletions: Vec<(Bytes, Bytes)>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    comparator: Arc<dyn Comparator>,
+    direction: Direction,
+    /// the current key and its value
+    current: Option<(Bytes, Bytes)>,
+    status: Result<()>,
+}
+
+impl BaseDeltaIterator {
+    /// Return the current key of the delta, `None` if it runs out of keys or leaves the
+    /// prefix of the base
+    fn delta_key(&self) -> Option<&[u8]> {
+        self.delta
+            .get(self.delta_index)
+            .map(|(key, _)| key.as_ref())
+            .filter(|key| self.base.is_in_prefix(key))
+    }
+
+    fn base_key(&self) -> Option<&[u8]> {
+        if self.base.is_valid() {
+            Some(self.base.key())
+        } else {
+            None
+        }
+    }
+
+    /// Position the delta at the first key greater than or equal to `key`
+    fn seek_delta(&mut self, key: &[u8]) {
+        self.delta_index = self
+            .delta
+            .partition_point(|(x, _)| self.comparator.compare(x, key).is_lt());
+    }
+
+    /// Move the delta one step in the current direction
+    fn step_delta(&mut self) {
+        self.delta_index = match self.direction {
+            Direction::Forward => self.delta_index + 1,
+            Direction::Backward if self.delta_index == 0 => self.delta.len(),
+            Direction::Backward => self.delta_index - 1,
+        };
+    }
+
+    /// Resolve the value of the smaller key of the base and the delta moving forward, the
+    /// larger one moving backward, then skip it and go on while it is invisible
+    fn find_current(&mut self) {
+        self.current = None;
+        loop {
+            if let Err(e) = self.base.status() {
+                self.status = Err(e);
+                return;
+            }
+            let (key, in_base, in_delta) = match (self.base_key(), self.delta_key()) {
+                (None, None) => return,
+                (Some(base), None) => (base, true, false),
+                (None, Some(delta)) => (delta, false, true),
+                (Some(base), Some(delta)) => {
+                    let ordering = match self.direction {
+                        Direction::Forward => self.comparator.compare(base, delta),
+                        Direction::Backward => self.comparator.compare(delta, base),
+                    };
+                    let key = if ordering.is_lt() { base } else { delta };
+                    (key, ordering.is_le(), ordering.is_ge())
+                }
+            };
+            let key = Bytes::copy_from_slice(key);
+            let value = if in_delta {
+                let existing = || {
+                    Ok(if in_base {
+                        Some(Bytes::copy_from_slice(self.base.value()))
+                    } else {
+                        None
+                    })
+                };
+                let entry = &self.delta[self.delta_index].1;
+                match entry.value(key.as_ref(), existing, self.merge_operator.as_deref()) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        self.status = Err(e);
+                        return;
+                    }
+                }
+            } else if self.is_range_deleted(key.as_ref()) {
+                None
+            } else {
+                Some(Bytes::copy_from_slice(self.base.value()))
+            };
+            if let Some(value) = value {
+                self.current = Some((key, value));
+                return;
+            }
+            if in_base {
+                match self.direction {
+                    Direction::Forward => self.base.next(),
+                    Direction::Backward => self.base.prev(),
+                }
+            }
+            if in_delta {
+                self.step_delta();
+            }
+        }
+    }
+
+    /// Move the base and the delta past the current key in the current direction
+    fn step(&mut self, key: &[u8]) {
+        if self.base_key() == Some(key) {
+            match self.direction {
+                Direction::Forward => self.base.next(),
+                Direction::Backward => self.base.prev(),
+            }
+        }
+        if self.delta_key() == Some(key) {
+            self.step_delta();
+        }
+        self.find_current();
+    }
+
+    fn is_range_deleted(&self, key: &[u8]) -> bool {
+        self.range_deletions.iter().any(|(start, end)| {
+            self.comparator.compare(start, key).is_le() && self.comparator.compare(key, end).is_lt()
+        })
+    }
+}
+
+impl Iterator for BaseDeltaIterator {
+    type Item = BaseDeltaIteratorParts;
+
+    fn new(arg: Self::Item) -> Self {
+        let delta_index = arg.delta.len();
+        BaseDeltaIterator {
+            base: arg.base,
+            delta: arg.delta,
+            delta_index,
+            range_deletions: arg.range_deletions,
+            merge_operator: arg.merge_operator,
+            comparator: arg.comparator,
+            direction: Direction::Forward,
+            current: None,
+            status: Ok(()),
+        }
+    }
+
+    fn seek_to_first(&mut self) {
+        self.direction = Direction::Forward;
+        self.base.seek_to_first();
+        self.delta_index = 0;
+        self.find_current();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.direction = Direction::Backward;
+        self.base.seek_to_last();
+        self.delta_index = self.delta.len().saturating_sub(1);
+        self.find_current();
+    }
+
+    fn seek_to_key(&mut self, key: &[u8]) {
+        self.direction = Direction::Forward;
+        self.base.seek_to_key(key);
+        self.seek_delta(key);
+        self.find_current();
+    }
+
+    fn is_valid(&self) -> bool {
+        self.status.is_ok() && self.current.is_some()
+    }
+
+    fn next(&mut self) {
+        if !self.is_valid() {
+            return;
+        }
+        let key = self.current.as_ref().unwrap().0.clone();
+        if self.direction == Direction::Backward {
+            // position the base and the delta at the first key not less than the current one
+            self.direction = Direction::Forward;
+            self.base.seek_to_key(key.as_ref());
+            self.seek_delta(key.as_ref());
+        }
+        self.step(key.as_ref());
+    }
+
+    fn prev(&mut self) {
+        if !self.is_valid() {
+            return;
+        }
+        let key = self.current.as_ref().unwrap().0.clone();
+        if self.direction == Direction::Forward {
+            // position the base and the delta at the last key not greater than the current
+            // one
+            self.direction = Direction::Backward;
+            self.base.seek_to_key(key.as_ref());
+            if !self.base.is_valid() {
+                self.base.seek_to_last();
+            } else if self.base.key() != key.as_ref() {
+                self.base.prev();
+            }
+            self.seek_delta(key.as_ref());
+            if self.delta_key() != Some(key.as_ref()) {
+                self.step_delta();
+            }
+        }
+        self.step(key.as_ref());
+    }
+
+    fn key(&self) -> &[u8] {
+        self.current.as_ref().unwrap().0.as_ref()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().1.as_ref()
+    }
+
+    fn status(&self) -> Result<()> {
+        self.status.clone()
+    }
+}
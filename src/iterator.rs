@@ -1,3 +1,5 @@
+use crate::util::error::Result;
+
 pub trait Iterator {
     type Item;
     fn new(arg: Self::Item) -> Self;
@@ -9,4 +11,9 @@ pub trait Iterator {
     fn prev(&mut self);
     fn key(&self) -> &[u8];
     fn value(&self) -> &[u8];
+    /// Return the error which makes the iterator invalid, `Ok` if the iterator simply
+    /// runs out of records
+    fn status(&self) -> Result<()> {
+        Ok(())
+    }
 }
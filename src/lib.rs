@@ -4,6 +4,12 @@
 
 
 pub mod blocks;
+pub mod compaction_filter;
+pub mod comparator;
+pub mod compression;
+pub mod merge_operator;
+pub mod options;
+pub mod slice_transform;
 pub mod sstable;
 pub mod memtable;
 pub mod util;
@@ -11,18 +17,42 @@ pub mod util;
 pub mod iterator;
 
 pub mod db;
-use anyhow::Result;
-use crate::db::DB;
+pub use crate::util::error::{Error, Result};
+use crate::db::column_family::ColumnFamilyDescriptor;
+use crate::options::Options;
+/// Open the database in directory `path` with the default options
 pub fn open(path:&str) -> Result<Box<dyn db::DB>>{
-    let db_impl = db::DBImpl::new();
-    match db_impl.open(path) {
-        Ok(_) => {
-            return Ok(Box::new(db_impl));
-        },
-        Err(e) =>{
-            Err(e)
-        }
-    }
+    open_with_options(path, Options::default())
+}
+pub fn open_with_options(path:&str, options: Options) -> Result<Box<dyn db::DB>>{
+    let db_impl = db::DBImpl::open(path, options)?;
+    Ok(Box::new(db_impl))
+}
+/// Open the database in directory `path` along with `column_families`, every column family of
+/// the database must be given, see `list_column_families`
+pub fn open_with_column_families(
+    path: &str,
+    options: Options,
+    column_families: Vec<ColumnFamilyDescriptor>,
+) -> Result<Box<dyn db::DB>> {
+    let db_impl = db::DBImpl::open_with_column_families(path, options, column_families)?;
+    Ok(Box::new(db_impl))
+}
+/// Open the database in directory `path` read only with the default options, see
+/// `DBImpl::open_read_only`
+pub fn open_read_only(path: &str) -> Result<Box<dyn db::DB>> {
+    let db_impl = db::DBImpl::open_read_only(path, Options::default())?;
+    Ok(Box::new(db_impl))
+}
+/// Open the database in directory `primary_path` written by another process with the default
+/// options, see `DBImpl::open_as_secondary`
+pub fn open_as_secondary(primary_path: &str) -> Result<Box<dyn db::DB>> {
+    let db_impl = db::DBImpl::open_as_secondary(primary_path, Options::default())?;
+    Ok(Box::new(db_impl))
+}
+/// Return the names of the column families of the database in directory `path`
+pub fn list_column_families(path: &str) -> Result<Vec<String>> {
+    db::DBImpl::list_column_families(path, &Options::default())
 }
 
 #[cfg(test)]
@@ -30,6 +60,10 @@ mod tests {
     use super::*;
     #[test]
     fn it_works() {
-        let ret = open("");
+        let path = "/summer_kv_test/it_works";
+        let db = open_with_options(path, options::test_options()).unwrap();
+        db.put(b"key", b"value").unwrap();
+        assert_eq!(db.get(b"key").unwrap(), Some("value".into()));
+        assert!(open("").is_err());
     }
 }
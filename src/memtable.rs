@@ -1,68 +1,402 @@
-use crate::memtable::logger::{LogRecordsBuilder, MemTableLogger, OperationType};
-use crate::util::env::{get_global_sequence_number, FileObject};
-use anyhow::Result;
+use crate::comparator::{bytewise_comparator, Comparator};
+use crate::db::format::{
+    decode_expiring_payload, decode_merge_payload, decode_value, encode_expiring_payload,
+    encode_merge_payload, encode_value, BlobIndex, MergeBase, RangeTombstone, RangeTombstoneList,
+    SequenceNumber, ValueType, VALUE_HEADER_SIZE,
+};
+use crate::memtable::iterator::{MemTableIterator, MemTableIteratorParts};
+use crate::memtable::logger::{LoggerRecord, OperationType};
+use crate::memtable::rep::btree::BTreeRep;
+use crate::memtable::rep::hash_linklist::HashLinkListRep;
+use crate::memtable::rep::skiplist::SkipListRep;
+use crate::memtable::rep::vector::VectorRep;
+use crate::memtable::rep::MemTableRep;
+use crate::options::{MemTableFactory, Options};
 use bytes::Bytes;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
+use std::sync::atomic::{self, AtomicU64, AtomicUsize};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 
+pub mod arena;
+pub mod iterator;
 pub mod logger;
+pub mod rep;
+pub mod skiplist;
 
+/// `MemTableKey` is the key of a record of the memtable, ordered by the comparator of the
+/// memtable, then by the sequence number from the newest
+#[derive(Clone)]
+pub struct MemTableKey {
+    key: Bytes,
+    seq: SequenceNumber,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl MemTableKey {
+    pub fn new(key: &[u8], seq: SequenceNumber, comparator: Arc<dyn Comparator>) -> Self {
+        MemTableKey {
+            key: Bytes::copy_from_slice(key),
+            seq,
+            comparator,
+        }
+    }
+
+    pub fn key(&self) -> &Bytes {
+        &self.key
+    }
+
+    pub fn seq(&self) -> SequenceNumber {
+        self.seq
+    }
+}
+
+impl PartialEq for MemTableKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MemTableKey {}
+
+impl PartialOrd for MemTableKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MemTableKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator
+            .compare(&self.key, &other.key)
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+/// the size of the arena blocks of the memtables not created from `Options`
+const DEFAULT_ARENA_BLOCK_SIZE: usize = 4096;
+
+/// The records of a memtable kept by `BTreeRep`
+pub type MemTableMap = BTreeMap<MemTableKey, Bytes>;
+
+/// The memtable keeps the records not flushed to SSTables yet in memory. It does not log the
+/// records by itself, the caller logs them to the log file shared by all the column families
+/// before inserting them, see `MemTableLogger`.
+///
+/// The records are inserted by several writers at the same time along with the readers. A
+/// reader which must not see the writes in progress reads at the sequence number of the
+/// latest write it may see, see `get_entry_at` and `iter_at`.
 pub struct MemTable {
-    /// table is the sorted searching data structure
-    /// `key` is the user record key, `value` is the user record value
-    table: BTreeMap<Bytes, Bytes>,
-    /// memtable unique sequence number, which represents the related log
-    /// file number, it is global unique
-    seq: u64,
-    logger: MemTableLogger,
+    /// table is the searching data structure, see `MemTableFactory`
+    /// `key` is the user record key, `value` is the user record value prefixed with the header
+    /// described in `db::format`.
+    table: Box<dyn MemTableRep>,
+    range_tombstones: RwLock<RangeTombstoneList>,
+    comparator: Arc<dyn Comparator>,
+    /// the amount of bytes of the range tombstones, the rep tracks the memory of the other
+    /// records by itself
+    approximate_size: AtomicUsize,
+    /// the greatest sequence number inserted
+    last_sequence: AtomicU64,
+}
+
+impl Default for MemTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MemTable {
+    /// Create a memtable in the bytewise order
     pub fn new() -> Self {
-        let seq = get_global_sequence_number();
+        Self::with_comparator(bytewise_comparator())
+    }
+
+    /// Create a memtable in the order of `comparator` by the default `MemTableFactory`
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        let table = Box::new(SkipListRep::new(
+            comparator.clone(),
+            DEFAULT_ARENA_BLOCK_SIZE,
+        ));
+        Self::with_rep(table, comparator)
+    }
+
+    /// Create a memtable by `memtable_factory` of `options`
+    pub fn with_options(options: &Options) -> Self {
+        let comparator = options.comparator.clone();
+        let table: Box<dyn MemTableRep> = match &options.memtable_factory {
+            MemTableFactory::SkipList => Box::new(SkipListRep::new(
+                comparator.clone(),
+                options.arena_block_size(),
+            )),
+            MemTableFactory::BTree => Box::new(BTreeRep::new(comparator.clone())),
+            MemTableFactory::Vector => Box::new(VectorRep::new(comparator.clone())),
+            MemTableFactory::HashLinkList { bucket_count } => Box::new(HashLinkListRep::new(
+                comparator.clone(),
+                options.prefix_extractor.clone(),
+                *bucket_count,
+            )),
+        };
+        Self::with_rep(table, comparator)
+    }
+
+    /// Create a memtable keeping the records in `table`, which is ordered by `comparator`
+    pub fn with_rep(table: Box<dyn MemTableRep>, comparator: Arc<dyn Comparator>) -> Self {
         MemTable {
-            table: BTreeMap::new(),
-            seq,
-            logger: MemTableLogger::new(seq),
+            table,
+            range_tombstones: RwLock::new(RangeTombstoneList::with_comparator(comparator.clone())),
+            comparator,
+            approximate_size: AtomicUsize::new(0),
+            last_sequence: AtomicU64::new(0),
         }
     }
-    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        // logging and flushing to disk first
-        let mut log_record_builder = LogRecordsBuilder::new();
-        if value.len() == 0{
-            log_record_builder.add(OperationType::DELETE, key, value)?;
-        }
-        else{
-            log_record_builder.add(OperationType::PUT, key, value)?;
+    pub fn put(&self, sequence: SequenceNumber, key: &[u8], value: &[u8]) {
+        self.add_operation(OperationType::PUT, sequence, key, value)
+    }
+    /// Return the value of `key`, `None` if the key is absent or deleted in this memtable.
+    /// Merge records are not resolved by the memtable, `None` is returned for them as well.
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        let entry = self.get_entry(key)?;
+        let decoded = decode_value(entry.as_ref()).ok()?;
+        if decoded.value_type != ValueType::Value
+            || decoded.seq < self.range_tombstones().max_covering_seq(key)
+        {
+            return None;
         }
-        let ret = self.logger.log_and_sync(log_record_builder.build());
+        Some(entry.slice(VALUE_HEADER_SIZE..))
+    }
 
-        assert!(ret.is_ok());
-        self.table
-            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
-        Ok(())
+    /// Return the record `key` reads as with the header, which is its latest record unless
+    /// that is a merge record, see `RecordFolder`. The range tombstones covering the record
+    /// itself are not taken into account.
+    pub fn get_entry(&self, key: &[u8]) -> Option<Bytes> {
+        self.get_entry_at(key, SequenceNumber::MAX)
     }
-    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        match self.table.get(key) {
-            None => None,
-            Some(value) => {
-                if value.is_empty() {
-                    return None;
-                }
-                Some(value.clone())
+
+    /// Like `get_entry`, but the records and the range tombstones newer than `visible_seq`
+    /// are ignored
+    pub fn get_entry_at(&self, key: &[u8], visible_seq: SequenceNumber) -> Option<Bytes> {
+        let mut folder = RecordFolder::new(self.max_covering_seq(key, visible_seq));
+        self.table.get(key, &mut |record| {
+            let decoded = decode_value(record).expect("records are encoded by the memtable");
+            decoded.seq > visible_seq || folder.add(record)
+        });
+        folder.finish()
+    }
+
+    /// Return the greatest sequence number of the range tombstones not newer than
+    /// `visible_seq` which contain `key`, 0 if there is none
+    pub fn max_covering_seq(&self, key: &[u8], visible_seq: SequenceNumber) -> SequenceNumber {
+        let range_tombstones = self.range_tombstones();
+        let comparator = range_tombstones.comparator().clone();
+        range_tombstones
+            .iter()
+            .filter(|x| x.seq <= visible_seq && x.contains(key, comparator.as_ref()))
+            .map(|x| x.seq)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// delete is composed by putting a new record of `Deletion` type
+    pub fn delete(&self, sequence: SequenceNumber, key: &[u8]) {
+        self.add_operation(OperationType::DELETE, sequence, key, b"")
+    }
+
+    /// put a record of `key` which expires at `expire_at`, see `encode_expiring_payload`
+    pub fn put_with_expiry(
+        &self,
+        sequence: SequenceNumber,
+        key: &[u8],
+        value: &[u8],
+        expire_at: u64,
+    ) {
+        let payload = encode_expiring_payload(expire_at, value);
+        self.add_operation(
+            OperationType::PUT_WITH_TTL,
+            sequence,
+            key,
+            payload.as_slice(),
+        )
+    }
+
+    /// merge `operand` into the value of `key`, see `MergeOperator`
+    pub fn merge(&self, sequence: SequenceNumber, key: &[u8], operand: &[u8]) {
+        self.add_operation(OperationType::MERGE, sequence, key, operand)
+    }
+
+    /// delete all the keys in `[start, end)` written before `sequence` by a single record
+    pub fn delete_range(&self, sequence: SequenceNumber, start: &[u8], end: &[u8]) {
+        self.add_operation(OperationType::RANGE_DELETE, sequence, start, end)
+    }
+
+    fn add_operation(
+        &self,
+        opt: OperationType,
+        sequence: SequenceNumber,
+        key: &[u8],
+        value: &[u8],
+    ) {
+        self.add(&LoggerRecord::new(opt, sequence, 0, key, value));
+    }
+
+    /// Insert the logged `record` into the memtable, the column family of the record is not
+    /// checked. A merge is inserted as a record of its own, it is folded with the older
+    /// records of the key when read, see `RecordFolder`.
+    pub fn add(&self, record: &LoggerRecord) {
+        let value = match record.opt_type() {
+            OperationType::PUT => encode_value(ValueType::Value, record.seq(), record.value()),
+            OperationType::DELETE => {
+                encode_value(ValueType::Deletion, record.seq(), record.value())
+            }
+            OperationType::PUT_WITH_TTL => {
+                encode_value(ValueType::ExpiringValue, record.seq(), record.value())
+            }
+            OperationType::MERGE => {
+                let operand = Bytes::copy_from_slice(record.value());
+                let payload = encode_merge_payload(&MergeBase::Unresolved, &[operand]);
+                encode_value(ValueType::Merge, record.seq(), payload.as_slice())
             }
+            OperationType::RANGE_DELETE => {
+                self.approximate_size.fetch_add(
+                    record.key().len() + record.value().len(),
+                    atomic::Ordering::Relaxed,
+                );
+                self.range_tombstones
+                    .write()
+                    .unwrap()
+                    .add(RangeTombstone::new(
+                        record.key(),
+                        record.value(),
+                        record.seq(),
+                    ));
+                self.last_sequence
+                    .fetch_max(record.seq(), atomic::Ordering::Release);
+                return;
+            }
+        };
+        self.table
+            .insert(record.key(), record.seq(), value.as_ref());
+        self.last_sequence
+            .fetch_max(record.seq(), atomic::Ordering::Release);
+    }
+
+    /// Return the range tombstones of the memtable, the writers of range tombstones wait
+    /// until the returned guard is dropped
+    pub fn range_tombstones(&self) -> RwLockReadGuard<'_, RangeTombstoneList> {
+        self.range_tombstones.read().unwrap()
+    }
+
+    /// Create an iterator over the records of the memtable inserted so far, the records
+    /// come with the header described in `db::format`
+    pub fn iter(&self) -> MemTableIterator {
+        self.iter_at(self.last_sequence.load(atomic::Ordering::Acquire))
+    }
+
+    /// Create an iterator over the records and the range tombstones not newer than
+    /// `visible_seq`, see `iter`
+    pub fn iter_at(&self, visible_seq: SequenceNumber) -> MemTableIterator {
+        use crate::iterator::Iterator;
+        let range_tombstones = self.range_tombstones();
+        let mut visible = RangeTombstoneList::with_comparator(self.comparator.clone());
+        for tombstone in range_tombstones.iter().filter(|x| x.seq <= visible_seq) {
+            visible.add(tombstone.clone());
         }
+        MemTableIterator::new(MemTableIteratorParts {
+            iter: self.table.iter(visible_seq),
+            range_tombstones: visible,
+            comparator: self.comparator.clone(),
+        })
     }
 
-    /// delete is composed by putting a new record with zero length value portion
-    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
-        self.put(key, "".as_bytes())
+    /// Tell the memtable that nothing is written to it any more, see
+    /// `MemTableRep::mark_read_only`
+    pub fn mark_read_only(&self) {
+        self.table.mark_read_only();
     }
-    pub fn recover(file: &FileObject) -> Self {
-        todo!()
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty() && self.range_tombstones().is_empty()
     }
 
-    pub fn seq_num(&self) -> u64 {
-        self.seq
+    /// Return the amount of bytes taken by the records
+    pub fn approximate_size(&self) -> usize {
+        self.approximate_size.load(atomic::Ordering::Relaxed) + self.table.memory_usage()
+    }
+}
+
+/// `RecordFolder` folds the records of a key in a memtable, given from the newest to the
+/// oldest, into the one record the key reads as. The records on top of the latest record
+/// other than a merge record are all merge records, they fold into one merge record whose
+/// base is that record. The base is a deletion if the record is covered by a range
+/// tombstone of the memtable, or unresolved if there is no such record in the memtable.
+pub(crate) struct RecordFolder {
+    /// the greatest sequence number of the range tombstones covering the key
+    covering_seq: SequenceNumber,
+    /// the newest record, which the key reads as unless it is a merge record
+    latest: Option<Bytes>,
+    /// the operands of the merge records, from the newest to the oldest
+    operands: Vec<Bytes>,
+    base: MergeBase,
+}
+
+impl RecordFolder {
+    pub(crate) fn new(covering_seq: SequenceNumber) -> Self {
+        RecordFolder {
+            covering_seq,
+            latest: None,
+            operands: Vec::new(),
+            base: MergeBase::Unresolved,
+        }
+    }
+
+    /// Add the next older record of the key, return whether the older records are still
+    /// needed
+    pub(crate) fn add(&mut self, record: &[u8]) -> bool {
+        let decoded = decode_value(record).expect("records are encoded by the memtable");
+        if self.latest.is_none() {
+            self.latest = Some(Bytes::copy_from_slice(record));
+            if decoded.value_type != ValueType::Merge {
+                return false;
+            }
+        } else if decoded.seq < self.covering_seq {
+            self.base = MergeBase::Deletion;
+            return false;
+        }
+        self.base = match decoded.value_type {
+            ValueType::Deletion => MergeBase::Deletion,
+            ValueType::Value => MergeBase::Value(Bytes::copy_from_slice(decoded.payload)),
+            ValueType::ExpiringValue => {
+                let (expire_at, value) = decode_expiring_payload(decoded.payload)
+                    .expect("records are encoded by the memtable");
+                MergeBase::ExpiringValue(expire_at, Bytes::copy_from_slice(value))
+            }
+            ValueType::BlobIndex => MergeBase::BlobIndex(
+                BlobIndex::decode(decoded.payload).expect("records are encoded by the memtable"),
+            ),
+            ValueType::Merge => {
+                let (base, operands) = decode_merge_payload(decoded.payload)
+                    .expect("records are encoded by the memtable");
+                self.operands.extend(operands.into_iter().rev());
+                base
+            }
+        };
+        self.base == MergeBase::Unresolved
+    }
+
+    /// Return the record the key reads as, `None` if no record is added
+    pub(crate) fn finish(mut self) -> Option<Bytes> {
+        let latest = self.latest?;
+        let decoded = decode_value(latest.as_ref()).expect("records are encoded by the memtable");
+        if decoded.value_type != ValueType::Merge {
+            return Some(latest);
+        }
+        self.operands.reverse();
+        let payload = encode_merge_payload(&self.base, self.operands.as_slice());
+        Some(encode_value(
+            ValueType::Merge,
+            decoded.seq,
+            payload.as_slice(),
+        ))
     }
 }
 
@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// every allocation is aligned to 8 bytes, enough for the atomic pointers of the skiplist
+const ALIGN: usize = 8;
+
+/// `Arena` hands out memory from large blocks which are only freed all together when the
+/// arena is dropped, so that the records of a memtable take a few allocations instead of
+/// two per record, and the memory they take is known exactly.
+///
+/// Allocating takes a short lock, the memory handed out is never touched by the arena again
+/// and may be used by several threads at the same time.
+pub struct Arena {
+    block_size: usize,
+    inner: Mutex<ArenaInner>,
+    /// the amount of bytes of the blocks allocated
+    memory_usage: AtomicUsize,
+}
+
+struct ArenaInner {
+    /// the blocks allocated along with their number of words, freed on drop
+    blocks: Vec<(*mut u64, usize)>,
+    /// the unused part of the current block
+    ptr: *mut u8,
+    remaining: usize,
+}
+
+// SAFETY: the blocks are owned by the arena, the raw pointers are only a way to share the
+// memory with the users of the arena
+unsafe impl Send for ArenaInner {}
+
+impl Arena {
+    pub fn new(block_size: usize) -> Self {
+        Arena {
+            block_size: block_size.max(ALIGN),
+            inner: Mutex::new(ArenaInner {
+                blocks: Vec::new(),
+                ptr: std::ptr::null_mut(),
+                remaining: 0,
+            }),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    /// Allocate `size` zeroed bytes aligned to 8 bytes, the memory stays valid until the
+    /// arena is dropped. An allocation larger than a quarter of the block size gets a block
+    /// of its own, so that the rest of the current block is not wasted.
+    pub fn allocate(&self, size: usize) -> *mut u8 {
+        let size = (size.max(1) + ALIGN - 1) & !(ALIGN - 1);
+        let mut inner = self.inner.lock().unwrap();
+        if size > inner.remaining {
+            if size > self.block_size / 4 {
+                return self.new_block(&mut inner, size);
+            }
+            inner.ptr = self.new_block(&mut inner, self.block_size);
+            inner.remaining = self.block_size;
+        }
+        let result = inner.ptr;
+        // SAFETY: `size` bytes remain in the current block
+        inner.ptr = unsafe { inner.ptr.add(size) };
+        inner.remaining -= size;
+        result
+    }
+
+    fn new_block(&self, inner: &mut ArenaInner, size: usize) -> *mut u8 {
+        let words = size.div_ceil(ALIGN);
+        let block = Box::into_raw(vec![0u64; words].into_boxed_slice()) as *mut u64;
+        inner.blocks.push((block, words));
+        self.memory_usage
+            .fetch_add(words * ALIGN, Ordering::Relaxed);
+        block as *mut u8
+    }
+
+    /// Return the amount of bytes taken by the arena
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for (block, words) in self.inner.get_mut().unwrap().blocks.drain(..) {
+            // SAFETY: the block is allocated by `new_block` as a boxed slice of `words` words
+            drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(block, words)) });
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::comparator::Comparator;
+use crate::db::format::RangeTombstoneList;
+use crate::iterator::Iterator;
+use crate::memtable::rep::MemTableRepIterator;
+use crate::memtable::RecordFolder;
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// Everything a `MemTableIterator` is made of
+pub struct MemTableIteratorParts {
+    pub iter: Box<dyn MemTableRepIterator>,
+    /// the range tombstones of the memtable
+    pub range_tombstones: RangeTombstoneList,
+    /// the comparator the records of `iter` are sorted by
+    pub comparator: Arc<dyn Comparator>,
+}
+
+/// MemTableIterator yields the record every key of a memtable reads as in the order of key,
+/// whichever `MemTableRep` the memtable keeps the records in. The merge records of a key are
+/// folded with the older records like `MemTable::get_entry` does. Later writes to the
+/// memtable are not visible.
+pub struct MemTableIterator {
+    /// positioned at the first record of the key after the current one
+    iter: Box<dyn MemTableRepIterator>,
+    range_tombstones: RangeTombstoneList,
+    comparator: Arc<dyn Comparator>,
+    /// the current key and the record it reads as
+    current: Option<(Bytes, Bytes)>,
+}
+
+impl MemTableIterator {
+    /// Fold the records of the key `iter` is at, then move `iter` past them
+    fn fold_forward(&mut self) {
+        self.current = None;
+        if !self.iter.is_valid() {
+            return;
+        }
+        let key = Bytes::copy_from_slice(self.iter.key());
+        let mut folder = RecordFolder::new(self.range_tombstones.max_covering_seq(&key));
+        let mut needed = true;
+        while self.iter.is_valid() && self.comparator.compare(self.iter.key(), &key).is_eq() {
+            if needed {
+                needed = folder.add(self.iter.value());
+            }
+            self.iter.next();
+        }
+        self.current = folder.finish().map(|record| (key, record));
+    }
+}
+
+impl Iterator for MemTableIterator {
+    type Item = MemTableIteratorParts;
+
+    fn new(arg: Self::Item) -> Self {
+        MemTableIterator {
+            iter: arg.iter,
+            range_tombstones: arg.range_tombstones,
+            comparator: arg.comparator,
+            current: None,
+        }
+    }
+
+    fn seek_to_first(&mut self) {
+        self.iter.seek_to_first();
+        self.fold_forward();
+    }
+
+    fn seek_to_last(&mut self) {
+        // the last record is the oldest one of the last key
+        self.iter.seek_to_last();
+        if self.iter.is_valid() {
+            let key = self.iter.key().to_vec();
+            self.iter.seek(key.as_slice());
+        }
+        self.fold_forward();
+    }
+
+    /// Position at the first record whose key is not less than `key`
+    fn seek_to_key(&mut self, key: &[u8]) {
+        self.iter.seek(key);
+        self.fold_forward();
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn next(&mut self) {
+        if self.current.is_some() {
+            self.fold_forward();
+        }
+    }
+
+    fn prev(&mut self) {
+        let Some((key, _)) = self.current.take() else {
+            return;
+        };
+        // move to the oldest record of the previous key, then to its newest one
+        self.iter.seek(key.as_ref());
+        self.iter.prev();
+        if self.iter.is_valid() {
+            let key = self.iter.key().to_vec();
+            self.iter.seek(key.as_slice());
+            self.fold_forward();
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        self.current.as_ref().unwrap().0.as_ref()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().1.as_ref()
+    }
+}
@@ -1,75 +1,163 @@
 use crate::blocks::SIZE_U16;
-use crate::memtable::logger::OperationType::{DELETE, PUT};
-use crate::util::env::{logfile_path, FileObject};
-use anyhow::Result;
+use crate::db::format::SequenceNumber;
+use crate::memtable::logger::OperationType::{DELETE, MERGE, PUT, PUT_WITH_TTL, RANGE_DELETE};
+use crate::util::env::{logfile_path, read_file, Env, WritableFile};
+use crate::util::error::{Error, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::fmt::Formatter;
 
 pub struct MemTableLogger {
     seq: u64,
-    file_obj: FileObject,
+    file_obj: Box<dyn WritableFile>,
 }
 impl MemTableLogger {
-    pub fn new(seq: u64) -> Self {
-        let file_obj = FileObject::create(logfile_path(seq as usize).as_str()).unwrap();
-        MemTableLogger { seq, file_obj }
+    /// Create the log file numbered `seq` in the database directory `dir`
+    pub fn new(env: &dyn Env, dir: &str, seq: u64) -> Result<Self> {
+        let file_obj = env.new_writable_file(logfile_path(dir, seq as usize).as_str())?;
+        Ok(MemTableLogger { seq, file_obj })
     }
+    /// Log the records built by `LogRecordsBuilder` as a single write and sync the log file.
+    /// The records are prefixed with their length, so that a write interrupted by a crash
+    /// is dropped as a whole on recovery.
     pub fn log_and_sync(&mut self, log_records: &[u8]) -> Result<()> {
-        self.file_obj.write(log_records)?;
+        let mut buf = Vec::with_capacity(SIZE_U32 + log_records.len());
+        buf.put_u32(log_records.len() as u32);
+        buf.put_slice(log_records);
+        self.file_obj.append(buf.as_slice())?;
         self.file_obj.sync()
     }
+
+    /// Return the number of the log file
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Read all the records of the log file at `path`.
+    /// The last write may be partially written if the process crashed in the middle of
+    /// logging, the decoding stops at the first incomplete or broken write and the records
+    /// before it are returned.
+    pub fn read_records(env: &dyn Env, path: &str) -> Result<Vec<LoggerRecord>> {
+        let buf = read_file(env, path)?;
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some(mut length) = buf.get(offset..offset + SIZE_U32) {
+            let length = length.get_u32() as usize;
+            let Some(mut batch) = buf.get(offset + SIZE_U32..offset + SIZE_U32 + length) else {
+                break;
+            };
+            let mut batch_records = Vec::new();
+            while !batch.is_empty() {
+                match LoggerRecord::decode(batch) {
+                    Ok((record, consumed)) => {
+                        batch_records.push(record);
+                        batch = &batch[consumed..];
+                    }
+                    Err(_) => return Ok(records),
+                }
+            }
+            records.append(&mut batch_records);
+            offset += SIZE_U32 + length;
+        }
+        Ok(records)
+    }
 }
 
 pub struct LoggerRecord {
     opt_type: OperationType,
+    seq: SequenceNumber,
+    /// id of the column family the record belongs to
+    column_family: u32,
     key: Bytes,
     value: Bytes,
 }
 impl LoggerRecord {
-    pub fn new(opt: OperationType, key: &[u8], value: &[u8]) -> Self {
+    pub fn new(
+        opt: OperationType,
+        seq: SequenceNumber,
+        column_family: u32,
+        key: &[u8],
+        value: &[u8],
+    ) -> Self {
         LoggerRecord {
             opt_type: opt,
+            seq,
+            column_family,
             key: Bytes::copy_from_slice(key),
             value: Bytes::copy_from_slice(value),
         }
     }
     pub fn encode(&self) -> Bytes {
         let mut buf = BytesMut::new();
+        buf.put_u8(self.opt_type.to_u8());
+        buf.put_u64(self.seq);
+        buf.put_u32(self.column_family);
         //encoding key portion of the records
         buf.put_u16(self.key.len() as u16);
         buf.put_slice(self.key.as_ref());
         // encoding value portion of the records
         match self.opt_type {
-            PUT => {
-                buf.put_u16(self.value.len() as u16);
+            PUT | RANGE_DELETE | MERGE | PUT_WITH_TTL => {
+                buf.put_u32(self.value.len() as u32);
                 buf.put_slice(self.value.as_ref());
             }
             DELETE => {
-                buf.put_u16(0);
+                buf.put_u32(0);
             }
         }
         buf.freeze()
     }
 
-    pub fn decode(buf: &[u8]) -> Self {
-        let raw = buf;
-        let key_length = raw[..SIZE_U16].as_ref().get_u16();
-        let key_raw = raw[SIZE_U16..SIZE_U16 + key_length as usize].as_ref();
-        let key = Bytes::copy_from_slice(key_raw);
-        let value_portion = raw[SIZE_U16 + key_length as usize..].as_ref();
-        let value_length = value_portion[..SIZE_U16].as_ref().get_u16();
-        if value_length == 0 {
-            return LoggerRecord {
-                opt_type: DELETE,
-                key,
-                value: Bytes::copy_from_slice("".as_bytes()),
-            };
+    /// Decode one record from the beginning of `buf`, return the record and the number of
+    /// bytes it occupies.
+    /// Failed with `Corruption` if `buf` is shorter than the lengths it claims, the offset of
+    /// the `Corruption` is relative to the beginning of `buf`
+    pub fn decode(buf: &[u8]) -> Result<(Self, usize)> {
+        let mut raw = buf;
+        if raw.len() < LOG_RECORD_HEADER_SIZE {
+            return Err(Error::corruption("", 0));
         }
-        return LoggerRecord {
-            opt_type: PUT,
+        let opt_type =
+            OperationType::from_u8(raw.get_u8()).ok_or_else(|| Error::corruption("", 0))?;
+        let seq = raw.get_u64();
+        let column_family = raw.get_u32();
+        let key_length = raw.get_u16() as usize;
+        let key_raw = raw
+            .get(..key_length)
+            .ok_or_else(|| Error::corruption("", LOG_RECORD_HEADER_SIZE as u64))?;
+        let key = Bytes::copy_from_slice(key_raw);
+        let value_portion = &raw[key_length..];
+        let value_offset = LOG_RECORD_HEADER_SIZE + key_length;
+        let value_length = value_portion
+            .get(..SIZE_U32)
+            .ok_or_else(|| Error::corruption("", value_offset as u64))?
+            .get_u32() as usize;
+        let value_raw = value_portion
+            .get(SIZE_U32..SIZE_U32 + value_length)
+            .ok_or_else(|| Error::corruption("", (value_offset + SIZE_U32) as u64))?;
+        let record = LoggerRecord {
+            opt_type,
+            seq,
+            column_family,
             key,
-            value: Bytes::copy_from_slice(value_portion[SIZE_U16..SIZE_U16+value_length as usize].as_ref()),
+            value: Bytes::copy_from_slice(value_raw),
         };
+        Ok((record, value_offset + SIZE_U32 + value_length))
+    }
+
+    pub fn opt_type(&self) -> &OperationType {
+        &self.opt_type
+    }
+    pub fn seq(&self) -> SequenceNumber {
+        self.seq
+    }
+    pub fn column_family(&self) -> u32 {
+        self.column_family
+    }
+    pub fn key(&self) -> &[u8] {
+        self.key.as_ref()
+    }
+    pub fn value(&self) -> &[u8] {
+        self.value.as_ref()
     }
 }
 
@@ -77,38 +165,97 @@ impl std::fmt::Display for LoggerRecord {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "===\nopt: {:?}\nkey: {:?}\nvalue: {:?}\n===",
-            self.opt_type, self.key, self.value,
+            "===\nopt: {:?}\nseq: {}\ncolumn family: {}\nkey: {:?}\nvalue: {:?}\n===",
+            self.opt_type, self.seq, self.column_family, self.key, self.value,
         )
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
 pub enum OperationType {
     #[default]
     PUT,
     DELETE,
+    /// delete the keys in `[key, value)`
+    RANGE_DELETE,
+    /// merge the operand `value` into the value of `key`
+    MERGE,
+    /// put a value which expires, `value` is encoded by `encode_expiring_payload`
+    PUT_WITH_TTL,
 }
 
+impl OperationType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            PUT => 0,
+            DELETE => 1,
+            RANGE_DELETE => 2,
+            MERGE => 3,
+            PUT_WITH_TTL => 4,
+        }
+    }
+    pub fn from_u8(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(PUT),
+            1 => Some(DELETE),
+            2 => Some(RANGE_DELETE),
+            3 => Some(MERGE),
+            4 => Some(PUT_WITH_TTL),
+            _ => None,
+        }
+    }
+}
+
+/// length of the fixed fields before the key payload
+pub const LOG_RECORD_HEADER_SIZE: usize = 1 + 8 + SIZE_U32 + SIZE_U16;
+const SIZE_U32: usize = std::mem::size_of::<u32>();
+
 /// LogRecords is a sequence of `Entity`
 /// `Entity` format is described below
 ///
 /// ``` text
-/// +----------------------------------------------------------------------------------------------------------------------+
-/// | Key Length (2 bytes) |  Key PayLoads(key-length bytes) | Value Length (2 bytes) | Value PayLoad (value-length bytes) |
-/// +----------------------------------------------------------------------------------------------------------------------+
+/// +----------------------------------------------------------------------------------------------------------------------------------------------------------------------------+
+/// | Operation Type#1 | Sequence#8 | Column Family#4 | Key Length (2 bytes) |  Key PayLoads(key-length bytes) | Value Length (4 bytes) | Value PayLoad (value-length bytes) |
+/// +----------------------------------------------------------------------------------------------------------------------------------------------------------------------------+
 /// ```
-/// It is same as the block entry, if the `Value Length` portion is zero, yields a delete operation
-/// on that key
+/// A `DELETE` record carries no value, a `RANGE_DELETE` record deletes the keys from `Key`
+/// (inclusive) to `Value` (exclusive), a `MERGE` record carries the merge operand as `Value`
+/// and a `PUT_WITH_TTL` record carries the value prefixed with the expiry moment, see
+/// `encode_expiring_payload`.
+///
+/// The log file is a sequence of writes, every write holds the records of a
+/// `LogRecordsBuilder` prefixed with their length as a 4 bytes big endian integer.
 pub struct LogRecordsBuilder {
     data: Vec<u8>,
 }
+impl Default for LogRecordsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl LogRecordsBuilder {
     pub fn new() -> Self {
         LogRecordsBuilder { data: Vec::new() }
     }
-    pub fn add(&mut self, opt: OperationType, key: &[u8], value: &[u8]) -> Result<()> {
-        let record = LoggerRecord::new(opt, key, value);
+    /// Failed with `InvalidArgument` if `key` or `value` is longer than its length field can
+    /// describe
+    pub fn add(
+        &mut self,
+        opt: OperationType,
+        seq: SequenceNumber,
+        column_family: u32,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<()> {
+        if key.len() > u16::MAX as usize || value.len() > u32::MAX as usize {
+            return Err(Error::InvalidArgument(format!(
+                "record with key of {} bytes and value of {} bytes is too long",
+                key.len(),
+                value.len()
+            )));
+        }
+        let record = LoggerRecord::new(opt, seq, column_family, key, value);
 
         self.data.put_slice(record.encode().as_ref());
         Ok(())
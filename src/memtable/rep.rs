@@ -0,0 +1,150 @@
+use crate::comparator::Comparator;
+use crate::db::format::SequenceNumber;
+use bytes::Bytes;
+use std::sync::Arc;
+
+pub mod btree;
+pub mod hash_linklist;
+pub mod skiplist;
+pub mod vector;
+
+/// `MemTableRep` is the data structure a memtable keeps its records in, it is created by
+/// `Options::memtable_factory`. A record is inserted for every write of a key, the rep keeps
+/// all the records of a key, so that the merge records stacked on a key are folded when read.
+///
+/// The records are inserted by several writers at the same time along with the readers, so
+/// the records of a key may be inserted in any order of their sequence numbers.
+pub trait MemTableRep: Send + Sync {
+    /// Insert the record of `key` written with the sequence number `seq`
+    fn insert(&self, key: &[u8], seq: SequenceNumber, value: &[u8]);
+
+    /// Call `visit` with the records of `key` from the newest to the oldest, until it returns
+    /// `false`
+    fn get(&self, key: &[u8], visit: &mut dyn FnMut(&[u8]) -> bool);
+
+    /// Tell the rep that nothing is inserted any more, called before the memtable is
+    /// flushed. A rep may reorganize its records for the reads then, but must still accept
+    /// the inserts of a memtable put back after a failed flush.
+    fn mark_read_only(&self) {}
+
+    /// Return an iterator over the records not newer than `visible_seq`. The records come
+    /// in the order of the comparator whatever the order the rep keeps them in, the records
+    /// of a key from the newest to the oldest.
+    fn iter(&self, visible_seq: SequenceNumber) -> Box<dyn MemTableRepIterator>;
+
+    fn is_empty(&self) -> bool;
+
+    /// Return the amount of bytes taken by the records
+    fn memory_usage(&self) -> usize;
+}
+
+/// `MemTableRepIterator` yields the records of a `MemTableRep` sorted by the key, see
+/// `MemTableRep::iter`. The records of a key are all visited one after another.
+pub trait MemTableRepIterator: Send {
+    fn seek_to_first(&mut self);
+    fn seek_to_last(&mut self);
+    /// Position at the newest record of `key`, or at the first record of the next key if
+    /// there is none
+    fn seek(&mut self, key: &[u8]);
+    fn is_valid(&self) -> bool;
+    fn next(&mut self);
+    fn prev(&mut self);
+    fn key(&self) -> &[u8];
+    fn value(&self) -> &[u8];
+}
+
+/// A record of the reps which do not keep the records sorted all the time
+#[derive(Clone)]
+pub struct Record {
+    pub key: Bytes,
+    pub seq: SequenceNumber,
+    pub value: Bytes,
+}
+
+impl Record {
+    fn new(key: &[u8], seq: SequenceNumber, value: &[u8]) -> Self {
+        Record {
+            key: Bytes::copy_from_slice(key),
+            seq,
+            value: Bytes::copy_from_slice(value),
+        }
+    }
+
+    /// Return the amount of bytes taken by the record
+    fn size(&self) -> usize {
+        std::mem::size_of::<Record>() + self.key.len() + self.value.len()
+    }
+}
+
+/// Keep the records in `records` not newer than `visible_seq`, sorted by the key in the
+/// order of `comparator` and the records of a key from the newest
+fn sort_records(
+    mut records: Vec<Record>,
+    visible_seq: SequenceNumber,
+    comparator: &dyn Comparator,
+) -> Vec<Record> {
+    records.retain(|x| x.seq <= visible_seq);
+    records.sort_by(|a, b| comparator.compare(&a.key, &b.key).then(b.seq.cmp(&a.seq)));
+    records
+}
+
+/// `SortedRecordsIterator` iterates over the records sorted by `sort_records`
+pub struct SortedRecordsIterator {
+    records: Arc<Vec<Record>>,
+    comparator: Arc<dyn Comparator>,
+    /// the current record, `records.len()` if the iterator is not valid
+    index: usize,
+}
+
+impl SortedRecordsIterator {
+    fn new(records: Arc<Vec<Record>>, comparator: Arc<dyn Comparator>) -> Self {
+        let index = records.len();
+        SortedRecordsIterator {
+            records,
+            comparator,
+            index,
+        }
+    }
+}
+
+impl MemTableRepIterator for SortedRecordsIterator {
+    fn seek_to_first(&mut self) {
+        self.index = 0;
+    }
+
+    fn seek_to_last(&mut self) {
+        self.index = self.records.len().saturating_sub(1);
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.index = self
+            .records
+            .partition_point(|x| self.comparator.compare(&x.key, key).is_lt());
+    }
+
+    fn is_valid(&self) -> bool {
+        self.index < self.records.len()
+    }
+
+    fn next(&mut self) {
+        if self.is_valid() {
+            self.index += 1;
+        }
+    }
+
+    fn prev(&mut self) {
+        self.index = match self.index {
+            0 => self.records.len(),
+            index if index < self.records.len() => index - 1,
+            index => index,
+        };
+    }
+
+    fn key(&self) -> &[u8] {
+        self.records[self.index].key.as_ref()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.records[self.index].value.as_ref()
+    }
+}
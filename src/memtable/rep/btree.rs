@@ -0,0 +1,143 @@
+use crate::comparator::Comparator;
+use crate::db::format::SequenceNumber;
+use crate::memtable::rep::{MemTableRep, MemTableRepIterator};
+use crate::memtable::{MemTableKey, MemTableMap};
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Included, Unbounded};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// `BTreeRep` keeps the records in a `BTreeMap`, see `MemTableFactory::BTree`. The map is
+/// shared with the iterators created from the rep, which skip the records inserted after
+/// them by their sequence numbers like the iterators of `SkipListRep` do.
+pub struct BTreeRep {
+    table: Arc<RwLock<MemTableMap>>,
+    comparator: Arc<dyn Comparator>,
+    /// the amount of bytes of the keys and values inserted
+    memory_usage: AtomicUsize,
+}
+
+impl BTreeRep {
+    pub fn new(comparator: Arc<dyn Comparator>) -> Self {
+        BTreeRep {
+            table: Arc::new(RwLock::new(BTreeMap::new())),
+            comparator,
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    fn memtable_key(&self, key: &[u8], seq: SequenceNumber) -> MemTableKey {
+        MemTableKey::new(key, seq, self.comparator.clone())
+    }
+}
+
+impl MemTableRep for BTreeRep {
+    fn insert(&self, key: &[u8], seq: SequenceNumber, value: &[u8]) {
+        self.memory_usage
+            .fetch_add(key.len() + value.len(), Ordering::Relaxed);
+        let key = self.memtable_key(key, seq);
+        self.table
+            .write()
+            .unwrap()
+            .insert(key, Bytes::copy_from_slice(value));
+    }
+
+    fn get(&self, key: &[u8], visit: &mut dyn FnMut(&[u8]) -> bool) {
+        let start = self.memtable_key(key, SequenceNumber::MAX);
+        let table = self.table.read().unwrap();
+        for (record_key, value) in table.range((Included(start), Unbounded)) {
+            if self.comparator.compare(record_key.key(), key).is_ne() || !visit(value) {
+                return;
+            }
+        }
+    }
+
+    fn iter(&self, visible_seq: SequenceNumber) -> Box<dyn MemTableRepIterator> {
+        Box::new(BTreeRepIterator {
+            table: self.table.clone(),
+            comparator: self.comparator.clone(),
+            visible_seq,
+            current: None,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.table.read().unwrap().is_empty()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+}
+
+/// `BTreeRepIterator` shares the map of the rep, and yields its records not newer than
+/// `visible_seq`. The map is locked by every move of the iterator, which keeps a copy of the
+/// current record.
+pub struct BTreeRepIterator {
+    table: Arc<RwLock<MemTableMap>>,
+    comparator: Arc<dyn Comparator>,
+    visible_seq: SequenceNumber,
+    current: Option<(MemTableKey, Bytes)>,
+}
+
+impl BTreeRepIterator {
+    /// Position at the first visible record of `records`
+    fn set_current<'a>(&mut self, mut records: impl Iterator<Item = (&'a MemTableKey, &'a Bytes)>) {
+        self.current = records
+            .find(|(k, _)| k.seq() <= self.visible_seq)
+            .map(|(k, v)| (k.clone(), v.clone()));
+    }
+}
+
+impl MemTableRepIterator for BTreeRepIterator {
+    fn seek_to_first(&mut self) {
+        let table = self.table.clone();
+        self.set_current(table.read().unwrap().iter());
+    }
+
+    fn seek_to_last(&mut self) {
+        let table = self.table.clone();
+        self.set_current(table.read().unwrap().iter().rev());
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        let table = self.table.clone();
+        let key = MemTableKey::new(key, self.visible_seq, self.comparator.clone());
+        self.set_current(table.read().unwrap().range((Included(key), Unbounded)));
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn next(&mut self) {
+        let Some((key, _)) = self.current.take() else {
+            return;
+        };
+        let table = self.table.clone();
+        self.set_current(table.read().unwrap().range((Excluded(key), Unbounded)));
+    }
+
+    fn prev(&mut self) {
+        let Some((key, _)) = self.current.take() else {
+            return;
+        };
+        let table = self.table.clone();
+        self.set_current(
+            table
+                .read()
+                .unwrap()
+                .range((Unbounded, Excluded(key)))
+                .rev(),
+        );
+    }
+
+    fn key(&self) -> &[u8] {
+        self.current.as_ref().unwrap().0.key().as_ref()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().1.as_ref()
+    }
+}
@@ -0,0 +1,107 @@
+use crate::comparator::Comparator;
+use crate::db::format::SequenceNumber;
+use crate::memtable::rep::{
+    sort_records, MemTableRep, MemTableRepIterator, Record, SortedRecordsIterator,
+};
+use crate::slice_transform::SliceTransform;
+use crate::util::bloom::bloom_hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// `HashLinkListRep` spreads the records over a fixed number of buckets by the hash of the
+/// prefix of their keys, see `MemTableFactory::HashLinkList`. Every bucket keeps its records
+/// sorted, so a lookup only searches the bucket of the key, and the keys sharing a prefix are
+/// found together. The keys out of the domain of the prefix extractor, or all the keys
+/// without an extractor, are hashed as a whole.
+///
+/// There is no order across the buckets, an iterator sorts a copy of all the records.
+/// Every bucket has a lock of its own, so the writers of different buckets do not wait for
+/// each other.
+pub struct HashLinkListRep {
+    buckets: Vec<RwLock<Vec<Record>>>,
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    comparator: Arc<dyn Comparator>,
+    len: AtomicUsize,
+    memory_usage: AtomicUsize,
+}
+
+impl HashLinkListRep {
+    pub fn new(
+        comparator: Arc<dyn Comparator>,
+        prefix_extractor: Option<Arc<dyn SliceTransform>>,
+        bucket_count: usize,
+    ) -> Self {
+        HashLinkListRep {
+            buckets: (0..bucket_count.max(1))
+                .map(|_| RwLock::new(Vec::new()))
+                .collect(),
+            prefix_extractor,
+            comparator,
+            len: AtomicUsize::new(0),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    fn bucket(&self, key: &[u8]) -> usize {
+        let prefix = match self.prefix_extractor.as_deref() {
+            Some(extractor) if extractor.in_domain(key) => extractor.transform(key),
+            _ => key,
+        };
+        bloom_hash(prefix) as usize % self.buckets.len()
+    }
+
+    /// Return the position of the first record of `bucket` not less than the record of
+    /// `key` and `seq`, the records of a key are sorted from the newest
+    fn position(&self, bucket: &[Record], key: &[u8], seq: SequenceNumber) -> usize {
+        bucket.partition_point(|x| {
+            self.comparator
+                .compare(&x.key, key)
+                .then(seq.cmp(&x.seq))
+                .is_lt()
+        })
+    }
+}
+
+impl MemTableRep for HashLinkListRep {
+    fn insert(&self, key: &[u8], seq: SequenceNumber, value: &[u8]) {
+        let record = Record::new(key, seq, value);
+        self.memory_usage
+            .fetch_add(record.size(), Ordering::Relaxed);
+        let mut bucket = self.buckets[self.bucket(key)].write().unwrap();
+        let position = self.position(&bucket, key, seq);
+        bucket.insert(position, record);
+        self.len.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self, key: &[u8], visit: &mut dyn FnMut(&[u8]) -> bool) {
+        let bucket = self.buckets[self.bucket(key)].read().unwrap();
+        let position = self.position(&bucket, key, SequenceNumber::MAX);
+        for record in &bucket[position..] {
+            if self.comparator.compare(&record.key, key).is_ne() || !visit(&record.value) {
+                return;
+            }
+        }
+    }
+
+    fn iter(&self, visible_seq: SequenceNumber) -> Box<dyn MemTableRepIterator> {
+        let records = self
+            .buckets
+            .iter()
+            .flat_map(|x| x.read().unwrap().clone())
+            .collect();
+        let sorted = sort_records(records, visible_seq, self.comparator.as_ref());
+        Box::new(SortedRecordsIterator::new(
+            Arc::new(sorted),
+            self.comparator.clone(),
+        ))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len.load(Ordering::Relaxed) == 0
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+            + self.buckets.len() * std::mem::size_of::<RwLock<Vec<Record>>>()
+    }
+}
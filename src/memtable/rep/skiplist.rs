@@ -0,0 +1,119 @@
+use crate::comparator::Comparator;
+use crate::db::format::SequenceNumber;
+use crate::memtable::rep::{MemTableRep, MemTableRepIterator};
+use crate::memtable::skiplist::{SkipList, SkipListIterator};
+use std::sync::Arc;
+
+/// `SkipListRep` inserts every record into a `SkipList`, see `MemTableFactory::SkipList`.
+/// The list is shared with the iterators created from the rep, they ignore the records
+/// inserted later by their sequence numbers.
+pub struct SkipListRep {
+    list: Arc<SkipList>,
+}
+
+impl SkipListRep {
+    pub fn new(comparator: Arc<dyn Comparator>, arena_block_size: usize) -> Self {
+        SkipListRep {
+            list: Arc::new(SkipList::new(comparator, arena_block_size)),
+        }
+    }
+}
+
+impl MemTableRep for SkipListRep {
+    fn insert(&self, key: &[u8], seq: SequenceNumber, value: &[u8]) {
+        self.list.insert(key, seq, value);
+    }
+
+    fn get(&self, key: &[u8], visit: &mut dyn FnMut(&[u8]) -> bool) {
+        let comparator = self.list.comparator().clone();
+        let mut iter = SkipListIterator::new(self.list.clone());
+        iter.seek(key, SequenceNumber::MAX);
+        while iter.is_valid() && comparator.compare(iter.key(), key).is_eq() {
+            if !visit(iter.value()) {
+                return;
+            }
+            iter.next();
+        }
+    }
+
+    fn iter(&self, visible_seq: SequenceNumber) -> Box<dyn MemTableRepIterator> {
+        Box::new(SkipListRepIterator {
+            iter: SkipListIterator::new(self.list.clone()),
+            visible_seq,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// The memory is told exactly by the arena of the list
+    fn memory_usage(&self) -> usize {
+        self.list.memory_usage()
+    }
+}
+
+/// `SkipListRepIterator` yields the records of the list not newer than `visible_seq`
+pub struct SkipListRepIterator {
+    iter: SkipListIterator,
+    visible_seq: SequenceNumber,
+}
+
+impl SkipListRepIterator {
+    /// Move forward to the first visible record from the current one
+    fn skip_invisible_forward(&mut self) {
+        while self.iter.is_valid() && self.iter.seq() > self.visible_seq {
+            self.iter.next();
+        }
+    }
+
+    /// Move backward to the last visible record from the current one
+    fn skip_invisible_backward(&mut self) {
+        while self.iter.is_valid() && self.iter.seq() > self.visible_seq {
+            self.iter.prev();
+        }
+    }
+}
+
+impl MemTableRepIterator for SkipListRepIterator {
+    fn seek_to_first(&mut self) {
+        self.iter.seek_to_first();
+        self.skip_invisible_forward();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.iter.seek_to_last();
+        self.skip_invisible_backward();
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.iter.seek(key, self.visible_seq);
+        self.skip_invisible_forward();
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) {
+        if self.iter.is_valid() {
+            self.iter.next();
+            self.skip_invisible_forward();
+        }
+    }
+
+    fn prev(&mut self) {
+        if self.iter.is_valid() {
+            self.iter.prev();
+            self.skip_invisible_backward();
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+}
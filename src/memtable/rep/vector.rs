@@ -0,0 +1,107 @@
+use crate::comparator::Comparator;
+use crate::db::format::SequenceNumber;
+use crate::memtable::rep::{
+    sort_records, MemTableRep, MemTableRepIterator, Record, SortedRecordsIterator,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// `VectorRep` appends the records to a vector in the order they are inserted, see
+/// `MemTableFactory::Vector`. Inserting is cheap, but reading is not until the rep is
+/// marked read only: a lookup scans the whole vector, and an iterator sorts a copy of it.
+/// Once read only, the vector is sorted in place once and shared by the iterators. A
+/// later insert, into a memtable put back after a failed flush, unsorts it again.
+pub struct VectorRep {
+    records: RwLock<VectorRecords>,
+    comparator: Arc<dyn Comparator>,
+    memory_usage: AtomicUsize,
+}
+
+#[derive(Default)]
+struct VectorRecords {
+    records: Vec<Record>,
+    /// the records sorted by `sort_records`, once the rep is read only
+    sorted: Option<Arc<Vec<Record>>>,
+}
+
+impl VectorRep {
+    pub fn new(comparator: Arc<dyn Comparator>) -> Self {
+        VectorRep {
+            records: RwLock::default(),
+            comparator,
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemTableRep for VectorRep {
+    fn insert(&self, key: &[u8], seq: SequenceNumber, value: &[u8]) {
+        let record = Record::new(key, seq, value);
+        self.memory_usage
+            .fetch_add(record.size(), Ordering::Relaxed);
+        let mut records = self.records.write().unwrap();
+        if let Some(sorted) = records.sorted.take() {
+            records.records = Arc::try_unwrap(sorted).unwrap_or_else(|x| (*x).clone());
+        }
+        records.records.push(record);
+    }
+
+    fn get(&self, key: &[u8], visit: &mut dyn FnMut(&[u8]) -> bool) {
+        let comparator = self.comparator.as_ref();
+        let guard = self.records.read().unwrap();
+        let records: Vec<&Record> = match &guard.sorted {
+            Some(sorted) => {
+                let index = sorted.partition_point(|x| comparator.compare(&x.key, key).is_lt());
+                sorted[index..]
+                    .iter()
+                    .take_while(|x| comparator.compare(&x.key, key).is_eq())
+                    .collect()
+            }
+            None => {
+                let mut records: Vec<&Record> = guard
+                    .records
+                    .iter()
+                    .filter(|x| comparator.compare(&x.key, key).is_eq())
+                    .collect();
+                records.sort_by_key(|x| std::cmp::Reverse(x.seq));
+                records
+            }
+        };
+        for record in records {
+            if !visit(record.value.as_ref()) {
+                return;
+            }
+        }
+    }
+
+    fn mark_read_only(&self) {
+        let mut records = self.records.write().unwrap();
+        if records.sorted.is_none() {
+            let unsorted = std::mem::take(&mut records.records);
+            let sorted = sort_records(unsorted, SequenceNumber::MAX, self.comparator.as_ref());
+            records.sorted = Some(Arc::new(sorted));
+        }
+    }
+
+    fn iter(&self, visible_seq: SequenceNumber) -> Box<dyn MemTableRepIterator> {
+        let records = self.records.read().unwrap();
+        let sorted = match &records.sorted {
+            Some(sorted) => sorted.clone(),
+            None => Arc::new(sort_records(
+                records.records.clone(),
+                visible_seq,
+                self.comparator.as_ref(),
+            )),
+        };
+        Box::new(SortedRecordsIterator::new(sorted, self.comparator.clone()))
+    }
+
+    fn is_empty(&self) -> bool {
+        let records = self.records.read().unwrap();
+        records.records.is_empty() && records.sorted.as_ref().is_none_or(|x| x.is_empty())
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+}
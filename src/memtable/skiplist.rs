@@ -0,0 +1,386 @@
+use crate::comparator::Comparator;
+use crate::db::format::SequenceNumber;
+use crate::memtable::arena::Arena;
+use std::cmp::Ordering;
+use std::mem::size_of;
+use std::ptr::null_mut;
+use std::sync::atomic::{self, AtomicPtr, AtomicU64, AtomicUsize};
+use std::sync::Arc;
+
+pub const MAX_HEIGHT: usize = 12;
+/// one node in `BRANCHING` of a level is linked in the level above as well
+const BRANCHING: u64 = 4;
+
+/// The header of a node of the skiplist. The header is followed by the tower of `height`
+/// pointers to the next nodes of every level, then the key and the value, all of them
+/// allocated from the arena at once.
+#[repr(C)]
+struct Node {
+    seq: SequenceNumber,
+    key_len: u32,
+    value_len: u32,
+    height: usize,
+}
+
+/// Return the pointer to the next node of `node` in `level`
+///
+/// # Safety
+/// `node` points to a node allocated by `SkipList::allocate_node` with more than `level` levels
+unsafe fn tower(node: *const Node, level: usize) -> *const AtomicPtr<Node> {
+    (node as *const u8)
+        .add(size_of::<Node>())
+        .cast::<AtomicPtr<Node>>()
+        .add(level)
+}
+
+/// # Safety
+/// see `tower`
+unsafe fn next(node: *const Node, level: usize) -> *mut Node {
+    (*tower(node, level)).load(atomic::Ordering::Acquire)
+}
+
+/// Return the key of `node`, valid as long as the arena of the node
+///
+/// # Safety
+/// `node` points to a node allocated by `SkipList::allocate_node`
+unsafe fn key<'a>(node: *const Node) -> &'a [u8] {
+    let start =
+        (node as *const u8).add(size_of::<Node>() + (*node).height * size_of::<AtomicPtr<Node>>());
+    std::slice::from_raw_parts(start, (*node).key_len as usize)
+}
+
+/// # Safety
+/// see `key`
+unsafe fn value<'a>(node: *const Node) -> &'a [u8] {
+    let start = key(node).as_ptr().add((*node).key_len as usize);
+    std::slice::from_raw_parts(start, (*node).value_len as usize)
+}
+
+/// `SkipList` keeps the entries of a memtable sorted by the key in the order of the
+/// comparator, the entries of the same key are sorted by the sequence number from the
+/// newest to the oldest. An entry is never changed or removed once inserted, a newer entry
+/// of the key is inserted instead.
+///
+/// The entries are inserted by several threads at the same time, each of them links its
+/// node level by level with compare-and-swap. The readers take no lock at all. The nodes are
+/// allocated from an `Arena`, the memory taken by the list is told by `memory_usage`.
+pub struct SkipList {
+    arena: Arena,
+    /// the node before the first entry in every level, it holds no entry
+    head: *mut Node,
+    /// the number of levels in use
+    max_height: AtomicUsize,
+    comparator: Arc<dyn Comparator>,
+    /// the state of the random generator of the node heights
+    random: AtomicU64,
+    len: AtomicUsize,
+}
+
+// SAFETY: the nodes are owned by the arena of the list, they are only changed through the
+// atomic pointers of their towers
+unsafe impl Send for SkipList {}
+unsafe impl Sync for SkipList {}
+
+impl SkipList {
+    /// Create an empty list allocating the nodes from blocks of `arena_block_size` bytes
+    pub fn new(comparator: Arc<dyn Comparator>, arena_block_size: usize) -> Self {
+        let arena = Arena::new(arena_block_size);
+        let head = Self::allocate_node(&arena, b"", 0, b"", MAX_HEIGHT);
+        SkipList {
+            arena,
+            head,
+            max_height: AtomicUsize::new(1),
+            comparator,
+            random: AtomicU64::new(0x2545_f491_4f6c_dd1d),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn allocate_node(
+        arena: &Arena,
+        key: &[u8],
+        seq: SequenceNumber,
+        value: &[u8],
+        height: usize,
+    ) -> *mut Node {
+        let tower_size = height * size_of::<AtomicPtr<Node>>();
+        let size = size_of::<Node>() + tower_size + key.len() + value.len();
+        let node = arena.allocate(size) as *mut Node;
+        // SAFETY: the memory is large enough for the header, the tower, the key and the
+        // value, and it is aligned for the header and the tower. The tower is left null by
+        // the zeroed memory.
+        unsafe {
+            node.write(Node {
+                seq,
+                key_len: key.len() as u32,
+                value_len: value.len() as u32,
+                height,
+            });
+            let data = (node as *mut u8).add(size_of::<Node>() + tower_size);
+            std::ptr::copy_nonoverlapping(key.as_ptr(), data, key.len());
+            std::ptr::copy_nonoverlapping(value.as_ptr(), data.add(key.len()), value.len());
+        }
+        node
+    }
+
+    fn random_height(&self) -> usize {
+        // splitmix64, good enough to balance the levels
+        let mut random = self
+            .random
+            .fetch_add(0x9e37_79b9_7f4a_7c15, atomic::Ordering::Relaxed);
+        random = (random ^ (random >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        random = (random ^ (random >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        random ^= random >> 31;
+        let mut height = 1;
+        while height < MAX_HEIGHT && random.is_multiple_of(BRANCHING) {
+            height += 1;
+            random /= BRANCHING;
+        }
+        height
+    }
+
+    /// Compare the entry of `node` with the entry of `key` and `seq`
+    fn compare(&self, node: *const Node, key: &[u8], seq: SequenceNumber) -> Ordering {
+        // SAFETY: every node reachable from the head is allocated by `allocate_node`
+        let (node_key, node_seq) = unsafe { (self::key(node), (*node).seq) };
+        self.comparator
+            .compare(node_key, key)
+            .then_with(|| seq.cmp(&node_seq))
+    }
+
+    /// Return the nodes of `level` between which the entry of `key` and `seq` belongs,
+    /// searching from `before` which is known to be less than the entry
+    fn find_splice_for_level(
+        &self,
+        key: &[u8],
+        seq: SequenceNumber,
+        mut before: *mut Node,
+        level: usize,
+    ) -> (*mut Node, *mut Node) {
+        loop {
+            // SAFETY: `before` is the head or a node of the list higher than `level`
+            let after = unsafe { next(before, level) };
+            if after.is_null() || self.compare(after, key, seq).is_ge() {
+                return (before, after);
+            }
+            before = after;
+        }
+    }
+
+    /// Insert the entry of `key` and `seq`, which must not be in the list. It may be called
+    /// by several threads at the same time.
+    pub fn insert(&self, key: &[u8], seq: SequenceNumber, value: &[u8]) {
+        let height = self.random_height();
+        let node = Self::allocate_node(&self.arena, key, seq, value, height);
+        let list_height = self
+            .max_height
+            .fetch_max(height, atomic::Ordering::Relaxed)
+            .max(height);
+
+        let mut prev = [self.head; MAX_HEIGHT];
+        let mut next = [null_mut(); MAX_HEIGHT];
+        let mut before = self.head;
+        for level in (0..list_height).rev() {
+            (prev[level], next[level]) = self.find_splice_for_level(key, seq, before, level);
+            before = prev[level];
+        }
+        // link from the bottom up, so that the node is in every level below the ones it is
+        // found in
+        for level in 0..height {
+            loop {
+                // SAFETY: the node is `height` levels high, and `prev[level]` is the head or
+                // a node already linked in `level`
+                let linked = unsafe {
+                    (*tower(node, level)).store(next[level], atomic::Ordering::Relaxed);
+                    (*tower(prev[level], level)).compare_exchange(
+                        next[level],
+                        node,
+                        atomic::Ordering::AcqRel,
+                        atomic::Ordering::Acquire,
+                    )
+                };
+                if linked.is_ok() {
+                    break;
+                }
+                // another node is linked after `prev[level]` in the meantime
+                (prev[level], next[level]) =
+                    self.find_splice_for_level(key, seq, prev[level], level);
+            }
+        }
+        self.len.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    /// Return the first node not less than the entry of `key` and `seq`, null if there is
+    /// none
+    fn find_greater_or_equal(&self, key: &[u8], seq: SequenceNumber) -> *mut Node {
+        let mut before = self.head;
+        let mut after = null_mut();
+        for level in (0..self.max_height.load(atomic::Ordering::Relaxed)).rev() {
+            (before, after) = self.find_splice_for_level(key, seq, before, level);
+        }
+        after
+    }
+
+    /// Return the last node less than the entry of `key` and `seq`, the head if there is
+    /// none
+    fn find_less_than(&self, key: &[u8], seq: SequenceNumber) -> *mut Node {
+        self.find_last_where(|node| self.compare(node, key, seq).is_lt())
+    }
+
+    /// Return the last node of the list, the head if the list is empty
+    fn find_last(&self) -> *mut Node {
+        self.find_last_where(|_| true)
+    }
+
+    /// Return the last node satisfying `pred`, the head if there is none. `pred` must hold
+    /// for a prefix of the list.
+    fn find_last_where(&self, pred: impl Fn(*const Node) -> bool) -> *mut Node {
+        let mut before = self.head;
+        for level in (0..self.max_height.load(atomic::Ordering::Relaxed)).rev() {
+            loop {
+                // SAFETY: `before` is the head or a node of the list higher than `level`
+                let after = unsafe { next(before, level) };
+                if after.is_null() || !pred(after) {
+                    break;
+                }
+                before = after;
+            }
+        }
+        before
+    }
+
+    /// Return the number of entries in the list
+    pub fn len(&self) -> usize {
+        self.len.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the amount of bytes taken by the entries, including the nodes and the memory
+    /// of the arena not handed out yet
+    pub fn memory_usage(&self) -> usize {
+        self.arena.memory_usage()
+    }
+
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+}
+
+/// `SkipListIterator` visits every entry of a `SkipList`, including the older entries of a
+/// key. The entries inserted after the creation of the iterator may be visited or not.
+pub struct SkipListIterator {
+    list: Arc<SkipList>,
+    /// null if the iterator is not valid
+    node: *const Node,
+}
+
+// SAFETY: the iterator holds the list, which keeps the node alive
+unsafe impl Send for SkipListIterator {}
+unsafe impl Sync for SkipListIterator {}
+
+impl SkipListIterator {
+    pub fn new(list: Arc<SkipList>) -> Self {
+        SkipListIterator {
+            list,
+            node: std::ptr::null(),
+        }
+    }
+
+    pub fn list(&self) -> &Arc<SkipList> {
+        &self.list
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !self.node.is_null()
+    }
+
+    /// Position at the first entry not less than the entry of `key` and `seq`, that is the
+    /// newest entry of `key` not newer than `seq`, or the first entry of the next key
+    pub fn seek(&mut self, key: &[u8], seq: SequenceNumber) {
+        self.node = self.list.find_greater_or_equal(key, seq);
+    }
+
+    /// Position at the last entry less than the entry of `key` and `seq`
+    pub fn seek_for_prev(&mut self, key: &[u8], seq: SequenceNumber) {
+        self.set_backward(self.list.find_less_than(key, seq));
+    }
+
+    pub fn seek_to_first(&mut self) {
+        // SAFETY: the head is as high as the list
+        self.node = unsafe { next(self.list.head, 0) };
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.set_backward(self.list.find_last());
+    }
+
+    fn set_backward(&mut self, node: *mut Node) {
+        self.node = if node == self.list.head {
+            std::ptr::null()
+        } else {
+            node
+        };
+    }
+
+    pub fn next(&mut self) {
+        assert!(self.is_valid());
+        // SAFETY: the node is valid
+        self.node = unsafe { next(self.node, 0) };
+    }
+
+    pub fn prev(&mut self) {
+        assert!(self.is_valid());
+        let node = self.list.find_less_than(self.key(), self.seq());
+        self.set_backward(node);
+    }
+
+    /// Position at the first entry of the key after the current one
+    pub fn next_key(&mut self) {
+        assert!(self.is_valid());
+        let current = self.node;
+        loop {
+            self.next();
+            // SAFETY: both nodes are valid
+            if !self.is_valid() || unsafe { key(self.node) != key(current) } {
+                break;
+            }
+        }
+    }
+
+    /// Position at the last entry of the key before the current one, which is its oldest
+    /// entry
+    pub fn prev_key(&mut self) {
+        let node = self.list.find_less_than(self.key(), SequenceNumber::MAX);
+        self.set_backward(node);
+    }
+
+    /// Position at the newest entry of the current key not newer than `seq`, or at the
+    /// first entry of the next key if there is none
+    pub fn seek_in_key(&mut self, seq: SequenceNumber) {
+        assert!(self.is_valid());
+        // SAFETY: the node is valid, and the list holding it lives as long as `self`
+        let current = unsafe { key(self.node) };
+        self.node = self.list.find_greater_or_equal(current, seq);
+    }
+
+    pub fn key(&self) -> &[u8] {
+        assert!(self.is_valid());
+        // SAFETY: the node is valid, and the list holding it lives as long as `self`
+        unsafe { key(self.node) }
+    }
+
+    pub fn seq(&self) -> SequenceNumber {
+        assert!(self.is_valid());
+        // SAFETY: the node is valid
+        unsafe { (*self.node).seq }
+    }
+
+    pub fn value(&self) -> &[u8] {
+        assert!(self.is_valid());
+        // SAFETY: see `key`
+        unsafe { value(self.node) }
+    }
+}
@@ -1,48 +1,404 @@
+use crate::comparator::bytewise_comparator;
+use crate::db::format::{decode_merge_payload, decode_value, MergeBase, ValueType};
+use crate::iterator::Iterator;
+use crate::memtable::arena::Arena;
+use crate::memtable::logger::{LogRecordsBuilder, MemTableLogger, OperationType};
+use crate::memtable::skiplist::{SkipList, SkipListIterator};
 use crate::memtable::MemTable;
-use crate::util::env::logfile_path;
+use crate::options::{MemTableFactory, Options};
+use crate::slice_transform::FixedPrefixTransform;
+use crate::util::env::{logfile_path, test_env};
+use bytes::Bytes;
+use std::sync::Arc;
 
-struct RAII {
+const TEST_DIR: &str = "/summer_kv_test/memtable";
+
+struct Raii {
     pub seq: u64,
 }
 
-impl Drop for RAII {
+impl Drop for Raii {
     fn drop(&mut self) {
-        std::fs::remove_file(logfile_path(self.seq as usize)).expect("Testing expect");
+        test_env()
+            .remove_file(logfile_path(TEST_DIR, self.seq as usize).as_str())
+            .expect("Testing expect");
     }
 }
+
+fn new_logger(seq: u64) -> (MemTableLogger, Raii) {
+    let env = test_env();
+    env.create_dir_all(TEST_DIR).expect("Testing expect");
+    let _ = env.remove_file(logfile_path(TEST_DIR, seq as usize).as_str());
+    let logger = MemTableLogger::new(env.as_ref(), TEST_DIR, seq).expect("Testing expect");
+    (logger, Raii { seq })
+}
 #[test]
 fn test_memtable_put() {
-    let mut memtable = MemTable::new();
-    let raii = RAII {
-        seq: memtable.seq_num(),
-    };
-    let ret = memtable.put("key1".as_bytes(), "value1".as_bytes());
-    assert!(ret.is_ok())
+    let memtable = MemTable::new();
+    assert!(memtable.is_empty());
+    memtable.put(1, "key1".as_bytes(), "value1".as_bytes());
+    assert!(!memtable.is_empty());
 }
 #[test]
 fn test_memtable_get() {
-    let mut memtable = MemTable::new();
-    let raii = RAII {
-        seq: memtable.seq_num(),
-    };
-    let ret = memtable.put("key1".as_bytes(), "value1".as_bytes());
-    assert!(ret.is_ok());
-    assert_eq!(memtable.get("key1".as_bytes()),Some("value1".into()));
-    assert_eq!(memtable.get("key".as_bytes()),None);
+    let memtable = MemTable::new();
+    memtable.put(1, "key1".as_bytes(), "value1".as_bytes());
+    assert_eq!(memtable.get("key1".as_bytes()), Some("value1".into()));
+    assert_eq!(memtable.get("key".as_bytes()), None);
 }
 #[test]
 fn test_memtable_remove() {
-    let mut memtable = MemTable::new();
-    let raii = RAII {
-        seq: memtable.seq_num(),
+    let memtable = MemTable::new();
+    memtable.put(1, "key1".as_bytes(), "value1".as_bytes());
+    assert_eq!(memtable.get("key1".as_bytes()), Some("value1".into()));
+    assert_eq!(memtable.get("key1".as_bytes()), Some("value1".into()));
+    assert_eq!(memtable.get("key".as_bytes()), None);
+    memtable.delete(2, "key1".as_bytes());
+    assert_eq!(memtable.get("key1".as_bytes()), None);
+}
+#[test]
+fn test_memtable_delete_range() {
+    let memtable = MemTable::new();
+    memtable.put(1, b"a", b"1");
+    memtable.put(2, b"b", b"2");
+    memtable.put(3, b"c", b"3");
+    memtable.delete_range(4, b"a", b"c");
+    // written after the range tombstone
+    memtable.put(5, b"b", b"5");
+    assert_eq!(memtable.get(b"a"), None);
+    assert_eq!(memtable.get(b"b"), Some("5".into()));
+    assert_eq!(memtable.get(b"c"), Some("3".into()));
+}
+#[test]
+fn test_memtable_recover() {
+    let (mut logger, raii) = new_logger(5);
+    let mut builder = LogRecordsBuilder::new();
+    builder.add(OperationType::PUT, 1, 0, b"a", b"1").unwrap();
+    builder
+        .add(OperationType::RANGE_DELETE, 2, 0, b"a", b"b")
+        .unwrap();
+    logger.log_and_sync(builder.build()).unwrap();
+    builder.cleanup();
+    builder.add(OperationType::PUT, 3, 7, b"b", b"3").unwrap();
+    logger.log_and_sync(builder.build()).unwrap();
+
+    let records =
+        MemTableLogger::read_records(test_env().as_ref(), logfile_path(TEST_DIR, 5).as_str())
+            .unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[2].column_family(), 7);
+    let recovered = MemTable::new();
+    for record in &records {
+        recovered.add(record);
+    }
+    assert_eq!(recovered.get(b"a"), None);
+    assert_eq!(recovered.get(b"b"), Some("3".into()));
+    assert_eq!(recovered.range_tombstones().len(), 1);
+}
+#[test]
+fn test_log_drops_incomplete_write() {
+    let (mut logger, raii) = new_logger(6);
+    let mut builder = LogRecordsBuilder::new();
+    builder.add(OperationType::PUT, 1, 0, b"a", b"1").unwrap();
+    logger.log_and_sync(builder.build()).unwrap();
+    builder.cleanup();
+    builder.add(OperationType::PUT, 2, 0, b"b", b"2").unwrap();
+    builder.add(OperationType::PUT, 3, 0, b"c", b"3").unwrap();
+    // the second write is cut in the middle of its second record
+    let raw = builder.build();
+    let mut torn = (raw.len() as u32).to_be_bytes().to_vec();
+    torn.extend_from_slice(&raw[..raw.len() - 2]);
+    test_env()
+        .new_appendable_file(logfile_path(TEST_DIR, 6).as_str())
+        .and_then(|mut file| file.append(torn.as_slice()))
+        .unwrap();
+
+    let records =
+        MemTableLogger::read_records(test_env().as_ref(), logfile_path(TEST_DIR, 6).as_str())
+            .unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].key(), b"a");
+}
+#[test]
+fn test_memtable_merge_records() {
+    let memtable = MemTable::new();
+    let operand = vec![b'x'; 1024];
+    for i in 0..200 {
+        memtable.merge(i + 1, b"a", operand.as_slice());
+    }
+    // every merge takes the memory of its own operand only
+    assert!(memtable.approximate_size() < 200 * 2 * 1024);
+    let entry = memtable.get_entry(b"a").unwrap();
+    let decoded = decode_value(entry.as_ref()).unwrap();
+    assert_eq!(decoded.value_type, ValueType::Merge);
+    assert_eq!(decoded.seq, 200);
+    let (base, operands) = decode_merge_payload(decoded.payload).unwrap();
+    assert_eq!(base, MergeBase::Unresolved);
+    assert_eq!(operands.len(), 200);
+
+    // the merges fold on the latest other record, a covered one reads as deleted
+    memtable.put(201, b"b", b"1");
+    memtable.merge(202, b"b", b"2");
+    memtable.merge(203, b"b", b"3");
+    memtable.put(204, b"c", b"1");
+    memtable.delete_range(205, b"c", b"d");
+    memtable.merge(206, b"c", b"2");
+    let mut iter = memtable.iter();
+    iter.seek_to_key(b"b");
+    let decoded = decode_value(iter.value()).unwrap();
+    let (base, operands) = decode_merge_payload(decoded.payload).unwrap();
+    assert_eq!(base, MergeBase::Value("1".into()));
+    assert_eq!(operands, vec![Bytes::from("2"), Bytes::from("3")]);
+    iter.next();
+    assert_eq!(iter.key(), b"c");
+    let decoded = decode_value(iter.value()).unwrap();
+    let (base, operands) = decode_merge_payload(decoded.payload).unwrap();
+    assert_eq!(base, MergeBase::Deletion);
+    assert_eq!(operands, vec![Bytes::from("2")]);
+    iter.prev();
+    assert_eq!(iter.key(), b"b");
+    iter.prev();
+    assert_eq!(iter.key(), b"a");
+    iter.prev();
+    assert!(!iter.is_valid());
+}
+#[test]
+fn test_arena_memory_usage() {
+    let arena = Arena::new(1024);
+    assert_eq!(arena.memory_usage(), 0);
+    let a = arena.allocate(10);
+    let b = arena.allocate(3);
+    assert_eq!(a as usize % 8, 0);
+    assert_eq!(b as usize - a as usize, 16);
+    assert_eq!(arena.memory_usage(), 1024);
+    // out of the current block and too large to share a new one
+    arena.allocate(1020);
+    assert_eq!(arena.memory_usage(), 2048);
+    // the current block is still in use
+    let c = arena.allocate(8);
+    assert_eq!(c as usize - b as usize, 8);
+    assert_eq!(arena.memory_usage(), 2048);
+}
+#[test]
+fn test_skiplist_versions() {
+    let list = Arc::new(SkipList::new(bytewise_comparator(), 256));
+    list.insert(b"b", 1, b"b1");
+    list.insert(b"a", 2, b"a2");
+    list.insert(b"b", 3, b"b3");
+    list.insert(b"c", 4, b"c4");
+    assert_eq!(list.len(), 4);
+    let mut iter = SkipListIterator::new(list.clone());
+    let mut entries = Vec::new();
+    iter.seek_to_first();
+    while iter.is_valid() {
+        entries.push((iter.key().to_vec(), iter.seq()));
+        iter.next();
+    }
+    let expected = [(b"a", 2), (b"b", 3), (b"b", 1), (b"c", 4)];
+    assert_eq!(entries, expected.map(|(k, seq)| (k.to_vec(), seq)));
+    // the newest entry of `b` not newer than 2
+    iter.seek(b"b", 2);
+    assert_eq!(
+        (iter.key(), iter.value()),
+        (b"b".as_slice(), b"b1".as_slice())
+    );
+    iter.prev();
+    assert_eq!(iter.seq(), 3);
+    iter.prev_key();
+    assert_eq!(iter.key(), b"a");
+    iter.seek_to_last();
+    assert_eq!(iter.key(), b"c");
+    iter.prev_key();
+    assert_eq!(iter.seq(), 1);
+    iter.next_key();
+    assert_eq!(iter.key(), b"c");
+    iter.next_key();
+    assert!(!iter.is_valid());
+}
+#[test]
+fn test_skiplist_concurrent_insert() {
+    let list = Arc::new(SkipList::new(bytewise_comparator(), 4096));
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let list = list.clone();
+            std::thread::spawn(move || {
+                for i in 0..2000u64 {
+                    let seq = i * 4 + t;
+                    let key = format!("key_{:05}", seq % 1000);
+                    list.insert(key.as_bytes(), seq, seq.to_string().as_bytes());
+                }
+            })
+        })
+        .collect();
+    // the readers see the list sorted at any moment
+    let reader = {
+        let list = list.clone();
+        std::thread::spawn(move || {
+            for _ in 0..20 {
+                let mut iter = SkipListIterator::new(list.clone());
+                let mut last: Option<(Vec<u8>, u64)> = None;
+                iter.seek_to_first();
+                while iter.is_valid() {
+                    let current = (iter.key().to_vec(), iter.seq());
+                    if let Some((key, seq)) = &last {
+                        assert!(*key < current.0 || (*key == current.0 && *seq > current.1));
+                    }
+                    last = Some(current);
+                    iter.next();
+                }
+            }
+        })
     };
-    let ret = memtable.put("key1".as_bytes(), "value1".as_bytes());
-    assert!(ret.is_ok());
-    assert_eq!(memtable.get("key1".as_bytes()),Some("value1".into()));
-    assert_eq!(memtable.get("key1".as_bytes()),Some("value1".into()));
-    assert_eq!(memtable.get("key".as_bytes()),None);
-    let ret = memtable.delete("key1".as_bytes());
-    assert!(ret.is_ok());
-    assert_eq!(memtable.get("key1".as_bytes()),None);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    reader.join().unwrap();
+    assert_eq!(list.len(), 8000);
+    let mut iter = SkipListIterator::new(list.clone());
+    let mut count = 0;
+    iter.seek_to_first();
+    while iter.is_valid() {
+        assert_eq!(iter.value(), iter.seq().to_string().as_bytes());
+        count += 1;
+        iter.next();
+    }
+    assert_eq!(count, 8000);
+    iter.seek(b"key_00500", u64::MAX);
+    assert_eq!(iter.seq(), 7500);
+    assert!(list.memory_usage() > 8000 * 32);
+}
+#[test]
+fn test_memtable_factories() {
+    for factory in [
+        MemTableFactory::SkipList,
+        MemTableFactory::BTree,
+        MemTableFactory::Vector,
+        MemTableFactory::HashLinkList { bucket_count: 4 },
+    ] {
+        let options = Options {
+            memtable_factory: factory,
+            prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(1))),
+            ..Options::default()
+        };
+        let memtable = MemTable::with_options(&options);
+        memtable.put(1, b"a", b"1");
+        memtable.put(2, b"b", b"2");
+        memtable.put(3, b"c", b"3");
+        let mut iter = memtable.iter();
+        // the iterator does not see the later writes
+        memtable.put(4, b"b", b"4");
+        memtable.delete(5, b"a");
+        memtable.put(6, b"d", b"6");
+        assert_eq!(memtable.get(b"a"), None);
+        assert_eq!(memtable.get(b"b"), Some("4".into()));
+        assert!(memtable.approximate_size() > 0);
+
+        let mut keys = Vec::new();
+        iter.seek_to_first();
+        while iter.is_valid() {
+            keys.push(iter.key().to_vec());
+            iter.next();
+        }
+        assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        iter.seek_to_last();
+        assert_eq!(iter.key(), b"c");
+        iter.prev();
+        assert_eq!(&iter.value()[9..], b"2");
+        iter.seek_to_key(b"bb");
+        assert_eq!(iter.key(), b"c");
+        iter.next();
+        assert!(!iter.is_valid());
+
+        let mut iter = memtable.iter();
+        iter.seek_to_key(b"b");
+        assert_eq!(&iter.value()[9..], b"4");
+        iter.prev();
+        assert_eq!(iter.key(), b"a");
+        iter.seek_to_last();
+        assert_eq!(iter.key(), b"d");
 
+        // the records are sorted for the flush whatever the order they are written in
+        for i in (0..100).rev() {
+            memtable.put(7 + i, format!("k{:03}", i * 7 % 100).as_bytes(), b"v");
+        }
+        memtable.mark_read_only();
+        assert_eq!(memtable.get(b"k042"), Some("v".into()));
+        assert_eq!(memtable.get(b"c"), Some("3".into()));
+        let mut iter = memtable.iter();
+        let mut keys = Vec::new();
+        iter.seek_to_first();
+        while iter.is_valid() {
+            keys.push(iter.key().to_vec());
+            iter.next();
+        }
+        let mut expected: Vec<Vec<u8>> = (0..100)
+            .map(|i| format!("k{:03}", i).into_bytes())
+            .collect();
+        expected.splice(0..0, [b"a", b"b", b"c", b"d"].map(|x| x.to_vec()));
+        assert_eq!(keys, expected);
+    }
+}
+
+#[test]
+fn test_memtable_concurrent_insert() {
+    for factory in [
+        MemTableFactory::SkipList,
+        MemTableFactory::BTree,
+        MemTableFactory::Vector,
+        MemTableFactory::HashLinkList { bucket_count: 4 },
+    ] {
+        let options = Options {
+            memtable_factory: factory,
+            prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(5))),
+            ..Options::default()
+        };
+        let memtable = MemTable::with_options(&options);
+        std::thread::scope(|scope| {
+            for t in 0..4u64 {
+                let memtable = &memtable;
+                scope.spawn(move || {
+                    for i in 0..500u64 {
+                        let seq = i * 4 + t + 1;
+                        let key = format!("key_{:03}", seq % 100);
+                        memtable.put(seq, key.as_bytes(), seq.to_string().as_bytes());
+                    }
+                });
+            }
+            // the reads see no record newer than they read at
+            scope.spawn(|| {
+                for _ in 0..20 {
+                    let visible_seq = 1000;
+                    let mut iter = memtable.iter_at(visible_seq);
+                    iter.seek_to_first();
+                    while iter.is_valid() {
+                        assert!(decode_value(iter.value()).unwrap().seq <= visible_seq);
+                        iter.next();
+                    }
+                    if let Some(entry) = memtable.get_entry_at(b"key_042", visible_seq) {
+                        assert!(decode_value(entry.as_ref()).unwrap().seq <= visible_seq);
+                    }
+                }
+            });
+        });
+
+        // every key reads as its latest record, whichever writer inserts it first
+        let mut iter = memtable.iter();
+        let mut count = 0;
+        iter.seek_to_first();
+        while iter.is_valid() {
+            let latest = if count == 0 { 2000 } else { 1900 + count };
+            assert_eq!(iter.key(), format!("key_{:03}", count).as_bytes());
+            assert_eq!(&iter.value()[9..], latest.to_string().as_bytes());
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, 100);
+        assert_eq!(memtable.get(b"key_000"), Some("2000".into()));
+        assert_eq!(
+            decode_value(memtable.get_entry_at(b"key_000", 1000).unwrap().as_ref())
+                .unwrap()
+                .seq,
+            1000
+        );
+    }
 }
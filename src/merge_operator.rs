@@ -0,0 +1,139 @@
+use crate::util::error::{Error, Result};
+
+/// A `MergeOperator` combines the operands written by `DB::merge` with the existing value of
+/// a key, so that read-modify-write updates such as counters do not need to read the value
+/// first. The operands are kept as they are written and combined when the key is read or
+/// compacted.
+///
+/// Replacing the operator of a database with a different one changes the meaning of the
+/// operands written before.
+pub trait MergeOperator: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Apply the `operands`, ordered from the oldest to the newest, on the `existing` value
+    /// of `key`, `None` if the key does not exist or is deleted
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Vec<u8>>;
+
+    /// Combine the `operands`, ordered from the oldest to the newest, into a single operand
+    /// without knowing the existing value. `None` if the operands can not be combined, they
+    /// are kept as they are then.
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// `UInt64AddOperator` treats the values and operands as 8 bytes little endian unsigned
+/// integers and adds them up, the sum wraps around on overflow. A missing value counts as 0.
+pub struct UInt64AddOperator {}
+
+impl UInt64AddOperator {
+    fn decode(key: &[u8], raw: &[u8]) -> Result<u64> {
+        let raw: [u8; 8] = raw.try_into().map_err(|_| {
+            Error::InvalidArgument(format!(
+                "operand of {} bytes for key {:?} is not a u64",
+                raw.len(),
+                key
+            ))
+        })?;
+        Ok(u64::from_le_bytes(raw))
+    }
+}
+
+impl MergeOperator for UInt64AddOperator {
+    fn name(&self) -> &str {
+        "summer_kv.UInt64AddOperator"
+    }
+
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Vec<u8>> {
+        let mut sum = match existing {
+            None => 0,
+            Some(existing) => Self::decode(key, existing)?,
+        };
+        for operand in operands {
+            sum = sum.wrapping_add(Self::decode(key, operand)?);
+        }
+        Ok(sum.to_le_bytes().to_vec())
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Vec<u8>> {
+        self.full_merge(key, None, operands).ok()
+    }
+}
+
+/// `BytesAppendOperator` appends the operands to the existing value, separated by
+/// `delimiter`
+pub struct BytesAppendOperator {
+    delimiter: Vec<u8>,
+}
+
+impl BytesAppendOperator {
+    pub fn new(delimiter: &[u8]) -> Self {
+        BytesAppendOperator {
+            delimiter: delimiter.to_vec(),
+        }
+    }
+}
+
+impl MergeOperator for BytesAppendOperator {
+    fn name(&self) -> &str {
+        "summer_kv.BytesAppendOperator"
+    }
+
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Vec<u8>> {
+        let mut parts: Vec<&[u8]> = existing.into_iter().collect();
+        parts.extend_from_slice(operands);
+        Ok(parts.join(self.delimiter.as_slice()))
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Vec<u8>> {
+        Some(operands.join(self.delimiter.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merge_operator::{BytesAppendOperator, MergeOperator, UInt64AddOperator};
+    use crate::util::error::Error;
+
+    #[test]
+    fn test_uint64_add() {
+        let operator = UInt64AddOperator {};
+        let one = 1u64.to_le_bytes();
+        let two = 2u64.to_le_bytes();
+        let ret = operator.full_merge(b"k", None, &[&one, &two]).unwrap();
+        assert_eq!(ret, 3u64.to_le_bytes());
+        let ret = operator.full_merge(b"k", Some(&ret), &[&two]).unwrap();
+        assert_eq!(ret, 5u64.to_le_bytes());
+        let partial = operator.partial_merge(b"k", &[&one, &one]).unwrap();
+        assert_eq!(partial, 2u64.to_le_bytes());
+        let ret = operator.full_merge(b"k", Some(b"abc"), &[&one]);
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_bytes_append() {
+        let operator = BytesAppendOperator::new(b",");
+        let ret = operator.full_merge(b"k", None, &[b"a", b"b"]).unwrap();
+        assert_eq!(ret, b"a,b");
+        let ret = operator.full_merge(b"k", Some(b"x"), &[b"a"]).unwrap();
+        assert_eq!(ret, b"x,a");
+        let partial = operator.partial_merge(b"k", &[b"a", b"b"]).unwrap();
+        let ret = operator.full_merge(b"k", Some(b"x"), &[&partial]).unwrap();
+        assert_eq!(ret, b"x,a,b");
+    }
+}
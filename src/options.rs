@@ -0,0 +1,242 @@
+use crate::blocks::{BLOCK_SIZE, DEFAULT_RESTART_INTERVAL};
+use crate::compaction_filter::CompactionFilterFactory;
+use crate::comparator::{bytewise_comparator, Comparator};
+use crate::compression::{builtin_compressor, Compressor, LzCompressor, NoCompressor};
+use crate::merge_operator::MergeOperator;
+use crate::slice_transform::SliceTransform;
+use crate::sstable::block_cache::BlockCache;
+use crate::util::clock::{Clock, SystemClock};
+use crate::util::env::{Env, PosixEnv};
+use std::sync::Arc;
+
+/// How the SSTables of a column family are compacted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactionStyle {
+    /// the SSTables are merged into the deeper levels, every level is several times larger
+    /// than the upper one
+    Level,
+    /// the SSTables stay in level 0 and the oldest ones are dropped once the SSTables take
+    /// more than `max_table_files_size` bytes, the records in them are lost. Suitable for
+    /// data which is only useful for a while, such as logs.
+    Fifo { max_table_files_size: u64 },
+}
+
+/// The data structure the memtables of a column family keep the records in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum MemTableFactory {
+    /// a skiplist of the records allocated from an arena. The writes are inserted while the
+    /// iterators are reading it, and the memory it takes is tracked exactly.
+    #[default]
+    SkipList,
+    /// a `BTreeMap` of the records behind a lock, the simple alternative. The iterators
+    /// read the map along with the writes.
+    BTree,
+    /// an unsorted vector, sorted once when the memtable is flushed. Suitable for bulk
+    /// loading, the lookups and the iterators before the flush are slow: every lookup scans
+    /// all the records, and every iterator copies and sorts all of them when it is created.
+    Vector,
+    /// `bucket_count` buckets of sorted records, hashed by the prefix of the keys as told
+    /// by `prefix_extractor`. Suitable for point lookups and the keys sharing a prefix, but
+    /// not for iterating: every iterator copies and sorts all the records when it is
+    /// created.
+    HashLinkList { bucket_count: usize },
+}
+
+/// `Options` controls the behaviours of the storage engine, the default value is suitable
+/// for most of the workloads.
+///
+/// Every column family has its own options, the options the database is opened with are
+/// the ones of the default column family.
+#[derive(Clone)]
+pub struct Options {
+    /// approximate size of the data blocks of SSTables before compression, at most 64KB
+    pub block_size: usize,
+    /// number of records between two restart points of a data block
+    pub block_restart_interval: usize,
+    /// compressor used by the data blocks of SSTables in every level, the `i`th compressor is
+    /// used by level `i`. Levels beyond the end of the list use the last one.
+    pub compression_per_level: Vec<Arc<dyn Compressor>>,
+    /// amount of bytes written to the memtable before it is flushed to a level 0 SSTable
+    pub write_buffer_size: usize,
+    pub memtable_factory: MemTableFactory,
+    /// size of the blocks the skiplist memtable allocates its memory in, 0 picks an eighth
+    /// of `write_buffer_size`
+    pub arena_block_size: usize,
+    /// number of level 0 SSTables which triggers the compaction of level 0
+    pub level0_file_num_compaction_trigger: usize,
+    /// max amount of bytes of level 1, every deeper level is 10 times larger than the upper
+    /// one. The compaction of a level is triggered once it exceeds the size.
+    pub max_bytes_for_level_base: u64,
+    /// SSTables produced by compaction are split once they reach the size
+    pub target_file_size: usize,
+    pub compaction_style: CompactionStyle,
+    /// combines the operands written by `DB::merge`, `DB::merge` is rejected if it is not
+    /// configured
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// creates the `CompactionFilter` every compaction passes the records to
+    pub compaction_filter_factory: Option<Arc<dyn CompactionFilterFactory>>,
+    /// tells the time the records written by `DB::put_with_ttl` expire by
+    pub clock: Arc<dyn Clock>,
+    /// the file system the files are read and written through. The database and all of its
+    /// column families must be opened with the same one.
+    pub env: Arc<dyn Env>,
+    /// keeps the recently read data blocks in memory, the cache may be shared by several
+    /// column families and databases. `None` disables the caching.
+    pub block_cache: Option<Arc<BlockCache>>,
+    /// extracts the prefix of the keys for the iterators in prefix mode, see
+    /// `ReadOptions::prefix_same_as_start`. Every SSTable carries a bloom filter of the
+    /// prefixes of its keys, so that the SSTables without the prefix are skipped by the
+    /// iterators in prefix mode and by the point lookups.
+    pub prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    /// bits of the prefix filter for every distinct prefix, 10 bits give about 1% false
+    /// positives. 0 disables the prefix filter.
+    pub prefix_bloom_bits_per_key: usize,
+    /// the order of the keys, it can not change once the column family is created
+    pub comparator: Arc<dyn Comparator>,
+    /// whether the values of at least `min_blob_size` bytes are stored in blob files, see
+    /// `db::blob`. The SSTables only keep where the values are, so that compaction does not
+    /// rewrite the large values again and again.
+    pub enable_blob_files: bool,
+    pub min_blob_size: usize,
+    /// a blob file is finished once it reaches the size
+    pub blob_file_size: u64,
+    /// whether compaction relocates the values of the oldest blob files into new ones, a
+    /// blob file is deleted once no SSTable refers to it
+    pub enable_blob_garbage_collection: bool,
+    /// the fraction of the blob files whose values are relocated by compaction, the oldest
+    /// ones first
+    pub blob_garbage_collection_age_cutoff: f64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            block_size: BLOCK_SIZE,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            // the young levels are rewritten frequently, save the cpu for them
+            compression_per_level: vec![
+                Arc::new(NoCompressor {}),
+                Arc::new(NoCompressor {}),
+                Arc::new(LzCompressor {}),
+            ],
+            write_buffer_size: 4 * 1024 * 1024,
+            memtable_factory: MemTableFactory::SkipList,
+            arena_block_size: 0,
+            level0_file_num_compaction_trigger: 4,
+            max_bytes_for_level_base: 10 * 1024 * 1024,
+            target_file_size: 2 * 1024 * 1024,
+            compaction_style: CompactionStyle::Level,
+            merge_operator: None,
+            compaction_filter_factory: None,
+            clock: Arc::new(SystemClock {}),
+            env: Arc::new(PosixEnv {}),
+            block_cache: Some(Arc::new(BlockCache::new(8 * 1024 * 1024))),
+            prefix_extractor: None,
+            prefix_bloom_bits_per_key: 10,
+            comparator: bytewise_comparator(),
+            enable_blob_files: false,
+            min_blob_size: 4096,
+            blob_file_size: 256 * 1024 * 1024,
+            enable_blob_garbage_collection: true,
+            blob_garbage_collection_age_cutoff: 0.25,
+        }
+    }
+}
+
+impl Options {
+    /// Return the compressor used by SSTables of `level`
+    pub fn compressor_for_level(&self, level: usize) -> Arc<dyn Compressor> {
+        match self.compression_per_level.last() {
+            None => Arc::new(NoCompressor {}),
+            Some(last) => self
+                .compression_per_level
+                .get(level)
+                .unwrap_or(last)
+                .clone(),
+        }
+    }
+
+    /// Return the size of the blocks of the skiplist memtable, see `arena_block_size`
+    pub fn arena_block_size(&self) -> usize {
+        match self.arena_block_size {
+            0 => self.write_buffer_size / 8,
+            size => size,
+        }
+    }
+
+    /// Return the max amount of bytes of `level`, level 0 is limited by the number of files
+    /// instead
+    pub fn max_bytes_for_level(&self, level: usize) -> u64 {
+        let mut result = self.max_bytes_for_level_base;
+        for _ in 1..level {
+            result *= 10;
+        }
+        result
+    }
+
+    /// Return the compressor identified by the type byte `id` of a block trailer, the
+    /// built-in compressors and the ones configured in `compression_per_level` are searched
+    pub fn compressor_by_id(&self, id: u8) -> Option<Arc<dyn Compressor>> {
+        if let Some(compressor) = builtin_compressor(id) {
+            return Some(compressor);
+        }
+        self.compression_per_level
+            .iter()
+            .find(|x| x.id() == id)
+            .cloned()
+    }
+}
+
+/// The default options running on the in-memory file system of the tests, see
+/// `env::test_env`
+#[cfg(test)]
+pub(crate) fn test_options() -> Options {
+    Options {
+        env: crate::util::env::test_env(),
+        ..Options::default()
+    }
+}
+
+/// `ReadOptions` controls a single read of the database, see `DB::iter_with_options`
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// the iterator skips the keys less than the bound, inclusive
+    pub iterate_lower_bound: Option<Vec<u8>>,
+    /// the iterator stops at the first key greater than or equal to the bound, exclusive.
+    /// The SSTables and data blocks beyond the bound are not read.
+    pub iterate_upper_bound: Option<Vec<u8>>,
+    /// once positioned by `seek_to_key`, the iterator only visits the keys sharing the
+    /// prefix of the seek key, as told by `Options::prefix_extractor`. No effect without the
+    /// extractor, for seek keys out of its domain, or for `seek_to_first` and `seek_to_last`.
+    pub prefix_same_as_start: bool,
+    /// whether the data blocks read are put into the block cache, turn it off for bulk scans
+    /// so that they do not evict the blocks of the point lookups
+    pub fill_cache: bool,
+    /// whether the checksums of the data blocks read are verified
+    pub verify_checksums: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            iterate_lower_bound: None,
+            iterate_upper_bound: None,
+            prefix_same_as_start: false,
+            fill_cache: true,
+            verify_checksums: true,
+        }
+    }
+}
+
+impl ReadOptions {
+    /// Whether `key` is less than `iterate_lower_bound` in the order of `comparator`
+    pub fn before_lower_bound(&self, key: &[u8], comparator: &dyn Comparator) -> bool {
+        matches!(&self.iterate_lower_bound, Some(bound) if comparator.compare(key, bound).is_lt())
+    }
+
+    /// Whether `key` is greater than or equal to `iterate_upper_bound` in the order of
+    /// `comparator`
+    pub fn reaches_upper_bound(&self, key: &[u8], comparator: &dyn Comparator) -> bool {
+        matches!(&self.iterate_upper_bound, Some(bound) if comparator.compare(key, bound).is_ge())
+    }
+}
@@ -0,0 +1,45 @@
+/// A `SliceTransform` extracts the prefix of a key, the keys sharing a prefix are adjacent in
+/// the key order. It is used as `Options::prefix_extractor` to confine the iterators to the
+/// keys sharing the prefix of the seek key, see `ReadOptions::prefix_same_as_start`.
+///
+/// The prefix filters of the SSTables are only used by the extractor of the same name, so
+/// the name must change whenever the prefixes it extracts change.
+pub trait SliceTransform: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Return the prefix of `key`, only called for the keys in the domain
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8];
+
+    /// Whether `key` has a prefix, the keys out of the domain are not confined by the prefix
+    fn in_domain(&self, key: &[u8]) -> bool;
+}
+
+/// `FixedPrefixTransform` takes the first `len` bytes of a key as the prefix, the keys
+/// shorter than `len` bytes have no prefix
+pub struct FixedPrefixTransform {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefixTransform {
+    pub fn new(len: usize) -> Self {
+        FixedPrefixTransform {
+            len,
+            name: format!("summer_kv.FixedPrefix.{}", len),
+        }
+    }
+}
+
+impl SliceTransform for FixedPrefixTransform {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.len]
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        key.len() >= self.len
+    }
+}
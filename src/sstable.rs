@@ -1,13 +1,22 @@
 use crate::blocks::iterator::BlockRecordIterator;
 use crate::blocks::{Blocks, SIZE_U16};
+use crate::comparator::Comparator;
+use crate::compression::{is_worth_compressing, Compressor, NO_COMPRESSION};
+use crate::db::format::{RangeTombstone, RangeTombstoneList, SequenceNumber, VALUE_HEADER_SIZE};
 use crate::iterator::Iterator;
-use crate::util::env;
-use anyhow::Result;
+use crate::options::{Options, ReadOptions};
+use crate::slice_transform::SliceTransform;
+use crate::sstable::block_cache::new_cache_id;
+use crate::util::bloom::{bloom_hash, bloom_may_match};
+use crate::util::error::{Error, Result};
+use crate::util::{crc32, env};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::sync::Arc;
 
-mod iterator;
-mod sstable_builder;
+pub mod block_cache;
+pub mod iterator;
+pub mod sst_file_writer;
+pub mod sstable_builder;
 pub type KVPair = (Bytes, Bytes);
 /// # SSTable format
 /// SSTable is a data structure that represent the disk file which hold the data in order.
@@ -18,17 +27,47 @@ pub type KVPair = (Bytes, Bytes);
 /// <data block>
 /// ...
 /// <data block>
+/// <meta block>
+/// ...
+/// <meta block>
+/// <meta index block>
 /// <index block>
 /// ...
 /// <index block>
 /// <footer>
 /// <file end>
 /// ```
+/// ## meta block
+/// meta blocks hold the information about the SSTable other than the user records, every meta
+/// block has a name. The `meta index block` is a block whose records map the name of a meta
+/// block to its `block pointer`. The meta blocks known so far:
+/// - `summer_kv.range_del`: the range tombstones of the SSTable, see
+///   `RangeTombstone::encode_value` for the record format. The name appears once for every
+///   block if the range tombstones take more than one block
+/// - `summer_kv.prefix_filter.<extractor name>`: the bloom filter of the prefixes of the keys
+///   extracted by `Options::prefix_extractor`, see `build_bloom_filter`. The block is the raw
+///   filter followed by the block trailer. The filter is ignored if the extractor changes.
+///
+/// ## block trailer
+/// Every block, data block or index block, is followed by a 5 bytes trailer. The trailer is
+/// the id of the `Compressor` which compressed the block content (see `compression`) and the
+/// CRC-32C of the block content and the compression type, the `block pointer` covers both the
+/// block content and the trailer.
+/// ``` text
+/// +------------------------------------------------------------------+
+/// | Block Content (maybe compressed) | Compression Type#1 | CRC-32C#4 |
+/// +------------------------------------------------------------------+
+/// ```
+/// Data blocks are compressed by the compressor configured for the level of the SSTable in
+/// `Options::compression_per_level`, index blocks are never compressed.
+///
 /// ## index block
 /// index block is basically a data block but the record is consist of a key and a `block pointer`
 ///
 /// index block is of a record sorted.  The key of each record in index block is represent the
-/// largest key of a data block in current SSTable, the value is the related data `block pointer`
+/// largest key of a data block in current SSTable, the value is the related data `block pointer`.
+/// The records of the data blocks and the index blocks are sorted by `Options::comparator`,
+/// the meta index block is always sorted bytewise by the block names.
 ///
 /// Records of the index block
 /// ``` text
@@ -40,69 +79,372 @@ pub type KVPair = (Bytes, Bytes);
 /// `block pointer` is consist of offset and size which are all `usize` type.
 /// ```text
 /// +-----------------+
-/// | offset#2+size#2 |
+/// | offset#8+size#8 |
 /// +-----------------+
 /// ```
 ///
 /// ## footer
 /// ``` text
-/// +----------------------|----------------------|------|---------------------+
-/// |index block pointer#4 |index block pointer#4 |------|index block number#2 |
-/// +----------------------|----------------------|------|---------------------+
+/// +---------------------------|----------------------|------|---------------------+
+/// |meta index block pointer#16|index block pointer#16|------|index block number#2 |
+/// +---------------------------|----------------------|------|---------------------+
 /// ```
 /// So the decode of SSTable disk file procedure is like this:
 /// - read the last 2 bytes to get the index block size.
 /// - based on the index block size, fetch all block pointer point to the index block.
 /// - decode every index block which their records represents data block with mata data of
-/// largest key, offset and size of the block.
+///   largest key, offset and size of the block.
 /// - Searching a user records can start with binary searching with the data block meta data
-/// to determine which data block contain the demanded user records and iterate the records of
-/// the data block to fetch the result
-///
-
+///   to determine which data block contain the demanded user records and iterate the records
+///   of the data block to fetch the result
 pub struct SSTable {
-    file_object: env::FileObject,
+    file_object: Box<dyn env::RandomAccessFile>,
     indexes: Vec<IndexBlockRecord>,
     seq: usize,
+    options: Arc<Options>,
+    range_tombstones: RangeTombstoneList,
+    /// the smallest and the largest key of the records and the range tombstones, the end key
+    /// of the range tombstones is taken as the largest key although it is exclusive
+    smallest_key: Vec<u8>,
+    largest_key: Vec<u8>,
+    /// identifies the SSTable in `Options::block_cache`
+    cache_id: u64,
+    /// the bloom filter of the prefixes of the keys
+    prefix_filter: Option<Vec<u8>>,
+    /// the sequence number every record of the SSTable is read with instead of its own, see
+    /// `DB::ingest_external_file`
+    global_seq: Option<SequenceNumber>,
 }
+pub const BLOCK_TRAILER_SIZE: usize = 5;
+pub const RANGE_DEL_BLOCK_NAME: &str = "summer_kv.range_del";
+
+/// Return the name of the meta block holding the prefix filter built by `prefix_extractor`
+fn prefix_filter_block_name(prefix_extractor: &dyn SliceTransform) -> String {
+    format!("summer_kv.prefix_filter.{}", prefix_extractor.name())
+}
+
+/// Compress the encoded `block` by `compressor` and append the block trailer, the block is
+/// kept uncompressed if the compressed output does not save enough space
+fn encode_block_with_trailer(block: &[u8], compressor: &dyn Compressor) -> Vec<u8> {
+    let mut buf = compressor.compress(block);
+    let mut compression_type = compressor.id();
+    if compression_type != NO_COMPRESSION && !is_worth_compressing(block.len(), buf.len()) {
+        buf = block.to_vec();
+        compression_type = NO_COMPRESSION;
+    }
+    buf.push(compression_type);
+    let checksum = crc32::value(buf.as_slice());
+    buf.put_u32(checksum);
+    buf
+}
+
 impl SSTable {
-    /// create a new SSTable object by a exists disk file identified by sequence number
-    fn open(seq: usize) -> Result<Self> {
-        let file_path = env::sstfile_path(seq);
-        let file_object = env::FileObject::open(file_path.as_str())?;
+    /// create a new SSTable object by a exists disk file identified by sequence number in
+    /// the directory `dir`
+    pub fn open(dir: &str, seq: usize, options: Arc<Options>) -> Result<Self> {
+        Self::open_file(env::sstfile_path(dir, seq).as_str(), seq, None, options)
+    }
+
+    /// Open the SSTable file at `path` as the SSTable numbered `seq`. Every record is read
+    /// with `global_seq` as its sequence number if it is given, the file is not changed.
+    pub fn open_file(
+        path: &str,
+        seq: usize,
+        global_seq: Option<SequenceNumber>,
+        options: Arc<Options>,
+    ) -> Result<Self> {
+        let file_object = options.env.new_random_access_file(path)?;
 
         // Initialize the `indexes` field
         // init the footer
         let mut buf = file_object.read_last_of(2)?;
         let index_block_num = buf.get_u16() as usize;
 
-        let footer_length = index_block_num * std::mem::size_of::<BlockPointer>() + 2;
+        let footer_length = (index_block_num + 1) * std::mem::size_of::<BlockPointer>() + 2;
         let footer_buf = file_object.read_last_of(footer_length)?;
+        let footer_offset = file_object.size()? - footer_length as u64;
 
         let mut indexes: Vec<IndexBlockRecord> = Vec::new();
 
-        let footer_obj = Footer::decode(footer_buf.as_ref());
+        let footer_obj = Footer::decode(footer_buf.as_ref())
+            .map_err(|e| e.locate(file_object.path(), footer_offset))?;
         // read records of each index block pointed by footer
         for index_block_pointer in &footer_obj.index_block_pointers {
             //read the index block
-            let buf = file_object.read_at(index_block_pointer.0 as u64, index_block_pointer.1)?;
-            let index_block_obj = Arc::new(Blocks::decode(buf.as_ref().clone()));
+            let index_block_obj = Arc::new(Self::read_block(
+                file_object.as_ref(),
+                &options,
+                index_block_pointer,
+                true,
+            )?);
             let mut record_iter = BlockRecordIterator::new(index_block_obj);
             record_iter.seek_to_first();
             while record_iter.is_valid() {
                 let record = IndexBlockRecord {
-                    largest_key: record_iter.key().to_vec().clone(),
-                    data_block_pointer: BlockPointer::decode(record_iter.value()),
+                    largest_key: record_iter.key().to_vec(),
+                    data_block_pointer: BlockPointer::decode(record_iter.value())
+                        .map_err(|e| e.locate(file_object.path(), index_block_pointer.0 as u64))?,
                 };
                 indexes.push(record);
                 record_iter.next();
             }
         }
-        Ok(SSTable {
+
+        // read the meta blocks
+        let mut range_tombstones = RangeTombstoneList::with_comparator(options.comparator.clone());
+        let meta_index_pointer = &footer_obj.meta_index_block_pointer;
+        let meta_index_block = Arc::new(Self::read_block(
+            file_object.as_ref(),
+            &options,
+            meta_index_pointer,
+            true,
+        )?);
+        let mut meta_iter = BlockRecordIterator::new(meta_index_block);
+        meta_iter.seek_to_key(RANGE_DEL_BLOCK_NAME.as_bytes());
+        while meta_iter.is_valid() && meta_iter.key() == RANGE_DEL_BLOCK_NAME.as_bytes() {
+            let pointer = BlockPointer::decode(meta_iter.value())
+                .map_err(|e| e.locate(file_object.path(), meta_index_pointer.0 as u64))?;
+            let block = Arc::new(Self::read_block(
+                file_object.as_ref(),
+                &options,
+                &pointer,
+                true,
+            )?);
+            let mut record_iter = BlockRecordIterator::new(block);
+            record_iter.seek_to_first();
+            while record_iter.is_valid() {
+                let mut tombstone = RangeTombstone::decode(record_iter.key(), record_iter.value())
+                    .map_err(|e| e.locate(file_object.path(), pointer.0 as u64))?;
+                tombstone.seq = global_seq.unwrap_or(tombstone.seq);
+                range_tombstones.add(tombstone);
+                record_iter.next();
+            }
+            meta_iter.next();
+        }
+        let mut prefix_filter = None;
+        if let Some(prefix_extractor) = options.prefix_extractor.as_deref() {
+            let name = prefix_filter_block_name(prefix_extractor);
+            meta_iter.seek_to_key(name.as_bytes());
+            if meta_iter.is_valid() && meta_iter.key() == name.as_bytes() {
+                let pointer = BlockPointer::decode(meta_iter.value())
+                    .map_err(|e| e.locate(file_object.path(), meta_index_pointer.0 as u64))?;
+                let (filter, _) = Self::read_raw_block(file_object.as_ref(), &pointer, true)?;
+                prefix_filter = Some(filter.to_vec());
+            }
+        }
+
+        let mut sstable = SSTable {
             file_object,
             indexes,
             seq,
-        })
+            options,
+            range_tombstones,
+            smallest_key: Vec::new(),
+            largest_key: Vec::new(),
+            cache_id: new_cache_id(),
+            prefix_filter,
+            global_seq,
+        };
+        let mut smallest_key = Vec::new();
+        if !sstable.indexes.is_empty() {
+            smallest_key = sstable
+                .read_data_block(0, &ReadOptions::default())?
+                .smallest_key();
+        }
+        let largest_key = sstable
+            .indexes
+            .last()
+            .map(|x| x.largest_key.clone())
+            .unwrap_or_default();
+        sstable.set_key_range(smallest_key, largest_key);
+        Ok(sstable)
+    }
+
+    /// Set the key range of the SSTable by the key range of the records, the range is
+    /// extended by the range tombstones
+    fn set_key_range(&mut self, mut smallest_key: Vec<u8>, mut largest_key: Vec<u8>) {
+        let comparator = self.options.comparator.as_ref();
+        if let Some((start, end)) = self.range_tombstones.key_range() {
+            if self.indexes.is_empty() || comparator.compare(&start, &smallest_key).is_lt() {
+                smallest_key = start.to_vec();
+            }
+            if self.indexes.is_empty() || comparator.compare(&end, &largest_key).is_gt() {
+                largest_key = end.to_vec();
+            }
+        }
+        self.smallest_key = smallest_key;
+        self.largest_key = largest_key;
+    }
+
+    /// Return the value of the record whose key is `key`
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        Ok(self.multi_get(&[key])?.pop().unwrap())
+    }
+
+    /// Return the values of the records of `keys` in the same order, `keys` must be sorted by
+    /// the comparator. The keys falling in the same data block are looked up in the block
+    /// read once.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        let comparator = &self.options.comparator;
+        let mut values = Vec::with_capacity(keys.len());
+        // the data block the previous key falls in along with its index
+        let mut current: Option<(usize, BlockRecordIterator)> = None;
+        for key in keys {
+            let data_block_index = self
+                .indexes
+                .partition_point(|x| comparator.compare(&x.largest_key, key).is_lt());
+            if data_block_index == self.indexes.len() {
+                values.push(None);
+                continue;
+            }
+            if !matches!(&current, Some((index, _)) if *index == data_block_index) {
+                let block = self.read_data_block(data_block_index, &ReadOptions::default())?;
+                let iter = BlockRecordIterator::with_comparator(block, comparator.clone());
+                current = Some((data_block_index, iter));
+            }
+            let iter = &mut current.as_mut().unwrap().1;
+            iter.seek_to_key(key);
+            values.push(
+                (iter.is_valid() && comparator.compare(iter.key(), key).is_eq())
+                    .then(|| Bytes::copy_from_slice(iter.value())),
+            );
+        }
+        Ok(values)
+    }
+
+    /// Whether the SSTable may hold keys with `prefix` extracted by `Options::prefix_extractor`,
+    /// `false` if the prefix filter rules it out
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        match &self.prefix_filter {
+            Some(filter) => bloom_may_match(filter.as_slice(), bloom_hash(prefix)),
+            None => true,
+        }
+    }
+
+    /// Whether the SSTable may hold `key`, `false` if the prefix filter rules out the prefix
+    /// of the key
+    pub fn may_contain_key(&self, key: &[u8]) -> bool {
+        match self.options.prefix_extractor.as_deref() {
+            Some(prefix_extractor) if prefix_extractor.in_domain(key) => {
+                self.may_contain_prefix(prefix_extractor.transform(key))
+            }
+            _ => true,
+        }
+    }
+
+    pub fn range_tombstones(&self) -> &RangeTombstoneList {
+        &self.range_tombstones
+    }
+
+    pub fn smallest_key(&self) -> &[u8] {
+        self.smallest_key.as_slice()
+    }
+
+    pub fn largest_key(&self) -> &[u8] {
+        self.largest_key.as_slice()
+    }
+
+    pub fn seq(&self) -> usize {
+        self.seq
+    }
+
+    pub fn global_seq(&self) -> Option<SequenceNumber> {
+        self.global_seq
+    }
+
+    /// Return the comparator the keys of the SSTable are sorted by
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.options.comparator
+    }
+
+    pub fn file_size(&self) -> Result<u64> {
+        self.file_object.size()
+    }
+
+    /// Read the block pointed by `pointer` and verify the checksum if `verify_checksum` is
+    /// set, return the block content and the compression type
+    fn read_raw_block(
+        file_object: &dyn env::RandomAccessFile,
+        pointer: &BlockPointer,
+        verify_checksum: bool,
+    ) -> Result<(Bytes, u8)> {
+        if pointer.1 < BLOCK_TRAILER_SIZE {
+            return Err(Error::corruption(file_object.path(), pointer.0 as u64));
+        }
+        let raw = file_object.read_at(pointer.0 as u64, pointer.1)?;
+        let (checked, mut checksum) = raw.split_at(raw.len() - 4);
+        if verify_checksum && crc32::value(checked) != checksum.get_u32() {
+            return Err(Error::corruption(file_object.path(), pointer.0 as u64));
+        }
+        let compression_type = raw[raw.len() - BLOCK_TRAILER_SIZE];
+        Ok((
+            raw.slice(..raw.len() - BLOCK_TRAILER_SIZE),
+            compression_type,
+        ))
+    }
+
+    /// Read the block pointed by `pointer`, verify the checksum if `verify_checksum` is set,
+    /// then decompress and decode it based on the trailer
+    fn read_block(
+        file_object: &dyn env::RandomAccessFile,
+        options: &Options,
+        pointer: &BlockPointer,
+        verify_checksum: bool,
+    ) -> Result<Blocks> {
+        let corruption = || Error::corruption(file_object.path(), pointer.0 as u64);
+        let (content, compression_type) =
+            Self::read_raw_block(file_object, pointer, verify_checksum)?;
+        let block = if compression_type == NO_COMPRESSION {
+            Blocks::decode(content.as_ref())
+        } else {
+            let compressor = options.compressor_by_id(compression_type).ok_or_else(|| {
+                Error::corruption(
+                    file_object.path(),
+                    (pointer.0 + pointer.1 - BLOCK_TRAILER_SIZE) as u64,
+                )
+            })?;
+            let decompressed = compressor
+                .decompress(content.as_ref())
+                .map_err(|_| corruption())?;
+            Blocks::decode(decompressed.as_slice()).map_err(|_| corruption())
+        };
+        block.map_err(|e| e.locate(file_object.path(), pointer.0 as u64))
+    }
+
+    /// Read and decode the data block pointed by the `data_block_index`th index record, the
+    /// block is looked up in the block cache first
+    fn read_data_block(
+        &self,
+        data_block_index: usize,
+        read_options: &ReadOptions,
+    ) -> Result<Arc<Blocks>> {
+        let pointer = &self.indexes[data_block_index].data_block_pointer;
+        let block_cache = self.options.block_cache.as_ref();
+        if let Some(block) = block_cache.and_then(|x| x.get(self.cache_id, pointer.0 as u64)) {
+            return Ok(block);
+        }
+        let mut block = Self::read_block(
+            self.file_object.as_ref(),
+            &self.options,
+            pointer,
+            read_options.verify_checksums,
+        )?;
+        if let Some(global_seq) = self.global_seq {
+            // the sequence number follows the value type in the header of every value
+            let mut corrupted = false;
+            block.update_values(|value| match value.get_mut(1..VALUE_HEADER_SIZE) {
+                Some(seq) => seq.copy_from_slice(&global_seq.to_be_bytes()),
+                None => corrupted = true,
+            });
+            if corrupted {
+                return Err(Error::corruption(self.file_object.path(), pointer.0 as u64));
+            }
+        }
+        let block = Arc::new(block);
+        if let Some(block_cache) = block_cache.filter(|_| read_options.fill_cache) {
+            block_cache.insert(self.cache_id, pointer.0 as u64, block.clone());
+        }
+        Ok(block)
     }
 }
 
@@ -122,24 +464,28 @@ impl IndexBlockRecord {
         buf.put_slice(block_pointer_seq.as_ref());
         buf.freeze()
     }
-    fn decode(raw: &[u8]) -> Self {
-        let mut buf = &raw[..];
-        let key_length = buf.get_u16() as usize;
-        let key_buf = &raw[SIZE_U16..SIZE_U16 + key_length];
-        let mut key_vec: Vec<u8> = Vec::new();
-        for i in key_buf {
-            key_vec.push(i.clone());
+    fn decode(raw: &[u8]) -> Result<Self> {
+        let mut buf = raw;
+        if buf.len() < SIZE_U16 {
+            return Err(Error::corruption("", 0));
         }
+        let key_length = buf.get_u16() as usize;
+        let key_buf = raw
+            .get(SIZE_U16..SIZE_U16 + key_length)
+            .ok_or_else(|| Error::corruption("", SIZE_U16 as u64))?;
 
-        let value_buf = &raw[SIZE_U16 + key_length + SIZE_U16..];
-        let block_pointer = BlockPointer::decode(value_buf);
-        IndexBlockRecord {
-            largest_key: key_vec,
+        let value_buf = raw
+            .get(SIZE_U16 + key_length + SIZE_U16..)
+            .ok_or_else(|| Error::corruption("", (SIZE_U16 + key_length) as u64))?;
+        let block_pointer = BlockPointer::decode(value_buf)?;
+        Ok(IndexBlockRecord {
+            largest_key: key_buf.to_vec(),
             data_block_pointer: block_pointer,
-        }
+        })
     }
 }
 struct Footer {
+    meta_index_block_pointer: BlockPointer,
     index_block_pointers: Vec<BlockPointer>,
     num_of_index_block: usize,
 }
@@ -148,6 +494,7 @@ impl Footer {
     fn encode(&self) -> Bytes {
         let mut buf = BytesMut::new();
 
+        buf.put_slice(self.meta_index_block_pointer.encode().as_ref());
         for index_block_pointer in &self.index_block_pointers {
             buf.put_slice(index_block_pointer.encode().as_ref());
         }
@@ -155,20 +502,28 @@ impl Footer {
         buf.put_u16(self.num_of_index_block as u16);
         buf.freeze()
     }
-    fn decode(raw: &[u8]) -> Footer {
-        use std::iter::Iterator;
+    fn decode(raw: &[u8]) -> Result<Footer> {
+        if raw.len() < SIZE_U16 {
+            return Err(Error::corruption("", 0));
+        }
         let mut last_two_bytes = &raw[raw.len() - SIZE_U16..];
         let num_of_index_block = last_two_bytes.get_u16() as usize;
 
-        let raw_index_block_pointers = &raw[..raw.len() - SIZE_U16];
-        let buf_vec = raw_index_block_pointers
+        let pointer_size = std::mem::size_of::<BlockPointer>();
+        if raw.len() != (num_of_index_block + 1) * pointer_size + SIZE_U16 {
+            return Err(Error::corruption("", 0));
+        }
+        let meta_index_block_pointer = BlockPointer::decode(&raw[..pointer_size])?;
+        let raw_index_block_pointers = &raw[pointer_size..raw.len() - SIZE_U16];
+        let index_block_pointers = raw_index_block_pointers
             .chunks(std::mem::size_of::<BlockPointer>())
-            .map(|x| BlockPointer::decode(x))
-            .collect();
-        Footer {
-            index_block_pointers: buf_vec,
+            .map(BlockPointer::decode)
+            .collect::<Result<Vec<BlockPointer>>>()?;
+        Ok(Footer {
+            meta_index_block_pointer,
+            index_block_pointers,
             num_of_index_block,
-        }
+        })
     }
 }
 /// offset and length
@@ -181,11 +536,14 @@ impl BlockPointer {
         buf.put_slice(self.1.to_be_bytes().as_ref());
         buf.freeze()
     }
-    fn decode(raw: &[u8]) -> Self {
-        let mut buf = &raw[..];
+    fn decode(raw: &[u8]) -> Result<Self> {
+        if raw.len() < std::mem::size_of::<BlockPointer>() {
+            return Err(Error::corruption("", 0));
+        }
+        let mut buf = raw;
         let offset = buf.get_uint(std::mem::size_of::<usize>()) as usize;
         let size = buf.get_uint(std::mem::size_of::<usize>()) as usize;
-        BlockPointer(offset, size)
+        Ok(BlockPointer(offset, size))
     }
 }
 impl PartialEq for BlockPointer {
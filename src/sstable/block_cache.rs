@@ -0,0 +1,96 @@
+use crate::blocks::Blocks;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+static NEXT_CACHE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Return a new id identifying an SSTable in the `BlockCache`, the ids are unique in the
+/// process so that a cache is able to be shared by several databases
+pub fn new_cache_id() -> u64 {
+    NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// `BlockCache` keeps the decoded data blocks of SSTables in memory, up to `capacity` bytes.
+/// The least recently used blocks are evicted once the capacity is exceeded. A block is
+/// identified by the cache id of its SSTable and its offset in the file.
+pub struct BlockCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// the total size of the cached blocks
+    usage: usize,
+    /// increases on every access, the entry with the smallest tick is the least recently used
+    tick: u64,
+    entries: HashMap<(u64, u64), CacheEntry>,
+    /// the key of every entry by its last access tick
+    lru: BTreeMap<u64, (u64, u64)>,
+}
+
+struct CacheEntry {
+    block: Arc<Blocks>,
+    tick: u64,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        BlockCache {
+            capacity,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Return the total size of the cached blocks
+    pub fn usage(&self) -> usize {
+        self.state.lock().unwrap().usage
+    }
+
+    /// Return the block at `offset` of the SSTable `cache_id`, the block becomes the most
+    /// recently used one
+    pub fn get(&self, cache_id: u64, offset: u64) -> Option<Arc<Blocks>> {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let entry = state.entries.get_mut(&(cache_id, offset))?;
+        let old_tick = std::mem::replace(&mut entry.tick, tick);
+        let block = entry.block.clone();
+        state.lru.remove(&old_tick);
+        state.lru.insert(tick, (cache_id, offset));
+        Some(block)
+    }
+
+    /// Cache the block at `offset` of the SSTable `cache_id`, then evict the least recently
+    /// used blocks until the usage fits in the capacity. A block larger than the capacity is
+    /// not cached.
+    pub fn insert(&self, cache_id: u64, offset: u64, block: Arc<Blocks>) {
+        let charge = block.size() as usize;
+        if charge > self.capacity {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let entry = CacheEntry { block, tick };
+        if let Some(old) = state.entries.insert((cache_id, offset), entry) {
+            state.lru.remove(&old.tick);
+            state.usage -= old.block.size() as usize;
+        }
+        state.lru.insert(tick, (cache_id, offset));
+        state.usage += charge;
+        while state.usage > self.capacity {
+            let (_, key) = state
+                .lru
+                .pop_first()
+                .expect("the usage is made of cached blocks");
+            let evicted = state.entries.remove(&key).unwrap();
+            state.usage -= evicted.block.size() as usize;
+        }
+    }
+}
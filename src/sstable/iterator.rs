@@ -1,101 +1,173 @@
 use crate::blocks::iterator::BlockRecordIterator;
-use crate::blocks::Blocks;
 use crate::iterator::Iterator;
+use crate::options::ReadOptions;
 use crate::sstable::SSTable;
+use crate::util::error::Result;
 use std::cmp::Ordering;
 use std::sync::Arc;
 
+/// `SSTableRecordIterator` iterates the records of an SSTable within the bounds of its
+/// `ReadOptions`, the data blocks wholly beyond the bounds are not read.
 pub struct SSTableRecordIterator {
     sstable: Arc<SSTable>,
-    data_block_iterator: BlockRecordIterator,
+    read_options: Arc<ReadOptions>,
+    data_block_iterator: Option<BlockRecordIterator>,
     data_block_index: usize,
     is_valid: bool,
+    /// the first error encountered while loading data blocks
+    status: Result<()>,
 }
 
-impl Iterator for SSTableRecordIterator {
-    type Item = Arc<SSTable>;
-    fn new(sstable: Self::Item) -> Self {
-        let data_block_index = 0;
-        let offset = sstable.indexes[data_block_index].data_block_pointer.0 as u64;
-        let length = sstable.indexes[data_block_index].data_block_pointer.1;
-        let data_block_raw = sstable
-            .file_object
-            .read_at(offset, length)
-            .expect("A SSTable file should contain one data block at least");
-
-        let data_block = Arc::new(Blocks::decode(data_block_raw.as_ref()));
-        let data_block_iterator = BlockRecordIterator::new(data_block);
+impl SSTableRecordIterator {
+    pub fn with_read_options(sstable: Arc<SSTable>, read_options: Arc<ReadOptions>) -> Self {
         SSTableRecordIterator {
             sstable,
-            data_block_iterator,
-            data_block_index,
+            read_options,
+            data_block_iterator: None,
+            data_block_index: 0,
             is_valid: false,
+            status: Ok(()),
         }
     }
-    fn seek_to_first(&mut self) {
-        if self.data_block_index == 0 {
-            self.data_block_iterator.seek_to_first();
-            self.is_valid = true;
-            return;
+
+    pub fn sstable(&self) -> &Arc<SSTable> {
+        &self.sstable
+    }
+
+    /// Read and decode the data block at `data_block_index` of the index records, the
+    /// returned iterator is not positioned yet
+    fn load_data_block(&self, data_block_index: usize) -> Result<BlockRecordIterator> {
+        let data_block = self
+            .sstable
+            .read_data_block(data_block_index, self.read_options.as_ref())?;
+        Ok(BlockRecordIterator::with_comparator(
+            data_block,
+            self.sstable.comparator().clone(),
+        ))
+    }
+
+    /// Switch to the data block at `data_block_index`, on failure the iterator becomes
+    /// invalid and the error is kept in `status`
+    fn switch_data_block(&mut self, data_block_index: usize) -> bool {
+        match self.load_data_block(data_block_index) {
+            Ok(iter) => {
+                self.data_block_iterator = Some(iter);
+                self.data_block_index = data_block_index;
+                true
+            }
+            Err(e) => {
+                self.data_block_iterator = None;
+                self.is_valid = false;
+                self.status = Err(e);
+                false
+            }
         }
-        self.data_block_index = 0;
-        let data_block_index = 0;
-        let offset = self.sstable.indexes[data_block_index].data_block_pointer.0 as u64;
-        let length = self.sstable.indexes[data_block_index].data_block_pointer.1;
-        let data_block_raw = self
+    }
+}
+
+impl SSTableRecordIterator {
+    /// Position at the first record whose key is greater than or equal to `key`, ignoring
+    /// the bounds
+    fn seek(&mut self, key: &[u8]) {
+        let comparator = self.sstable.comparator();
+        let found = self
             .sstable
-            .file_object
-            .read_at(offset, length)
-            .expect("A SSTable file should contain one data block at least");
+            .indexes
+            .iter()
+            .position(|index| comparator.compare(key, &index.largest_key) != Ordering::Greater);
+        let Some(data_block_index) = found else {
+            self.is_valid = false;
+            return;
+        };
+        if !self.switch_data_block(data_block_index) {
+            return;
+        }
+        let iter = self.data_block_iterator.as_mut().unwrap();
+        iter.seek_to_key(key);
+        self.is_valid = iter.is_valid();
+    }
 
-        let data_block = Arc::new(Blocks::decode(data_block_raw.as_ref()));
-        self.data_block_iterator = BlockRecordIterator::new(data_block);
-        self.is_valid = true;
+    /// Position at the last record, ignoring the bounds
+    fn seek_last(&mut self) {
+        if self.sstable.indexes.is_empty() {
+            self.is_valid = false;
+            return;
+        }
+        let last = self.sstable.indexes.len() - 1;
+        if (self.data_block_index != last || self.data_block_iterator.is_none())
+            && !self.switch_data_block(last)
+        {
+            return;
+        }
+        let iter = self.data_block_iterator.as_mut().unwrap();
+        iter.seek_to_last();
+        self.is_valid = iter.is_valid();
     }
 
-    fn seek_to_last(&mut self) {
-        if self.data_block_index == self.sstable.indexes.len() - 1 {
-            self.data_block_iterator.seek_to_last();
-            self.is_valid = true;
+    /// Invalidate the iterator if the current record is out of the bounds
+    fn check_bounds(&mut self) {
+        let comparator = self.sstable.comparator().as_ref();
+        if self.is_valid
+            && (self.read_options.before_lower_bound(self.key(), comparator)
+                || self
+                    .read_options
+                    .reaches_upper_bound(self.key(), comparator))
+        {
+            self.is_valid = false;
+        }
+    }
+}
+
+impl Iterator for SSTableRecordIterator {
+    type Item = Arc<SSTable>;
+    fn new(sstable: Self::Item) -> Self {
+        Self::with_read_options(sstable, Arc::new(ReadOptions::default()))
+    }
+    fn seek_to_first(&mut self) {
+        if let Some(lower_bound) = self.read_options.iterate_lower_bound.clone() {
+            self.seek(lower_bound.as_slice());
+            self.check_bounds();
             return;
         }
-        self.data_block_index = self.sstable.indexes.len() - 1;
-        let data_block_index = self.data_block_index;
-        let offset = self.sstable.indexes[data_block_index].data_block_pointer.0 as u64;
-        let length = self.sstable.indexes[data_block_index].data_block_pointer.1;
-        let data_block_raw = self
-            .sstable
-            .file_object
-            .read_at(offset, length)
-            .expect("A SSTable file should contain one data block at least");
-        let data_block = Arc::new(Blocks::decode(data_block_raw.as_ref()));
-        self.data_block_iterator = BlockRecordIterator::new(data_block);
-        self.data_block_iterator.seek_to_last();
-        self.is_valid = true;
+        if self.sstable.indexes.is_empty() {
+            self.is_valid = false;
+            return;
+        }
+        if (self.data_block_index != 0 || self.data_block_iterator.is_none())
+            && !self.switch_data_block(0)
+        {
+            return;
+        }
+        let iter = self.data_block_iterator.as_mut().unwrap();
+        iter.seek_to_first();
+        self.is_valid = iter.is_valid();
+        self.check_bounds();
     }
 
-    fn seek_to_key(&mut self, key: &[u8]) {
-        for index in &self.sstable.indexes {
-            if key.cmp(index.largest_key.as_slice()) != Ordering::Greater {
-                let offset = index.data_block_pointer.0 as u64;
-                let length = index.data_block_pointer.1;
-                let data_block_raw = self
-                    .sstable
-                    .file_object
-                    .read_at(offset, length)
-                    .expect("A SSTable file block pointer invalid");
-                let data_block = Arc::new(Blocks::decode(data_block_raw.as_ref()));
-                self.data_block_iterator = BlockRecordIterator::new(data_block);
-                self.data_block_iterator.seek_to_key(key);
-                if !self.data_block_iterator.is_valid() {
-                    self.is_valid = false;
-                    return;
+    fn seek_to_last(&mut self) {
+        match self.read_options.iterate_upper_bound.clone() {
+            Some(upper_bound) => {
+                // the last record before the first one not less than the bound
+                self.seek(upper_bound.as_slice());
+                if self.is_valid {
+                    self.prev();
+                } else if self.status.is_ok() {
+                    self.seek_last();
                 }
-                self.is_valid = true;
-                return;
             }
+            None => self.seek_last(),
         }
-        self.is_valid = false;
+        self.check_bounds();
+    }
+
+    fn seek_to_key(&mut self, key: &[u8]) {
+        let read_options = self.read_options.clone();
+        if read_options.before_lower_bound(key, self.sstable.comparator().as_ref()) {
+            self.seek(read_options.iterate_lower_bound.as_ref().unwrap());
+        } else {
+            self.seek(key);
+        }
+        self.check_bounds();
     }
 
     fn is_valid(&self) -> bool {
@@ -103,39 +175,83 @@ impl Iterator for SSTableRecordIterator {
     }
 
     fn next(&mut self) {
-        self.data_block_iterator.next();
-        if self.data_block_iterator.is_valid() {
+        let Some(iter) = self.data_block_iterator.as_mut() else {
+            self.is_valid = false;
+            return;
+        };
+        iter.next();
+        if iter.is_valid() {
             self.is_valid = true;
+            self.check_bounds();
+            return;
+        }
+        // the following blocks only hold keys beyond the largest key of this one
+        let largest_key = self.sstable.indexes[self.data_block_index]
+            .largest_key
+            .as_slice();
+        if self
+            .read_options
+            .reaches_upper_bound(largest_key, self.sstable.comparator().as_ref())
+        {
+            self.is_valid = false;
             return;
         }
         if self.data_block_index + 1 < self.sstable.indexes.len() {
-            self.data_block_index += 1;
-            let data_block_index = self.data_block_index;
-            let offset = self.sstable.indexes[data_block_index].data_block_pointer.0 as u64;
-            let length = self.sstable.indexes[data_block_index].data_block_pointer.1;
-            let data_block_raw = self
-                .sstable
-                .file_object
-                .read_at(offset, length)
-                .expect("A SSTable file should contain one data block at least");
-            let data_block = Arc::new(Blocks::decode(data_block_raw.as_ref()));
-            self.data_block_iterator = BlockRecordIterator::new(data_block);
-            self.data_block_iterator.seek_to_first();
-            self.is_valid = true;
+            if !self.switch_data_block(self.data_block_index + 1) {
+                return;
+            }
+            let iter = self.data_block_iterator.as_mut().unwrap();
+            iter.seek_to_first();
+            self.is_valid = iter.is_valid();
+            self.check_bounds();
         } else {
             self.is_valid = false;
         }
     }
 
     fn prev(&mut self) {
-        todo!()
+        let Some(iter) = self.data_block_iterator.as_mut() else {
+            self.is_valid = false;
+            return;
+        };
+        iter.prev();
+        if iter.is_valid() {
+            self.is_valid = true;
+            self.check_bounds();
+            return;
+        }
+        if self.data_block_index > 0 {
+            let largest_key = self.sstable.indexes[self.data_block_index - 1]
+                .largest_key
+                .as_slice();
+            if self
+                .read_options
+                .before_lower_bound(largest_key, self.sstable.comparator().as_ref())
+            {
+                self.is_valid = false;
+                return;
+            }
+            if !self.switch_data_block(self.data_block_index - 1) {
+                return;
+            }
+            let iter = self.data_block_iterator.as_mut().unwrap();
+            iter.seek_to_last();
+            self.is_valid = iter.is_valid();
+            self.check_bounds();
+        } else {
+            self.is_valid = false;
+        }
     }
 
     fn key(&self) -> &[u8] {
-        self.data_block_iterator.key()
+        self.data_block_iterator.as_ref().unwrap().key()
     }
 
     fn value(&self) -> &[u8] {
-        self.data_block_iterator.value()
+        self.data_block_iterator.as_ref().unwrap().value()
+    }
+
+    fn status(&self) -> Result<()> {
+        self.status.clone()
     }
 }
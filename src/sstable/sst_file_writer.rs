@@ -0,0 +1,97 @@
+use crate::db::format::{encode_merge_payload, encode_value, MergeBase, RangeTombstone, ValueType};
+use crate::options::Options;
+use crate::sstable::sstable_builder::SSTableBuilder;
+use crate::util::error::{Error, Result};
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// `SstFileWriter` builds a SSTable at a path of the caller's choosing, the SSTable is
+/// loaded into a database by `DB::ingest_external_file` without being rewritten. The options
+/// must be the ones of the column family the SSTable is ingested into, the comparator and the
+/// prefix extractor in particular.
+///
+/// The records are written with sequence number 0, the ingestion gives all of them a new
+/// sequence number so that they are newer than the records in the database.
+pub struct SstFileWriter {
+    path: String,
+    options: Arc<Options>,
+    builder: SSTableBuilder,
+    last_key: Option<Vec<u8>>,
+}
+
+impl SstFileWriter {
+    /// Create a writer of the SSTable at `path`, the file is written by `finish`
+    pub fn new(path: &str, options: Options) -> Self {
+        let options = Arc::new(options);
+        SstFileWriter {
+            path: path.to_string(),
+            // the ingested SSTables are placed at any level
+            builder: SSTableBuilder::new(options.clone(), 0),
+            options,
+            last_key: None,
+        }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.add(key, encode_value(ValueType::Value, 0, value))
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.add(key, encode_value(ValueType::Deletion, 0, b""))
+    }
+
+    /// Merge `operand` into the value of `key` in the database, see `DB::merge`
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<()> {
+        let payload =
+            encode_merge_payload(&MergeBase::Unresolved, &[Bytes::copy_from_slice(operand)]);
+        self.add(key, encode_value(ValueType::Merge, 0, payload.as_slice()))
+    }
+
+    /// Delete every key in `[start, end)` of the database, see `DB::delete_range`. The range
+    /// tombstones may be written in any order.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) -> Result<()> {
+        if self.options.comparator.compare(start, end).is_gt() {
+            return Err(Error::InvalidArgument(
+                "the start key of the range is greater than the end key".to_string(),
+            ));
+        }
+        if start != end {
+            self.builder
+                .add_range_tombstone(RangeTombstone::new(start, end, 0));
+        }
+        Ok(())
+    }
+
+    /// Failed with `InvalidArgument` if `key` is not greater than the previous key
+    fn add(&mut self, key: &[u8], record: Bytes) -> Result<()> {
+        if let Some(last_key) = self.last_key.as_deref() {
+            if self.options.comparator.compare(last_key, key).is_ge() {
+                return Err(Error::InvalidArgument(format!(
+                    "key {:?} is not greater than the previous key {:?}",
+                    key, last_key
+                )));
+            }
+        }
+        self.builder.add(key, record.as_ref())?;
+        self.last_key = Some(key.to_vec());
+        Ok(())
+    }
+
+    /// Write the SSTable to the file.
+    /// Failed with `InvalidArgument` if nothing is written, or if the file exists
+    pub fn finish(mut self) -> Result<()> {
+        if self.builder.is_empty() {
+            return Err(Error::InvalidArgument(
+                "no record is written to the SSTable".to_string(),
+            ));
+        }
+        if self.options.env.file_exists(self.path.as_str()) {
+            return Err(Error::InvalidArgument(format!(
+                "file {} already exists",
+                self.path
+            )));
+        }
+        self.builder.build_file(self.path.as_str(), 0)?;
+        Ok(())
+    }
+}
@@ -1,122 +1,263 @@
 use crate::blocks::{BlockBuilder, Blocks};
-use crate::sstable::{BlockPointer, Footer, IndexBlockRecord, SSTable};
-use crate::util::env::{get_global_sequence_number, sstfile_path, FileObject};
-use anyhow::Result;
+use crate::compression::{Compressor, NoCompressor};
+use crate::db::format::{RangeTombstone, RangeTombstoneList};
+use crate::options::Options;
+use crate::sstable::block_cache::new_cache_id;
+use crate::sstable::{
+    encode_block_with_trailer, prefix_filter_block_name, BlockPointer, Footer, IndexBlockRecord,
+    SSTable, RANGE_DEL_BLOCK_NAME,
+};
+use crate::util::bloom::{bloom_hash, build_bloom_filter};
+use crate::util::env::{rename_and_sync, sstfile_path, temp_file_path, WritableFile};
+use crate::util::error::Result;
+use std::sync::Arc;
 
-pub const SSTABLE_SIZE_LIMIT: usize = 4 * 1024 * 1024; // 4MB
 pub struct SSTableBuilder {
     data_blocks: Vec<Blocks>,
+    /// the total size of the finished data blocks
+    data_blocks_size: usize,
     block_builder: BlockBuilder,
+    range_tombstones: RangeTombstoneList,
+    options: Arc<Options>,
+    /// compressor of the data blocks, decided by the level the SSTable is built for
+    compressor: Arc<dyn Compressor>,
+    /// the hashes of the distinct prefixes of the keys for the prefix filter
+    prefix_hashes: Vec<u32>,
+    last_prefix: Option<Vec<u8>>,
 }
 
 impl SSTableBuilder {
-    pub fn new() -> Self {
+    /// Create a builder of the SSTable which is going to be placed in `level`
+    pub fn new(options: Arc<Options>, level: usize) -> Self {
         SSTableBuilder {
             data_blocks: Vec::new(),
-            block_builder: BlockBuilder::new(),
+            data_blocks_size: 0,
+            range_tombstones: RangeTombstoneList::with_comparator(options.comparator.clone()),
+            block_builder: BlockBuilder::with_block_size(
+                options.block_size,
+                options.block_restart_interval,
+            ),
+            compressor: options.compressor_for_level(level),
+            options,
+            prefix_hashes: Vec::new(),
+            last_prefix: None,
         }
     }
 
-    pub fn approximate_size_after_add(&self, key: &[u8], value: &[u8]) -> usize {
-        // TODO(summerxwu): accumulate the data size of current builder memory
-        SSTABLE_SIZE_LIMIT
+    /// Return the approximate size of the SSTable if it is built now, compression is not
+    /// taken into account
+    pub fn approximate_size(&self) -> usize {
+        self.data_blocks_size + self.block_builder.estimated_size()
     }
 
     /// [`add`] function append user specified key and value pair to current builder.
-    /// notice that there is no limitations of file size, because of the SSTable is ba
+    /// notice that there is no limitations of file size, the caller decides when to build the
+    /// SSTable by `approximate_size`. Keys must be added in ascending order of
+    /// `Options::comparator`.
     pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        if self.approximate_size_after_add(key, value) > SSTABLE_SIZE_LIMIT {
-            return Ok(());
-        }
+        self.add_prefix(key);
         if self.block_builder.add(key, value).is_err() {
             // finish current data_block
-            let data_block_holder = self.block_builder.build();
-            self.data_blocks.push(data_block_holder);
-            self.block_builder.clean_up();
-            // Add the failed KV pair agine
-            // panic if failed
-            self.block_builder
-                .add(key, value)
-                .expect("The build has already been reset, it should not failed to add content");
+            self.finish_data_block();
+            // Add the failed KV pair again, an empty block always accepts the record unless
+            // the record itself can not be encoded
+            self.block_builder.add(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Record the prefix of `key` for the prefix filter, the keys sharing a prefix are added
+    /// one after another
+    fn add_prefix(&mut self, key: &[u8]) {
+        let Some(prefix_extractor) = self.options.prefix_extractor.as_deref() else {
+            return;
+        };
+        if !prefix_extractor.in_domain(key) {
+            return;
+        }
+        let prefix = prefix_extractor.transform(key);
+        if self.last_prefix.as_deref() != Some(prefix) {
+            self.prefix_hashes.push(bloom_hash(prefix));
+            self.last_prefix = Some(prefix.to_vec());
         }
-        return Ok(());
     }
-    /// build will return the `SSTable` object and serializable the content to disk file
-    pub fn build(&mut self) -> Result<SSTable> {
-        if !self.block_builder.is_empty(){
-            let data_block_holder = self.block_builder.build();
-            self.data_blocks.push(data_block_holder);
-            self.block_builder.clean_up();
+
+    /// Add a range tombstone, range tombstones may be added in any order
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.range_tombstones.add(tombstone);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data_blocks.is_empty()
+            && self.block_builder.is_empty()
+            && self.range_tombstones.is_empty()
+    }
+
+    fn finish_data_block(&mut self) {
+        let data_block_holder = self.block_builder.build();
+        self.data_blocks_size += data_block_holder.size() as usize;
+        self.data_blocks.push(data_block_holder);
+        self.block_builder.clean_up();
+    }
+
+    /// Write every block in `blocks` uncompressed, return the pointers of them
+    fn write_raw_blocks(
+        file_obj: &mut dyn WritableFile,
+        offset_counter: &mut usize,
+        blocks: &[Blocks],
+    ) -> Result<Vec<BlockPointer>> {
+        let mut pointers = Vec::new();
+        for block in blocks {
+            let buf = encode_block_with_trailer(block.encode().as_ref(), &NoCompressor {});
+            file_obj.append(buf.as_ref())?;
+            pointers.push(BlockPointer(*offset_counter, buf.len()));
+            *offset_counter += buf.len();
         }
-        let seq = get_global_sequence_number();
-        let mut file_obj = FileObject::create(sstfile_path(seq as usize).as_str())?;
+        Ok(pointers)
+    }
+
+    /// Put the records into as many blocks as needed
+    fn build_blocks<'a, I>(records: I) -> Result<Vec<Blocks>>
+    where
+        I: IntoIterator<Item = (&'a [u8], Vec<u8>)>,
+    {
+        let mut blocks = Vec::new();
+        let mut block_builder = BlockBuilder::new();
+        for (key, value) in records {
+            if block_builder.add(key, value.as_slice()).is_err() {
+                // current block is full, start a new block
+                blocks.push(block_builder.build());
+                block_builder.clean_up();
+                block_builder.add(key, value.as_slice())?;
+            }
+        }
+        // finish the last block, it is kept even if empty so that there is always a block
+        blocks.push(block_builder.build());
+        Ok(blocks)
+    }
+
+    /// build will return the `SSTable` object and serializable the content to the disk file
+    /// numbered `seq` in `dir`
+    pub fn build(&mut self, dir: &str, seq: usize) -> Result<SSTable> {
+        self.build_file(sstfile_path(dir, seq).as_str(), seq)
+    }
+
+    /// Write the SSTable to a new file at `path`, return it as the SSTable numbered `seq`.
+    /// The SSTable is written to a temporary file renamed to `path` once synced, so that no
+    /// partially written SSTable is seen under `path`.
+    pub fn build_file(&mut self, path: &str, seq: usize) -> Result<SSTable> {
+        if !self.block_builder.is_empty() {
+            self.finish_data_block();
+        }
+        let temp_path = temp_file_path(path);
+        let env = self.options.env.as_ref();
+        let _ = env.remove_file(temp_path.as_str());
+        let mut file_obj = env.new_writable_file(temp_path.as_str())?;
 
         let mut indexes_records: Vec<IndexBlockRecord> = Vec::new();
         let mut offset_counter = 0;
         // Write data portion of SSTable
         for data_block in &self.data_blocks {
-            let buf = data_block.encode();
+            let buf =
+                encode_block_with_trailer(data_block.encode().as_ref(), self.compressor.as_ref());
 
             let data_block_pointer = BlockPointer(offset_counter, buf.len());
-            let largest_key = data_block.largest_key().to_vec();
+            let largest_key = data_block.largest_key();
             let item = IndexBlockRecord {
                 largest_key,
                 data_block_pointer,
             };
             indexes_records.push(item);
 
-            file_obj.write(buf.as_ref())?;
-            offset_counter = offset_counter + buf.len();
+            file_obj.append(buf.as_ref())?;
+            offset_counter += buf.len();
         }
 
-        // Write index portion of SSTable
-        let mut index_block_builder = BlockBuilder::new();
-        let mut index_block_pointers: Vec<BlockPointer> = Vec::new();
-        for indexes_record in &indexes_records {
-            if index_block_builder
-                .add(
-                    indexes_record.largest_key.as_slice(),
-                    indexes_record.data_block_pointer.encode().as_ref(),
-                )
-                .is_err()
-            {
-                // current block is full. flush content of current block to disk
-                let buf = index_block_builder.build().encode();
-                let ret = file_obj.write(buf.as_ref())?;
-
-                let index_block_pointer = BlockPointer(offset_counter, buf.len());
-                offset_counter = offset_counter + buf.len();
-                index_block_pointers.push(index_block_pointer);
-
-                // start new block
-                index_block_builder.clean_up();
-                let ret = index_block_builder.add(
-                    indexes_record.largest_key.as_slice(),
-                    indexes_record.data_block_pointer.encode().as_ref(),
-                )?;
+        // Write meta portion of SSTable, the range deletion block is only written if there
+        // is any range tombstone. The meta index records are sorted by the block names.
+        let mut meta_index_records: Vec<(&[u8], Vec<u8>)> = Vec::new();
+        let mut prefix_filter = None;
+        let prefix_filter_name = self
+            .options
+            .prefix_extractor
+            .as_deref()
+            .map(prefix_filter_block_name);
+        if let Some(name) = prefix_filter_name.as_ref() {
+            if self.options.prefix_bloom_bits_per_key > 0 {
+                let filter = build_bloom_filter(
+                    self.prefix_hashes.as_slice(),
+                    self.options.prefix_bloom_bits_per_key,
+                );
+                let buf = encode_block_with_trailer(filter.as_slice(), &NoCompressor {});
+                file_obj.append(buf.as_ref())?;
+                let pointer = BlockPointer(offset_counter, buf.len());
+                offset_counter += buf.len();
+                meta_index_records.push((name.as_bytes(), pointer.encode().to_vec()));
+                prefix_filter = Some(filter);
             }
         }
-        // finish the last block
-        let buf = index_block_builder.build().encode();
-        let ret = file_obj.write(buf.as_ref())?;
-        let index_block_pointer = BlockPointer(offset_counter, buf.len());
-        index_block_pointers.push(index_block_pointer);
+        if !self.range_tombstones.is_empty() {
+            let blocks = Self::build_blocks(
+                self.range_tombstones
+                    .iter()
+                    .map(|x| (x.start.as_ref(), x.encode_value())),
+            )?;
+            // every range deletion block is recorded by the meta index under the same name
+            let pointers = Self::write_raw_blocks(file_obj.as_mut(), &mut offset_counter, &blocks)?;
+            for pointer in pointers {
+                meta_index_records
+                    .push((RANGE_DEL_BLOCK_NAME.as_bytes(), pointer.encode().to_vec()));
+            }
+        }
+        let meta_index_block = Self::build_blocks(meta_index_records)?;
+        let meta_index_block_pointer =
+            Self::write_raw_blocks(file_obj.as_mut(), &mut offset_counter, &meta_index_block)?
+                .remove(0);
+
+        // Write index portion of SSTable
+        let index_blocks = Self::build_blocks(indexes_records.iter().map(|x| {
+            (
+                x.largest_key.as_slice(),
+                x.data_block_pointer.encode().to_vec(),
+            )
+        }))?;
+        let index_block_pointers =
+            Self::write_raw_blocks(file_obj.as_mut(), &mut offset_counter, &index_blocks)?;
 
         // Write Footer
         let footer = Footer {
+            meta_index_block_pointer,
             num_of_index_block: index_block_pointers.len(),
             index_block_pointers,
         };
         let buf = footer.encode();
-        let ret = file_obj.write(buf.as_ref())?;
-        Ok(SSTable {
-            file_object: file_obj,
+        file_obj.append(buf.as_ref())?;
+        file_obj.sync()?;
+        drop(file_obj);
+        rename_and_sync(env, temp_path.as_str(), path)?;
+
+        let smallest_key = self
+            .data_blocks
+            .first()
+            .map(|x| x.smallest_key())
+            .unwrap_or_default();
+        let largest_key = indexes_records
+            .last()
+            .map(|x| x.largest_key.clone())
+            .unwrap_or_default();
+        let mut sstable = SSTable {
+            file_object: env.new_random_access_file(path)?,
             indexes: indexes_records,
-            seq: seq as usize,
-        })
-    }
-    fn evaluate_sstable_size(&self) -> usize {
-        todo!()
+            seq,
+            options: self.options.clone(),
+            range_tombstones: self.range_tombstones.clone(),
+            smallest_key: Vec::new(),
+            largest_key: Vec::new(),
+            cache_id: new_cache_id(),
+            prefix_filter,
+            global_seq: None,
+        };
+        sstable.set_key_range(smallest_key, largest_key);
+        Ok(sstable)
     }
 }
-
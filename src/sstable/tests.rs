@@ -1,11 +1,20 @@
+use crate::compression::{Compressor, LzCompressor, NoCompressor};
+use crate::db::format::RangeTombstone;
 use crate::iterator::Iterator;
+use crate::options::{self, Options, ReadOptions};
+use crate::slice_transform::FixedPrefixTransform;
+use crate::sstable::block_cache::BlockCache;
 use crate::sstable::iterator::SSTableRecordIterator;
 use crate::sstable::sstable_builder::SSTableBuilder;
 use crate::sstable::{BlockPointer, Footer, SSTable};
-use crate::util::env::sstfile_path;
-use std::fs;
+use crate::util::env::{read_file, sstfile_path, test_env};
+use crate::util::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
+const TEST_DIR: &str = "/summer_kv_test/sstable";
+static NEXT_TABLE_NUMBER: AtomicUsize = AtomicUsize::new(1);
+
 struct TestSSTable {
     sstable: SSTable,
     builder: SSTableBuilder,
@@ -13,16 +22,19 @@ struct TestSSTable {
 }
 impl TestSSTable {
     pub fn create_for_test(number: u16) -> Self {
-        let mut builder = SSTableBuilder::new();
+        Self::create_with_values(number, 0, |i| format!("value_{}", i + 1).into_bytes())
+    }
+    pub fn create_with_values<F>(number: u16, level: usize, value_of: F) -> Self
+    where
+        F: Fn(u16) -> Vec<u8>,
+    {
+        let mut builder = SSTableBuilder::new(test_options(), level);
         for i in 0..number {
             builder
-                .add(
-                    format!("key_{}", i + 1).as_bytes(),
-                    format!("value_{}", i + 1).as_bytes(),
-                )
+                .add(format!("key_{}", i + 1).as_bytes(), value_of(i).as_slice())
                 .unwrap();
         }
-        let sstable = builder.build().unwrap();
+        let sstable = builder.build(TEST_DIR, new_table_number()).unwrap();
         TestSSTable {
             sstable,
             builder,
@@ -30,9 +42,26 @@ impl TestSSTable {
         }
     }
 }
+/// Every test creates its own files, so that the tests are able to run in parallel
+fn new_table_number() -> usize {
+    test_env().create_dir_all(TEST_DIR).expect("Testing expect");
+    let number = NEXT_TABLE_NUMBER.fetch_add(1, Ordering::SeqCst);
+    let _ = test_env().remove_file(sstfile_path(TEST_DIR, number).as_str());
+    number
+}
+fn test_options() -> Arc<Options> {
+    let compression_per_level: Vec<Arc<dyn Compressor>> =
+        vec![Arc::new(NoCompressor {}), Arc::new(LzCompressor {})];
+    Arc::new(Options {
+        compression_per_level,
+        ..options::test_options()
+    })
+}
 impl Drop for TestSSTable {
     fn drop(&mut self) {
-        fs::remove_file(sstfile_path(self.sstable.seq)).expect("Testing expect");
+        test_env()
+            .remove_file(sstfile_path(TEST_DIR, self.sstable.seq).as_str())
+            .expect("Testing expect");
     }
 }
 
@@ -43,6 +72,7 @@ fn test_footer_encode_and_decode() {
         block_pointer_vec.push(BlockPointer(i, i));
     }
     let footer = Footer {
+        meta_index_block_pointer: BlockPointer(7, 7),
         num_of_index_block: block_pointer_vec.len(),
         index_block_pointers: block_pointer_vec,
     };
@@ -50,7 +80,11 @@ fn test_footer_encode_and_decode() {
     let buf = footer.encode();
 
     // decode the footer
-    let decode_footer = Footer::decode(buf.as_ref());
+    let decode_footer = Footer::decode(buf.as_ref()).expect("Testing expect");
+    assert_eq!(
+        footer.meta_index_block_pointer,
+        decode_footer.meta_index_block_pointer
+    );
     for i in 0..decode_footer.num_of_index_block {
         assert_eq!(
             footer.index_block_pointers[i],
@@ -70,17 +104,17 @@ fn test_build_sstable_multi_records() {
 #[test]
 fn test_open_exists_sstable() {
     let test_sstable = TestSSTable::create_for_test(10);
-    SSTable::open(test_sstable.sstable.seq).unwrap();
+    SSTable::open(TEST_DIR, test_sstable.sstable.seq, test_options()).unwrap();
 }
 #[test]
 fn test_open_non_exists_sstable() {
-    let ret = SSTable::open(2);
+    let ret = SSTable::open(TEST_DIR, 100000, test_options());
     assert!(ret.is_err());
 }
 #[test]
 fn test_sstable_iterator() {
     let test_sstable = TestSSTable::create_for_test(100);
-    let sstable = SSTable::open(test_sstable.sstable.seq).unwrap();
+    let sstable = SSTable::open(TEST_DIR, test_sstable.sstable.seq, test_options()).unwrap();
     let mut sstable_iter = SSTableRecordIterator::new(Arc::new(sstable));
     sstable_iter.seek_to_first();
     assert_eq!(sstable_iter.key(), b"key_1".as_slice());
@@ -96,7 +130,7 @@ fn test_sstable_iterator() {
 #[test]
 fn test_sstable_seek() {
     let test_sstable = TestSSTable::create_for_test(99);
-    let sstable = SSTable::open(test_sstable.sstable.seq).unwrap();
+    let sstable = SSTable::open(TEST_DIR, test_sstable.sstable.seq, test_options()).unwrap();
     let mut sstable_iter = SSTableRecordIterator::new(Arc::new(sstable));
     sstable_iter.seek_to_first();
     assert_eq!(sstable_iter.key(), b"key_1".as_slice());
@@ -107,3 +141,345 @@ fn test_sstable_seek() {
     sstable_iter.seek_to_key("key_not_exists".as_bytes());
     assert!(!sstable_iter.is_valid());
 }
+#[test]
+fn test_open_corrupted_sstable() {
+    let test_sstable = TestSSTable::create_for_test(10);
+    // append garbage so that the footer can not be decoded any more
+    let mut file_obj = test_env()
+        .new_appendable_file(sstfile_path(TEST_DIR, test_sstable.sstable.seq).as_str())
+        .unwrap();
+    file_obj.append(&[0xff, 0xff]).unwrap();
+    match SSTable::open(TEST_DIR, test_sstable.sstable.seq, test_options()) {
+        Err(Error::Corruption { file, .. }) => {
+            assert_eq!(file, sstfile_path(TEST_DIR, test_sstable.sstable.seq))
+        }
+        _ => panic!("expect corruption error"),
+    }
+}
+
+#[test]
+fn test_compressed_sstable() {
+    let value_of = |i: u16| format!("tenant/42/user/{:06}/value", i).into_bytes();
+    let plain = TestSSTable::create_with_values(1000, 0, value_of);
+    let compressed = TestSSTable::create_with_values(1000, 1, value_of);
+    let plain_size = plain.sstable.file_object.size().unwrap();
+    let compressed_size = compressed.sstable.file_object.size().unwrap();
+    assert!(compressed_size * 2 < plain_size);
+
+    let sstable = SSTable::open(TEST_DIR, compressed.sstable.seq, test_options()).unwrap();
+    let mut sstable_iter = SSTableRecordIterator::new(Arc::new(sstable));
+    sstable_iter.seek_to_first();
+    for i in 0..1000 {
+        assert!(sstable_iter.is_valid());
+        assert_eq!(sstable_iter.value(), value_of(i).as_slice());
+        sstable_iter.next();
+    }
+    assert!(!sstable_iter.is_valid());
+    assert!(sstable_iter.status().is_ok());
+}
+
+#[test]
+fn test_incompressible_sstable() {
+    // values made of pseudo random bytes can not be compressed, they are stored as is
+    let value_of = |i: u16| {
+        let mut state = i as u32 + 1;
+        (0..64)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<u8>>()
+    };
+    let plain = TestSSTable::create_with_values(200, 0, value_of);
+    let compressed = TestSSTable::create_with_values(200, 1, value_of);
+    assert_eq!(
+        plain.sstable.file_object.size().unwrap(),
+        compressed.sstable.file_object.size().unwrap()
+    );
+    let sstable = SSTable::open(TEST_DIR, compressed.sstable.seq, test_options()).unwrap();
+    let mut sstable_iter = SSTableRecordIterator::new(Arc::new(sstable));
+    sstable_iter.seek_to_key(b"key_57");
+    assert_eq!(sstable_iter.value(), value_of(56).as_slice());
+}
+
+#[test]
+fn test_sstable_range_tombstones() {
+    let mut builder = SSTableBuilder::new(test_options(), 0);
+    builder.add(b"key_b", b"value_b").unwrap();
+    builder.add(b"key_c", b"value_c").unwrap();
+    builder.add_range_tombstone(RangeTombstone::new(b"key_c", b"key_z", 9));
+    builder.add_range_tombstone(RangeTombstone::new(b"key_a", b"key_b", 7));
+    let number = new_table_number();
+    let built = builder.build(TEST_DIR, number).unwrap();
+    let test_sstable = TestSSTable {
+        sstable: built,
+        builder,
+        record_num: 2,
+    };
+
+    let sstable = SSTable::open(TEST_DIR, number, test_options()).unwrap();
+    assert_eq!(sstable.smallest_key(), b"key_a".as_slice());
+    assert_eq!(sstable.largest_key(), b"key_z".as_slice());
+    let tombstones: Vec<RangeTombstone> = sstable.range_tombstones().iter().cloned().collect();
+    assert_eq!(
+        tombstones,
+        vec![
+            RangeTombstone::new(b"key_a", b"key_b", 7),
+            RangeTombstone::new(b"key_c", b"key_z", 9)
+        ]
+    );
+    assert_eq!(sstable.get(b"key_c").unwrap(), Some("value_c".into()));
+    assert_eq!(sstable.get(b"key_d").unwrap(), None);
+}
+
+#[test]
+fn test_sstable_only_range_tombstones() {
+    let mut builder = SSTableBuilder::new(test_options(), 0);
+    builder.add_range_tombstone(RangeTombstone::new(b"a", b"b", 1));
+    let number = new_table_number();
+    let built = builder.build(TEST_DIR, number).unwrap();
+    let test_sstable = TestSSTable {
+        sstable: built,
+        builder,
+        record_num: 0,
+    };
+    let sstable = SSTable::open(TEST_DIR, number, test_options()).unwrap();
+    assert_eq!(sstable.range_tombstones().len(), 1);
+    let mut sstable_iter = SSTableRecordIterator::new(Arc::new(sstable));
+    sstable_iter.seek_to_first();
+    assert!(!sstable_iter.is_valid());
+}
+
+/// Build an SSTable of the records `key_0000` to `key_{number - 1}` whose values are
+/// `value_0000` and so on
+fn build_padded(number: usize, options: Arc<Options>) -> TestSSTable {
+    let mut builder = SSTableBuilder::new(options, 0);
+    for i in 0..number {
+        builder
+            .add(
+                format!("key_{:04}", i).as_bytes(),
+                format!("value_{:04}", i).as_bytes(),
+            )
+            .unwrap();
+    }
+    let sstable = builder.build(TEST_DIR, new_table_number()).unwrap();
+    TestSSTable {
+        sstable,
+        builder,
+        record_num: number as u16,
+    }
+}
+
+#[test]
+fn test_sstable_iterator_bounds() {
+    let block_cache = Arc::new(BlockCache::new(1024 * 1024));
+    let options = Arc::new(Options {
+        block_size: 512,
+        block_cache: Some(block_cache.clone()),
+        ..options::test_options()
+    });
+    let test_sstable = build_padded(1000, options.clone());
+    let sstable = Arc::new(SSTable::open(TEST_DIR, test_sstable.sstable.seq, options).unwrap());
+    assert!(sstable.indexes.len() > 10);
+    let read_options = Arc::new(ReadOptions {
+        iterate_lower_bound: Some(b"key_0500".to_vec()),
+        iterate_upper_bound: Some(b"key_0510".to_vec()),
+        ..ReadOptions::default()
+    });
+    let mut iter = SSTableRecordIterator::with_read_options(sstable.clone(), read_options);
+    let mut keys = Vec::new();
+    iter.seek_to_first();
+    while iter.is_valid() {
+        keys.push(iter.key().to_vec());
+        iter.next();
+    }
+    let expected: Vec<Vec<u8>> = (500..510)
+        .map(|i| format!("key_{:04}", i).into_bytes())
+        .collect();
+    assert_eq!(keys, expected);
+
+    let mut keys = Vec::new();
+    iter.seek_to_last();
+    while iter.is_valid() {
+        keys.push(iter.key().to_vec());
+        iter.prev();
+    }
+    keys.reverse();
+    assert_eq!(keys, expected);
+
+    iter.seek_to_key(b"key_0100");
+    assert_eq!(iter.key(), b"key_0500");
+    iter.seek_to_key(b"key_0600");
+    assert!(!iter.is_valid());
+    assert!(iter.status().is_ok());
+    // only the first block read on open and the blocks around the bounds are read
+    let usage = block_cache.usage() as u64;
+    let total: u64 = (0..sstable.indexes.len())
+        .map(|i| {
+            sstable
+                .read_data_block(i, &ReadOptions::default())
+                .unwrap()
+                .size()
+        })
+        .sum();
+    assert!(usage * 4 < total);
+}
+
+#[test]
+fn test_sstable_fill_cache() {
+    let block_cache = Arc::new(BlockCache::new(1024 * 1024));
+    let options = Arc::new(Options {
+        block_cache: Some(block_cache.clone()),
+        ..options::test_options()
+    });
+    let test_sstable = build_padded(1000, options.clone());
+    let sstable = Arc::new(SSTable::open(TEST_DIR, test_sstable.sstable.seq, options).unwrap());
+    let usage = block_cache.usage();
+    let read_options = Arc::new(ReadOptions {
+        fill_cache: false,
+        ..ReadOptions::default()
+    });
+    let mut iter = SSTableRecordIterator::with_read_options(sstable.clone(), read_options);
+    iter.seek_to_first();
+    while iter.is_valid() {
+        iter.next();
+    }
+    assert_eq!(block_cache.usage(), usage);
+
+    let mut iter = SSTableRecordIterator::new(sstable.clone());
+    iter.seek_to_first();
+    while iter.is_valid() {
+        iter.next();
+    }
+    let total: u64 = (0..sstable.indexes.len())
+        .map(|i| {
+            sstable
+                .read_data_block(i, &ReadOptions::default())
+                .unwrap()
+                .size()
+        })
+        .sum();
+    assert_eq!(block_cache.usage() as u64, total);
+}
+
+#[test]
+fn test_sstable_checksum_mismatch() {
+    let options = Arc::new(Options {
+        block_cache: None,
+        ..options::test_options()
+    });
+    let test_sstable = build_padded(1000, options.clone());
+    let seq = test_sstable.sstable.seq;
+    // flip a byte of the first value of the second data block, the block still decodes
+    let pointer = &test_sstable.sstable.indexes[1].data_block_pointer;
+    let offset = pointer.0 + 3 + b"key_0000".len() + 1;
+    let path = sstfile_path(TEST_DIR, seq);
+    let env = test_env();
+    let mut content = read_file(env.as_ref(), path.as_str()).unwrap();
+    content[offset] ^= 0x01;
+    env.remove_file(path.as_str()).unwrap();
+    let mut file_obj = env.new_writable_file(path.as_str()).unwrap();
+    file_obj.append(content.as_slice()).unwrap();
+
+    let sstable = Arc::new(SSTable::open(TEST_DIR, seq, options).unwrap());
+    let mut iter = SSTableRecordIterator::new(sstable.clone());
+    iter.seek_to_first();
+    while iter.is_valid() {
+        iter.next();
+    }
+    match iter.status() {
+        Err(Error::Corruption { file, offset }) => {
+            assert_eq!(file, path);
+            assert_eq!(offset, pointer.0 as u64);
+        }
+        _ => panic!("expect corruption error"),
+    }
+
+    let read_options = Arc::new(ReadOptions {
+        verify_checksums: false,
+        ..ReadOptions::default()
+    });
+    let mut iter = SSTableRecordIterator::with_read_options(sstable, read_options);
+    let mut changed = 0;
+    iter.seek_to_first();
+    while iter.is_valid() {
+        if !iter.value().starts_with(b"value_") {
+            changed += 1;
+        }
+        iter.next();
+    }
+    assert!(iter.status().is_ok());
+    assert_eq!(changed, 1);
+}
+
+#[test]
+fn test_sstable_prefix_filter() {
+    let options = Arc::new(Options {
+        prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(6))),
+        ..options::test_options()
+    });
+    let test_sstable = build_padded(1000, options.clone());
+    let seq = test_sstable.sstable.seq;
+    for sstable in [
+        &test_sstable.sstable,
+        &SSTable::open(TEST_DIR, seq, options).unwrap(),
+    ] {
+        assert!((0..10).all(|i| sstable.may_contain_prefix(format!("key_0{}", i).as_bytes())));
+        let false_positives = (0..100)
+            .filter(|i| sstable.may_contain_prefix(format!("zzz_{:02}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 20);
+        assert!(sstable.may_contain_key(b"key_0123"));
+        assert!(!sstable.may_contain_key(b"zzz_00"));
+        // the keys out of the domain of the extractor may be anywhere
+        assert!(sstable.may_contain_key(b"zzz"));
+    }
+
+    // the filter built by another extractor is ignored
+    let options = Arc::new(Options {
+        prefix_extractor: Some(Arc::new(FixedPrefixTransform::new(4))),
+        ..options::test_options()
+    });
+    let sstable = SSTable::open(TEST_DIR, seq, options).unwrap();
+    assert!(sstable.prefix_filter.is_none());
+    assert!(sstable.may_contain_key(b"zzz_00"));
+    assert_eq!(sstable.get(b"key_0123").unwrap(), Some("value_0123".into()));
+}
+
+#[test]
+fn test_sstable_multi_get() {
+    let options = Arc::new(Options {
+        block_size: 512,
+        ..options::test_options()
+    });
+    let test_sstable = build_padded(1000, options);
+    let sstable = &test_sstable.sstable;
+    assert!(sstable.indexes.len() > 10);
+    let keys: Vec<Vec<u8>> = [
+        "a",
+        "key_0000",
+        "key_0001",
+        "key_0001",
+        "key_0100",
+        "key_01000",
+        "key_0500",
+        "key_0999",
+        "zzz",
+    ]
+    .iter()
+    .map(|x| x.as_bytes().to_vec())
+    .collect();
+    let keys: Vec<&[u8]> = keys.iter().map(|x| x.as_slice()).collect();
+    let values = sstable.multi_get(keys.as_slice()).unwrap();
+    assert_eq!(values.len(), keys.len());
+    for (key, value) in keys.iter().zip(values) {
+        assert_eq!(value, sstable.get(key).unwrap());
+    }
+    assert_eq!(
+        sstable.multi_get(&[b"key_0001", b"key_0999"]).unwrap(),
+        vec![Some("value_0001".into()), Some("value_0999".into())]
+    );
+    assert!(sstable.multi_get(&[]).unwrap().is_empty());
+}
@@ -1,4 +1,7 @@
 pub mod error;
 pub mod env;
+pub mod bloom;
+pub mod coding;
+pub mod crc32;
 
-
+pub mod clock;
@@ -0,0 +1,97 @@
+/// Return the hash of `data` the bloom filters are built with
+pub fn bloom_hash(data: &[u8]) -> u32 {
+    const SEED: u32 = 0xbc9f_1d34;
+    const M: u32 = 0xc6a4_a793;
+    let mut h = SEED ^ (data.len() as u32).wrapping_mul(M);
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        h = h.wrapping_add(u32::from_le_bytes(chunk.try_into().unwrap()));
+        h = h.wrapping_mul(M);
+        h ^= h >> 16;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, byte) in rest.iter().enumerate() {
+            h = h.wrapping_add((*byte as u32) << (8 * i));
+        }
+        h = h.wrapping_mul(M);
+        h ^= h >> 24;
+    }
+    h
+}
+
+/// Build a bloom filter of the keys whose `bloom_hash` are `hashes`, with about
+/// `bits_per_key` bits for every key. The last byte of the filter is the number of probes.
+///
+/// 10 bits per key give about 1% false positives.
+pub fn build_bloom_filter(hashes: &[u32], bits_per_key: usize) -> Vec<u8> {
+    // ln(2) * bits_per_key probes minimize the false positives
+    let probes = (bits_per_key * 69 / 100).clamp(1, 30);
+    // a tiny filter has a high false positive rate, keep a minimum length
+    let bits = (hashes.len() * bits_per_key).max(64);
+    let bytes = bits.div_ceil(8);
+    let bits = bytes * 8;
+    let mut filter = vec![0u8; bytes + 1];
+    for hash in hashes {
+        // double hashing, the probes are `hash + i * delta`
+        let mut h = *hash;
+        let delta = h.rotate_right(17);
+        for _ in 0..probes {
+            let position = h as usize % bits;
+            filter[position / 8] |= 1 << (position % 8);
+            h = h.wrapping_add(delta);
+        }
+    }
+    filter[bytes] = probes as u8;
+    filter
+}
+
+/// Whether the key whose `bloom_hash` is `hash` may be in the `filter` built by
+/// `build_bloom_filter`. `false` means the key is certainly not in it.
+pub fn bloom_may_match(filter: &[u8], hash: u32) -> bool {
+    if filter.len() < 2 {
+        return true;
+    }
+    let bits = (filter.len() - 1) * 8;
+    let probes = filter[filter.len() - 1];
+    if probes > 30 {
+        // reserved for other filter formats
+        return true;
+    }
+    let mut h = hash;
+    let delta = h.rotate_right(17);
+    for _ in 0..probes {
+        let position = h as usize % bits;
+        if filter[position / 8] & (1 << (position % 8)) == 0 {
+            return false;
+        }
+        h = h.wrapping_add(delta);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::bloom::{bloom_hash, bloom_may_match, build_bloom_filter};
+
+    #[test]
+    fn test_bloom_filter() {
+        let hashes: Vec<u32> = (0..10000u32)
+            .map(|i| bloom_hash(format!("prefix_{}", i).as_bytes()))
+            .collect();
+        let filter = build_bloom_filter(hashes.as_slice(), 10);
+        assert!(hashes
+            .iter()
+            .all(|x| bloom_may_match(filter.as_slice(), *x)));
+        let false_positives = (10000..20000u32)
+            .filter(|i| {
+                let hash = bloom_hash(format!("prefix_{}", i).as_bytes());
+                bloom_may_match(filter.as_slice(), hash)
+            })
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+
+        let empty = build_bloom_filter(&[], 10);
+        assert!(!bloom_may_match(empty.as_slice(), bloom_hash(b"prefix")));
+    }
+}
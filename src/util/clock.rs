@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `Clock` tells the current time, it decides when the records written by
+/// `DB::put_with_ttl` expire
+pub trait Clock: Send + Sync {
+    /// milliseconds since the unix epoch
+    fn now_millis(&self) -> u64;
+}
+
+/// `SystemClock` reads the time of the operating system
+pub struct SystemClock {}
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// `ManualClock` only moves when it is told to, so that tests are able to control the time
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now_millis: u64) -> Self {
+        ManualClock {
+            now: AtomicU64::new(now_millis),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now
+            .fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
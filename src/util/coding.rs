@@ -0,0 +1,131 @@
+use bytes::BufMut;
+
+/// the longest encoded length of a `u32` varint
+pub const MAX_VARINT32_LENGTH: usize = 5;
+/// the longest encoded length of a `u64` varint
+pub const MAX_VARINT64_LENGTH: usize = 10;
+
+/// Append `value` to `buf` in the LEB128 varint format, every byte carries 7 bits of the value
+/// from the lowest bits and the highest bit marks whether more bytes follow
+pub fn put_varint32(buf: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buf.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Decode a varint from the beginning of `buf`, return the value and the number of bytes
+/// consumed, `None` if `buf` ends in the middle of the varint or the varint overflows `u32`
+pub fn get_varint32(buf: &[u8]) -> Option<(u32, usize)> {
+    let mut value: u32 = 0;
+    for (i, byte) in buf.iter().take(MAX_VARINT32_LENGTH).enumerate() {
+        let bits = (*byte & 0x7f) as u32;
+        if i == MAX_VARINT32_LENGTH - 1 && bits > 0x0f {
+            return None;
+        }
+        value |= bits << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// return the number of bytes `value` takes in the varint format
+pub fn varint32_length(mut value: u32) -> usize {
+    let mut length = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        length += 1;
+    }
+    length
+}
+
+/// `u64` version of `put_varint32`
+pub fn put_varint64(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// `u64` version of `get_varint32`
+pub fn get_varint64(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+    for (i, byte) in buf.iter().take(MAX_VARINT64_LENGTH).enumerate() {
+        let bits = (*byte & 0x7f) as u64;
+        if i == MAX_VARINT64_LENGTH - 1 && bits > 0x01 {
+            return None;
+        }
+        value |= bits << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Append `value` prefixed with its length in varint
+pub fn put_length_prefixed_slice(buf: &mut Vec<u8>, value: &[u8]) {
+    put_varint32(buf, value.len() as u32);
+    buf.extend_from_slice(value);
+}
+
+/// Decode a slice encoded by `put_length_prefixed_slice` from the beginning of `buf`, return
+/// the slice and the number of bytes consumed
+pub fn get_length_prefixed_slice(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let (length, consumed) = get_varint32(buf)?;
+    let value = buf.get(consumed..consumed + length as usize)?;
+    Some((value, consumed + length as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::coding::{
+        get_length_prefixed_slice, get_varint32, get_varint64, put_length_prefixed_slice,
+        put_varint32, put_varint64, varint32_length,
+    };
+
+    #[test]
+    fn test_varint32() {
+        let values = [0, 1, 127, 128, 300, 16383, 16384, u16::MAX as u32, u32::MAX];
+        let mut buf = Vec::new();
+        for value in values {
+            put_varint32(&mut buf, value);
+        }
+        let mut offset = 0;
+        for value in values {
+            let (decoded, length) = get_varint32(&buf[offset..]).unwrap();
+            assert_eq!(decoded, value);
+            assert_eq!(length, varint32_length(value));
+            offset += length;
+        }
+        assert_eq!(offset, buf.len());
+        // truncated varint
+        assert!(get_varint32(&[0x80, 0x80]).is_none());
+        // overflowed varint
+        assert!(get_varint32(&[0xff, 0xff, 0xff, 0xff, 0x7f]).is_none());
+    }
+
+    #[test]
+    fn test_varint64_and_slice() {
+        let values = [0, 300, u32::MAX as u64 + 1, u64::MAX];
+        let mut buf = Vec::new();
+        for value in values {
+            put_varint64(&mut buf, value);
+        }
+        put_length_prefixed_slice(&mut buf, b"summer_kv");
+        let mut offset = 0;
+        for value in values {
+            let (decoded, length) = get_varint64(&buf[offset..]).unwrap();
+            assert_eq!(decoded, value);
+            offset += length;
+        }
+        let (slice, length) = get_length_prefixed_slice(&buf[offset..]).unwrap();
+        assert_eq!(slice, b"summer_kv");
+        assert_eq!(offset + length, buf.len());
+        assert!(get_length_prefixed_slice(&buf[offset..buf.len() - 1]).is_none());
+    }
+}
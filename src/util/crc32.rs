@@ -0,0 +1,51 @@
+/// The reversed polynomial of CRC-32C (Castagnoli)
+const POLYNOMIAL: u32 = 0x82f6_3b78;
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Return the CRC-32C of `data`
+pub fn value(data: &[u8]) -> u32 {
+    extend(0, data)
+}
+
+/// Return the CRC-32C of the concatenation of the data whose CRC-32C is `crc` and `data`
+pub fn extend(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc = TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::crc32::{extend, value};
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(value(b""), 0);
+        assert_eq!(value(b"123456789"), 0xe306_9283);
+        assert_eq!(value(&[0u8; 32]), 0x8a91_36aa);
+        assert_eq!(extend(value(b"1234"), b"56789"), value(b"123456789"));
+    }
+}
@@ -1,164 +1,777 @@
-use anyhow::Result;
+use crate::util::error::{Error, Result};
 use bytes::{Bytes, BytesMut};
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Read, Write};
 use std::os::unix::fs::FileExt;
+use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 static GLOBAL_SEQUENCE_NUMBER: AtomicU64 = AtomicU64::new(0);
-pub struct FileObject {
-    file_handler: File,
+
+/// `Env` is the file system the database lives in, every file of the database is read and
+/// written through it, see `Options::env`
+pub trait Env: Send + Sync {
+    /// Open the file at `path` to be read from the beginning to the end
+    fn new_sequential_file(&self, path: &str) -> Result<Box<dyn SequentialFile>>;
+
+    /// Open the file at `path` to be read at any offset
+    fn new_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>>;
+
+    /// Create a new file at `path` to be written.
+    /// Failed if the file exists
+    fn new_writable_file(&self, path: &str) -> Result<Box<dyn WritableFile>>;
+
+    /// Open the file at `path` to append to its content.
+    /// Failed if the file does not exist
+    fn new_appendable_file(&self, path: &str) -> Result<Box<dyn WritableFile>>;
+
+    /// Whether there is a file or a directory at `path`
+    fn file_exists(&self, path: &str) -> bool;
+
+    /// Return the names of the files and the directories in directory `dir`
+    fn get_children(&self, dir: &str) -> Result<Vec<String>>;
+
+    /// Create directory `dir` along with its missing parents
+    fn create_dir_all(&self, dir: &str) -> Result<()>;
+
+    fn remove_file(&self, path: &str) -> Result<()>;
+
+    /// Remove directory `dir` along with everything in it
+    fn remove_dir_all(&self, dir: &str) -> Result<()>;
+
+    /// Rename the file `from` to `to` atomically, a file at `to` is replaced
+    fn rename_file(&self, from: &str, to: &str) -> Result<()>;
+
+    /// Hard link the file `from` to `to`.
+    /// Failed if there is a file at `to`, or the files can not be linked such as when they
+    /// are on different file systems
+    fn link_file(&self, from: &str, to: &str) -> Result<()>;
+
+    /// Sync directory `dir`, so that the files created, renamed or removed in it survive a
+    /// crash
+    fn sync_dir(&self, dir: &str) -> Result<()>;
+
+    /// Lock the file at `path` exclusively until the returned lock is dropped, the file is
+    /// created if it does not exist.
+    /// Failed with `Busy` if the file is locked by others, including the other locks taken
+    /// by this process
+    fn lock_file(&self, path: &str) -> Result<Box<dyn FileLock>>;
+}
+
+/// `SequentialFile` reads a file from the beginning to the end
+pub trait SequentialFile: Send + Sync {
+    /// Read the next bytes of the file into `buf`, return the number of bytes read which is
+    /// 0 at the end of the file
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+}
+
+/// `RandomAccessFile` reads a file at any offset, the reads may be issued concurrently
+pub trait RandomAccessFile: Send + Sync {
+    /// Read content from file from `offset` by `length` long.
+    /// Failed if the file ends before
+    fn read_at(&self, offset: u64, length: usize) -> Result<Bytes>;
+
+    // Return the current size of the file
+    fn size(&self) -> Result<u64>;
+
+    fn path(&self) -> &str;
+
+    /// Read Last length bytes of file content
+    /// Failed with `Corruption` if the file is shorter than `length`
+    fn read_last_of(&self, length: usize) -> Result<Bytes> {
+        let size = self.size()?;
+        let offset = size
+            .checked_sub(length as u64)
+            .ok_or_else(|| Error::corruption(self.path(), 0))?;
+        self.read_at(offset, length)
+    }
+}
+
+/// `WritableFile` appends to a file
+pub trait WritableFile: Send + Sync {
+    fn append(&mut self, buf: &[u8]) -> Result<()>;
+
+    // Do fsync(), flush data to disk
+    fn sync(&mut self) -> Result<()>;
+
+    fn path(&self) -> &str;
+}
+
+/// `FileLock` is an exclusive lock taken by `Env::lock_file`, the lock is released when
+/// dropped
+pub trait FileLock: Send + Sync {}
+
+/// `PosixEnv` is the file system of the operating system
+pub struct PosixEnv {}
+
+struct PosixSequentialFile {
+    file: File,
+}
+
+impl SequentialFile for PosixSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        Ok(self.file.read(buf)?)
+    }
+}
+
+struct PosixRandomAccessFile {
+    file: File,
+    path: String,
+}
+
+impl RandomAccessFile for PosixRandomAccessFile {
+    fn read_at(&self, offset: u64, length: usize) -> Result<Bytes> {
+        let mut buf = BytesMut::zeroed(length);
+        self.file.read_exact_at(buf.as_mut(), offset)?;
+        Ok(buf.freeze())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn path(&self) -> &str {
+        self.path.as_str()
+    }
+}
+
+struct PosixWritableFile {
+    file: File,
+    path: String,
+}
+
+impl WritableFile for PosixWritableFile {
+    fn append(&mut self, buf: &[u8]) -> Result<()> {
+        self.file.write_all(buf)?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    fn path(&self) -> &str {
+        self.path.as_str()
+    }
+}
+
+/// The `flock` on a file, released when the file is closed or the process exits
+struct PosixFileLock {
+    file: File,
 }
-impl FileObject {
-    /// Create a new FileObject by a given file name.
-    /// Failed if file exists.
-    pub fn create(path: &str) -> Result<Self> {
+
+impl FileLock for PosixFileLock {}
+
+impl Env for PosixEnv {
+    fn new_sequential_file(&self, path: &str) -> Result<Box<dyn SequentialFile>> {
+        Ok(Box::new(PosixSequentialFile {
+            file: File::open(path)?,
+        }))
+    }
+
+    fn new_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>> {
+        Ok(Box::new(PosixRandomAccessFile {
+            file: File::open(path)?,
+            path: path.to_string(),
+        }))
+    }
+
+    /// The directory is synced as well, so that the file survives a crash
+    fn new_writable_file(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        sync_parent_dir(self, path)?;
+        Ok(Box::new(PosixWritableFile {
+            file,
+            path: path.to_string(),
+        }))
+    }
+
+    fn new_appendable_file(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        Ok(Box::new(PosixWritableFile {
+            file: OpenOptions::new().append(true).open(path)?,
+            path: path.to_string(),
+        }))
+    }
+
+    fn file_exists(&self, path: &str) -> bool {
+        Path::new(path).exists()
+    }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> {
+        let mut children = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            if let Ok(name) = entry?.file_name().into_string() {
+                children.push(name);
+            }
+        }
+        Ok(children)
+    }
+
+    fn create_dir_all(&self, dir: &str) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> Result<()> {
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, dir: &str) -> Result<()> {
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    fn rename_file(&self, from: &str, to: &str) -> Result<()> {
+        std::fs::rename(from, to)?;
+        Ok(())
+    }
+
+    fn link_file(&self, from: &str, to: &str) -> Result<()> {
+        std::fs::hard_link(from, to)?;
+        Ok(())
+    }
+
+    fn sync_dir(&self, dir: &str) -> Result<()> {
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    fn lock_file(&self, path: &str) -> Result<Box<dyn FileLock>> {
         let file = OpenOptions::new()
             .read(true)
             .write(true)
-            .create_new(true)
+            .create(true)
+            .truncate(false)
             .open(path)?;
-        Ok(FileObject { file_handler: file })
+        match file.try_lock() {
+            Ok(()) => Ok(Box::new(PosixFileLock { file })),
+            Err(TryLockError::WouldBlock) => Err(locked_error(path)),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
     }
+}
 
-    /// open a new FileObject by a given file name.
-    /// Failed if file not exists.
-    pub fn open(path: &str) -> Result<Self> {
-        let file_handler = OpenOptions::new().read(true).write(true).open(path)?;
-        let size = file_handler.metadata()?.len();
-        Ok(FileObject { file_handler })
+/// `MemEnv` keeps the files in memory, the clones of a `MemEnv` share the same files. Nothing
+/// is lost by a crash of the database as long as the `MemEnv` is alive, so syncing does
+/// nothing.
+#[derive(Clone, Default)]
+pub struct MemEnv {
+    fs: Arc<Mutex<MemFileSystem>>,
+}
+
+#[derive(Default)]
+struct MemFileSystem {
+    /// the content of every file by path, the hard links of a file share its content
+    files: HashMap<String, Arc<RwLock<Vec<u8>>>>,
+    dirs: HashSet<String>,
+    /// the paths of the files locked by `Env::lock_file`
+    locks: HashSet<String>,
+}
+
+impl MemFileSystem {
+    /// The root and the current directory always exist
+    fn dir_exists(&self, dir: &str) -> bool {
+        dir.is_empty() || dir == "/" || dir == "." || self.dirs.contains(dir)
     }
 
-    pub fn read(&mut self, length: usize) -> Result<Bytes> {
-        let mut buf = BytesMut::zeroed(length);
-        let read_size = self.file_handler.read(buf.as_mut())?;
-        if read_size == 0 {
-            return Err(anyhow::Error::msg::<String>("EOF reached".to_string()));
+    fn file(&self, path: &str) -> Result<Arc<RwLock<Vec<u8>>>> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| io_error(ErrorKind::NotFound, path))
+    }
+
+    /// Failed with `NotFound` if the directory to hold `path` does not exist, or
+    /// `AlreadyExists` if there is a file or a directory at `path` already
+    fn check_new_path(&self, path: &str) -> Result<()> {
+        if !self.dir_exists(parent_dir(path)) {
+            return Err(io_error(ErrorKind::NotFound, path));
         }
-        Ok(buf.freeze())
+        if self.files.contains_key(path) || self.dir_exists(path) {
+            return Err(io_error(ErrorKind::AlreadyExists, path));
+        }
+        Ok(())
     }
+}
 
-    /// Read content from file from `offset` by `length` long
-    pub fn read_at(&self, offset: u64, length: usize) -> Result<Bytes> {
-        let mut buf = BytesMut::zeroed(length);
-        self.file_handler.read_exact_at(buf.as_mut(), offset)?;
-        Ok(buf.freeze())
+struct MemSequentialFile {
+    content: Arc<RwLock<Vec<u8>>>,
+    offset: usize,
+}
+
+impl SequentialFile for MemSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let content = self.content.read().unwrap();
+        let rest = content.get(self.offset..).unwrap_or_default();
+        let length = rest.len().min(buf.len());
+        buf[..length].copy_from_slice(&rest[..length]);
+        self.offset += length;
+        Ok(length)
     }
+}
 
-    /// Read Last length bytes of file content
-    pub fn read_last_of(&self, length: usize) -> Result<Bytes> {
-        let offset = self.file_handler.metadata()?.len() - length as u64;
-        self.read_at(offset, length)
+/// The file keeps being readable after it is removed or renamed, like an opened file of
+/// `PosixEnv`
+struct MemRandomAccessFile {
+    content: Arc<RwLock<Vec<u8>>>,
+    path: String,
+}
+
+impl RandomAccessFile for MemRandomAccessFile {
+    fn read_at(&self, offset: u64, length: usize) -> Result<Bytes> {
+        let content = self.content.read().unwrap();
+        let buf = usize::try_from(offset)
+            .ok()
+            .and_then(|x| content.get(x..x.checked_add(length)?))
+            .ok_or_else(|| io_error(ErrorKind::UnexpectedEof, self.path.as_str()))?;
+        Ok(Bytes::copy_from_slice(buf))
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.content.read().unwrap().len() as u64)
+    }
+
+    fn path(&self) -> &str {
+        self.path.as_str()
     }
+}
+
+struct MemWritableFile {
+    content: Arc<RwLock<Vec<u8>>>,
+    path: String,
+}
 
-    /// Write Content to file
-    pub fn write(&mut self, buf: &[u8]) -> Result<()> {
-        let ret = self.file_handler.seek(SeekFrom::End(0))?;
-        self.file_handler.write(buf)?;
+impl WritableFile for MemWritableFile {
+    fn append(&mut self, buf: &[u8]) -> Result<()> {
+        self.content.write().unwrap().extend_from_slice(buf);
         Ok(())
     }
-    // Do fsync(), flush data to disk
-    pub fn sync(&self) -> Result<()> {
-        self.file_handler.sync_all()?;
+
+    fn sync(&mut self) -> Result<()> {
         Ok(())
     }
 
-    // Return the approximate size of current file
-    pub fn size(&self) -> u64 {
-        self.file_handler.metadata().unwrap().len()
+    fn path(&self) -> &str {
+        self.path.as_str()
     }
 }
-pub fn sstfile_path(seq: usize) -> String {
-    format!("/tmp/summer_kv_test/{}.sst", seq)
+
+struct MemFileLock {
+    fs: Arc<Mutex<MemFileSystem>>,
+    path: String,
 }
-pub fn logfile_path(seq: usize) -> String {
-    format!("/tmp/summer_kv_test/{}.log", seq)
+
+impl FileLock for MemFileLock {}
+
+impl Drop for MemFileLock {
+    fn drop(&mut self) {
+        self.fs.lock().unwrap().locks.remove(self.path.as_str());
+    }
 }
-pub fn get_global_sequence_number() -> u64 {
-    GLOBAL_SEQUENCE_NUMBER.fetch_add(1, Ordering::SeqCst).into()
+
+impl Env for MemEnv {
+    fn new_sequential_file(&self, path: &str) -> Result<Box<dyn SequentialFile>> {
+        Ok(Box::new(MemSequentialFile {
+            content: self.fs.lock().unwrap().file(path)?,
+            offset: 0,
+        }))
+    }
+
+    fn new_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>> {
+        Ok(Box::new(MemRandomAccessFile {
+            content: self.fs.lock().unwrap().file(path)?,
+            path: path.to_string(),
+        }))
+    }
+
+    fn new_writable_file(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        let mut fs = self.fs.lock().unwrap();
+        fs.check_new_path(path)?;
+        let content = Arc::new(RwLock::new(Vec::new()));
+        fs.files.insert(path.to_string(), content.clone());
+        Ok(Box::new(MemWritableFile {
+            content,
+            path: path.to_string(),
+        }))
+    }
+
+    fn new_appendable_file(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        Ok(Box::new(MemWritableFile {
+            content: self.fs.lock().unwrap().file(path)?,
+            path: path.to_string(),
+        }))
+    }
+
+    fn file_exists(&self, path: &str) -> bool {
+        let fs = self.fs.lock().unwrap();
+        fs.files.contains_key(path) || fs.dir_exists(path)
+    }
+
+    /// The names are sorted
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> {
+        let fs = self.fs.lock().unwrap();
+        if !fs.dir_exists(dir) {
+            return Err(io_error(ErrorKind::NotFound, dir));
+        }
+        let mut children: Vec<String> = fs
+            .files
+            .keys()
+            .chain(fs.dirs.iter())
+            .filter(|x| parent_dir(x) == dir)
+            .filter_map(|x| Some(Path::new(x).file_name()?.to_str()?.to_string()))
+            .collect();
+        children.sort();
+        Ok(children)
+    }
+
+    fn create_dir_all(&self, dir: &str) -> Result<()> {
+        let mut fs = self.fs.lock().unwrap();
+        for ancestor in Path::new(dir).ancestors() {
+            let ancestor = ancestor.to_str().unwrap_or_default();
+            if fs.files.contains_key(ancestor) {
+                return Err(io_error(ErrorKind::AlreadyExists, ancestor));
+            }
+            if !fs.dir_exists(ancestor) {
+                fs.dirs.insert(ancestor.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> Result<()> {
+        match self.fs.lock().unwrap().files.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io_error(ErrorKind::NotFound, path)),
+        }
+    }
+
+    fn remove_dir_all(&self, dir: &str) -> Result<()> {
+        let mut fs = self.fs.lock().unwrap();
+        if !fs.dirs.remove(dir) {
+            return Err(io_error(ErrorKind::NotFound, dir));
+        }
+        let prefix = format!("{}/", dir);
+        fs.files.retain(|x, _| !x.starts_with(prefix.as_str()));
+        fs.dirs.retain(|x| !x.starts_with(prefix.as_str()));
+        Ok(())
+    }
+
+    fn rename_file(&self, from: &str, to: &str) -> Result<()> {
+        let mut fs = self.fs.lock().unwrap();
+        let content = fs.file(from)?;
+        if !fs.dir_exists(parent_dir(to)) {
+            return Err(io_error(ErrorKind::NotFound, to));
+        }
+        if fs.dir_exists(to) {
+            return Err(io_error(ErrorKind::AlreadyExists, to));
+        }
+        fs.files.remove(from);
+        fs.files.insert(to.to_string(), content);
+        Ok(())
+    }
+
+    fn link_file(&self, from: &str, to: &str) -> Result<()> {
+        let mut fs = self.fs.lock().unwrap();
+        let content = fs.file(from)?;
+        fs.check_new_path(to)?;
+        fs.files.insert(to.to_string(), content);
+        Ok(())
+    }
+
+    fn sync_dir(&self, dir: &str) -> Result<()> {
+        match self.fs.lock().unwrap().dir_exists(dir) {
+            true => Ok(()),
+            false => Err(io_error(ErrorKind::NotFound, dir)),
+        }
+    }
+
+    fn lock_file(&self, path: &str) -> Result<Box<dyn FileLock>> {
+        let mut fs = self.fs.lock().unwrap();
+        if !fs.files.contains_key(path) {
+            fs.check_new_path(path)?;
+            fs.files.insert(path.to_string(), Arc::default());
+        }
+        if !fs.locks.insert(path.to_string()) {
+            return Err(locked_error(path));
+        }
+        Ok(Box::new(MemFileLock {
+            fs: self.fs.clone(),
+            path: path.to_string(),
+        }))
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::util::env::{get_global_sequence_number, FileObject};
-    use std::fs;
-    use std::io::Write;
-
-    const TMP_FILE: &str = "/tmp/test.test";
-    /// create file with amount of content of size `size` M
-    fn create_tmp_file(size: usize) {
-        let mut f = FileObject::create(TMP_FILE).unwrap();
-        for l in 0..size {
-            let buf: [u8; 1024] = [(l + 1) as u8; 1024];
-            f.file_handler.write(&buf).unwrap();
+fn io_error(kind: ErrorKind, path: &str) -> Error {
+    std::io::Error::new(kind, path.to_string()).into()
+}
+
+fn locked_error(path: &str) -> Error {
+    Error::Busy(format!("{} is locked by another process", path))
+}
+
+/// Return the directory holding the file at `path`, which is `.` for a relative path
+/// without any directory
+fn parent_dir(path: &str) -> &str {
+    match Path::new(path).parent().and_then(|x| x.to_str()) {
+        Some(dir) if !dir.is_empty() => dir,
+        _ => ".",
+    }
+}
+
+/// Sync the directory holding the file at `path`, so that the files created, renamed or
+/// removed in it survive a crash
+pub fn sync_parent_dir(env: &dyn Env, path: &str) -> Result<()> {
+    env.sync_dir(parent_dir(path))
+}
+
+/// Read the whole content of the file at `path`
+pub fn read_file(env: &dyn Env, path: &str) -> Result<Vec<u8>> {
+    let mut file = env.new_sequential_file(path)?;
+    let mut content = Vec::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match file.read(buf.as_mut_slice())? {
+            0 => return Ok(content),
+            length => content.extend_from_slice(&buf[..length]),
         }
-        f.file_handler.sync_all().unwrap();
     }
-    fn remove_tmp_file() {
-        let _ = fs::remove_file(TMP_FILE);
+}
+
+/// Rename the file `from` to `to` atomically and sync the directory, a file at `to` is
+/// replaced
+pub fn rename_and_sync(env: &dyn Env, from: &str, to: &str) -> Result<()> {
+    env.rename_file(from, to)?;
+    sync_parent_dir(env, to)
+}
+
+/// Replace the file at `path` by `data` atomically, the file holds either the old content
+/// or `data` after a crash. `data` is written to a temporary file renamed to `path` then.
+pub fn write_file_atomically(env: &dyn Env, path: &str, data: &[u8]) -> Result<()> {
+    let temp_path = temp_file_path(path);
+    let _ = env.remove_file(temp_path.as_str());
+    let mut file = env.new_writable_file(temp_path.as_str())?;
+    file.append(data)?;
+    file.sync()?;
+    rename_and_sync(env, temp_path.as_str(), path)
+}
+
+/// The temporary file a file at `path` is written to before renamed to `path`, the
+/// temporary files left by a crash are removed on open
+pub fn temp_file_path(path: &str) -> String {
+    format!("{}.tmp", path)
+}
+pub fn sstfile_path(dir: &str, seq: usize) -> String {
+    format!("{}/{}.sst", dir, seq)
+}
+/// Blob files hold the large values the SSTables refer to, see `db::blob`
+pub fn blobfile_path(dir: &str, number: u64) -> String {
+    format!("{}/{}.blob", dir, number)
+}
+pub fn logfile_path(dir: &str, seq: usize) -> String {
+    format!("{}/{}.log", dir, seq)
+}
+pub fn manifest_path(dir: &str, seq: usize) -> String {
+    format!("{}/MANIFEST-{}", dir, seq)
+}
+/// CURRENT file records the file name of the MANIFEST in use
+pub fn current_path(dir: &str) -> String {
+    format!("{}/CURRENT", dir)
+}
+
+/// LOCK file is locked by the process opening the database, see `FileLock`
+pub fn lockfile_path(dir: &str) -> String {
+    format!("{}/LOCK", dir)
+}
+
+/// Parse the file name of a database file, return the file number and the kind of file which
+/// is one of `log`, `sst`, `blob` and `MANIFEST`
+pub fn parse_file_name(name: &str) -> Option<(u64, &str)> {
+    if let Some(number) = name.strip_prefix("MANIFEST-") {
+        return Some((number.parse().ok()?, "MANIFEST"));
+    }
+    let (number, kind) = name.split_once('.')?;
+    match kind {
+        "log" | "sst" | "blob" => Some((number.parse().ok()?, kind)),
+        _ => None,
+    }
+}
+/// Hard link the file `from` to `to`, the file is copied instead if it can not be linked,
+/// such as when `to` is on another file system
+pub fn link_or_copy(env: &dyn Env, from: &str, to: &str) -> Result<()> {
+    if env.link_file(from, to).is_err() {
+        copy_file(env, from, to)?;
     }
-    struct RaiiFinalize {}
-    impl Drop for RaiiFinalize {
-        fn drop(&mut self) {
-            remove_tmp_file()
+    Ok(())
+}
+
+/// Copy the file `from` to a new file at `to` and sync it.
+/// Failed if there is a file at `to`
+pub fn copy_file(env: &dyn Env, from: &str, to: &str) -> Result<()> {
+    let mut source = env.new_sequential_file(from)?;
+    let mut target = env.new_writable_file(to)?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match source.read(buf.as_mut_slice())? {
+            0 => break,
+            length => target.append(&buf[..length])?,
         }
     }
+    target.sync()
+}
+
+pub fn get_global_sequence_number() -> u64 {
+    GLOBAL_SEQUENCE_NUMBER.fetch_add(1, Ordering::SeqCst)
+}
+
+/// The in-memory file system every test runs on, so that the tests leave nothing on disk
+#[cfg(test)]
+pub(crate) fn test_env() -> Arc<dyn Env> {
+    static ENV: std::sync::OnceLock<MemEnv> = std::sync::OnceLock::new();
+    Arc::new(ENV.get_or_init(MemEnv::default).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::env::{
+        get_global_sequence_number, link_or_copy, read_file, write_file_atomically, Env, MemEnv,
+        PosixEnv,
+    };
+    use crate::util::error::Error;
+    use std::io::ErrorKind;
+
+    /// Run `f` on a new empty directory of every `Env`, the directory of `PosixEnv` is
+    /// removed afterwards
+    fn for_each_env(name: &str, f: impl Fn(&dyn Env, &str)) {
+        f(&MemEnv::default(), format!("/env_test/{}", name).as_str());
+        let dir = std::env::temp_dir().join(format!("summer_kv_env_test_{}", name));
+        let dir = dir.to_str().unwrap();
+        let env = PosixEnv {};
+        let _ = env.remove_dir_all(dir);
+        f(&env, dir);
+        env.remove_dir_all(dir).unwrap();
+    }
+
+    fn is_not_found(result: Result<impl Sized, Error>) -> bool {
+        matches!(result, Err(Error::IoError(e)) if e.kind() == ErrorKind::NotFound)
+    }
+
     #[test]
-    fn test_create() {
-        let raii = RaiiFinalize {};
-        let ret = FileObject::create(TMP_FILE);
-        assert!(ret.is_ok());
-        let ret = FileObject::create(TMP_FILE);
-        assert!(ret.is_err());
+    fn test_write_and_read() {
+        for_each_env("write_and_read", |env, dir| {
+            env.create_dir_all(dir).unwrap();
+            let path = format!("{}/file", dir);
+            let mut file = env.new_writable_file(path.as_str()).unwrap();
+            for l in 0..4 {
+                file.append(&[(l + 1) as u8; 1024]).unwrap();
+            }
+            file.sync().unwrap();
+            assert!(env.new_writable_file(path.as_str()).is_err());
+
+            let file = env.new_random_access_file(path.as_str()).unwrap();
+            assert_eq!(file.size().unwrap(), 4096);
+            assert_eq!(file.read_at(1023, 4).unwrap().as_ref(), &[1, 2, 2, 2]);
+            assert!(file.read_at(4094, 4).is_err());
+            let mut appendable = env.new_appendable_file(path.as_str()).unwrap();
+            appendable.append(b"abcdefg").unwrap();
+            // the opened files see the appended content
+            assert_eq!(file.size().unwrap(), 4103);
+            assert_eq!(file.read_last_of(7).unwrap().as_ref(), b"abcdefg");
+
+            let content = read_file(env, path.as_str()).unwrap();
+            assert_eq!(content.len(), 4103);
+            assert_eq!(&content[4095..], b"\x04abcdefg");
+            assert!(is_not_found(read_file(
+                env,
+                format!("{}/missing", dir).as_str()
+            )));
+            assert!(is_not_found(env.new_writable_file("/missing_dir/file")));
+        });
     }
+
     #[test]
-    fn test_open() {
-        let raii = RaiiFinalize {};
-        create_tmp_file(4);
-        let ret = FileObject::open(TMP_FILE);
-        assert!(ret.is_ok());
+    fn test_read_last_of_short_file() {
+        for_each_env("read_last_of_short_file", |env, dir| {
+            env.create_dir_all(dir).unwrap();
+            let path = format!("{}/short", dir);
+            let mut file = env.new_writable_file(path.as_str()).unwrap();
+            file.append(b"abc").unwrap();
+            let file = env.new_random_access_file(path.as_str()).unwrap();
+            match file.read_last_of(4) {
+                Err(Error::Corruption { file, offset }) => {
+                    assert_eq!(file, path);
+                    assert_eq!(offset, 0);
+                }
+                _ => panic!("expect corruption error"),
+            }
+        });
     }
+
     #[test]
-    fn test_read() {
-        let raii = RaiiFinalize {};
-        create_tmp_file(4);
-        let ret = FileObject::open(TMP_FILE);
-        assert!(ret.is_ok());
-        let file_obj = ret.unwrap();
-        let ret = file_obj.read_at(1023, 4);
-        assert!(ret.is_ok());
-        let buf = ret.unwrap();
-        assert_eq!(buf.to_vec().len(), 4);
-        let result = format!("{:?}", buf.to_vec());
-        assert_eq!(result, "[1, 2, 2, 2]");
+    fn test_directories() {
+        for_each_env("directories", |env, dir| {
+            let sub_dir = format!("{}/a/b", dir);
+            env.create_dir_all(sub_dir.as_str()).unwrap();
+            env.create_dir_all(sub_dir.as_str()).unwrap();
+            write_file_atomically(env, format!("{}/x", dir).as_str(), b"1").unwrap();
+            write_file_atomically(env, format!("{}/x", dir).as_str(), b"22").unwrap();
+            assert_eq!(
+                read_file(env, format!("{}/x", dir).as_str()).unwrap(),
+                b"22"
+            );
+            env.new_writable_file(format!("{}/y", sub_dir).as_str())
+                .unwrap();
+            let mut children = env.get_children(dir).unwrap();
+            children.sort();
+            assert_eq!(children, vec!["a", "x"]);
+            assert_eq!(env.get_children(sub_dir.as_str()).unwrap(), vec!["y"]);
+            env.sync_dir(sub_dir.as_str()).unwrap();
+
+            // the renamed and the linked files share the content
+            let x = format!("{}/x", dir);
+            let z = format!("{}/z", sub_dir);
+            env.rename_file(x.as_str(), z.as_str()).unwrap();
+            assert!(!env.file_exists(x.as_str()));
+            link_or_copy(env, z.as_str(), x.as_str()).unwrap();
+            assert!(env.link_file(z.as_str(), x.as_str()).is_err());
+            env.remove_file(z.as_str()).unwrap();
+            assert!(is_not_found(env.remove_file(z.as_str())));
+            assert_eq!(read_file(env, x.as_str()).unwrap(), b"22");
+
+            env.remove_dir_all(format!("{}/a", dir).as_str()).unwrap();
+            assert!(!env.file_exists(sub_dir.as_str()));
+            assert!(is_not_found(env.get_children(sub_dir.as_str())));
+            assert_eq!(env.get_children(dir).unwrap(), vec!["x"]);
+        });
     }
 
     #[test]
-    fn test_write_and_read_last() {
-        let raii = RaiiFinalize {};
-        create_tmp_file(1);
-        let ret = FileObject::open(TMP_FILE);
-        assert!(ret.is_ok());
-        let mut file_obj = ret.unwrap();
-        file_obj
-            .write("abcdefg".as_bytes())
-            .expect("Testing expect");
-        let ret = file_obj.read_last_of(7).expect("Testing expect");
-        assert_eq!(ret.len(), 7);
-        assert_eq!(ret.as_ref(), "abcdefg".as_bytes())
+    fn test_lock_file() {
+        for_each_env("lock_file", |env, dir| {
+            env.create_dir_all(dir).unwrap();
+            let path = format!("{}/LOCK", dir);
+            let lock = env.lock_file(path.as_str()).unwrap();
+            assert!(env.file_exists(path.as_str()));
+            match env.lock_file(path.as_str()) {
+                Err(Error::Busy(msg)) => assert!(msg.contains(path.as_str())),
+                _ => panic!("the file is locked twice"),
+            }
+            drop(lock);
+            env.lock_file(path.as_str()).unwrap();
+        });
     }
 
     #[test]
-    fn test_size() {
-        let raii = RaiiFinalize {};
-        create_tmp_file(1);
-        let ret = FileObject::open(TMP_FILE);
-        assert!(ret.is_ok());
-        let mut file_obj = ret.unwrap();
-        file_obj
-            .write("abcdefg".as_bytes())
-            .expect("Testing expect");
-        assert_eq!(1031, file_obj.size())
+    fn test_mem_env_is_shared_by_clones() {
+        let env = MemEnv::default();
+        let clone = env.clone();
+        env.create_dir_all("/shared").unwrap();
+        write_file_atomically(&env, "/shared/file", b"content").unwrap();
+        assert_eq!(read_file(&clone, "/shared/file").unwrap(), b"content");
+        assert!(!MemEnv::default().file_exists("/shared/file"));
     }
 
     #[test]
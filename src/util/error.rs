@@ -1,8 +1,91 @@
+use std::fmt::Formatter;
+use std::sync::Arc;
 
-pub struct ErrorInfo(u16, &'static str);
-pub const ERROR_BOARD: &'static [ErrorInfo] = &[
-    ErrorInfo(0, "block overflow"),
-    ErrorInfo(1, ""),
-    ErrorInfo(2, ""),
-];
+/// `Error` is the failure type returned by every public API of summer_kv.
+/// Callers are expected to `match` on the variant to decide how to react, e.g. retrying on
+/// `Busy` or reporting the damaged file on `Corruption`.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The requested key or file does not exist
+    NotFound,
+    /// The content of `file` can not be decoded, `offset` is the position where the decoder
+    /// gave up. An empty `file` means the data was not read from a disk file
+    Corruption { file: String, offset: u64 },
+    /// Error reported by the operating system
+    IoError(Arc<std::io::Error>),
+    /// The argument provided by the caller can not be accepted
+    InvalidArgument(String),
+    /// The resource is held by others, the operation may succeed if retried later
+    Busy(String),
+    /// The operation gave up waiting for a resource, e.g. a lock held by another transaction
+    TimedOut(String),
+    /// The transaction is aborted to break a deadlock, `keys` are the keys the transactions
+    /// in the cycle wait for
+    Deadlock { keys: Vec<Vec<u8>> },
+    /// The database is closing and does not accept new operations
+    ShutdownInProgress,
+    /// The operation is not supported by the database, e.g. writing to a database opened
+    /// read only
+    NotSupported(String),
+}
 
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn corruption(file: &str, offset: u64) -> Self {
+        Error::Corruption {
+            file: file.to_string(),
+            offset,
+        }
+    }
+
+    /// Attach the file name to a `Corruption` produced by a decoder which only knows the
+    /// relative position inside the buffer it was given. `base` is the offset of that buffer
+    /// inside the file. Other variants are returned untouched.
+    pub fn locate(self, file: &str, base: u64) -> Self {
+        match self {
+            Error::Corruption { offset, .. } => Error::Corruption {
+                file: file.to_string(),
+                offset: base + offset,
+            },
+            other => other,
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::NotFound)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "not found"),
+            Error::Corruption { file, offset } => {
+                write!(f, "corruption in file `{}` at offset {}", file, offset)
+            }
+            Error::IoError(e) => write!(f, "io error: {}", e),
+            Error::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            Error::Busy(msg) => write!(f, "busy: {}", msg),
+            Error::TimedOut(msg) => write!(f, "timed out: {}", msg),
+            Error::Deadlock { keys } => write!(f, "deadlock on keys {:?}", keys),
+            Error::ShutdownInProgress => write!(f, "shutdown in progress"),
+            Error::NotSupported(msg) => write!(f, "not supported: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IoError(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::IoError(Arc::new(e))
+    }
+}